@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Copy commands tried in order; the first one installed is used.
+const COPY_COMMANDS: &[(&str, &[&str])] = &[
+    ("pbcopy", &[]),
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+];
+
+/// Paste commands tried in order, matching `COPY_COMMANDS`.
+const PASTE_COMMANDS: &[(&str, &[&str])] = &[
+    ("pbpaste", &[]),
+    ("wl-paste", &["--no-newline"]),
+    ("xclip", &["-selection", "clipboard", "-o"]),
+    ("xsel", &["--clipboard", "--output"]),
+];
+
+/// Puts `text` on the system clipboard with the first available copy
+/// command, or with an OSC 52 escape sequence the terminal forwards (this
+/// also works over ssh and inside tmux with `set-clipboard on`). Returns
+/// how it was copied.
+///
+/// # Errors
+///
+/// Will return `Err` if the copy command fails or the terminal cannot be
+/// written to.
+pub fn copy(text: &str) -> Result<&'static str> {
+    for (program, args) in COPY_COMMANDS {
+        let Ok(mut child) = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow!("{program} failed ({status})"));
+        }
+        return Ok(program);
+    }
+
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    stdout.flush()?;
+    Ok("OSC 52")
+}
+
+/// Text on the system clipboard, from the first paste command that
+/// succeeds. `None` when none is installed or the clipboard holds no text;
+/// OSC 52 cannot be used here since most terminals refuse to report the
+/// clipboard.
+#[must_use]
+pub fn paste() -> Option<String> {
+    PASTE_COMMANDS.iter().find_map(|(program, args)| {
+        let output = Command::new(program).args(*args).stderr(Stdio::null()).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8(output.stdout).ok())
+            .flatten()
+    })
+}
+
+/// Standard base64 with padding, for the OSC 52 payload.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[(n >> (18 - 6 * i) & 63) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_the_last_group() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("ALL_PROXY=socks5h://127.0.0.1:1080".as_bytes()), "QUxMX1BST1hZPXNvY2tzNWg6Ly8xMjcuMC4wLjE6MTA4MA==");
+    }
+}
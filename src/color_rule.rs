@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Context, Result};
+use ratatui::style::{Color, Modifier, Style};
+use regex::Regex;
+use serde::Deserialize;
+use std::str::FromStr;
+
+use crate::search::SearchField;
+use crate::ssh;
+
+/// A `color_rules` entry as written in the config file, e.g.
+/// `{ "match": "tag:prod", "style": "bold red" }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColorRuleConfig {
+    #[serde(rename = "match")]
+    pub matches: String,
+    pub style: String,
+}
+
+/// The `match` of a rule: `<field>:<glob>` or `<field>:re:<regex>`, true
+/// when any value of the field matches.
+#[derive(Debug, Clone)]
+pub struct HostPattern {
+    field: SearchField,
+    pattern: Pattern,
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl HostPattern {
+    /// # Errors
+    ///
+    /// Will return `Err` if the field or pattern is invalid.
+    pub fn parse(text: &str) -> Result<HostPattern> {
+        let (field, pattern) = text
+            .split_once(':')
+            .ok_or(anyhow!("Rule {text:?} needs a field, e.g. tag:prod"))?;
+        let field =
+            SearchField::from_qualifier(field).ok_or(anyhow!("Unknown field {field:?} in rule {text:?}"))?;
+        let pattern = match pattern.strip_prefix("re:") {
+            Some(regex) => Pattern::Regex(
+                Regex::new(regex).with_context(|| format!("Invalid rule regex: {regex}"))?,
+            ),
+            None => Pattern::Glob(
+                glob::Pattern::new(pattern).with_context(|| format!("Invalid rule pattern: {pattern}"))?,
+            ),
+        };
+        Ok(HostPattern { field, pattern })
+    }
+
+    #[must_use]
+    pub fn matches(&self, host: &ssh::Host) -> bool {
+        self.field.values(host).iter().any(|value| match &self.pattern {
+            Pattern::Glob(glob) => glob.matches(value),
+            Pattern::Regex(regex) => regex.is_match(value),
+        })
+    }
+}
+
+/// Styles the rows of hosts matching a `HostPattern`.
+#[derive(Debug, Clone)]
+pub struct ColorRule {
+    pattern: HostPattern,
+    pub style: Style,
+}
+
+impl ColorRule {
+    /// # Errors
+    ///
+    /// Will return `Err` if the pattern or style is invalid.
+    pub fn parse(rule: &ColorRuleConfig) -> Result<ColorRule> {
+        Ok(ColorRule {
+            pattern: HostPattern::parse(&rule.matches)?,
+            style: parse_style(&rule.style)?,
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if any of the rules is invalid.
+    pub fn parse_all(rules: &[ColorRuleConfig]) -> Result<Vec<ColorRule>> {
+        rules.iter().map(Self::parse).collect()
+    }
+
+    #[must_use]
+    pub fn matches(&self, host: &ssh::Host) -> bool {
+        self.pattern.matches(host)
+    }
+}
+
+/// The combined style of every rule matching `host`; later rules override
+/// the colors of earlier ones and add their modifiers.
+#[must_use]
+pub fn style_for(rules: &[ColorRule], host: &ssh::Host) -> Option<Style> {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(host))
+        .map(|rule| rule.style)
+        .reduce(Style::patch)
+}
+
+/// `bold red`, `italic #7dd3fc on black`: modifiers, a foreground color and
+/// an optional `on <color>` background, in any order.
+fn parse_style(text: &str) -> Result<Style> {
+    let color = |word: &str| Color::from_str(word).map_err(|_| anyhow!("Unknown color {word:?} in {text:?}"));
+
+    let mut style = Style::default();
+    let mut words = text.split_whitespace();
+    while let Some(word) = words.next() {
+        style = match word.to_lowercase().as_str() {
+            "bold" => style.add_modifier(Modifier::BOLD),
+            "dim" => style.add_modifier(Modifier::DIM),
+            "italic" => style.add_modifier(Modifier::ITALIC),
+            "underlined" | "underline" => style.add_modifier(Modifier::UNDERLINED),
+            "reversed" => style.add_modifier(Modifier::REVERSED),
+            "on" => {
+                let background = words.next().ok_or(anyhow!("Missing color after \"on\" in {text:?}"))?;
+                style.bg(color(background)?)
+            }
+            _ => style.fg(color(word)?),
+        };
+    }
+    Ok(style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(matches: &str, style: &str) -> ColorRule {
+        ColorRule::parse(&ColorRuleConfig {
+            matches: matches.to_string(),
+            style: style.to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn matching_rules_are_combined_in_order() {
+        let rules = vec![rule("destination:10.0.*", "blue"), rule("tag:prod", "bold red on black")];
+        let host = ssh::Host {
+            destination: "10.0.3.4".to_string(),
+            tags: vec!["web".to_string(), "prod".to_string()],
+            ..ssh::Host::default()
+        };
+
+        assert_eq!(
+            style_for(&rules, &host),
+            Some(Style::default().fg(Color::Red).bg(Color::Black).add_modifier(Modifier::BOLD))
+        );
+        assert_eq!(style_for(&rules[..1], &ssh::Host::default()), None);
+        let db = ssh::Host {
+            name: "db-12".to_string(),
+            ..ssh::Host::default()
+        };
+        assert!(rule("name:re:^db-[0-9]+$", "#ff0000").matches(&db));
+    }
+
+    #[test]
+    fn invalid_rules_are_reported() {
+        let parse = |matches: &str, style: &str| {
+            ColorRule::parse(&ColorRuleConfig {
+                matches: matches.to_string(),
+                style: style.to_string(),
+            })
+        };
+        assert!(parse("prod", "red").is_err());
+        assert!(parse("color:prod", "red").is_err());
+        assert!(parse("tag:prod", "reddish").is_err());
+        assert!(parse("tag:prod", "red on").is_err());
+    }
+}
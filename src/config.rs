@@ -0,0 +1,144 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::color_rule::ColorRuleConfig;
+use crate::edit::HostTemplate;
+use crate::help::Footer;
+use crate::i18n::Lang;
+use crate::icon::{IconMode, IconRuleConfig};
+use crate::search::{GroupKey, SortKey};
+use crate::snippet::Snippet;
+use crate::source::SourceDefaults;
+use crate::theme::ThemeName;
+use crate::ui::EscAction;
+
+/// Settings read from sgh's own config file. Every field is optional so the
+/// file only needs to contain what the user wants to change; CLI flags are
+/// applied on top.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserConfig {
+    /// Hosts to hide, as globs (`github.com`, `*.internal`) or `re:<regex>`.
+    pub exclude: Vec<String>,
+    /// Extra host sources, same syntax as `--source`.
+    pub sources: Vec<String>,
+    /// Fold `Host *` style pattern blocks into matching hosts (default true).
+    pub apply_patterns: Option<bool>,
+    /// Use the Host name as Hostname when none is set (default true).
+    pub default_hostname: Option<bool>,
+    /// Collapse hosts with identical settings into one row (default true).
+    pub merge: Option<bool>,
+    /// Start with one row per alias instead of one per host.
+    pub split_aliases: bool,
+    /// Start with destinations, users and ports masked (`--redact`).
+    pub redact: bool,
+    /// Pre-fill the search with the host on the clipboard.
+    pub from_clipboard: bool,
+    /// Keys to sort the host list by, e.g. `["tags", "name"]`.
+    pub sort_by: Vec<SortKey>,
+    /// Compare numbers in names by value (default true); false sorts
+    /// strictly character by character.
+    pub natural_sort: Option<bool>,
+    /// Group the host list by `tag`, `file`, `user` or `proxy`.
+    pub group_by: Option<GroupKey>,
+    /// Row styles by host attribute, applied in order.
+    pub color_rules: Vec<ColorRuleConfig>,
+    /// Color scheme: `dark` (default) or `high-contrast`.
+    pub theme: Option<ThemeName>,
+    /// Draw with ASCII only (same as `--ascii`).
+    pub ascii: bool,
+    /// UI language, `en` or `ja`; the locale decides when unset.
+    pub language: Option<Lang>,
+    /// Footer row: hide it, pick the keys its help lists, or set its text.
+    pub footer: Footer,
+    /// What Esc does: `quit`, `clear` or `double`.
+    pub esc: Option<EscAction>,
+    /// Icon column: `nerd` glyphs, `ascii` labels or `off` (default).
+    pub icons: Option<IconMode>,
+    /// Icons by host attribute; the first matching rule wins over tags.
+    pub icon_rules: Vec<IconRuleConfig>,
+    /// How strongly connection frequency lifts a host in search results.
+    pub frequency_weight: Option<f64>,
+    /// Command the `{{secret "<path>"}}` template helper runs with the path
+    /// appended (default `pass show`).
+    pub secret_command: Option<String>,
+    /// Terminal / tmux window title during a session (default `{{name}}`);
+    /// an empty string leaves the title alone.
+    pub title_template: Option<String>,
+    /// Notify when a session that lasted at least this many seconds ends.
+    pub notify_after: Option<u64>,
+    /// Quit after this many seconds without a key press or mouse event.
+    pub idle_timeout: Option<u64>,
+    /// Hide the hosts after this many seconds without input (`--lock-after`).
+    pub lock_after: Option<u64>,
+    /// Passphrase to type to reveal the hosts again; any key does without it.
+    pub lock_passphrase: Option<String>,
+    /// Never write to SSH config files (same as `--read-only`).
+    pub read_only: bool,
+    /// Fail on references to missing fields instead of rendering them empty.
+    pub strict_templates: bool,
+    /// Command printing a one-time code (e.g. `oathtool --totp -b <KEY>`),
+    /// run before each connection and exposed to templates as `{{otp}}`.
+    pub otp_command: Option<String>,
+    /// Read hosts from a running `sgh daemon` when there is one.
+    pub use_daemon: bool,
+    /// Starting points offered when saving a new host.
+    pub host_templates: Vec<HostTemplate>,
+    /// For each of `hostname`, `user`, `port` and `identityfile`, the source
+    /// whose value wins over the SSH config, e.g. `{"hostname": "gcp"}`.
+    pub source_priority: BTreeMap<String, String>,
+    /// User, port and connect template for the hosts of each kind of
+    /// source, e.g. `{"gcp": {"user": "ops"}}`.
+    pub source_defaults: BTreeMap<String, SourceDefaults>,
+    /// Seconds the hosts of slow sources are reused from the cache before
+    /// asking again (default 600; 0 always asks).
+    pub source_cache_ttl: Option<u64>,
+    /// Named sets of `-L` forwards to add to a connection with Alt+T, e.g.
+    /// `{"db": ["5432:localhost:5432"]}`.
+    pub tunnels: BTreeMap<String, Vec<String>>,
+    /// Local port a SOCKS proxy (Alt+S) tries first (default 1080); the
+    /// next free one is used when it is taken.
+    pub socks_port: Option<u16>,
+    /// Remote commands to run on the selected host from the Alt+R list.
+    pub snippets: Vec<Snippet>,
+    /// Command template snippets run with (same as `--snippet-template`).
+    pub snippet_template: Option<String>,
+}
+
+impl UserConfig {
+    /// Path of the config file: `$SGH_CONFIG` when set, otherwise
+    /// `sgh/config.json` under the platform config directory
+    /// (`~/.config/sgh/config.json` on Linux).
+    #[must_use]
+    pub fn path() -> Option<PathBuf> {
+        match std::env::var_os("SGH_CONFIG") {
+            Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+            _ => dirs::config_dir().map(|dir| dir.join("sgh").join("config.json")),
+        }
+    }
+
+    /// Loads the config file, or the defaults when it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file exists but cannot be read or parsed.
+    pub fn load() -> Result<UserConfig> {
+        let Some(path) = Self::path() else {
+            return Ok(UserConfig::default());
+        };
+
+        let json = match std::fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(UserConfig::default())
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+            }
+        };
+
+        serde_json::from_str(&json).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
@@ -0,0 +1,365 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A JSON-RPC 2.0 request read from a control client.
+#[derive(Debug)]
+pub struct Call {
+    client: usize,
+    /// `None` for notifications, which get no reply.
+    id: Option<Value>,
+    pub method: String,
+    pub params: Value,
+}
+
+/// JSON-RPC error object sent back for a failed call.
+#[derive(Debug, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    #[must_use]
+    pub fn method_not_found(method: &str) -> RpcError {
+        RpcError {
+            code: -32601,
+            message: format!("Unknown method {method}"),
+        }
+    }
+
+    #[must_use]
+    pub fn invalid_params(message: impl Into<String>) -> RpcError {
+        RpcError {
+            code: -32602,
+            message: message.into(),
+        }
+    }
+
+    /// The request was understood but could not be carried out.
+    #[must_use]
+    pub fn failed(message: impl Into<String>) -> RpcError {
+        RpcError {
+            code: -32000,
+            message: message.into(),
+        }
+    }
+}
+
+/// Longest request line accepted, in bytes. A client that sends more
+/// without a newline is answered with an error and disconnected, so it
+/// cannot make sgh buffer without bound.
+pub const MAX_LINE: usize = 64 * 1024;
+
+/// Error sent to a client before dropping it for an overlong line.
+#[must_use]
+pub fn line_too_long() -> RpcError {
+    RpcError {
+        code: -32600,
+        message: format!("Invalid request: line longer than {MAX_LINE} bytes"),
+    }
+}
+
+struct Client {
+    id: usize,
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    /// The client closed its side; it is dropped once every call is answered.
+    eof: bool,
+    pending: usize,
+}
+
+/// Unix socket other programs (editor plugins, scripts) use to drive a
+/// running sgh with newline-delimited JSON-RPC 2.0 (`--control-socket`).
+/// Everything is non-blocking so the event loop can poll it between frames.
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client>,
+    next_client: usize,
+}
+
+impl ControlSocket {
+    /// Listens on `path`, replacing a stale socket left by a crashed sgh.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if another process is listening on `path` or the
+    /// socket cannot be created.
+    pub fn bind(path: &Path) -> Result<ControlSocket> {
+        let listener = listen(path)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(ControlSocket {
+            path: path.to_path_buf(),
+            listener,
+            clients: Vec::new(),
+            next_client: 0,
+        })
+    }
+
+    /// Accepts new clients and returns every complete request received since
+    /// the last call. Malformed lines are answered with an error right away.
+    pub fn poll(&mut self) -> Vec<Call> {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client {
+                    id: self.next_client,
+                    stream,
+                    buffer: Vec::new(),
+                    eof: false,
+                    pending: 0,
+                });
+                self.next_client += 1;
+            }
+        }
+
+        let mut calls = Vec::new();
+        for client in &mut self.clients {
+            let mut chunk = [0; 4096];
+            // 1 回の poll で読むのは MAX_LINE まで (残りは次のフレームで)
+            while !client.eof && client.buffer.len() <= MAX_LINE {
+                match client.stream.read(&mut chunk) {
+                    Ok(0) => client.eof = true,
+                    Ok(n) => client.buffer.extend_from_slice(&chunk[..n]),
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(_) => client.eof = true,
+                }
+            }
+
+            let mut too_long = false;
+            while let Some(end) = client.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = client.buffer.drain(..=end).collect();
+                if line.len() > MAX_LINE + 1 {
+                    too_long = true;
+                    break;
+                }
+                let line = String::from_utf8_lossy(&line);
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match parse(&line) {
+                    Ok((id, method, params)) => {
+                        if id.is_some() {
+                            client.pending += 1;
+                        }
+                        calls.push(Call {
+                            client: client.id,
+                            id,
+                            method,
+                            params,
+                        });
+                    }
+                    Err(error) => send(&mut client.stream, &response(&Value::Null, Err(error))),
+                }
+            }
+            if too_long || client.buffer.len() > MAX_LINE {
+                send(&mut client.stream, &response(&Value::Null, Err(line_too_long())));
+                client.buffer.clear();
+                client.eof = true;
+                client.pending = 0;
+            }
+        }
+        self.clients.retain(|client| !client.eof || client.pending > 0);
+
+        calls
+    }
+
+    /// Sends the result of `call` back to the client that made it.
+    pub fn reply(&mut self, call: &Call, result: Result<Value, RpcError>) {
+        let Some(id) = &call.id else {
+            return;
+        };
+        let Some(client) = self.clients.iter_mut().find(|client| client.id == call.client) else {
+            return;
+        };
+
+        send(&mut client.stream, &response(id, result));
+        client.pending = client.pending.saturating_sub(1);
+        self.clients.retain(|client| !client.eof || client.pending > 0);
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Listens on `path`, readable and writable by the current user only.
+///
+/// # Errors
+///
+/// Will return `Err` if another process is listening on `path` or the
+/// socket cannot be created.
+pub fn listen(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        // 生きているインスタンスのソケットは奪わない
+        if UnixStream::connect(path).is_ok() {
+            bail!("{} is already in use by another process", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove the stale socket {}", path.display()))?;
+    }
+
+    // 接続できれば任意のホストに接続させられるので本人だけに絞る。bind 直後から
+    // chmod までの間も他人に開かないよう、本人だけの 0700 ディレクトリ内で作って
+    // 権限を絞ってから移す
+    let name = path.file_name().context("The socket path has no file name")?;
+    let staging = path.with_file_name(format!(".{}.{}", name.to_string_lossy(), std::process::id()));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+    let staged = staging.join("socket");
+    let listener = UnixListener::bind(&staged)
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, path)?;
+            Ok(listener)
+        })
+        .with_context(|| format!("Failed to listen on {}", path.display()));
+    let _ = std::fs::remove_dir_all(&staging);
+
+    listener
+}
+
+/// Splits a request line into id, method and params.
+///
+/// # Errors
+///
+/// Will return `Err` with the JSON-RPC error to send back when the line is
+/// not a valid request.
+pub fn parse(line: &str) -> Result<(Option<Value>, String, Value), RpcError> {
+    let request: Value = serde_json::from_str(line).map_err(|err| RpcError {
+        code: -32700,
+        message: format!("Parse error: {err}"),
+    })?;
+    let method = request["method"].as_str().ok_or(RpcError {
+        code: -32600,
+        message: "Invalid request: missing method".to_string(),
+    })?;
+    let params = match &request["params"] {
+        Value::Null => json!({}),
+        params => params.clone(),
+    };
+
+    Ok((request.get("id").cloned(), method.to_string(), params))
+}
+
+#[must_use]
+pub fn response(id: &Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": error.code, "message": error.message },
+        }),
+    }
+}
+
+/// Writes one response line. A client that stops reading is given up on
+/// rather than stalling the UI.
+fn send(stream: &mut UnixStream, message: &Value) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    let _ = writeln!(stream, "{message}");
+    let _ = stream.set_nonblocking(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sgh-control-{}-{name}.sock", std::process::id()))
+    }
+
+    #[test]
+    fn requests_are_read_and_answered() {
+        let path = socket_path("roundtrip");
+        let mut socket = ControlSocket::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        writeln!(client, r#"{{"jsonrpc":"2.0","id":7,"method":"set_filter","params":{{"query":"db"}}}}"#)
+            .unwrap();
+        writeln!(client, "not json").unwrap();
+
+        let mut calls = Vec::new();
+        for _ in 0..100 {
+            calls = socket.poll();
+            if !calls.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].method, "set_filter");
+        assert_eq!(calls[0].params["query"], "db");
+        socket.reply(&calls[0], Ok(json!({ "matches": 2 })));
+
+        let mut lines = BufReader::new(client).lines();
+        let parse_error: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(parse_error["error"]["code"], -32700);
+        let reply: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(reply, json!({ "jsonrpc": "2.0", "id": 7, "result": { "matches": 2 } }));
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn the_socket_is_private_from_the_start() {
+        let path = socket_path("mode");
+        let _socket = ControlSocket::bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let staging = path.with_file_name(format!(".{}.{}", path.file_name().unwrap().to_string_lossy(), std::process::id()));
+        assert!(!staging.exists());
+    }
+
+    #[test]
+    fn clients_sending_overlong_lines_are_dropped() {
+        let path = socket_path("overlong");
+        let mut socket = ControlSocket::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        let writer = {
+            let mut client = client.try_clone().unwrap();
+            // 改行なしで上限を超えて送り続ける
+            std::thread::spawn(move || {
+                let chunk = vec![b'x'; 4096];
+                while client.write_all(&chunk).is_ok() {}
+            })
+        };
+
+        for _ in 0..100 {
+            assert!(socket.poll().is_empty());
+            if socket.clients.is_empty() && socket.next_client == 1 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(socket.clients.is_empty());
+
+        client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let mut reply = String::new();
+        BufReader::new(&mut client).read_line(&mut reply).unwrap();
+        let reply: Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply["error"]["code"], -32600);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn a_live_socket_is_not_taken_over() {
+        let path = socket_path("live");
+        let _socket = ControlSocket::bind(&path).unwrap();
+        assert!(ControlSocket::bind(&path).is_err());
+    }
+}
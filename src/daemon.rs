@@ -0,0 +1,340 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::control::{self, RpcError};
+use crate::source::Source;
+use crate::ssh::{self, OptionSource};
+use crate::store;
+use crate::ui::{self, AppConfig};
+
+/// How long either side waits on a silent peer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Socket used when `--daemon-socket` is not given: `$XDG_RUNTIME_DIR/sgh`
+/// when the platform has one, otherwise the data directory.
+#[must_use]
+pub fn default_socket() -> Option<PathBuf> {
+    dirs::runtime_dir()
+        .map(|dir| dir.join("sgh"))
+        .or_else(store::data_dir)
+        .map(|dir| dir.join("daemon.sock"))
+}
+
+/// A host as sent over the socket. The fields `Host` keeps out of its JSON
+/// (they are hidden from templates) travel next to it.
+#[derive(Serialize, Deserialize)]
+struct CachedHost {
+    #[serde(flatten)]
+    host: ssh::Host,
+    is_pattern: bool,
+    on_session_start_template: Option<String>,
+    on_session_end_template: Option<String>,
+    otp_command: Option<String>,
+    option_sources: BTreeMap<String, OptionSource>,
+}
+
+impl From<ssh::Host> for CachedHost {
+    fn from(mut host: ssh::Host) -> CachedHost {
+        CachedHost {
+            is_pattern: host.is_pattern,
+            on_session_start_template: host.on_session_start_template.take(),
+            on_session_end_template: host.on_session_end_template.take(),
+            otp_command: host.otp_command.take(),
+            option_sources: std::mem::take(&mut host.option_sources),
+            host,
+        }
+    }
+}
+
+impl From<CachedHost> for ssh::Host {
+    fn from(cached: CachedHost) -> ssh::Host {
+        ssh::Host {
+            is_pattern: cached.is_pattern,
+            on_session_start_template: cached.on_session_start_template,
+            on_session_end_template: cached.on_session_end_template,
+            otp_command: cached.otp_command,
+            option_sources: cached.option_sources,
+            ..cached.host
+        }
+    }
+}
+
+/// Digest of the settings that decide which hosts are loaded (config files,
+/// sources and how they are merged, pipeline stages). A client sends its
+/// own with `hosts`, so a daemon started with another `--config`,
+/// `--source` or `--raw` is not used in its place.
+#[must_use]
+pub fn fingerprint(config: &AppConfig) -> String {
+    let settings = format!(
+        "{:?}",
+        (
+            ssh::expand_config_paths(&config.config_paths),
+            config.strict_missing,
+            config.pipeline,
+            &config.sources,
+            &config.source_priority,
+            &config.source_defaults,
+        )
+    );
+    let mut hasher = DefaultHasher::new();
+    settings.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// The merged hosts and the files they were read from, so edits are noticed
+/// without a file watcher.
+struct Inventory {
+    hosts: Value,
+    count: usize,
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    loaded_at: Instant,
+    /// Source hosts are only good for `source_cache_ttl`, like the cache
+    /// the picker reads them from; `None` when no source needs refreshing.
+    ttl: Option<Duration>,
+}
+
+impl Inventory {
+    fn load(config: &AppConfig) -> Result<Inventory> {
+        let hosts = ui::load_hosts(config)?;
+
+        // Include で読まれたファイルもホストの定義元として拾える
+        let paths: BTreeSet<PathBuf> = ssh::expand_config_paths(&config.config_paths)
+            .into_iter()
+            .chain(hosts.iter().filter_map(|host| host.source_file.as_ref().map(PathBuf::from)))
+            .collect();
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let modified = modified(&path);
+                (path, modified)
+            })
+            .collect();
+
+        let count = hosts.len();
+        let cached: Vec<CachedHost> = hosts.into_iter().map(CachedHost::from).collect();
+        let slow = config.sources.iter().any(Source::is_slow);
+        Ok(Inventory {
+            hosts: serde_json::to_value(cached)?,
+            count,
+            files,
+            loaded_at: Instant::now(),
+            ttl: (slow && !config.source_cache.offline).then_some(config.source_cache.ttl),
+        })
+    }
+
+    fn is_stale(&self) -> bool {
+        self.files.iter().any(|(path, loaded)| modified(path) != *loaded)
+            || self.ttl.is_some_and(|ttl| self.loaded_at.elapsed() >= ttl)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// `sgh daemon`: parses the hosts once and serves them on `socket` until
+/// killed, re-reading them when a config file changes.
+///
+/// # Errors
+///
+/// Will return `Err` if the hosts cannot be loaded or the socket cannot be
+/// created.
+pub fn run(config: &AppConfig, socket: &Path) -> Result<()> {
+    let mut inventory = Inventory::load(config)?;
+    if let Some(dir) = socket.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let listener = control::listen(socket)?;
+    eprintln!("sgh daemon: serving {} hosts on {}", inventory.count, socket.display());
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        if let Err(err) = serve(&mut inventory, config, stream) {
+            eprintln!("sgh daemon: {err:#}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Answers one client's requests until it disconnects.
+fn serve(inventory: &mut Inventory, config: &AppConfig, stream: UnixStream) -> Result<()> {
+    // 応答しないクライアントに他のクライアントを待たせない
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let mut line = Vec::new();
+        let limit = control::MAX_LINE as u64 + 1;
+        if (&mut reader).take(limit).read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if line.len() > control::MAX_LINE && !line.ends_with(b"\n") {
+            writeln!(writer, "{}", control::response(&Value::Null, Err(control::line_too_long())))?;
+            break;
+        }
+        let line = String::from_utf8_lossy(&line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match control::parse(&line) {
+            Ok((id, method, params)) => {
+                let result = handle(inventory, config, &method, &params);
+                let Some(id) = id else {
+                    continue;
+                };
+                control::response(&id, result)
+            }
+            Err(error) => control::response(&Value::Null, Err(error)),
+        };
+        writeln!(writer, "{reply}")?;
+    }
+
+    Ok(())
+}
+
+fn handle(inventory: &mut Inventory, config: &AppConfig, method: &str, params: &Value) -> Result<Value, RpcError> {
+    let reload = |inventory: &mut Inventory| {
+        *inventory = Inventory::load(config).map_err(|err| RpcError::failed(format!("{err:#}")))?;
+        Ok::<_, RpcError>(())
+    };
+
+    match method {
+        "hosts" => {
+            // 設定の違うクライアントには別のホスト一覧を渡さない (クライアントが自分で読む)
+            if params["fingerprint"].as_str().is_some_and(|theirs| theirs != fingerprint(config)) {
+                return Err(RpcError::failed("The daemon was started with other settings"));
+            }
+            if inventory.is_stale() {
+                reload(inventory)?;
+            }
+            Ok(json!({ "hosts": inventory.hosts }))
+        }
+        "reload" => {
+            reload(inventory)?;
+            Ok(json!({ "hosts": inventory.count }))
+        }
+        "status" => Ok(json!({
+            "hosts": inventory.count,
+            "age_secs": inventory.loaded_at.elapsed().as_secs(),
+        })),
+        method => Err(RpcError::method_not_found(method)),
+    }
+}
+
+/// Asks the daemon listening on `socket` for its hosts, loaded with the
+/// settings `config` would load them with.
+///
+/// # Errors
+///
+/// Will return `Err` if no daemon is listening, it was started with other
+/// settings, or it reports an error.
+pub fn fetch_hosts(socket: &Path, config: &AppConfig) -> Result<Vec<ssh::Host>> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("No sgh daemon on {}", socket.display()))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let params = json!({ "fingerprint": fingerprint(config) });
+    writeln!(stream, "{}", json!({ "jsonrpc": "2.0", "id": 1, "method": "hosts", "params": params }))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let mut response: Value = serde_json::from_str(&line)?;
+    if let Some(error) = response.get("error") {
+        bail!("sgh daemon: {}", error["message"].as_str().unwrap_or("unknown error"));
+    }
+
+    let hosts = response["result"]["hosts"].take();
+    let hosts: Vec<CachedHost> =
+        serde_json::from_value(hosts).map_err(|err| anyhow!("Unexpected reply from the sgh daemon: {err}"))?;
+    Ok(hosts.into_iter().map(ssh::Host::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_survive_the_round_trip() {
+        let mut host = ssh::Host {
+            name: "db".to_string(),
+            port: Some("2222".to_string()),
+            is_pattern: true,
+            otp_command: Some("pass otp db".to_string()),
+            ..ssh::Host::default()
+        };
+        host.option_sources.insert(
+            "port".to_string(),
+            OptionSource {
+                block: "Host db".to_string(),
+                location: Some("config:2".to_string()),
+                inherited: false,
+            },
+        );
+
+        let json = serde_json::to_string(&CachedHost::from(host)).unwrap();
+        let host = ssh::Host::from(serde_json::from_str::<CachedHost>(&json).unwrap());
+
+        assert_eq!(host.name, "db");
+        assert_eq!(host.port.as_deref(), Some("2222"));
+        assert!(host.is_pattern);
+        assert_eq!(host.otp_command.as_deref(), Some("pass otp db"));
+        assert_eq!(host.option_sources["port"].location.as_deref(), Some("config:2"));
+    }
+
+    fn inventory(ttl: Option<Duration>) -> Inventory {
+        Inventory {
+            hosts: json!([]),
+            count: 0,
+            files: Vec::new(),
+            loaded_at: Instant::now(),
+            ttl,
+        }
+    }
+
+    #[test]
+    fn clients_with_other_settings_are_turned_away() {
+        let config = crate::ui::tests::test_config();
+        let mut inventory = inventory(None);
+        let ours = json!({ "fingerprint": fingerprint(&config) });
+        assert!(handle(&mut inventory, &config, "hosts", &ours).is_ok());
+        assert!(handle(&mut inventory, &config, "hosts", &json!({})).is_ok());
+
+        let others = [
+            AppConfig {
+                config_paths: vec!["/tmp/other_config".to_string()],
+                ..config.clone()
+            },
+            AppConfig {
+                pipeline: ssh::Pipeline::RAW,
+                ..config.clone()
+            },
+            AppConfig {
+                sources: vec!["vagrant:/srv/vm".parse().unwrap()],
+                ..config.clone()
+            },
+        ];
+        for other in others {
+            let theirs = json!({ "fingerprint": fingerprint(&other) });
+            let err = handle(&mut inventory, &config, "hosts", &theirs).unwrap_err();
+            assert_eq!(err.code, -32000);
+        }
+    }
+
+    #[test]
+    fn source_hosts_go_stale_after_the_ttl() {
+        assert!(!inventory(None).is_stale());
+        assert!(!inventory(Some(Duration::from_secs(600))).is_stale());
+        assert!(inventory(Some(Duration::ZERO)).is_stale());
+    }
+}
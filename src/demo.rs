@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+use crate::{resolve, ssh, ssh_config};
+
+/// Synthetic hosts shown by `--demo`. Addresses are from the documentation
+/// ranges (RFC 5737 / `example.com`) so nothing real is ever reached.
+const CONFIG: &str = r"Host *
+  ServerAliveInterval 30
+
+Host *.corp.example.com
+  User deploy
+  ProxyJump bastion
+
+Host bastion
+  HostName 192.0.2.10
+  User ops
+  IdentityFile ~/.ssh/id_ed25519
+  # sgh:tags infra
+  # sgh:note Entry point for the corp network
+
+Host web-01.corp.example.com web1
+  # sgh:tags prod web
+  LocalForward 8080 localhost:80
+
+Host web-02.corp.example.com web2
+  # sgh:tags prod web
+
+Host db-primary.corp.example.com db
+  Port 2222
+  LocalForward 5432 localhost:5432
+  # sgh:tags prod sql
+  # sgh:note Primary, do not reboot during business hours
+
+Host db-replica.corp.example.com
+  Port 2222
+  # sgh:tags prod sql
+
+Host cache.corp.example.com
+  LocalForward 6379 localhost:6379
+  # sgh:tags prod
+
+Host staging staging.example.com
+  HostName 198.51.100.20
+  User ubuntu
+  # sgh:tags staging
+
+Host ci-runner
+  HostName 198.51.100.31
+  User runner
+  # sgh:tags ci
+
+Host gpu-box
+  HostName 203.0.113.7
+  User research
+  DynamicForward 1080
+  # sgh:tags lab
+
+Host raspberry
+  HostName 192.0.2.50
+  User pi
+  # sgh:tags home
+
+Host nas
+  HostName 192.0.2.51
+  User admin
+  Port 2200
+  # sgh:tags home
+
+Host github.com
+  User git
+  IdentityFile ~/.ssh/github
+";
+
+/// The demo hosts, processed like a real config.
+///
+/// # Errors
+///
+/// Will return `Err` if the bundled config cannot be parsed.
+pub fn hosts(pipeline: ssh::Pipeline) -> Result<Vec<ssh::Host>> {
+    ssh::parse_config_str(CONFIG, Path::new("demo"), pipeline)
+        .map_err(|err| anyhow!("Failed to parse the demo hosts: {err:?}"))
+}
+
+/// The demo config as written, for the pattern playground.
+///
+/// # Errors
+///
+/// Will return `Err` if the bundled config cannot be parsed.
+pub fn blocks() -> Result<resolve::Blocks> {
+    ssh_config::Parser::new()
+        .parse_blocks(&mut std::io::Cursor::new(CONFIG), Some(Path::new("demo")))
+        .map_err(|err| anyhow!("Failed to parse the demo hosts: {err:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demo_hosts_parse() {
+        let hosts = hosts(ssh::Pipeline::default()).unwrap();
+        assert!(hosts.len() >= 10);
+        let db = hosts.iter().find(|h| h.name == "db-primary.corp.example.com").unwrap();
+        assert_eq!(db.user.as_deref(), Some("deploy"));
+        assert_eq!(db.proxy_jump.as_deref(), Some("bastion"));
+        assert_eq!(db.tags, vec!["prod", "sql"]);
+    }
+}
@@ -0,0 +1,667 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{journal, ssh, store};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Refuses every config change from now on (`--read-only`).
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::Relaxed);
+}
+
+#[must_use]
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+fn ensure_writable(path: &Path) -> Result<()> {
+    if is_read_only() {
+        return Err(anyhow!("Not changing {}: sgh is running read-only", path.display()));
+    }
+    Ok(())
+}
+
+/// A `Host` block sgh writes to an SSH config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostBlock {
+    pub name: String,
+    /// `(keyword, value)` pairs in the order they are written.
+    pub entries: Vec<(String, String)>,
+    /// `# sgh:<key> <value>` comment directives written after the entries.
+    pub directives: Vec<(String, String)>,
+}
+
+impl HostBlock {
+    /// Block for an ad-hoc host, keeping the destination, user and port that
+    /// were typed in the search bar.
+    #[must_use]
+    pub fn from_adhoc(name: &str, host: &ssh::Host) -> HostBlock {
+        let mut entries = vec![("HostName".to_string(), host.destination.clone())];
+        if let Some(user) = &host.user {
+            entries.push(("User".to_string(), user.clone()));
+        }
+        if let Some(port) = &host.port {
+            entries.push(("Port".to_string(), port.clone()));
+        }
+
+        HostBlock {
+            name: name.to_string(),
+            entries,
+            directives: Vec::new(),
+        }
+    }
+
+    /// Adds the entries and tags of `template`, with `{{placeholder}}`s
+    /// replaced by `values`. A keyword the block already has takes the
+    /// template's value; a line whose placeholders are all left empty is
+    /// left out.
+    pub fn apply_template(&mut self, template: &HostTemplate, values: &[(String, String)]) {
+        for line in &template.entries {
+            let placeholders = placeholders(line);
+            let filled = |name: &String| values.iter().any(|(n, v)| n == name && !v.trim().is_empty());
+            if !placeholders.is_empty() && !placeholders.iter().any(filled) {
+                continue;
+            }
+
+            let mut line = line.clone();
+            for (name, value) in values {
+                line = line.replace(&format!("{{{{{name}}}}}"), value.trim());
+            }
+            let (keyword, value) = line.trim().split_once(char::is_whitespace).unwrap_or((line.trim(), ""));
+            let value = value.trim().to_string();
+            match self.entries.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(keyword)) {
+                Some(entry) => entry.1 = value,
+                None => self.entries.push((keyword.to_string(), value)),
+            }
+        }
+
+        if !template.tags.is_empty() {
+            self.directives.push(("tags".to_string(), template.tags.join(" ")));
+        }
+    }
+
+    /// The block as it is written to the file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a name, keyword or value contains a line break,
+    /// which would add lines of its own (e.g. a `ProxyCommand`) to the block.
+    pub fn render(&self) -> Result<String> {
+        let fields = self
+            .entries
+            .iter()
+            .chain(&self.directives)
+            .flat_map(|(key, value)| [key, value]);
+        if let Some(field) = std::iter::once(&self.name).chain(fields).find(|field| field.contains(['\n', '\r'])) {
+            return Err(anyhow!("Not writing Host {:?}: {field:?} contains a line break", self.name));
+        }
+
+        let mut out = format!("Host {}\n", self.name);
+        for (keyword, value) in &self.entries {
+            out.push_str(&format!("  {keyword} {value}\n"));
+        }
+        for (key, value) in &self.directives {
+            out.push_str(&format!("  # sgh:{key} {value}\n"));
+        }
+        Ok(out)
+    }
+}
+
+/// A reusable starting point for new `Host` blocks, from `host_templates` in
+/// the config file, e.g. "behind bastion" with `ProxyJump {{bastion}}`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostTemplate {
+    pub name: String,
+    /// `Keyword value` lines, which may contain `{{placeholder}}`s.
+    pub entries: Vec<String>,
+    /// Written as a `# sgh:tags` directive.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl HostTemplate {
+    /// Placeholder names in the order they first appear.
+    #[must_use]
+    pub fn placeholders(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for name in self.entries.iter().flat_map(|line| placeholders(line)) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Entries that `apply_template` would turn into a broken line: an
+    /// unclosed or empty `{{}}`, or a placeholder in the keyword.
+    #[must_use]
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for line in &self.entries {
+            let opened = line.matches("{{").count();
+            let closed = line.matches("}}").count();
+            let keyword = line.split_whitespace().next().unwrap_or_default();
+            if line.trim().is_empty() {
+                problems.push("empty entry".to_string());
+            } else if opened != closed {
+                problems.push(format!("unclosed placeholder in {line:?}"));
+            } else if placeholders(line).iter().any(String::is_empty) {
+                problems.push(format!("empty placeholder in {line:?}"));
+            } else if keyword.contains("{{") {
+                problems.push(format!("placeholder in the keyword of {line:?}"));
+            }
+        }
+        problems
+    }
+}
+
+/// `{{name}}` references in `line`.
+fn placeholders(line: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = line;
+    while let Some((_, after)) = rest.split_once("{{") {
+        let Some((name, after)) = after.split_once("}}") else {
+            break;
+        };
+        names.push(name.trim().to_string());
+        rest = after;
+    }
+    names
+}
+
+/// A change sgh made to a config file, with a copy of the previous content
+/// in `<data_dir>/backups` so it can be undone.
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// Copy of the file before the change; `None` when sgh created it.
+    pub backup: Option<PathBuf>,
+    /// What the change did, as recorded in the journal.
+    pub action: String,
+    /// Content sgh wrote, to detect later edits before undoing.
+    written: String,
+}
+
+impl Backup {
+    /// Puts the file back the way it was before the change (removing it if
+    /// sgh created it).
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file was changed since, or cannot be restored.
+    pub fn restore(&self) -> Result<()> {
+        let current = read_existing(&self.path)?.unwrap_or_default();
+        if current != self.written {
+            return Err(anyhow!("{} was changed since, not undoing", self.path.display()));
+        }
+
+        let action = format!("Undo: {}", self.action);
+        match &self.backup {
+            Some(backup) => {
+                let previous = std::fs::read_to_string(backup)
+                    .with_context(|| format!("Failed to read backup {}", backup.display()))?;
+                write_config(&self.path, &previous, &action)?;
+            }
+            None => remove_config(&self.path, &action)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Appends `block` to the config file at `path`, separated from the existing
+/// content by a blank line. The file and its directory are created if needed.
+///
+/// # Errors
+///
+/// Will return `Err` if the file cannot be read or written.
+pub fn append_host(path: &Path, block: &HostBlock) -> Result<Backup> {
+    let existing = read_existing(path)?.unwrap_or_default();
+
+    // 既存の末尾に改行がなければ補い、ブロックの間に空行を 1 つ入れる
+    let separator = match existing.as_str() {
+        "" => "",
+        s if s.ends_with("\n\n") => "",
+        s if s.ends_with('\n') => "\n",
+        _ => "\n\n",
+    };
+
+    write_config(
+        path,
+        &format!("{existing}{separator}{}", block.render()?),
+        &format!("Add Host {}", block.name),
+    )
+}
+
+/// A block a bulk edit sets a keyword on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionChange {
+    pub path: PathBuf,
+    /// The block's `Host` line, e.g. `Host web web.example.com`.
+    pub block: String,
+    pub line: usize,
+    /// Value the block had before; `None` when the keyword is added.
+    pub previous: Option<String>,
+}
+
+/// The blocks of each file in `targets` (file and host names) that setting
+/// `keyword` to `value` would change, for the preview. Blocks that already
+/// have the value are left out.
+///
+/// # Errors
+///
+/// Will return `Err` if a file cannot be read.
+pub fn plan_option(targets: &[(PathBuf, Vec<String>)], keyword: &str, value: &str) -> Result<Vec<OptionChange>> {
+    let mut changes = Vec::new();
+    for (path, names) in targets {
+        let content = read_existing(path)?.unwrap_or_default();
+        let (_, blocks) = set_option_in(&content, names, keyword, value);
+        changes.extend(blocks.into_iter().map(|(block, line, previous)| OptionChange {
+            path: path.clone(),
+            block,
+            line,
+            previous,
+        }));
+    }
+    Ok(changes)
+}
+
+/// Sets `keyword` to `value` in the `Host` blocks naming the hosts in
+/// `targets`: an existing line is updated in place, otherwise the keyword
+/// is appended to the block. Each file is written once and its backup
+/// pushed to `backups`, so files written before a failure can be undone.
+///
+/// # Errors
+///
+/// Will return `Err` if a file cannot be read or written.
+pub fn set_option(
+    targets: &[(PathBuf, Vec<String>)],
+    keyword: &str,
+    value: &str,
+    backups: &mut Vec<Backup>,
+) -> Result<()> {
+    for (path, names) in targets {
+        let content = read_existing(path)?.unwrap_or_default();
+        let (updated, blocks) = set_option_in(&content, names, keyword, value);
+        if blocks.is_empty() {
+            continue;
+        }
+        let action = format!("Set {keyword} {value} on {} host(s)", blocks.len());
+        backups.push(write_config(path, &updated, &action)?);
+    }
+    Ok(())
+}
+
+/// `content` with `keyword` set in every block whose `Host` line lists one
+/// of `names`, and the `(Host line, line number, previous value)` of each
+/// block that changed.
+fn set_option_in(
+    content: &str,
+    names: &[String],
+    keyword: &str,
+    value: &str,
+) -> (String, Vec<(String, usize, Option<String>)>) {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let starts = block_starts(&lines);
+
+    let mut changes = Vec::new();
+    // 後ろのブロックから書き換えて、前のブロックの行番号をずらさない
+    for (n, &start) in starts.iter().enumerate().rev() {
+        if !names_block(&lines[start], names) {
+            continue;
+        }
+
+        let end = starts.get(n + 1).copied().unwrap_or(lines.len());
+        let block = lines[start].trim().to_string();
+        let existing = (start + 1..end).find(|&i| split_line(&lines[i]).is_some_and(|(k, _)| k.eq_ignore_ascii_case(keyword)));
+        let indent = block_indent(&lines, start, end);
+
+        match existing {
+            Some(i) => {
+                let previous = split_line(&lines[i]).map(|(_, v)| v.to_string()).unwrap_or_default();
+                if previous == value {
+                    continue;
+                }
+                lines[i] = format!("{indent}{keyword} {value}");
+                changes.push((block, start + 1, Some(previous)));
+            }
+            None => {
+                let last = (start..end).rev().find(|&i| !lines[i].trim().is_empty()).unwrap_or(start);
+                lines.insert(last + 1, format!("{indent}{keyword} {value}"));
+                changes.push((block, start + 1, None));
+            }
+        }
+    }
+    changes.reverse();
+
+    (join_lines(&lines, content), changes)
+}
+
+/// Adds, changes or removes one `LocalForward` of the `Host` block listing
+/// one of `names`: `old` is the value to change or remove (`None` adds
+/// `new`), `new` the value to write (`None` removes `old`). Values are
+/// written as in the config file, e.g. `5432 localhost:5432`.
+///
+/// # Errors
+///
+/// Will return `Err` if the block or the `old` line is not in the file, or
+/// the file cannot be read or written.
+pub fn edit_local_forward(path: &Path, names: &[String], old: Option<&str>, new: Option<&str>) -> Result<Backup> {
+    let action = match (old, new) {
+        (None, Some(new)) => format!("Add LocalForward {new}"),
+        (Some(old), Some(new)) => format!("Change LocalForward {old} to {new}"),
+        (Some(old), None) => format!("Remove LocalForward {old}"),
+        (None, None) => return Err(anyhow!("No LocalForward to change")),
+    };
+    let content = read_existing(path)?.unwrap_or_default();
+    let updated = edit_local_forward_in(&content, names, old, new)?;
+    write_config(path, &updated, &action)
+}
+
+fn edit_local_forward_in(content: &str, names: &[String], old: Option<&str>, new: Option<&str>) -> Result<String> {
+    let normalize = |value: &str| value.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let starts = block_starts(&lines);
+    let (start, end) = starts
+        .iter()
+        .enumerate()
+        .find(|(_, &start)| names_block(&lines[start], names))
+        .map(|(n, &start)| (start, starts.get(n + 1).copied().unwrap_or(lines.len())))
+        .ok_or_else(|| anyhow!("No Host block for {} in the file", names.join(", ")))?;
+
+    match (old, new) {
+        (Some(old), new) => {
+            let i = (start + 1..end)
+                .find(|&i| {
+                    split_line(&lines[i])
+                        .is_some_and(|(k, v)| k.eq_ignore_ascii_case("LocalForward") && normalize(v) == normalize(old))
+                })
+                .ok_or_else(|| anyhow!("LocalForward {old} is not in the host's own block (set by a pattern block?)"))?;
+            match new {
+                Some(new) => {
+                    let indent: String = lines[i].chars().take_while(|c| c.is_whitespace()).collect();
+                    lines[i] = format!("{indent}LocalForward {new}");
+                }
+                None => {
+                    lines.remove(i);
+                }
+            }
+        }
+        (None, Some(new)) => {
+            let indent = block_indent(&lines, start, end);
+            let last = (start..end).rev().find(|&i| !lines[i].trim().is_empty()).unwrap_or(start);
+            lines.insert(last + 1, format!("{indent}LocalForward {new}"));
+        }
+        (None, None) => {}
+    }
+
+    Ok(join_lines(&lines, content))
+}
+
+/// Keyword and value of a config line; `None` for blank lines and comments.
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (word, rest) = line.split_once(|c: char| c.is_whitespace() || c == '=').unwrap_or((line, ""));
+    Some((word, rest.trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim()))
+}
+
+/// Indexes of the `Host` and `Match` lines that start each block.
+fn block_starts(lines: &[String]) -> Vec<usize> {
+    (0..lines.len())
+        .filter(|&i| {
+            split_line(&lines[i]).is_some_and(|(word, _)| {
+                word.eq_ignore_ascii_case("host") || word.eq_ignore_ascii_case("match")
+            })
+        })
+        .collect()
+}
+
+/// Whether `line` is a `Host` line listing one of `names`.
+fn names_block(line: &str, names: &[String]) -> bool {
+    split_line(line).is_some_and(|(word, patterns)| {
+        word.eq_ignore_ascii_case("host")
+            && patterns.split_whitespace().any(|pattern| names.iter().any(|name| name == pattern))
+    })
+}
+
+/// Indentation of the first entry of the block, two spaces when empty.
+fn block_indent(lines: &[String], start: usize, end: usize) -> String {
+    (start + 1..end)
+        .find(|&i| split_line(&lines[i]).is_some())
+        .map_or("  ".to_string(), |i| {
+            lines[i].chars().take_while(|c| c.is_whitespace()).collect()
+        })
+}
+
+/// `lines` joined back, ending with a newline when `content` did.
+fn join_lines(lines: &[String], content: &str) -> String {
+    let mut updated = lines.join("\n");
+    if content.ends_with('\n') || content.is_empty() {
+        updated.push('\n');
+    }
+    updated
+}
+
+/// Replaces the content of the config file at `path`, first copying the
+/// current content to `<data_dir>/backups` and recording `action` in the
+/// journal. Every config change sgh makes goes through here.
+///
+/// # Errors
+///
+/// Will return `Err` if the backup, the journal or the file cannot be written.
+pub fn write_config(path: &Path, content: &str, action: &str) -> Result<Backup> {
+    ensure_writable(path)?;
+    let previous = read_existing(path)?;
+    let backup = match &previous {
+        Some(previous) => Some(save_backup(path, previous)?),
+        None => None,
+    };
+    journal::record(path, action, previous.as_deref(), Some(content))?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // 権限や symlink を保つため置き換えではなく上書きする
+    std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(Backup {
+        path: path.to_path_buf(),
+        backup,
+        action: action.to_string(),
+        written: content.to_string(),
+    })
+}
+
+/// Removes a config file sgh created, keeping a backup and a journal entry
+/// like `write_config`.
+///
+/// # Errors
+///
+/// Will return `Err` if the backup, the journal or the removal fails.
+pub fn remove_config(path: &Path, action: &str) -> Result<()> {
+    ensure_writable(path)?;
+    if let Some(previous) = read_existing(path)? {
+        save_backup(path, &previous)?;
+        journal::record(path, action, Some(&previous), None)?;
+        std::fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn read_existing(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn save_backup(path: &Path, content: &str) -> Result<PathBuf> {
+    let dir = store::data_dir()
+        .ok_or(anyhow!("Cannot determine the data directory for backups"))?
+        .join("backups");
+    std::fs::create_dir_all(&dir)?;
+
+    let name = path.file_name().map_or("config".into(), |n| n.to_string_lossy());
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let backup = dir.join(format!("{name}.{stamp}"));
+    std::fs::write(&backup, content)
+        .with_context(|| format!("Failed to write backup {}", backup.display()))?;
+
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tempdir() -> std::path::PathBuf {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!("sgh-edit-test-{}-{n}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn templates_fill_placeholders_and_drop_empty_lines() {
+        let template = HostTemplate {
+            name: "behind bastion".to_string(),
+            entries: vec![
+                "ProxyJump {{bastion}}".to_string(),
+                "User {{user}}".to_string(),
+                "LocalForward {{port}} localhost:{{port}}".to_string(),
+                "ForwardAgent yes".to_string(),
+            ],
+            tags: vec!["prod".to_string()],
+        };
+        assert_eq!(template.placeholders(), ["bastion", "user", "port"]);
+
+        let mut block = HostBlock::from_adhoc("db", &ssh::Host::adhoc("alice@db.lan").unwrap());
+        let values = [("bastion", "jump"), ("user", "deploy"), ("port", " ")]
+            .map(|(n, v)| (n.to_string(), v.to_string()));
+        block.apply_template(&template, &values);
+        assert_eq!(
+            block.render().unwrap(),
+            "Host db\n  HostName db.lan\n  User deploy\n  ProxyJump jump\n  ForwardAgent yes\n  # sgh:tags prod\n"
+        );
+    }
+
+    #[test]
+    fn bulk_edits_update_or_append_the_keyword() {
+        let content = "Host *\n  User all\n\nHost web web.lan\n    ServerAliveInterval 60\n\nHost db\n\tPort 2222\n  # sgh:tags prod\n\nHost cache\n  Port 1\n";
+        let names = ["web".to_string(), "db".to_string()];
+
+        let (updated, changes) = set_option_in(content, &names, "ServerAliveInterval", "30");
+        assert_eq!(
+            updated,
+            "Host *\n  User all\n\nHost web web.lan\n    ServerAliveInterval 30\n\nHost db\n\tPort 2222\n  # sgh:tags prod\n\tServerAliveInterval 30\n\nHost cache\n  Port 1\n"
+        );
+        assert_eq!(
+            changes,
+            vec![
+                ("Host web web.lan".to_string(), 4, Some("60".to_string())),
+                ("Host db".to_string(), 7, None),
+            ]
+        );
+
+        // 既に同じ値なら何も変えない
+        let (again, changes) = set_option_in(&updated, &names, "serveraliveinterval", "30");
+        assert_eq!(again, updated);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn local_forwards_are_added_changed_and_removed_in_place() {
+        let content = "Host *\n  LocalForward 1080 proxy:1080\n\nHost db db.lan\n    User deploy\n    LocalForward 5432  localhost:5432\n\nHost web\n";
+        let names = ["db".to_string()];
+
+        let added = edit_local_forward_in(content, &names, None, Some("6379 localhost:6379")).unwrap();
+        assert_eq!(
+            added,
+            "Host *\n  LocalForward 1080 proxy:1080\n\nHost db db.lan\n    User deploy\n    LocalForward 5432  localhost:5432\n    LocalForward 6379 localhost:6379\n\nHost web\n"
+        );
+        let changed = edit_local_forward_in(&added, &names, Some("5432 localhost:5432"), Some("15432 localhost:5432")).unwrap();
+        assert!(changed.contains("    LocalForward 15432 localhost:5432\n"));
+        let removed = edit_local_forward_in(&changed, &names, Some("6379 localhost:6379"), None).unwrap();
+        assert_eq!(removed, changed.replace("    LocalForward 6379 localhost:6379\n", ""));
+
+        // パターンブロック由来の転送や、定義のないホストは書き換えない
+        assert!(edit_local_forward_in(content, &names, Some("1080 proxy:1080"), None).is_err());
+        assert!(edit_local_forward_in(content, &["cache".to_string()], None, Some("1 a:1")).is_err());
+    }
+
+    #[test]
+    fn changes_can_be_undone_from_their_backup() {
+        let dir = tempdir();
+        let path = dir.join("config");
+        std::fs::write(&path, "Host web\n").unwrap();
+
+        let block = HostBlock::from_adhoc("box", &ssh::Host::adhoc("box.lan").unwrap());
+        let backup = append_host(&path, &block).unwrap();
+        assert!(backup.backup.as_ref().unwrap().starts_with(store::data_dir().unwrap().join("backups")));
+        backup.restore().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Host web\n");
+
+        // 作成したファイルは削除で元に戻す。他で書き換えられていたら戻さない
+        let new_path = dir.join("new");
+        let created = append_host(&new_path, &block).unwrap();
+        created.restore().unwrap();
+        assert!(!new_path.exists());
+
+        let changed = append_host(&path, &block).unwrap();
+        std::fs::write(&path, "edited elsewhere\n").unwrap();
+        assert!(changed.restore().is_err());
+
+        let entries: Vec<_> = journal::load().into_iter().filter(|e| e.path == path).collect();
+        assert_eq!(entries[0].action, "Add Host box");
+        assert_eq!(entries[0].before.as_deref(), Some("Host web\n"));
+        assert_eq!(entries[1].action, "Undo: Add Host box");
+        assert_eq!(entries[1].after.as_deref(), Some("Host web\n"));
+    }
+
+    #[test]
+    fn adhoc_host_is_rendered_as_a_block() {
+        let host = ssh::Host::adhoc("admin@myhost:2200").unwrap();
+        let block = HostBlock::from_adhoc("myhost", &host);
+        assert_eq!(
+            block.render().unwrap(),
+            "Host myhost\n  HostName myhost\n  User admin\n  Port 2200\n"
+        );
+    }
+
+    #[test]
+    fn append_host_separates_blocks_with_a_blank_line() {
+        let dir = tempdir();
+        let path = dir.join("config");
+        std::fs::write(&path, "Host web\n  HostName web.example").unwrap();
+
+        let block = HostBlock::from_adhoc("box", &ssh::Host::adhoc("box.lan").unwrap());
+        append_host(&path, &block).unwrap();
+        append_host(&path, &block).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "Host web\n  HostName web.example\n\nHost box\n  HostName box.lan\n\nHost box\n  HostName box.lan\n"
+        );
+
+        // a missing file (and directory) is created
+        let new_path = dir.join("config.d").join("sgh");
+        append_host(&new_path, &block).unwrap();
+        assert_eq!(std::fs::read_to_string(&new_path).unwrap(), block.render().unwrap());
+    }
+}
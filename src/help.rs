@@ -0,0 +1,342 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::fmt::Write;
+
+use crate::{ssh, template};
+
+/// A key of the picker. The same list feeds the `?` help line, `--help`
+/// and the man page so they cannot drift apart.
+pub struct KeyBinding {
+    pub keys: &'static str,
+    /// Short key and label shown in the `?` help line; `None` for keys only
+    /// documented in `--help` and the man page.
+    pub chip: Option<(&'static str, &'static str)>,
+    pub description: &'static str,
+}
+
+const fn key(
+    keys: &'static str,
+    chip: Option<(&'static str, &'static str)>,
+    description: &'static str,
+) -> KeyBinding {
+    KeyBinding {
+        keys,
+        chip,
+        description,
+    }
+}
+
+pub const KEY_BINDINGS: &[KeyBinding] = &[
+    key("Up / Down", Some(("↑↓", "navigate")), "Move the selection"),
+    key("Home / End / PageUp / PageDown", None, "Jump to the first or last host, or by a page"),
+    key("Enter", Some(("↵", "connect")), "Connect to the selected host"),
+    key(
+        "Shift+Enter",
+        None,
+        "Connect in a new tmux window (terminals with the kitty keyboard protocol)",
+    ),
+    key("Alt+1 … Alt+9", None, "Select the nth host row on screen"),
+    key("typing, Backspace", Some(("⌫", "edit")), "Edit the fuzzy search query"),
+    key(
+        "Ctrl+O",
+        Some(("^o", "explain")),
+        "Explain where each effective setting of the selected host comes from (v compares with ssh -G)",
+    ),
+    key(
+        "Ctrl+S / Ctrl+F",
+        Some(("^s/^f", "filters")),
+        "Save the query under a name / open the saved filters",
+    ),
+    key(
+        "Ctrl+D / Ctrl+R",
+        Some(("^d/^r", "search field")),
+        "Search the destination only / cycle the single searched column",
+    ),
+    key(
+        "Ctrl+L",
+        Some(("^l", "aliases")),
+        "Switch between one row per host and one row per alias",
+    ),
+    key(
+        "Ctrl+V",
+        Some(("^v", "variables")),
+        "List the template variables with the selected host's values, and the helpers",
+    ),
+    key(
+        "Ctrl+Y",
+        Some(("^y", "playground")),
+        "Type a hostname and see which Host blocks apply and the resulting options",
+    ),
+    key("Ctrl+T", Some(("^t", "patterns")), "Show or hide wildcard pattern blocks"),
+    key(
+        "Ctrl+X",
+        Some(("^x", "drop filter")),
+        "Remove the rightmost filter chip, or the whole query when nothing matches",
+    ),
+    key(
+        "Tab / Shift+Tab / F1 … F4",
+        Some(("tab", "views")),
+        "Switch between the Hosts, Tunnels, Sessions and History views (Esc goes back to Hosts)",
+    ),
+    key("Tab", None, "When nothing matches, search for the closest host name instead"),
+    key("Alt+Up / Alt+Down", None, "Recall previous queries (also Alt+P / Alt+N)"),
+    key(
+        "Alt+E",
+        Some(("alt+e", "bulk edit")),
+        "Set a keyword on every listed host's config block, after a preview",
+    ),
+    key("Alt+U", Some(("alt+u", "undo edit")), "Undo the last config change made in this run"),
+    key(
+        "Alt+F",
+        Some(("alt+f", "forwards")),
+        "Add, change or remove the selected host's LocalForward lines (a suggests a free port)",
+    ),
+    key(
+        "Alt+R",
+        Some(("alt+r", "snippets")),
+        "Run a remote command from the snippets in the config file on the selected host",
+    ),
+    key(
+        "Alt+M",
+        Some(("alt+m", "macros")),
+        "Replay a macro: search for its query and run its snippet on every listed host (Ctrl+S in the snippet list records one)",
+    ),
+    key(
+        "Alt+L",
+        Some(("alt+l", "lock")),
+        "Hide the hosts until a key is pressed, or lock_passphrase from the config file is typed (also --lock-after)",
+    ),
+    key(
+        "Alt+X",
+        Some(("alt+x", "redact")),
+        "Mask destinations, users and ports for screenshots, keeping the names (also --redact)",
+    ),
+    key(
+        "Alt+S",
+        Some(("alt+s", "socks")),
+        "Start or stop a SOCKS proxy (ssh -N -D) through the selected host and copy the proxy variables",
+    ),
+    key(
+        "Alt+T",
+        Some(("alt+t", "tunnels")),
+        "Connect with the -L forwards of tunnel presets from the config file (space ticks several)",
+    ),
+    key(
+        "Alt+C / Alt+I",
+        None,
+        "With no hosts at all: create the SSH config file / import the destinations in your shell history",
+    ),
+    key("Ctrl+G", None, "Jump to the tmux window of the selected host's session"),
+    key("Ctrl+Z", None, "Suspend sgh to the shell"),
+    key(
+        "Esc / Ctrl+C",
+        Some(("esc", "quit")),
+        "Quit (with --esc, Esc can clear the search first or need a second press)",
+    ),
+    key("?", Some(("?", "close")), "With an empty query, toggle this key help"),
+];
+
+/// The `footer` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Footer {
+    /// Drop the footer row; it comes back while `?` help or a notice is
+    /// shown.
+    pub hidden: bool,
+    /// Labels of the keys the `?` help line lists, e.g. `["connect",
+    /// "explain", "quit"]`; empty lists every key.
+    pub keys: Vec<String>,
+    /// Text shown at the right of the status bar instead of `? help`.
+    pub text: Option<String>,
+}
+
+impl Footer {
+    /// # Errors
+    ///
+    /// Will return `Err` if a key label is not one of the help line's.
+    pub fn validate(&self) -> Result<()> {
+        let labels: Vec<&str> = KEY_BINDINGS.iter().filter_map(|binding| binding.chip).map(|(_, label)| label).collect();
+        match self.keys.iter().find(|key| !labels.contains(&key.as_str())) {
+            Some(key) => Err(anyhow!("Unknown footer key {key:?}; expected one of: {}", labels.join(", "))),
+            None => Ok(()),
+        }
+    }
+
+    /// Key and label of each binding the help line shows, in binding order.
+    pub fn chips(&self) -> impl Iterator<Item = (&'static str, &'static str)> + '_ {
+        KEY_BINDINGS
+            .iter()
+            .filter_map(|binding| binding.chip)
+            .filter(|(_, label)| self.keys.is_empty() || self.keys.iter().any(|key| key == label))
+    }
+}
+
+/// Helpers sgh registers on top of the Handlebars built-ins, as usage and
+/// description.
+pub const SGH_HELPERS: &[(&str, &str)] = &[
+    ("{{cmd \"<command>\"}}", "Output of a shell command, trimmed"),
+    (
+        "{{secret \"<path>\"}}",
+        "Output of `secret_command <path>` (default `pass show`), masked on screen",
+    ),
+    (
+        "{{shquote <value>...}}",
+        "The values joined and quoted as one shell word; a leading `~/` is kept",
+    ),
+];
+
+/// Extra text for `sgh --help`: template variables and key bindings.
+#[must_use]
+pub fn after_long_help() -> String {
+    let mut text = String::from("Template variables:\n");
+    for line in wrap(&variables().join(", "), 76) {
+        let _ = writeln!(text, "  {line}");
+    }
+    text.push_str("\nTemplate helpers:\n");
+    for line in wrap(&template::HELPERS.join(", "), 76) {
+        let _ = writeln!(text, "  {line}");
+    }
+
+    text.push_str("\nKeys:\n");
+    let width = KEY_BINDINGS.iter().map(|binding| binding.keys.len()).max().unwrap_or(0);
+    for binding in KEY_BINDINGS {
+        let _ = writeln!(text, "  {:width$}  {}", binding.keys, binding.description);
+    }
+    text
+}
+
+/// `sgh man`: a roff man page built from the CLI definition plus the
+/// template and key reference.
+#[must_use]
+pub fn man_page(command: &clap::Command) -> String {
+    let mut command = command.clone();
+    command.build();
+    let name = command.get_name().to_string();
+
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{name} {}\"\n",
+        name.to_uppercase(),
+        command.get_version().unwrap_or_default()
+    );
+    let _ = writeln!(
+        page,
+        ".SH NAME\n{name} \\- {}",
+        roff(&command.get_about().map(ToString::to_string).unwrap_or_default())
+    );
+    let _ = writeln!(page, ".SH SYNOPSIS\n\\fB{name}\\fR [\\fIOPTIONS\\fR] [\\fICOMMAND\\fR]");
+
+    page.push_str(".SH OPTIONS\n");
+    for arg in command.get_arguments().filter(|arg| !arg.is_hide_set()) {
+        let mut flags = Vec::new();
+        if let Some(short) = arg.get_short() {
+            flags.push(format!("\\fB\\-{short}\\fR"));
+        }
+        if let Some(long) = arg.get_long() {
+            flags.push(format!("\\fB\\-\\-{}\\fR", roff(long)));
+        }
+        let mut line = flags.join(", ");
+        if arg.get_action().takes_values() {
+            for value in arg.get_value_names().unwrap_or_default() {
+                let _ = write!(line, " \\fI{}\\fR", roff(value));
+            }
+        }
+        let help = arg.get_long_help().or(arg.get_help()).map(ToString::to_string);
+        let _ = writeln!(page, ".TP\n{line}\n{}", roff(&help.unwrap_or_default()));
+    }
+
+    page.push_str(".SH COMMANDS\n");
+    for subcommand in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let about = subcommand.get_about().map(ToString::to_string).unwrap_or_default();
+        let _ = writeln!(page, ".TP\n\\fB{name} {}\\fR\n{}", subcommand.get_name(), roff(&about));
+    }
+
+    let _ = writeln!(
+        page,
+        ".SH TEMPLATES\nCommand, hook, launcher and title templates are Handlebars templates rendered per host.\n.TP\nVariables\n{}\n.TP\nHelpers\n{}",
+        roff(&variables().join(", ")),
+        roff(&template::HELPERS.join(", "))
+    );
+
+    page.push_str(".SH KEYS\n");
+    for binding in KEY_BINDINGS {
+        let _ = writeln!(page, ".TP\n\\fB{}\\fR\n{}", roff(binding.keys), roff(binding.description));
+    }
+
+    page.push_str(
+        ".SH ENVIRONMENT\n.TP\n\\fBSGH_CONFIG\\fR\nPath of the settings file (default \\fI~/.config/sgh/config.json\\fR).\n.TP\n\\fBSGH_SSH_CONFIG\\fR\nSSH config files to read instead of the standard locations, separated by colons, unless \\fB\\-\\-config\\fR is given.\n.TP\n\\fBSGH_DATA_DIR\\fR\nDirectory for history, saved filters, backups, the config journal and the source cache (default \\fI~/.local/share/sgh\\fR).\n",
+    );
+    page
+}
+
+/// Template variable names, as `{{name}}` would be written.
+fn variables() -> Vec<String> {
+    ssh::template_variables()
+        .into_iter()
+        .map(|name| format!("{{{{{name}}}}}"))
+        .collect()
+}
+
+/// Escapes text for roff: backslashes and hyphens, and a leading `.` or `'`
+/// that would start a request.
+fn roff(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{line}")
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Splits a comma separated list into lines of at most `width` columns.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split(' ') {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roff_escapes_requests_and_hyphens() {
+        assert_eq!(roff(".hidden\nuse --exec"), "\\&.hidden\nuse \\-\\-exec");
+        assert_eq!(roff(r"C:\path"), r"C:\epath");
+    }
+
+    #[test]
+    fn footer_keys_pick_help_chips() {
+        let footer = Footer {
+            keys: vec!["quit".to_string(), "connect".to_string()],
+            ..Footer::default()
+        };
+        assert!(footer.validate().is_ok());
+        let labels: Vec<&str> = footer.chips().map(|(_, label)| label).collect();
+        assert_eq!(labels, ["connect", "quit"]);
+
+        let typo = Footer {
+            keys: vec!["conect".to_string()],
+            ..Footer::default()
+        };
+        assert!(typo.validate().is_err());
+        assert_eq!(Footer::default().chips().count(), KEY_BINDINGS.iter().filter(|b| b.chip.is_some()).count());
+    }
+
+    #[test]
+    fn long_lists_are_wrapped() {
+        assert_eq!(wrap("aa, bb, cc", 6), vec!["aa,", "bb, cc"]);
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::searchable::TableItem;
+use crate::session::format_duration;
+use crate::store;
+
+/// Per-host connection counters.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HostStats {
+    pub count: u32,
+    /// Unix time of the most recent connection.
+    pub last_connected: u64,
+    /// Seconds spent in the sessions sgh timed, and in the most recent one.
+    #[serde(default)]
+    pub total_secs: u64,
+    #[serde(default)]
+    pub last_secs: u64,
+}
+
+/// How often each host was connected to from sgh, persisted in the data
+/// directory and used to rank frequently used hosts first.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConnectionHistory {
+    hosts: BTreeMap<String, HostStats>,
+}
+
+impl ConnectionHistory {
+    const FILE_NAME: &'static str = "connections.json";
+
+    #[must_use]
+    pub fn load() -> ConnectionHistory {
+        store::load(Self::FILE_NAME)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the history cannot be written.
+    pub fn save(&self) -> Result<()> {
+        store::save(Self::FILE_NAME, self)
+    }
+
+    /// Counts a connection to `host_name` made now.
+    pub fn record(&mut self, host_name: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let stats = self.hosts.entry(host_name.to_string()).or_default();
+        stats.count = stats.count.saturating_add(1);
+        stats.last_connected = now;
+    }
+
+    /// Adds a session to `host_name` that lasted `duration`.
+    pub fn record_duration(&mut self, host_name: &str, duration: Duration) {
+        let stats = self.hosts.entry(host_name.to_string()).or_default();
+        stats.total_secs = stats.total_secs.saturating_add(duration.as_secs());
+        stats.last_secs = duration.as_secs();
+    }
+
+    #[must_use]
+    pub fn get(&self, host_name: &str) -> Option<&HostStats> {
+        self.hosts.get(host_name)
+    }
+
+    #[must_use]
+    pub fn count(&self, host_name: &str) -> u32 {
+        self.get(host_name).map_or(0, |stats| stats.count)
+    }
+
+    /// Every host connected to, most recent first.
+    #[must_use]
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        let mut entries: Vec<HistoryEntry> = self
+            .hosts
+            .iter()
+            .map(|(name, stats)| HistoryEntry {
+                name: name.clone(),
+                stats: stats.clone(),
+            })
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.stats.last_connected));
+        entries
+    }
+}
+
+/// A row of the History view.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub name: String,
+    pub stats: HostStats,
+}
+
+impl TableItem for HistoryEntry {
+    const HEADERS: &'static [&'static str] = &["NAME", "CONNECTIONS", "TOTAL TIME", "LAST"];
+
+    fn cells(&self) -> Vec<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        vec![
+            self.name.clone(),
+            self.stats.count.to_string(),
+            format_duration(Duration::from_secs(self.stats.total_secs)),
+            format_age(now.saturating_sub(self.stats.last_connected)),
+        ]
+    }
+}
+
+/// `sgh stats`: every host connected to from sgh, most used first, with
+/// the time spent in its sessions.
+#[must_use]
+pub fn format_stats(history: &ConnectionHistory, now: u64) -> String {
+    let mut entries = history.entries();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.stats.count));
+    let width = entries.iter().map(|entry| entry.name.len()).max().unwrap_or(0).max(4);
+
+    let mut text = format!("{:width$}  {:>11}  {:>10}  {:>12}  LAST\n", "HOST", "CONNECTIONS", "TOTAL TIME", "LAST SESSION");
+    for entry in entries {
+        let _ = writeln!(
+            text,
+            "{:width$}  {:>11}  {:>10}  {:>12}  {}",
+            entry.name,
+            entry.stats.count,
+            format_duration(Duration::from_secs(entry.stats.total_secs)),
+            format_duration(Duration::from_secs(entry.stats.last_secs)),
+            format_age(now.saturating_sub(entry.stats.last_connected)),
+        );
+    }
+    text
+}
+
+/// 経過秒数を "just now" / "5m ago" / "3h ago" / "12d ago" のように短く整形する
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_counts_connections_per_host() {
+        let mut history = ConnectionHistory::default();
+        history.record("db");
+        history.record("db");
+        history.record("web");
+
+        assert_eq!(history.count("db"), 2);
+        assert_eq!(history.count("web"), 1);
+        assert_eq!(history.count("unknown"), 0);
+        assert!(history.get("db").unwrap().last_connected > 0);
+    }
+
+    #[test]
+    fn entries_list_the_most_recent_connection_first() {
+        let mut history = ConnectionHistory::default();
+        history.hosts.insert("old".to_string(), HostStats { count: 5, last_connected: 100, ..HostStats::default() });
+        history.hosts.insert("new".to_string(), HostStats { count: 1, last_connected: 200, ..HostStats::default() });
+
+        let names: Vec<String> = history.entries().into_iter().map(|entry| entry.name).collect();
+        assert_eq!(names, ["new", "old"]);
+        assert_eq!(format_age(90), "1m ago");
+        assert_eq!(format_age(3 * 86400), "3d ago");
+    }
+
+    #[test]
+    fn session_durations_add_up_per_host() {
+        let mut history = ConnectionHistory::default();
+        history.hosts.insert("db".to_string(), HostStats { count: 2, last_connected: 1000, ..HostStats::default() });
+        history.record_duration("db", Duration::from_secs(600));
+        history.record_duration("db", Duration::from_secs(125));
+
+        let stats = history.get("db").unwrap();
+        assert_eq!((stats.total_secs, stats.last_secs), (725, 125));
+        assert_eq!(
+            format_stats(&history, 1000 + 7200),
+            "HOST  CONNECTIONS  TOTAL TIME  LAST SESSION  LAST\ndb              2         12m            2m  2h ago\n"
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use serde::Deserialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Language of the picker's labels, titles and key hints (`--lang`).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    #[default]
+    En,
+    Ja,
+}
+
+static LANG: AtomicU8 = AtomicU8::new(Lang::En as u8);
+
+/// Shows the UI in `lang` from now on.
+pub fn set_lang(lang: Lang) {
+    LANG.store(lang as u8, Ordering::Relaxed);
+}
+
+#[must_use]
+pub fn lang() -> Lang {
+    match LANG.load(Ordering::Relaxed) {
+        1 => Lang::Ja,
+        _ => Lang::En,
+    }
+}
+
+impl Lang {
+    /// The language of the locale variables, checked in the order `gettext`
+    /// uses: `LC_ALL`, `LC_MESSAGES`, then `LANG`. English when unset.
+    #[must_use]
+    pub fn from_env() -> Lang {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .map_or(Lang::En, |value| Lang::from_locale(&value))
+    }
+
+    fn from_locale(locale: &str) -> Lang {
+        if locale.starts_with("ja") {
+            Lang::Ja
+        } else {
+            Lang::En
+        }
+    }
+}
+
+/// `text` in the current language. UI strings are written in English in the
+/// code and looked up by that text; anything without a translation is shown
+/// as is.
+#[must_use]
+pub fn t(text: &'static str) -> &'static str {
+    translate(lang(), text)
+}
+
+/// `t(text)` with each `{name}` replaced by its value in `args`, for
+/// messages that embed a host name, a path or a count. A translation may
+/// put the placeholders in another order.
+#[must_use]
+pub fn t_fmt(text: &'static str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    fill(t(text), args)
+}
+
+// 一度だけ走査して置換する (値に `{path}` などが含まれていても展開しない)
+fn fill(text: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.split_once('}').and_then(|(name, tail)| {
+            args.iter().find(|(arg, _)| *arg == name).map(|(_, value)| (value, tail))
+        });
+        match value {
+            Some((value, tail)) => {
+                out.push_str(&value.to_string());
+                rest = tail;
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[must_use]
+pub fn translate(lang: Lang, text: &'static str) -> &'static str {
+    let bundle = match lang {
+        Lang::En => return text,
+        Lang::Ja => JA,
+    };
+    bundle
+        .iter()
+        .find(|(english, _)| *english == text)
+        .map_or(text, |(_, translated)| translated)
+}
+
+/// Japanese bundle. SSH keywords (HostName, ProxyJump, …) and key names stay
+/// as they are so they match the config file and the keyboard.
+const JA: &[(&str, &str)] = &[
+    // 検索バー・一覧
+    ("Hosts", "ホスト"),
+    ("Tunnels", "トンネル"),
+    ("History", "履歴"),
+    ("NAME", "名前"),
+    ("ALIASES", "別名"),
+    ("USER", "ユーザー"),
+    ("DESTINATION", "接続先"),
+    ("PORT", "ポート"),
+    ("PROXY", "プロキシ"),
+    ("SOURCE", "定義元"),
+    ("ad-hoc", "臨時"),
+    ("^x remove last", "^x 最後を削除"),
+    ("Terminal too small", "端末が小さすぎます"),
+    ("FORWARDS", "転送"),
+    ("SESSION", "セッション"),
+    ("TIME", "経過"),
+    ("CONNECTIONS", "接続回数"),
+    ("LAST", "最終接続"),
+    ("TOTAL TIME", "合計時間"),
+    ("No tunnels in the config file", "設定ファイルにトンネルがありません"),
+    ("No sessions started from sgh are running", "sgh から開始したセッションはありません"),
+    ("No connections made from sgh yet", "sgh からの接続はまだありません"),
+    // 詳細パネル
+    ("Host detail", "ホスト詳細"),
+    ("No host selected", "ホストが選択されていません"),
+    ("Pattern", "パターン"),
+    ("defaults applied to matching hosts", "一致するホストに適用される既定値"),
+    ("Ad-hoc", "臨時"),
+    ("not in your SSH config; connects directly", "SSH 設定にないホスト。直接接続します"),
+    ("Tags", "タグ"),
+    ("Note", "メモ"),
+    ("Directory", "ディレクトリ"),
+    ("Source", "定義元"),
+    ("(no extra settings)", "(追加の設定なし)"),
+    ("local user", "ローカルのユーザー"),
+    ("bare name", "ドメインなしの名前"),
+    ("not a port number; ssh will refuse it", "ポート番号ではないため ssh が拒否します"),
+    ("Sessions", "セッション"),
+    // 初回の案内
+    ("No hosts yet", "ホストがまだありません"),
+    ("No SSH config files are read (--no-default-config).", "SSH 設定ファイルを読んでいません (--no-default-config)。"),
+    ("sgh looked in:", "sgh が探した場所:"),
+    ("missing", "ファイルなし"),
+    ("no files", "該当ファイルなし"),
+    ("no Host blocks", "Host ブロックなし"),
+    ("To add your first host:", "最初のホストを追加するには:"),
+    ("create", "作成:"),
+    ("import the ssh destinations in your shell history", "シェル履歴にある ssh の接続先を取り込む"),
+    (
+        "type a destination and press Enter; sgh offers to save it afterwards",
+        "接続先を入力して Enter。接続後に保存するか尋ねます",
+    ),
+    // 一致なし
+    ("No host matches", "一致するホストがありません:"),
+    ("Did you mean", "もしかして"),
+    ("search for", "検索:"),
+    ("clear the search", "検索をクリア"),
+    // ステータスバー
+    ("hosts", "ホスト"),
+    ("sort", "並び順"),
+    ("best match", "一致度順"),
+    ("config order", "設定ファイル順"),
+    ("search", "検索対象"),
+    ("all fields", "全項目"),
+    ("group", "グループ"),
+    ("one row per alias", "別名ごとに 1 行"),
+    ("patterns shown", "パターン表示中"),
+    ("redacted", "伏せ字表示中"),
+    ("demo", "デモ"),
+    ("read-only", "読み取り専用"),
+    ("help", "ヘルプ"),
+    ("Press Esc again to quit", "もう一度 Esc で終了します"),
+    // キーヘルプ
+    ("navigate", "移動"),
+    ("connect", "接続"),
+    ("edit", "編集"),
+    ("explain", "設定の由来"),
+    ("filters", "フィルタ"),
+    ("search field", "検索列"),
+    ("aliases", "別名"),
+    ("variables", "変数"),
+    ("playground", "パターン確認"),
+    ("patterns", "パターン"),
+    ("drop filter", "フィルタ解除"),
+    ("bulk edit", "一括編集"),
+    ("undo edit", "編集の取り消し"),
+    ("forwards", "転送"),
+    ("tunnels", "トンネル"),
+    ("views", "表示切替"),
+    ("snippets", "スニペット"),
+    ("macros", "マクロ"),
+    ("lock", "ロック"),
+    ("redact", "伏せ字"),
+    ("socks", "SOCKS プロキシ"),
+    ("quit", "終了"),
+    ("close", "閉じる"),
+    // ポップアップ
+    ("Save filter as", "フィルタの保存名"),
+    ("Saved filters", "保存済みフィルタ"),
+    (" ↵ apply  del remove  esc close ", " ↵ 適用  del 削除  esc 閉じる "),
+    ("No saved filters. Press Ctrl+S to save the current query.", "保存済みフィルタはありません。Ctrl+S で今の検索を保存できます。"),
+    ("Pattern playground", "パターン確認"),
+    (
+        " type a hostname  ↑↓ scroll  esc close  (Match blocks are not evaluated) ",
+        " ホスト名を入力  ↑↓ スクロール  esc 閉じる  (Match ブロックは評価しません) ",
+    ),
+    ("Type a hostname to see which blocks apply to it.", "ホスト名を入力すると、適用されるブロックを表示します。"),
+    ("Blocks", "ブロック"),
+    ("No Host block matches.", "一致する Host ブロックはありません。"),
+    ("matches", "に一致"),
+    ("excluded by", "除外:"),
+    ("A Match block applies; its conditions are not evaluated.", "Match ブロックがあります。条件は評価していません。"),
+    ("Effective options", "有効なオプション"),
+    ("Set an option on the listed hosts", "一覧のホストにオプションを設定"),
+    (" ↵ apply  ↑↓ scroll  esc cancel ", " ↵ 適用  ↑↓ スクロール  esc 中止 "),
+    (" ↵ preview  esc cancel ", " ↵ プレビュー  esc 中止 "),
+    (
+        "Type `Keyword value`, e.g. ServerAliveInterval 30, and press Enter to preview.",
+        "`キーワード 値` (例: ServerAliveInterval 30) を入力し、Enter でプレビューします。",
+    ),
+    ("Every listed host already has this value.", "一覧のホストはすべて既にこの値です。"),
+    ("Save ad-hoc host", "臨時ホストを保存"),
+    (" ↵ save  tab file  ^t template  ↑↓ field  esc skip ", " ↵ 保存  tab ファイル  ^t テンプレート  ↑↓ 項目  esc 保存しない "),
+    (" ↵ save  tab file  esc skip ", " ↵ 保存  tab ファイル  esc 保存しない "),
+    ("File", "ファイル"),
+    ("Template", "テンプレート"),
+    ("(none)", "(なし)"),
+    ("Run a snippet", "スニペットを実行"),
+    (" type to filter  ↵ run  ^s save as macro  esc close ", " 入力で絞り込み  ↵ 実行  ^s マクロとして保存  esc 閉じる "),
+    ("COMMAND", "コマンド"),
+    ("Save macro as", "マクロの保存名"),
+    ("Replay a macro", "マクロを実行"),
+    (" type to filter  ↵ run  del remove  esc close ", " 入力で絞り込み  ↵ 実行  del 削除  esc 閉じる "),
+    ("QUERY", "検索語"),
+    ("Press Enter to return to sgh.", "Enter で sgh に戻ります。"),
+    ("Connect with tunnels", "トンネル付きで接続"),
+    (" space pick  ↵ connect  esc close ", " space 選択  ↵ 接続  esc 閉じる "),
+    ("Local forwards", "ローカル転送"),
+    (" a add  ↵ edit  del remove  esc close ", " a 追加  ↵ 編集  del 削除  esc 閉じる "),
+    (" ↵ save  esc cancel ", " ↵ 保存  esc 中止 "),
+    ("No LocalForward yet. Press a to add one.", "LocalForward はまだありません。a で追加できます。"),
+    ("Effective settings", "有効な設定"),
+    (
+        " ↑↓ scroll  v check with ssh -G  esc close  (Match blocks are not evaluated) ",
+        " ↑↓ スクロール  v ssh -G で確認  esc 閉じる  (Match ブロックは評価しません) ",
+    ),
+    ("own block", "自身のブロック"),
+    ("ssh -G agrees on every value", "すべての値が ssh -G と一致しています"),
+    ("Template variables", "テンプレート変数"),
+    (" ↑↓ scroll  esc close ", " ↑↓ スクロール  esc 閉じる "),
+    ("Variables", "変数"),
+    ("Helpers", "ヘルパー"),
+    ("built-in", "組み込み"),
+    (
+        "Use {{{var}}} (three braces) to insert a value without HTML escaping.",
+        "HTML エスケープせずに値を入れるには {{{var}}} (波括弧 3 つ) を使います。",
+    ),
+    ("(fetched when connecting)", "(接続時に取得)"),
+    ("(not set)", "(未設定)"),
+    ("(empty)", "(空)"),
+    // ステータスバーの通知
+    ("Session to {host} ended after {duration}", "{host} へのセッションが {duration} で終了しました"),
+    ("Session to {host} lasted {duration}", "{host} へのセッションは {duration} でした"),
+    ("Cancelled the run for {host} {when}", "{host} の実行 ({when}) を取り消しました"),
+    ("Read-only: config files are not changed", "読み取り専用: 設定ファイルは変更しません"),
+    ("No listed host is defined in a config file", "一覧のホストはどれも設定ファイルで定義されていません"),
+    ("Set {keyword} on {blocks} host block(s) in {files} file(s)", "{files} 個のファイルの {blocks} 個の Host ブロックに {keyword} を設定しました"),
+    ("{host} is not defined in a config file", "{host} は設定ファイルで定義されていません"),
+    ("No snippets in the config file", "設定ファイルにスニペットがありません"),
+    (
+        "No macros yet; press Ctrl+S in the snippet list (Alt+R) to record one",
+        "マクロはまだありません。スニペット一覧 (Alt+R) で Ctrl+S を押すと記録できます",
+    ),
+    ("No host matches {query}", "{query} に一致するホストがありません"),
+    ("Ran {snippet} on {count} hosts", "{count} 台のホストで {snippet} を実行しました"),
+    ("{snippet} failed on some hosts", "{snippet} は一部のホストで失敗しました"),
+    ("Source {source} failed: {error}", "ソース {source} の読み込みに失敗しました: {error}"),
+    ("Source values are not passed to a non-ssh command for {host}", "ssh 以外のコマンドには {host} のソースの値を渡せません"),
+    (
+        "Source values are not passed to a non-ssh command for {host} and {count} more",
+        "ssh 以外のコマンドには {host} ほか {count} 件のソースの値を渡せません",
+    ),
+    ("Invalid Port for {host}", "{host} の Port が不正です"),
+    ("Invalid Port for {host} and {count} more", "{host} ほか {count} 件の Port が不正です"),
+    ("{path} already exists", "{path} は既に存在します"),
+    ("Created {path}", "{path} を作成しました"),
+    ("Added {host} to {path}", "{host} を {path} に追加しました"),
+    ("Nothing to undo", "取り消す変更はありません"),
+    ("Undid the last change to {path}", "{path} への最後の変更を取り消しました"),
+    ("Stopped the SOCKS proxy on :{port} via {host}", "{host} 経由の SOCKS プロキシ (:{port}) を止めました"),
+    (
+        "SOCKS proxy via {host}: {proxy} (exports copied with {how})",
+        "{host} 経由の SOCKS プロキシ: {proxy} (export 行を {how} でコピーしました)",
+    ),
+    ("SOCKS proxy via {host}: {proxy}", "{host} 経由の SOCKS プロキシ: {proxy}"),
+    ("No tmux session for {host}", "{host} の tmux セッションはありません"),
+    ("Select a host in the Hosts view first", "先にホスト一覧でホストを選んでください"),
+    ("{host} is no longer in the SSH config", "{host} はもう SSH 設定にありません"),
+    ("Saved filter {name}", "フィルタ {name} を保存しました"),
+    ("Saved macro {name}", "マクロ {name} を保存しました"),
+    ("Showing pattern blocks", "パターンブロックを表示します"),
+    ("Hiding pattern blocks", "パターンブロックを隠します"),
+    // ロック画面
+    ("Locked", "ロック中"),
+    ("Press any key to unlock", "任意のキーで解除します"),
+    ("Type the passphrase and press Enter", "パスフレーズを入力して Enter"),
+    ("Wrong passphrase", "パスフレーズが違います"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::help::KEY_BINDINGS;
+
+    #[test]
+    fn strings_are_looked_up_by_their_english_text() {
+        assert_eq!(translate(Lang::Ja, "Hosts"), "ホスト");
+        assert_eq!(translate(Lang::En, "Hosts"), "Hosts");
+        assert_eq!(translate(Lang::Ja, "not translated"), "not translated");
+        assert_eq!(Lang::from_locale("ja_JP.UTF-8"), Lang::Ja);
+        assert_eq!(Lang::from_locale("C.UTF-8"), Lang::En);
+    }
+
+    #[test]
+    fn placeholders_are_filled_in_the_translated_order() {
+        let args: [(&str, &dyn fmt::Display); 2] = [("host", &"web-{path}"), ("path", &"/etc/ssh")];
+        assert_eq!(fill("Added {host} to {path}", &args), "Added web-{path} to /etc/ssh");
+        assert_eq!(fill(translate(Lang::Ja, "Added {host} to {path}"), &args), "web-{path} を /etc/ssh に追加しました");
+        assert_eq!(fill("{unknown} and {", &args), "{unknown} and {");
+    }
+
+    #[test]
+    fn translations_keep_the_placeholders() {
+        let names = |text: &str| {
+            let mut names: Vec<String> = text
+                .split('{')
+                .skip(1)
+                .filter_map(|part| part.split_once('}').map(|(name, _)| name.to_string()))
+                .collect();
+            names.sort();
+            names
+        };
+        for (english, translated) in JA {
+            assert_eq!(names(english), names(translated), "{english}");
+        }
+    }
+
+    #[test]
+    fn every_key_help_label_has_a_japanese_translation() {
+        for (_, label) in KEY_BINDINGS.iter().filter_map(|binding| binding.chip) {
+            assert_ne!(translate(Lang::Ja, label), label, "{label}");
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::color_rule::HostPattern;
+use crate::ssh;
+
+/// How the icon column is drawn (`--icons`).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IconMode {
+    /// No icon column.
+    #[default]
+    Off,
+    /// Nerd Font glyphs; needs a patched font in the terminal.
+    Nerd,
+    /// Short plain-text labels such as `deb` or `aws`.
+    Ascii,
+}
+
+/// Built-in icons: name, the tags that pick it, the Nerd Font glyph and the
+/// ASCII fallback.
+const ICONS: &[(&str, &[&str], &str, &str)] = &[
+    ("ubuntu", &["ubuntu"], "\u{f31b}", "ubu"),
+    ("debian", &["debian"], "\u{f306}", "deb"),
+    ("fedora", &["fedora"], "\u{f30a}", "fed"),
+    ("redhat", &["redhat", "rhel", "centos", "rocky"], "\u{f316}", "rh"),
+    ("arch", &["arch", "archlinux"], "\u{f303}", "arc"),
+    ("alpine", &["alpine"], "\u{f300}", "alp"),
+    ("linux", &["linux"], "\u{f17c}", "lnx"),
+    ("freebsd", &["freebsd", "bsd"], "\u{f30c}", "bsd"),
+    ("apple", &["macos", "mac", "apple", "darwin"], "\u{f179}", "mac"),
+    ("windows", &["windows", "win"], "\u{f17a}", "win"),
+    ("raspberry", &["raspberry", "raspi", "rpi"], "\u{f315}", "rpi"),
+    ("aws", &["aws", "ec2", "amazon"], "\u{f270}", "aws"),
+    ("gcp", &["gcp", "gce", "google"], "\u{f11f6}", "gcp"),
+    ("azure", &["azure"], "\u{f0805}", "az"),
+    ("docker", &["docker", "container"], "\u{f308}", "dkr"),
+    ("kubernetes", &["kubernetes", "k8s"], "\u{f10fe}", "k8s"),
+    ("github", &["github"], "\u{f09b}", "gh"),
+    ("gitlab", &["gitlab"], "\u{f296}", "gl"),
+    ("database", &["db", "database", "sql", "postgres", "mysql"], "\u{f1c0}", "db"),
+    ("server", &["server"], "\u{f233}", "srv"),
+];
+
+/// An `icon_rules` entry as written in the config file, e.g.
+/// `{ "match": "destination:*.amazonaws.com", "icon": "aws" }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IconRuleConfig {
+    #[serde(rename = "match")]
+    pub matches: String,
+    /// A built-in icon name, or any text to show as is.
+    pub icon: String,
+}
+
+/// Picks the icon of hosts matching a `HostPattern`.
+#[derive(Debug, Clone)]
+pub struct IconRule {
+    pattern: HostPattern,
+    icon: String,
+}
+
+impl IconRule {
+    /// # Errors
+    ///
+    /// Will return `Err` if the pattern is invalid or the icon is empty.
+    pub fn parse(rule: &IconRuleConfig) -> Result<IconRule> {
+        if rule.icon.trim().is_empty() {
+            return Err(anyhow!("Icon rule {:?} has an empty icon", rule.matches));
+        }
+        Ok(IconRule {
+            pattern: HostPattern::parse(&rule.matches)?,
+            icon: rule.icon.clone(),
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if any of the rules is invalid.
+    pub fn parse_all(rules: &[IconRuleConfig]) -> Result<Vec<IconRule>> {
+        rules.iter().map(Self::parse).collect()
+    }
+}
+
+/// The icon of `host`: the first matching rule, else the first tag naming a
+/// built-in icon. `None` when nothing applies or icons are off.
+#[must_use]
+pub fn icon_for(mode: IconMode, rules: &[IconRule], host: &ssh::Host) -> Option<String> {
+    let glyph = |name: &str| {
+        ICONS
+            .iter()
+            .find(|(icon, ..)| icon.eq_ignore_ascii_case(name))
+            .map(|(_, _, nerd, ascii)| if mode == IconMode::Nerd { *nerd } else { *ascii })
+    };
+
+    if mode == IconMode::Off {
+        return None;
+    }
+    if let Some(rule) = rules.iter().find(|rule| rule.pattern.matches(host)) {
+        // 組み込みの名前でなければ書かれた文字をそのまま使う
+        return Some(glyph(&rule.icon).map_or_else(|| rule.icon.clone(), str::to_string));
+    }
+    host.tags.iter().find_map(|tag| {
+        ICONS
+            .iter()
+            .find(|(_, tags, ..)| tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+            .and_then(|(name, ..)| glyph(name))
+            .map(str::to_string)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_win_over_tags_and_ascii_falls_back_to_labels() {
+        let rules = IconRule::parse_all(&[
+            IconRuleConfig {
+                matches: "destination:*.amazonaws.com".to_string(),
+                icon: "aws".to_string(),
+            },
+            IconRuleConfig {
+                matches: "name:lab-*".to_string(),
+                icon: "*".to_string(),
+            },
+        ])
+        .unwrap();
+        let host = |name: &str, destination: &str, tags: &[&str]| ssh::Host {
+            name: name.to_string(),
+            destination: destination.to_string(),
+            tags: tags.iter().map(ToString::to_string).collect(),
+            ..ssh::Host::default()
+        };
+
+        let ec2 = host("api", "ec2-1.compute.amazonaws.com", &["ubuntu"]);
+        assert_eq!(icon_for(IconMode::Nerd, &rules, &ec2).as_deref(), Some("\u{f270}"));
+        assert_eq!(icon_for(IconMode::Ascii, &rules, &ec2).as_deref(), Some("aws"));
+        assert_eq!(icon_for(IconMode::Off, &rules, &ec2), None);
+
+        let web = host("web", "10.0.0.1", &["prod", "Debian"]);
+        assert_eq!(icon_for(IconMode::Ascii, &rules, &web).as_deref(), Some("deb"));
+        assert_eq!(icon_for(IconMode::Ascii, &rules, &host("lab-1", "", &[])).as_deref(), Some("*"));
+        assert_eq!(icon_for(IconMode::Ascii, &rules, &host("x", "", &["prod"])), None);
+    }
+}
@@ -0,0 +1,376 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::edit::{self, HostBlock};
+use crate::ssh;
+
+/// Options of `ssh` that take a value, so their argument is not mistaken for
+/// the destination.
+const OPTIONS_WITH_VALUE: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+
+/// A destination found in shell history, with how often it was used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub destination: String,
+    pub user: Option<String>,
+    pub port: Option<String>,
+    pub identity_file: Option<String>,
+    pub proxy_jump: Option<String>,
+    pub count: usize,
+}
+
+impl Candidate {
+    /// The command as it would have been typed, e.g. `admin@db -p 2200`.
+    #[must_use]
+    pub fn describe(&self) -> String {
+        let mut out = match &self.user {
+            Some(user) => format!("{user}@{}", self.destination),
+            None => self.destination.clone(),
+        };
+        if let Some(port) = &self.port {
+            out.push_str(&format!(" -p {port}"));
+        }
+        if let Some(jump) = &self.proxy_jump {
+            out.push_str(&format!(" -J {jump}"));
+        }
+        if let Some(identity) = &self.identity_file {
+            out.push_str(&format!(" -i {identity}"));
+        }
+        out
+    }
+
+    /// Proposed block under `name`.
+    #[must_use]
+    pub fn block(&self, name: &str) -> HostBlock {
+        let mut entries = vec![("HostName".to_string(), self.destination.clone())];
+        let optional = [
+            ("User", &self.user),
+            ("Port", &self.port),
+            ("IdentityFile", &self.identity_file),
+            ("ProxyJump", &self.proxy_jump),
+        ];
+        for (keyword, value) in optional {
+            if let Some(value) = value {
+                entries.push((keyword.to_string(), value.clone()));
+            }
+        }
+
+        HostBlock {
+            name: name.to_string(),
+            entries,
+            directives: Vec::new(),
+        }
+    }
+
+    /// Default alias: the first label of a hostname, or the whole address.
+    #[must_use]
+    pub fn default_name(&self) -> String {
+        let is_address = self.destination.contains(':')
+            || self.destination.chars().all(|c| c.is_ascii_digit() || c == '.');
+        if is_address {
+            self.destination.clone()
+        } else {
+            self.destination
+                .split('.')
+                .next()
+                .unwrap_or(&self.destination)
+                .to_string()
+        }
+    }
+
+    fn key(&self) -> (&str, Option<&str>, Option<&str>) {
+        (&self.destination, self.user.as_deref(), self.port.as_deref())
+    }
+}
+
+/// History files of bash, zsh and fish that exist for the current user,
+/// including `$HISTFILE`.
+#[must_use]
+pub fn default_history_files() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::env::var_os("HISTFILE").map(PathBuf::from).into_iter().collect();
+    if let Some(home) = dirs::home_dir() {
+        files.push(home.join(".bash_history"));
+        files.push(home.join(".zsh_history"));
+        files.push(home.join(".zhistory"));
+    }
+    if let Some(data) = dirs::data_dir() {
+        files.push(data.join("fish").join("fish_history"));
+    }
+
+    files.dedup();
+    files.retain(|path| path.is_file());
+    files
+}
+
+/// Commands recorded in a history file. zsh's extended format
+/// (`: 1700000000:0;ssh db`) and fish's (`- cmd: ssh db`) are unwrapped;
+/// anything else is taken as one command per line.
+#[must_use]
+pub fn history_commands(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            if let Some(cmd) = line.strip_prefix("- cmd: ") {
+                return Some(cmd.to_string());
+            }
+            if line.starts_with("  when:") || line.starts_with("  paths:") || line.starts_with("    - ") {
+                return None;
+            }
+            if let Some(rest) = line.strip_prefix(": ") {
+                return rest.split_once(';').map(|(_, cmd)| cmd.to_string());
+            }
+            Some(line.to_string())
+        })
+        .collect()
+}
+
+/// Parses an `ssh [options] [user@]host [command]` invocation. Destinations in
+/// `ssh://user@host:port` form are understood too.
+#[must_use]
+pub fn parse_ssh_command(command: &str) -> Option<Candidate> {
+    let words = shlex::split(command.trim())?;
+    let mut words = words.iter().map(String::as_str);
+    if words.next()? != "ssh" {
+        return None;
+    }
+
+    let mut candidate = Candidate {
+        destination: String::new(),
+        user: None,
+        port: None,
+        identity_file: None,
+        proxy_jump: None,
+        count: 1,
+    };
+
+    while let Some(word) = words.next() {
+        let Some(flags) = word.strip_prefix('-') else {
+            // 最初の非オプション引数が接続先。それ以降はリモートコマンド
+            candidate.destination = word.to_string();
+            break;
+        };
+
+        // -p2200 のように値が続けて書かれていることもある
+        for (i, flag) in flags.char_indices() {
+            if !OPTIONS_WITH_VALUE.contains(flag) {
+                continue;
+            }
+            let attached = &flags[i + flag.len_utf8()..];
+            let value = if attached.is_empty() { words.next()? } else { attached };
+            match flag {
+                'p' => candidate.port = Some(value.to_string()),
+                'l' => candidate.user = Some(value.to_string()),
+                'i' => candidate.identity_file = Some(value.to_string()),
+                'J' => candidate.proxy_jump = Some(value.to_string()),
+                _ => {}
+            }
+            break;
+        }
+    }
+
+    let raw = std::mem::take(&mut candidate.destination);
+    let (is_uri, destination) = match raw.strip_prefix("ssh://") {
+        Some(destination) => (true, destination),
+        None => (false, raw.as_str()),
+    };
+    let (user, rest) = match destination.rsplit_once('@') {
+        Some((user, rest)) => (Some(user), rest),
+        None => (None, destination),
+    };
+    // ポートを書けるのは ssh:// 形式のみ。IPv6 は [] で囲まれている
+    let (host, port) = match rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+        Some((host, after)) => (host, after.strip_prefix(':')),
+        None if is_uri => match rest.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (rest, None),
+        },
+        None if rest.contains(':') => return None,
+        None => (rest, None),
+    };
+
+    if host.is_empty() || host.contains(['%', '$', '`', '/']) {
+        return None;
+    }
+    candidate.destination = host.to_string();
+    if let Some(user) = user {
+        candidate.user = Some(user.to_string());
+    }
+    if let Some(port) = port {
+        candidate.port = Some(port.to_string());
+    }
+    if candidate.port.as_deref().is_some_and(|p| p.parse::<u16>().is_err()) {
+        return None;
+    }
+
+    Some(candidate)
+}
+
+/// Distinct `ssh` destinations in `commands` that are not already configured
+/// hosts, most used first.
+#[must_use]
+pub fn collect_candidates<'a>(
+    commands: impl IntoIterator<Item = &'a str>,
+    known_hosts: &HashSet<String>,
+) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for candidate in commands.into_iter().filter_map(parse_ssh_command) {
+        if known_hosts.contains(&candidate.destination) {
+            continue;
+        }
+        match candidates.iter_mut().find(|c| c.key() == candidate.key()) {
+            Some(existing) => existing.count += 1,
+            None => candidates.push(candidate),
+        }
+    }
+
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.count));
+    candidates
+}
+
+/// `sgh import --from-history`: walks through the candidates on the terminal
+/// and appends the accepted ones to `target`.
+///
+/// # Errors
+///
+/// Will return `Err` if the terminal or `target` cannot be read or written.
+pub fn run_from_history(history_files: &[PathBuf], target: &Path, hosts: &[ssh::Host]) -> Result<()> {
+    let mut known: HashSet<String> = hosts
+        .iter()
+        .flat_map(|h| {
+            std::iter::once(h.name.clone())
+                .chain(h.aliases.split(", ").map(str::to_string))
+                .chain(std::iter::once(h.destination.clone()))
+        })
+        .collect();
+
+    let contents: Vec<String> = history_files
+        .iter()
+        .filter_map(|path| std::fs::read(path).ok())
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .collect();
+    let commands: Vec<String> = contents.iter().flat_map(|c| history_commands(c)).collect();
+    let candidates = collect_candidates(commands.iter().map(String::as_str), &known);
+
+    if candidates.is_empty() {
+        println!("No new ssh destinations found in {} history file(s).", history_files.len());
+        return Ok(());
+    }
+
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let mut ask = |prompt: &str| -> Result<String> {
+        print!("{prompt}");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        input.read_line(&mut line)?;
+        Ok(line.trim().to_string())
+    };
+
+    let total = candidates.len();
+    let mut saved = 0;
+    for (i, candidate) in candidates.iter().enumerate() {
+        let mut name = candidate.default_name();
+        println!();
+        println!("[{}/{total}] ssh {} (used {} times)", i + 1, candidate.describe(), candidate.count);
+
+        loop {
+            print!("{}", candidate.block(&name).render()?);
+            let answer = ask("Add to config? [y]es / [n]o / [r]ename / [q]uit: ")?;
+            match answer.as_str() {
+                "y" | "yes" if known.contains(&name) => {
+                    println!("{name} is already defined; rename it first.");
+                }
+                "y" | "yes" => {
+                    edit::append_host(target, &candidate.block(&name))?;
+                    known.insert(name);
+                    saved += 1;
+                    break;
+                }
+                "n" | "no" | "" => break,
+                "r" | "rename" => {
+                    let new_name = ask("Host alias: ")?;
+                    if !new_name.is_empty() && !new_name.contains(char::is_whitespace) {
+                        name = new_name;
+                    }
+                }
+                "q" | "quit" => {
+                    println!("Added {saved} host(s) to {}.", target.display());
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    println!("Added {saved} host(s) to {}.", target.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_invocations_are_parsed() {
+        let c = parse_ssh_command("ssh -p 2200 admin@10.0.0.5 uptime").unwrap();
+        assert_eq!(c.destination, "10.0.0.5");
+        assert_eq!(c.user.as_deref(), Some("admin"));
+        assert_eq!(c.port.as_deref(), Some("2200"));
+
+        let c = parse_ssh_command("ssh -A -i ~/.ssh/id_x -l root -J bastion db.example.com").unwrap();
+        assert_eq!(c.destination, "db.example.com");
+        assert_eq!(c.user.as_deref(), Some("root"));
+        assert_eq!(c.identity_file.as_deref(), Some("~/.ssh/id_x"));
+        assert_eq!(c.proxy_jump.as_deref(), Some("bastion"));
+
+        let c = parse_ssh_command("ssh -p2222 -v box").unwrap();
+        assert_eq!(c.port.as_deref(), Some("2222"));
+
+        let c = parse_ssh_command("ssh ssh://deploy@web.example:2022").unwrap();
+        assert_eq!(c.destination, "web.example");
+        assert_eq!(c.port.as_deref(), Some("2022"));
+
+        assert!(parse_ssh_command("sshfs host:/ mnt").is_none());
+        assert!(parse_ssh_command("ssh -p").is_none());
+        assert!(parse_ssh_command("ssh $HOST").is_none());
+        assert!(parse_ssh_command("ssh host:22").is_none());
+    }
+
+    #[test]
+    fn shell_history_formats_are_unwrapped() {
+        let zsh = ": 1700000000:0;ssh db\n: 1700000001:0;ls\n";
+        assert_eq!(history_commands(zsh), vec!["ssh db", "ls"]);
+
+        let fish = "- cmd: ssh web\n  when: 1700000000\n  paths:\n    - /tmp\n";
+        assert_eq!(history_commands(fish), vec!["ssh web"]);
+
+        assert_eq!(history_commands("ssh a\nssh b\n"), vec!["ssh a", "ssh b"]);
+    }
+
+    #[test]
+    fn candidates_are_deduplicated_and_skip_known_hosts() {
+        let known: HashSet<String> = ["configured".to_string()].into();
+        let commands = [
+            "ssh rare.example.com",
+            "ssh admin@db.example.com",
+            "ssh configured",
+            "ssh admin@db.example.com ls",
+            "ssh db.example.com",
+        ];
+
+        let candidates = collect_candidates(commands, &known);
+        let described: Vec<String> = candidates.iter().map(Candidate::describe).collect();
+        assert_eq!(
+            described,
+            vec!["admin@db.example.com", "rare.example.com", "db.example.com"]
+        );
+        assert_eq!(candidates[0].count, 2);
+        assert_eq!(candidates[0].default_name(), "db");
+        assert_eq!(
+            candidates[0].block("db").render().unwrap(),
+            "Host db\n  HostName db.example.com\n  User admin\n"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{edit, store};
+
+/// Append-only log of every change sgh made to an SSH config file, one JSON
+/// object per line in `<data_dir>/config-journal.jsonl`.
+const FILE_NAME: &str = "config-journal.jsonl";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u64,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub path: PathBuf,
+    /// What was done, e.g. `Add Host box`.
+    pub action: String,
+    /// Content before the change; `None` when the file did not exist.
+    pub before: Option<String>,
+    /// Content after the change; `None` when the file was removed.
+    pub after: Option<String>,
+}
+
+/// Appends an entry for a change to `path`.
+///
+/// # Errors
+///
+/// Will return `Err` if the journal cannot be written.
+pub fn record(path: &Path, action: &str, before: Option<&str>, after: Option<&str>) -> Result<()> {
+    let dir = store::data_dir().ok_or(anyhow!("Cannot determine the data directory"))?;
+    std::fs::create_dir_all(&dir)?;
+    let journal = dir.join(FILE_NAME);
+
+    let entry = Entry {
+        id: load().last().map_or(1, |last| last.id + 1),
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        // 別のディレクトリから復元できるよう絶対パスで残す
+        path: std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+        action: action.to_string(),
+        before: before.map(str::to_string),
+        after: after.map(str::to_string),
+    };
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&journal)
+        .with_context(|| format!("Failed to open {}", journal.display()))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)
+        .with_context(|| format!("Failed to write {}", journal.display()))?;
+
+    Ok(())
+}
+
+/// Every recorded entry, oldest first. Lines that cannot be parsed are
+/// skipped so a damaged line does not hide the rest of the history.
+#[must_use]
+pub fn load() -> Vec<Entry> {
+    store::data_dir()
+        .and_then(|dir| std::fs::read_to_string(dir.join(FILE_NAME)).ok())
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// `sgh history-config`: lists the journal, or shows / restores one entry.
+///
+/// # Errors
+///
+/// Will return `Err` if the entry does not exist or cannot be restored.
+pub fn run(show: Option<u64>, restore: Option<u64>) -> Result<()> {
+    let entries = load();
+    let find = |id: u64| {
+        entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .ok_or(anyhow!("No journal entry #{id}"))
+    };
+
+    if let Some(id) = show {
+        let entry = find(id)?;
+        println!("{}", describe(entry));
+        print!("{}", diff(entry.before.as_deref(), entry.after.as_deref()));
+    } else if let Some(id) = restore {
+        let entry = find(id)?;
+        let action = format!("Restore to before #{id}");
+        match &entry.before {
+            Some(before) => {
+                edit::write_config(&entry.path, before, &action)?;
+            }
+            None => edit::remove_config(&entry.path, &action)?,
+        }
+        println!("Restored {} to its state before #{id}.", entry.path.display());
+    } else if entries.is_empty() {
+        println!("sgh has not changed any config file yet.");
+    } else {
+        for entry in &entries {
+            println!("{}", describe(entry));
+        }
+    }
+
+    Ok(())
+}
+
+/// `#3  2026-10-15 09:12 UTC  ~/.ssh/config  Add Host box`
+fn describe(entry: &Entry) -> String {
+    format!(
+        "#{:<4} {}  {}  {}",
+        entry.id,
+        format_utc(entry.time),
+        entry.path.display(),
+        entry.action
+    )
+}
+
+/// `YYYY-MM-DD HH:MM UTC` for a Unix timestamp.
+fn format_utc(secs: u64) -> String {
+    let (year, month, day, minutes) = civil(secs);
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02} UTC", minutes / 60, minutes % 60)
+}
+
+/// Year, month, day and minute of the day of a Unix timestamp, in UTC (or
+/// in local time when the offset was added to `secs`).
+#[must_use]
+pub fn civil(secs: u64) -> (i64, i64, i64, u64) {
+    let days = i64::try_from(secs / 86_400).unwrap_or_default();
+    let minutes = secs % 86_400 / 60;
+
+    // Howard Hinnant の civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, minutes)
+}
+
+/// Lines removed (`-`) and added (`+`) between `before` and `after`, with
+/// the unchanged lines around the change left out.
+fn diff(before: Option<&str>, after: Option<&str>) -> String {
+    let before: Vec<&str> = before.map(|s| s.lines().collect()).unwrap_or_default();
+    let after: Vec<&str> = after.map(|s| s.lines().collect()).unwrap_or_default();
+
+    let prefix = before
+        .iter()
+        .zip(&after)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let removed = before[prefix..before.len() - suffix].iter().map(|line| format!("-{line}\n"));
+    let added = after[prefix..after.len() - suffix].iter().map(|line| format!("+{line}\n"));
+    removed.chain(added).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_formatted_as_utc() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_utc(1_709_210_096), "2024-02-29 12:34 UTC");
+    }
+
+    #[test]
+    fn diff_shows_only_the_changed_lines() {
+        assert_eq!(
+            diff(Some("Host a\n  User x\nHost b\n"), Some("Host a\n  User y\n  Port 2\nHost b\n")),
+            "-  User x\n+  User y\n+  Port 2\n"
+        );
+        assert_eq!(diff(None, Some("Host a\n")), "+Host a\n");
+    }
+}
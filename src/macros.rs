@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Result};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::search::{self, Query, SearchField};
+use crate::searchable::TableItem;
+use crate::snippet::Snippet;
+use crate::{ssh, store};
+
+/// What a macro replays: the query that picks the hosts and the snippet run
+/// on each of them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Steps {
+    pub query: String,
+    pub snippet: Snippet,
+}
+
+/// A macro with its name, as listed by Alt+M.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub steps: Steps,
+}
+
+impl TableItem for Macro {
+    const HEADERS: &'static [&'static str] = &["NAME", "QUERY", "COMMAND"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![self.name.clone(), self.steps.query.clone(), self.steps.snippet.command.clone()]
+    }
+}
+
+/// Macros recorded from the snippet list (Ctrl+S there), persisted in the
+/// data directory.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Macros(BTreeMap<String, Steps>);
+
+impl Macros {
+    const FILE_NAME: &'static str = "macros.json";
+
+    #[must_use]
+    pub fn load() -> Macros {
+        store::load(Self::FILE_NAME)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the macros cannot be written.
+    pub fn save(&self) -> Result<()> {
+        store::save(Self::FILE_NAME, self)
+    }
+
+    pub fn insert(&mut self, name: String, steps: Steps) {
+        self.0.insert(name, steps);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.remove(name);
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Macros sorted by name.
+    #[must_use]
+    pub fn list(&self) -> Vec<Macro> {
+        self.0
+            .iter()
+            .map(|(name, steps)| Macro {
+                name: name.clone(),
+                steps: steps.clone(),
+            })
+            .collect()
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if there is no macro called `name`.
+    pub fn get(&self, name: &str) -> Result<Macro> {
+        let steps = self.0.get(name).ok_or_else(|| {
+            let names: Vec<&str> = self.0.keys().map(String::as_str).collect();
+            match names.as_slice() {
+                [] => anyhow!("No macro {name:?}; record one with Ctrl+S in the snippet list (Alt+R)"),
+                _ => anyhow!("No macro {name:?}; expected one of: {}", names.join(", ")),
+            }
+        })?;
+        Ok(Macro {
+            name: name.to_string(),
+            steps: steps.clone(),
+        })
+    }
+}
+
+/// Hosts the macro's query lists, as the picker would show them without
+/// pattern blocks.
+#[must_use]
+pub fn targets(hosts: &[ssh::Host], fields: &[SearchField], query: &str) -> Vec<ssh::Host> {
+    let matcher = SkimMatcherV2::default();
+    let query = Query::parse(query);
+    hosts
+        .iter()
+        .filter(|host| !host.is_pattern && search::matches(&matcher, host, fields, &query))
+        .cloned()
+        .collect()
+}
+
+/// Runs the snippet on each host in turn through the command template
+/// `template` picks for it (the snippet template). Returns whether every run
+/// succeeded; a failure does not stop the remaining hosts.
+///
+/// # Errors
+///
+/// Will return `Err` if the template cannot be rendered or the command
+/// cannot be started.
+pub fn run<F>(steps: &Steps, hosts: &[ssh::Host], template: F, shell: bool) -> Result<bool>
+where
+    F: Fn(&ssh::Host) -> Result<String>,
+{
+    let mut succeeded = true;
+    for host in hosts {
+        let host = ssh::Host {
+            snippet: Some(steps.snippet.quoted()),
+            ..host.clone()
+        };
+        let status = host.run_command_template(&template(&host)?, shell)?;
+        if !status.success() {
+            println!("[sgh] {}: {status}", host.name);
+            succeeded = false;
+        }
+    }
+    Ok(succeeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn macros_replay_on_the_hosts_their_query_lists() {
+        let host = |name: &str, tags: &[&str]| ssh::Host {
+            name: name.to_string(),
+            destination: name.to_string(),
+            tags: tags.iter().map(ToString::to_string).collect(),
+            ..ssh::Host::default()
+        };
+        let hosts = vec![host("web1", &["web"]), host("db1", &["db"]), host("web2", &["web"])];
+        let names: Vec<String> = targets(&hosts, &[SearchField::Name], "tag:web")
+            .into_iter()
+            .map(|host| host.name)
+            .collect();
+        assert_eq!(names, ["web1", "web2"]);
+
+        let mut macros = Macros::default();
+        assert!(macros.get("restart").is_err());
+        let steps = Steps {
+            query: "tag:web".to_string(),
+            snippet: Snippet {
+                name: "restart".to_string(),
+                command: "sudo systemctl restart app".to_string(),
+            },
+        };
+        macros.insert("restart-web".to_string(), steps.clone());
+        assert_eq!(macros.get("restart-web").unwrap().steps, steps);
+        let err = macros.get("restart").unwrap_err().to_string();
+        assert!(err.contains("restart-web"), "{err}");
+    }
+}
@@ -1,43 +1,231 @@
+pub mod clipboard;
+pub mod color_rule;
+pub mod config;
+#[cfg(unix)]
+pub mod control;
+#[cfg(unix)]
+pub mod daemon;
+pub mod demo;
+pub mod edit;
+pub mod exchange;
+pub mod exclude;
+pub mod help;
+pub mod icon;
+pub mod history;
+pub mod i18n;
+pub mod import;
+pub mod journal;
+pub mod macros;
+pub mod notify;
+pub mod porcelain;
+pub mod resolve;
+pub mod schedule;
+pub mod search;
 pub mod searchable;
+pub mod session;
+pub mod snippet;
+pub mod source;
 pub mod ssh;
 pub mod ssh_config;
+pub mod store;
+pub mod template;
+pub mod text;
 pub mod theme;
+pub mod title;
+pub mod tunnel;
 pub mod ui;
+pub mod verify;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use config::UserConfig;
+use exchange::ExportFormat;
+use icon::IconMode;
+use search::{GroupKey, SavedFilters, SearchField, SortKey};
+use session::Launcher;
+use theme::ThemeName;
+use std::path::PathBuf;
 use ui::{App, AppConfig};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about=None)]
 struct Args {
-    /// SSH configuration files to load. When omitted, sgh reads the standard
-    /// locations (`/etc/ssh/ssh_config`, `~/.ssh/config`) and, unless
-    /// `--no-config-d` is set, every regular file under `~/.ssh/config.d/`.
+    /// SSH configuration files to load. When omitted, sgh reads the files
+    /// listed in `$SGH_SSH_CONFIG` (separated like `$PATH`), or else the
+    /// standard locations (`/etc/ssh/ssh_config`, `~/.ssh/config`) and,
+    /// unless `--no-config-d` is set, every regular file under
+    /// `~/.ssh/config.d/`.
     #[arg(short, long, num_args = 1..)]
     config: Option<Vec<String>>,
 
+    /// Read none of the standard locations when neither `--config` nor
+    /// `$SGH_SSH_CONFIG` names files, so only sources supply hosts.
+    #[arg(long, default_value_t = false)]
+    no_default_config: bool,
+
+    /// Extra host source as `<kind>:<argument>`, e.g. `encrypted:hosts.age`
+    /// for an age/GPG encrypted bundle decrypted in memory, `mdns` for
+    /// machines announcing SSH on the local network, `scan:<CIDR>` for
+    /// the addresses of a network answering on port 22, or `docker` /
+    /// `podman` (`:containers` adds running containers) for container hosts,
+    /// `vagrant:<DIR>` for the machines of a Vagrant project, or
+    /// `gcp[:<PROJECT>][,<LABEL>=<VALUE>…]` / `azure[:<GROUP>][,<TAG>=<VALUE>…]`
+    /// for cloud instances. Repeatable.
+    #[arg(long, value_name = "KIND:ARG")]
+    source: Vec<source::Source>,
+
+    /// Use the cached hosts of slow sources (cloud, scans, containers, …)
+    /// whatever their age and never ask the sources themselves.
+    #[arg(long, default_value_t = false)]
+    offline: bool,
+
     /// Disable the automatic discovery of `~/.ssh/config.d/*` when `--config`
     /// is not provided.
     #[arg(long, default_value_t = false)]
     no_config_d: bool,
 
+    /// Hide hosts whose name or alias matches this glob (or `re:<regex>`).
+    /// Repeatable; added to the `exclude` list of the config file.
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Keep hosts with identical settings as separate rows instead of merging
+    /// them into one row with aliases.
+    #[arg(long, default_value_t = false)]
+    no_merge: bool,
+
+    /// Do not fold the entries of `Host *` style pattern blocks into the
+    /// hosts they match.
+    #[arg(long, default_value_t = false)]
+    no_apply_patterns: bool,
+
+    /// Leave Hostname empty instead of defaulting it to the Host name.
+    #[arg(long, default_value_t = false)]
+    no_default_hostname: bool,
+
+    /// Show the hosts as written: implies `--no-merge`, `--no-apply-patterns`
+    /// and `--no-default-hostname`.
+    #[arg(long, default_value_t = false)]
+    raw: bool,
+
+    /// List every alias of a `Host a b` block as its own row so it can be
+    /// searched and selected directly. Ctrl+L toggles.
+    #[arg(long, default_value_t = false)]
+    split_aliases: bool,
+
+    /// Mask destinations, users and ports with `•••` while keeping host
+    /// names, for screenshots and screen sharing. Alt+X toggles.
+    #[arg(long, default_value_t = false)]
+    redact: bool,
+
+    /// List wildcard-only `Host` blocks (e.g. `Host *`) that are normally
+    /// folded into other hosts; useful for debugging defaults. Ctrl+T toggles.
+    #[arg(long, default_value_t = false)]
+    show_patterns: bool,
+
     // show the proxy command
     #[arg(long, default_value_t = false)]
     show_proxy_command: bool,
 
+    /// Show the config file and line each host was declared in as a column.
+    #[arg(long, default_value_t = false)]
+    show_source: bool,
+
+    /// Size table columns to the filtered rows rather than every host, so a
+    /// narrow result set is not padded to the widest host in the config.
+    #[arg(long, default_value_t = false)]
+    fit_columns: bool,
+
     // host search filter
     #[arg(short, long)]
     search: Option<String>,
 
-    // sort hosts by name
+    /// Start with a filter previously saved from the search bar (Ctrl+S).
+    #[arg(short, long, value_name = "NAME")]
+    filter: Option<String>,
+
+    /// Start with the host on the clipboard as the query when it is a listed
+    /// host name, alias or address (ignored with --search / --filter).
     #[arg(long, default_value_t = false)]
+    from_clipboard: bool,
+
+    /// Comma-separated host fields the search query is matched against.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = SearchField::DEFAULT
+    )]
+    search_fields: Vec<SearchField>,
+
+    // sort hosts by name
+    #[arg(long, default_value_t = false, conflicts_with = "sort_by")]
     sort: bool,
 
+    /// Comma-separated keys to sort the host list by, e.g. `tags,name`;
+    /// hosts equal on every key keep their config order.
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "KEYS")]
+    sort_by: Vec<SortKey>,
+
+    /// Sort text strictly character by character, so `web10` comes before
+    /// `web2`, instead of comparing numbers by value.
+    #[arg(long, default_value_t = false)]
+    lexicographic_sort: bool,
+
+    /// Group the host list by tag, file, user or proxy, with a header row
+    /// and host count per group.
+    #[arg(long, value_enum, value_name = "KEY")]
+    group_by: Option<GroupKey>,
+
+    /// Show an icon column picked by tag or `icon_rules`: `nerd` draws Nerd
+    /// Font glyphs, `ascii` short text labels.
+    #[arg(long, value_enum, value_name = "MODE")]
+    icons: Option<IconMode>,
+
+    /// Color scheme: `dark` (default) or `high-contrast`.
+    #[arg(long, value_enum, value_name = "NAME")]
+    theme: Option<ThemeName>,
+
+    /// Draw borders, markers and key hints with ASCII only, for screen
+    /// readers, limited terminals and serial consoles.
+    #[arg(long, default_value_t = false)]
+    ascii: bool,
+
+    /// Language of labels, titles and key hints: `en` or `ja` (default from
+    /// LC_ALL / LC_MESSAGES / LANG).
+    #[arg(long, value_enum, value_name = "LANG")]
+    lang: Option<i18n::Lang>,
+
+    /// What Esc does: `quit` right away (default), `clear` the search first,
+    /// or quit only on a `double` press. Ctrl+C always quits.
+    #[arg(long, value_enum, value_name = "ACTION")]
+    esc: Option<ui::EscAction>,
+
+    /// How strongly connection frequency lifts a host in search results
+    /// (default 1.0; 0 ranks by match quality only).
+    #[arg(long, value_name = "WEIGHT")]
+    frequency_weight: Option<f64>,
+
     // Handlebars template of the command to excute
-    #[arg(short, long, default_value = "ssh \"{{{name}}}\"")]
+    #[arg(short, long, default_value = ssh::DEFAULT_COMMAND_TEMPLATE)]
     template: String,
 
+    /// Run the rendered command, hooks and launcher template through
+    /// `$SHELL -c` so pipes, `&&` and redirection work. By default the
+    /// command is split into arguments and run directly.
+    #[arg(long, default_value_t = false)]
+    shell: bool,
+
+    /// Fail when a template refers to a missing field (e.g. a typo like
+    /// `{{destinaton}}`) instead of rendering it as an empty string.
+    #[arg(long, default_value_t = false)]
+    strict_templates: bool,
+
+    /// Command template snippets (Alt+R) run with; `{{{snippet}}}` is the
+    /// snippet's command quoted as one shell word.
+    #[arg(long, value_name = "TEMPLATE")]
+    snippet_template: Option<String>,
+
     // Handlebars template of the command to execute when an SSH session starts
     #[arg(long, value_name = "TEMPLATE")]
     on_session_start_template: Option<String>,
@@ -46,16 +234,246 @@ struct Args {
     #[arg(long, value_name = "TEMPLATE")]
     on_session_end_template: Option<String>,
 
+    /// Handlebars template of the terminal / tmux window title set while a
+    /// session runs and restored afterwards (default `{{name}}`). An empty
+    /// value leaves the title alone.
+    #[arg(long, value_name = "TEMPLATE")]
+    title_template: Option<String>,
+
+    /// Where to start the session: inline (default) suspends the picker, while
+    /// `tmux` and `template` launch it in the background and keep sgh open.
+    #[arg(long, value_enum, default_value_t = Launcher::Inline)]
+    launcher: Launcher,
+
+    /// Handlebars template used by `--launcher template`; `{{{command}}}` is the
+    /// rendered connect command, e.g. `alacritty -e sh -c "{{{command}}}"`. The
+    /// launcher must keep running until the session ends, since the session
+    /// is tracked by its process (`kitty @ launch` returns at once).
+    #[arg(long, value_name = "TEMPLATE")]
+    launcher_template: Option<String>,
+
     // Exit after ending the SSH session
     #[arg(short, long, default_value_t = false)]
     exit: bool,
+
+    /// Send a desktop notification (notify-send / osascript) when a session
+    /// that lasted at least SECS seconds ends, including background sessions
+    /// started with `--launcher` while sgh is open.
+    #[arg(long, value_name = "SECS")]
+    notify_after: Option<u64>,
+
+    /// Quit, restoring the terminal, when sgh has sat for SECS seconds
+    /// without a key press, e.g. when left open on a shared jump host.
+    #[arg(long, value_name = "SECS")]
+    idle_timeout: Option<u64>,
+
+    /// Hide the host list after SECS seconds without a key press, as Alt+L
+    /// does, until a key is pressed (or `lock_passphrase` from the config
+    /// file is typed).
+    #[arg(long, value_name = "SECS")]
+    lock_after: Option<u64>,
+
+    /// Never write to SSH config files: saving ad-hoc hosts, undo, `import`
+    /// and `history-config --restore` are disabled. For shared jump boxes.
+    #[arg(long, default_value_t = false)]
+    read_only: bool,
+
+    /// Show a bundled list of synthetic hosts instead of your config and
+    /// only display the command instead of running it. Implies
+    /// `--read-only`. For screenshots, trying sgh out and UI development.
+    #[arg(long, default_value_t = false)]
+    demo: bool,
+
+    /// Replace sgh with the connect command (Unix `exec`) instead of running
+    /// it as a child, so no parent process lingers and signals and the TTY
+    /// behave exactly as with plain ssh. Session end hooks are not run.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["launcher", "exit"])]
+    exec: bool,
+
+    /// Run the selected host's command later instead of right away: at the
+    /// next HH:MM of the local clock, or after a delay such as +15m or 1h30m.
+    /// sgh closes the picker and counts down (Ctrl+C cancels).
+    #[arg(long, value_name = "WHEN", conflicts_with = "launcher")]
+    at: Option<schedule::When>,
+
+    /// With `--at`, print a `systemd-run` timer or a crontab line that runs
+    /// the command then, and exit instead of waiting.
+    #[arg(long, value_enum, value_name = "SCHEDULER", requires = "at")]
+    emit: Option<schedule::Emit>,
+
+    /// Listen on this Unix socket for newline-delimited JSON-RPC requests
+    /// (`state`, `hosts`, `set_filter`, `select`, `connect`) so editor plugins
+    /// and scripts can drive the running picker.
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<PathBuf>,
+
+    /// Read the hosts from a running `sgh daemon` instead of parsing the
+    /// config files, falling back to parsing when none is running.
+    #[arg(long, default_value_t = false)]
+    use_daemon: bool,
+
+    /// Socket of `sgh daemon` (default `$XDG_RUNTIME_DIR/sgh/daemon.sock`,
+    /// or `daemon.sock` in the data directory).
+    #[arg(long, value_name = "PATH")]
+    daemon_socket: Option<PathBuf>,
+
+    /// Print the hosts as tab-separated lines with stable columns (name,
+    /// aliases, user, hostname, port, proxy_jump, tags, source, note) and
+    /// exit, for editor plugins and fzf pipelines.
+    #[arg(long, default_value_t = false)]
+    porcelain: bool,
+
+    /// End `--porcelain` records with NUL instead of a newline.
+    #[arg(long, default_value_t = false, requires = "porcelain")]
+    null: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Add hosts from shell history or a shared host list to your config.
+    Import(ImportArgs),
+    /// Write the merged host list as JSON or Termius CSV.
+    Export(ExportArgs),
+    /// Review the changes sgh made to config files, and restore a file to
+    /// its state before one of them.
+    HistoryConfig(HistoryConfigArgs),
+    /// Compare sgh's effective values for each host (or the given ones) with
+    /// `ssh -G` and list the differences; exits with 1 when there are any.
+    Verify(VerifyArgs),
+    /// Show which Host blocks apply to a hostname and the options ssh would
+    /// end up with, without connecting.
+    Resolve(ResolveArgs),
+    /// Run a macro recorded in the picker: its snippet on every host its
+    /// query lists, one after the other; exits with 1 when a run fails.
+    RunMacro(RunMacroArgs),
+    /// Print the connect command of every host a query lists, rendered from
+    /// the active template, e.g. for a runbook or GNU parallel.
+    ExportCommands(ExportCommandsArgs),
+    /// List the hosts connected to from sgh, most used first, with the
+    /// time spent in their sessions.
+    Stats,
+    /// Print the man page (roff), e.g. `sgh man > ~/.local/share/man/man1/sgh.1`.
+    Man,
+    /// Keep the parsed hosts in memory and serve them to `sgh --use-daemon`
+    /// over a Unix socket, for near-instant startup with large inventories.
+    Daemon,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    /// Host names to check (default: every host).
+    hosts: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct RunMacroArgs {
+    /// Name the macro was saved under.
+    name: String,
+
+    /// Run on the hosts the query matches; without it they are only listed.
+    #[arg(short, long, default_value_t = false)]
+    yes: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportCommandsArgs {
+    /// Search query picking the hosts, as typed in the picker (default:
+    /// every host). Pattern blocks are left out.
+    #[arg(default_value = "")]
+    query: String,
+
+    /// Write an executable `/bin/sh` script instead of printing to stdout.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ResolveArgs {
+    /// Hostname as it would be passed to ssh.
+    hostname: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct HistoryConfigArgs {
+    /// Show the lines changed by journal entry ID.
+    #[arg(long, value_name = "ID", conflicts_with = "restore")]
+    show: Option<u64>,
+
+    /// Put the file back the way it was before entry ID (recorded as a new
+    /// entry, so it can be restored in turn).
+    #[arg(long, value_name = "ID")]
+    restore: Option<u64>,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(group(
+    clap::ArgGroup::new("source")
+        .required(true)
+        .args(["from_history", "from_json", "from_termius"])
+))]
+struct ImportArgs {
+    /// Scan bash, zsh and fish history for `ssh` invocations and propose
+    /// new Host blocks interactively.
+    #[arg(long)]
+    from_history: bool,
+
+    /// Add the hosts of a host list written by `sgh export`.
+    #[arg(long, value_name = "FILE")]
+    from_json: Option<std::path::PathBuf>,
+
+    /// Add the hosts of a Termius CSV export.
+    #[arg(long, value_name = "FILE")]
+    from_termius: Option<std::path::PathBuf>,
+
+    /// History files to scan instead of the shells' default locations.
+    #[arg(long, value_name = "PATH")]
+    history_file: Vec<std::path::PathBuf>,
+
+    /// Config file the accepted hosts are appended to.
+    #[arg(long, value_name = "PATH", default_value = "~/.ssh/config")]
+    to: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+    format: ExportFormat,
+
+    /// File to write instead of stdout.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<std::path::PathBuf>,
+}
+
+/// The CLI with the template and key reference appended to `--help`.
+fn cli() -> clap::Command {
+    Args::command().after_long_help(help::after_long_help())
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let args = Args::from_arg_matches(&cli().get_matches()).unwrap_or_else(|err| err.exit());
+    let user_config = UserConfig::load()?;
+    if let Some(command) = user_config.secret_command.clone() {
+        template::set_secret_command(command);
+    }
+    template::set_strict(args.strict_templates || user_config.strict_templates);
+    edit::set_read_only(args.read_only || args.demo || user_config.read_only);
+    i18n::set_lang(args.lang.or(user_config.language).unwrap_or_else(i18n::Lang::from_env));
 
-    let (config_paths, strict_missing) = match args.config {
+    // --config、SGH_SSH_CONFIG、既定の場所の順に優先する
+    let env_paths = std::env::var_os("SGH_SSH_CONFIG")
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            std::env::split_paths(&value)
+                .filter(|path| !path.as_os_str().is_empty())
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        });
+    let (config_paths, strict_missing) = match args.config.or(env_paths) {
         Some(paths) => (paths, true),
+        None if args.no_default_config => (Vec::new(), false),
         None => {
             let mut defaults = vec![
                 "/etc/ssh/ssh_config".to_string(),
@@ -68,18 +486,299 @@ fn main() -> Result<()> {
         }
     };
 
-    let mut app = App::new(&AppConfig {
+    // CLI の無効化指定 (--raw を含む) が設定ファイルより優先する
+    let pipeline = ssh::Pipeline {
+        apply_patterns: !(args.raw || args.no_apply_patterns)
+            && user_config.apply_patterns.unwrap_or(true),
+        default_hostname: !(args.raw || args.no_default_hostname)
+            && user_config.default_hostname.unwrap_or(true),
+        merge: !(args.raw || args.no_merge) && user_config.merge.unwrap_or(true),
+    };
+
+    // 保存済みフィルタは --search の前に展開して結合する
+    let search_filter = match args.filter {
+        Some(name) => {
+            let saved = SavedFilters::load();
+            let query = saved
+                .get(&name)
+                .ok_or(anyhow::anyhow!("Unknown saved filter: {name}"))?;
+            Some(match args.search {
+                Some(search) => format!("{query} {search}"),
+                None => query.to_string(),
+            })
+        }
+        None => args.search,
+    };
+
+    let mut sources = user_config
+        .sources
+        .iter()
+        .map(|source| source.parse().map_err(|err| anyhow::anyhow!("Invalid source in config file: {err}")))
+        .collect::<Result<Vec<source::Source>>>()?;
+    sources.extend(args.source);
+
+    #[cfg(unix)]
+    let daemon_socket = args.daemon_socket.or_else(daemon::default_socket);
+    #[cfg(not(unix))]
+    let daemon_socket = args.daemon_socket;
+
+    let ascii = args.ascii || user_config.ascii;
+    user_config.footer.validate()?;
+    let app_config = AppConfig {
         config_paths,
         strict_missing,
-        search_filter: args.search,
-        sort_by_name: args.sort,
+        sources,
+        source_cache: source::CachePolicy {
+            ttl: user_config
+                .source_cache_ttl
+                .map_or(source::DEFAULT_CACHE_TTL, std::time::Duration::from_secs),
+            offline: args.offline,
+        },
+        source_priority: source::SourcePriority::parse(&user_config.source_priority)?,
+        source_defaults: {
+            source::SourceDefaults::validate(&user_config.source_defaults)?;
+            user_config.source_defaults.clone()
+        },
+        pipeline,
+        exclude: user_config
+            .exclude
+            .iter()
+            .chain(args.exclude.iter())
+            .cloned()
+            .collect(),
+        search_filter,
+        from_clipboard: args.from_clipboard || user_config.from_clipboard,
+        search_fields: args.search_fields,
+        sort_keys: if args.sort {
+            vec![SortKey::Name]
+        } else if args.sort_by.is_empty() {
+            user_config.sort_by.clone()
+        } else {
+            args.sort_by
+        },
+        natural_sort: !args.lexicographic_sort && user_config.natural_sort.unwrap_or(true),
+        group_by: args.group_by.or(user_config.group_by),
+        color_rules: color_rule::ColorRule::parse_all(&user_config.color_rules)?,
+        theme: args.theme.or(user_config.theme).unwrap_or_default(),
+        footer: user_config.footer.clone(),
+        esc: args.esc.or(user_config.esc).unwrap_or_default(),
+        ascii,
+        // --ascii では Nerd Font のグリフも使わない
+        icons: match args.icons.or(user_config.icons).unwrap_or_default() {
+            IconMode::Nerd if ascii => IconMode::Ascii,
+            icons => icons,
+        },
+        icon_rules: icon::IconRule::parse_all(&user_config.icon_rules)?,
+        frequency_weight: args
+            .frequency_weight
+            .or(user_config.frequency_weight)
+            .unwrap_or(1.0),
         show_proxy_command: args.show_proxy_command,
+        show_source: args.show_source,
+        fit_columns: args.fit_columns,
+        show_patterns: args.show_patterns,
+        split_aliases: args.split_aliases || user_config.split_aliases,
+        redact: args.redact || user_config.redact,
+        local_user: ssh::local_user(),
         command_template: args.template,
+        shell: args.shell,
         command_template_on_session_start: args.on_session_start_template,
         command_template_on_session_end: args.on_session_end_template,
+        otp_command: user_config.otp_command.clone(),
         exit_after_ssh_session_ends: args.exit,
-    })?;
-    app.start()?;
+        exec: args.exec,
+        schedule: args.at,
+        schedule_emit: args.emit,
+        demo: args.demo,
+        notify_after: args
+            .notify_after
+            .or(user_config.notify_after)
+            .map(std::time::Duration::from_secs),
+        idle_timeout: args
+            .idle_timeout
+            .or(user_config.idle_timeout)
+            .map(std::time::Duration::from_secs),
+        lock_after: args
+            .lock_after
+            .or(user_config.lock_after)
+            .map(std::time::Duration::from_secs),
+        lock_passphrase: user_config.lock_passphrase.clone(),
+        title_template: Some(
+            args.title_template
+                .or(user_config.title_template.clone())
+                .unwrap_or_else(|| title::DEFAULT_TITLE_TEMPLATE.to_string()),
+        )
+        .filter(|template| !template.is_empty()),
+        launcher: args.launcher,
+        launcher_template: args.launcher_template,
+        control_socket: args.control_socket,
+        daemon_socket: if args.use_daemon || user_config.use_daemon {
+            daemon_socket.clone()
+        } else {
+            None
+        },
+        host_templates: user_config.host_templates.clone(),
+        tunnels: tunnel::Tunnel::parse_all(&user_config.tunnels)?,
+        socks_port: user_config.socks_port.unwrap_or(session::DEFAULT_SOCKS_PORT),
+        snippets: user_config.snippets.clone(),
+        snippet_template: args
+            .snippet_template
+            .or(user_config.snippet_template.clone())
+            .unwrap_or_else(|| snippet::DEFAULT_SNIPPET_TEMPLATE.to_string()),
+    };
+
+    match args.command {
+        Some(Command::Import(import)) => return run_import(import, &app_config),
+        Some(Command::HistoryConfig(history)) => return journal::run(history.show, history.restore),
+        Some(Command::Verify(verify)) => {
+            let hosts = ui::load_hosts(&app_config)?;
+            let config = app_config.single_config_file();
+            if !verify::run(&hosts, &verify.hosts, config.as_deref())? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Resolve(resolve)) => {
+            let files = ssh::expand_config_paths(&app_config.config_paths);
+            let resolution = resolve::resolve(&resolve::read(&files)?, &resolve.hostname);
+            print!("{}", resolve::format(&resolve.hostname, &resolution));
+            return Ok(());
+        }
+        Some(Command::RunMacro(run)) => return run_macro(&run, &app_config),
+        Some(Command::ExportCommands(export)) => return run_export_commands(&export, &app_config),
+        Some(Command::Stats) => {
+            print!("{}", history::format_stats(&history::ConnectionHistory::load(), schedule::now()));
+            return Ok(());
+        }
+        Some(Command::Man) => {
+            print!("{}", help::man_page(&cli()));
+            return Ok(());
+        }
+        Some(Command::Daemon) => return run_daemon(&app_config, daemon_socket),
+        Some(Command::Export(export)) => {
+            let content = exchange::export(&ui::load_hosts(&app_config)?, export.format)?;
+            match export.output {
+                Some(path) => std::fs::write(path, content)?,
+                None => print!("{content}"),
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if args.porcelain {
+        let hosts = ui::load_hosts(&app_config)?;
+        print!("{}", porcelain::format(&hosts, app_config.show_patterns, args.null));
+        return Ok(());
+    }
+
+    let mut app = App::new(&app_config)?;
+    let code = app.start()?;
+    // セッションの後始末 (SOCKS プロキシ、制御ソケット) は drop で行う
+    drop(app);
+    if code != 0 {
+        std::process::exit(code);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn run_daemon(app_config: &AppConfig, socket: Option<PathBuf>) -> Result<()> {
+    let socket = socket.ok_or(anyhow::anyhow!("Cannot determine the daemon socket path"))?;
+    // デーモン自身は設定ファイルを読む
+    let config = AppConfig {
+        daemon_socket: None,
+        ..app_config.clone()
+    };
+    daemon::run(&config, &socket)
+}
+
+#[cfg(not(unix))]
+fn run_daemon(_app_config: &AppConfig, _socket: Option<PathBuf>) -> Result<()> {
+    anyhow::bail!("sgh daemon is only supported on Unix")
+}
+
+fn run_macro(run: &RunMacroArgs, app_config: &AppConfig) -> Result<()> {
+    if app_config.demo {
+        anyhow::bail!("sgh run-macro does not run commands in demo mode");
+    }
+    let recorded = macros::Macros::load().get(&run.name)?;
+    let query = &recorded.steps.query;
+    let hosts = macros::targets(&ui::load_hosts(app_config)?, &app_config.search_fields, query);
+    if hosts.is_empty() {
+        anyhow::bail!("No host matches {query:?}");
+    }
+
+    // 検索はあいまい一致なので、実行前に対象を必ず見せる
+    println!("{} runs `{}` on the {} host(s) {query:?} matches:", recorded.name, recorded.steps.snippet.command, hosts.len());
+    for host in &hosts {
+        println!("  {}", host.name);
+    }
+    if !run.yes {
+        println!("Nothing was run; pass --yes to run it on these hosts.");
+        return Ok(());
+    }
+
+    let template = |host: &ssh::Host| ui::command_template(app_config, host);
+    if !macros::run(&recorded.steps, &hosts, template, app_config.shell)? {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn run_export_commands(export: &ExportCommandsArgs, app_config: &AppConfig) -> Result<()> {
+    let hosts = macros::targets(&ui::load_hosts(app_config)?, &app_config.search_fields, &export.query);
+    if hosts.is_empty() {
+        anyhow::bail!("No host matches {:?}", export.query);
+    }
+    let commands = ui::connect_commands(app_config, &hosts)?;
+    let Some(path) = &export.output else {
+        for command in commands {
+            println!("{command}");
+        }
+        return Ok(());
+    };
+
+    // 改行を含むクエリでコメントの外に行が書かれないよう、エスケープして載せる
+    let mut script = format!("#!/bin/sh\n# sgh export-commands {:?}\n", export.query);
+    for command in commands {
+        script.push_str(&command);
+        script.push('\n');
+    }
+    std::fs::write(path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+fn run_import(import: ImportArgs, app_config: &AppConfig) -> Result<()> {
+    if edit::is_read_only() {
+        anyhow::bail!("sgh import is disabled in read-only mode");
+    }
+    let target = std::path::PathBuf::from(shellexpand::tilde(&import.to).to_string());
+    let hosts = ui::load_hosts(app_config)?;
+
+    let shared = if let Some(path) = import.from_json {
+        exchange::from_json(&std::fs::read_to_string(path)?)?
+    } else if let Some(path) = import.from_termius {
+        exchange::from_termius_csv(&std::fs::read_to_string(path)?)?
+    } else {
+        let history_files = if import.history_file.is_empty() {
+            import::default_history_files()
+        } else {
+            import.history_file
+        };
+        return import::run_from_history(&history_files, &target, &hosts);
+    };
 
+    let (added, skipped) = exchange::import_hosts(&shared, &target, &hosts)?;
+    println!("Added {} host(s) to {}.", added.len(), target.display());
+    if !skipped.is_empty() {
+        println!("Skipped {} already defined: {}", skipped.len(), skipped.join(", "));
+    }
     Ok(())
 }
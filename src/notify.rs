@@ -0,0 +1,55 @@
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::session::format_duration;
+
+/// Shows a desktop notification through `notify-send` (Linux, BSD) or
+/// `osascript` (macOS). Failures are ignored: a missing notifier should not
+/// get in the way of the session itself.
+pub fn send(summary: &str, body: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {} with title {}",
+            applescript_string(body),
+            applescript_string(summary)
+        );
+        let mut command = Command::new("osascript");
+        command.args(["-e", &script]);
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args(["--app-name", "sgh", summary, body]);
+        command
+    };
+
+    let _ = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// Notifies that the session to `host_name` ended, when it lasted at least
+/// `threshold`.
+pub fn session_ended(host_name: &str, elapsed: Duration, threshold: Option<Duration>) {
+    if threshold.is_some_and(|threshold| elapsed >= threshold) {
+        send(
+            &format!("Session to {host_name} ended"),
+            &format!("after {}", format_duration(elapsed)),
+        );
+    }
+}
+
+fn applescript_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applescript_strings_are_escaped() {
+        assert_eq!(applescript_string(r#"say "hi" \ bye"#), r#""say \"hi\" \\ bye""#);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::ssh;
+
+/// Columns of `--porcelain` output, in order. Columns are only ever
+/// appended, so scripts that pick fields by position keep working.
+pub const COLUMNS: [&str; 9] = [
+    "name",
+    "aliases",
+    "user",
+    "hostname",
+    "port",
+    "proxy_jump",
+    "tags",
+    "source",
+    "note",
+];
+
+/// One line per host with the `COLUMNS` separated by tabs. Records end with
+/// `\n`, or with NUL when `null` is set (`--null`). Empty values are empty
+/// fields; lists are comma separated.
+#[must_use]
+pub fn format(hosts: &[ssh::Host], show_patterns: bool, null: bool) -> String {
+    let terminator = if null { '\0' } else { '\n' };
+
+    hosts
+        .iter()
+        .filter(|host| show_patterns || !host.is_pattern)
+        .map(|host| {
+            let source = match (&host.source_file, host.source_line) {
+                (Some(file), Some(line)) => format!("{file}:{line}"),
+                (Some(file), None) => file.clone(),
+                _ => String::new(),
+            };
+            let fields = [
+                host.name.clone(),
+                host.aliases.split(", ").filter(|a| !a.is_empty()).collect::<Vec<_>>().join(","),
+                host.user.clone().unwrap_or_default(),
+                host.destination.clone(),
+                host.port.clone().unwrap_or_default(),
+                host.proxy_jump.clone().unwrap_or_default(),
+                host.tags.join(","),
+                source,
+                host.note.clone().unwrap_or_default(),
+            ];
+            let mut record = fields.map(|field| sanitize(&field)).join("\t");
+            record.push(terminator);
+            record
+        })
+        .collect()
+}
+
+/// Separators inside a value would shift the columns.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if matches!(c, '\t' | '\n' | '\r' | '\0') { ' ' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_are_written_as_tab_separated_records() {
+        let hosts = vec![
+            ssh::Host {
+                name: "db".to_string(),
+                aliases: "db1, primary".to_string(),
+                destination: "10.0.0.5".to_string(),
+                port: Some("2222".to_string()),
+                tags: vec!["prod".to_string(), "sql".to_string()],
+                source_file: Some("~/.ssh/config".to_string()),
+                source_line: Some(4),
+                note: Some("do\tnot\nreboot".to_string()),
+                ..ssh::Host::default()
+            },
+            ssh::Host {
+                name: "*.internal".to_string(),
+                is_pattern: true,
+                ..ssh::Host::default()
+            },
+        ];
+
+        assert_eq!(
+            format(&hosts, false, false),
+            "db\tdb1,primary\t\t10.0.0.5\t2222\t\tprod,sql\t~/.ssh/config:4\tdo not reboot\n"
+        );
+        assert_eq!(format(&hosts, true, true).matches('\0').count(), 2);
+    }
+}
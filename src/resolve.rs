@@ -0,0 +1,227 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use crate::ssh::{self, OptionSource};
+use crate::ssh_config::{self, EntryType};
+
+/// What OpenSSH would apply to a hostname: the blocks that match it and the
+/// effective options, first value wins as in `ssh_config(5)`.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub blocks: Vec<AppliedBlock>,
+    /// Blocks that matched but were excluded by a `!pattern`.
+    pub negated: Vec<AppliedBlock>,
+    /// Keyword → value and where it was set, in keyword order.
+    pub options: BTreeMap<String, (String, OptionSource)>,
+    /// A `Match` line was seen in an applying block; sgh does not evaluate
+    /// those, so the result may be incomplete.
+    pub has_match: bool,
+}
+
+/// A `Host` block and the pattern that decided whether it applies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedBlock {
+    pub block: String,
+    pub location: Option<String>,
+    pub pattern: String,
+}
+
+/// A config file as ssh reads it: the settings outside any Host block and
+/// the blocks in file order.
+pub type Blocks = (ssh_config::Host, Vec<ssh_config::Host>);
+
+/// Reads the config `files` in the order ssh reads them: user files first,
+/// `/etc/ssh/ssh_config` last. Missing files are skipped.
+///
+/// # Errors
+///
+/// Will return `Err` if a file cannot be read or parsed.
+pub fn read(files: &[PathBuf]) -> Result<Vec<Blocks>> {
+    let mut files = files.to_vec();
+    files.sort_by_key(|path| path.as_os_str() == "/etc/ssh/ssh_config");
+
+    files
+        .iter()
+        .filter(|file| file.exists())
+        .map(|file| {
+            let mut reader = BufReader::new(File::open(file)?);
+            ssh_config::Parser::new()
+                .parse_blocks(&mut reader, Some(file))
+                .map_err(|err| anyhow!("Failed to parse {}: {err:?}", file.display()))
+        })
+        .collect()
+}
+
+/// Resolves `name` against `configs` the way ssh does: global settings and
+/// every matching block in order, the first value of each keyword wins.
+#[must_use]
+pub fn resolve(configs: &[Blocks], name: &str) -> Resolution {
+    // ssh はホスト名を小文字にしてから照合する
+    let name = name.trim().to_lowercase();
+    let mut resolution = Resolution::default();
+
+    for (global, blocks) in configs {
+        take_values(&mut resolution, global);
+        for block in blocks {
+            let applied = |pattern: &str| AppliedBlock {
+                block: format!("Host {}", block.get_patterns().join(" ")),
+                location: block
+                    .source
+                    .as_ref()
+                    .map(|(file, line)| format!("{}:{line}", file.display())),
+                pattern: pattern.to_string(),
+            };
+            match matching_pattern(block.get_patterns(), &name) {
+                Some(Ok(pattern)) => {
+                    resolution.blocks.push(applied(pattern));
+                    take_values(&mut resolution, block);
+                }
+                Some(Err(pattern)) => resolution.negated.push(applied(pattern)),
+                None => {}
+            }
+        }
+    }
+
+    resolution
+}
+
+/// Copies the options `block` declares itself that are not set yet.
+fn take_values(resolution: &mut Resolution, block: &ssh_config::Host) {
+    for (entry, value) in block.entries() {
+        let provenance = block.provenance(entry);
+        if provenance.is_some_and(|provenance| provenance.inherited) {
+            continue;
+        }
+        if *entry == EntryType::Match {
+            resolution.has_match = true;
+            continue;
+        }
+        let source = provenance.map_or_else(
+            || OptionSource {
+                block: "(global)".to_string(),
+                location: None,
+                inherited: false,
+            },
+            OptionSource::from,
+        );
+        resolution
+            .options
+            .entry(entry.keyword())
+            .or_insert_with(|| (value.clone(), source));
+    }
+}
+
+/// `Some(Ok(pattern))` when a pattern matches `name` and none of the
+/// negated ones do, `Some(Err(pattern))` when a negated pattern excludes it.
+fn matching_pattern<'a>(patterns: &'a [String], name: &str) -> Option<Result<&'a str, &'a str>> {
+    if let Some(negated) = patterns
+        .iter()
+        .find(|pattern| pattern.strip_prefix('!').is_some_and(|p| glob_matches(p, name)))
+    {
+        return Some(Err(negated));
+    }
+    patterns
+        .iter()
+        .find(|pattern| !pattern.starts_with('!') && glob_matches(pattern, name))
+        .map(|pattern| Ok(pattern.as_str()))
+}
+
+/// `ssh_config` pattern matching: `*` is any run of characters, `?` exactly
+/// one, everything else literal and case-insensitive.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => (0..=text.len()).any(|skip| matches(rest, &text[skip..])),
+            Some(('?', rest)) => !text.is_empty() && matches(rest, &text[1..]),
+            Some((c, rest)) => text
+                .split_first()
+                .is_some_and(|(t, text)| c.eq_ignore_ascii_case(t) && matches(rest, text)),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches(&pattern, &text)
+}
+
+/// `sgh resolve <name>` output.
+#[must_use]
+pub fn format(name: &str, resolution: &Resolution) -> String {
+    let mut text = String::new();
+    if resolution.blocks.is_empty() {
+        let _ = writeln!(text, "No Host block matches {name}.");
+    }
+    for applied in &resolution.blocks {
+        let _ = writeln!(text, "{}  ({} matches){}", applied.block, applied.pattern, location(applied));
+    }
+    for applied in &resolution.negated {
+        let _ = writeln!(text, "{}  (excluded by {}){}", applied.block, applied.pattern, location(applied));
+    }
+    if resolution.has_match {
+        text.push_str("Match blocks are not evaluated; the result may be incomplete.\n");
+    }
+
+    if !resolution.options.is_empty() {
+        text.push('\n');
+        let width = resolution.options.keys().map(String::len).max().unwrap_or(0);
+        for (keyword, (value, source)) in &resolution.options {
+            let _ = writeln!(text, "{keyword:width$}  {value}  ← {}", source.describe());
+        }
+    }
+    text
+}
+
+fn location(applied: &AppliedBlock) -> String {
+    applied
+        .location
+        .as_ref()
+        .map(|location| format!("  {}", ssh::contract_home(location)))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_patterns_are_matched_like_openssh() {
+        assert!(glob_matches("*.internal", "db.internal"));
+        assert!(glob_matches("web-??", "WEB-01"));
+        assert!(!glob_matches("web-??", "web-1"));
+        assert!(!glob_matches("*.internal", "internal"));
+
+        let patterns = vec!["*.internal".to_string(), "!bastion.internal".to_string()];
+        assert_eq!(matching_pattern(&patterns, "db.internal"), Some(Ok("*.internal")));
+        assert_eq!(matching_pattern(&patterns, "bastion.internal"), Some(Err("!bastion.internal")));
+        assert_eq!(matching_pattern(&patterns, "example.com"), None);
+    }
+
+    #[test]
+    fn the_first_value_wins() {
+        let path = std::env::temp_dir().join(format!("sgh-resolve-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "User everyone\nHost db.internal\n  Port 2222\nHost *.internal !bastion.internal\n  Port 22\n  ProxyJump bastion\n  User admin\nHost bastion.internal\n  Port 2200\n",
+        )
+        .unwrap();
+
+        let configs = read(std::slice::from_ref(&path)).unwrap();
+        let resolution = resolve(&configs, "DB.internal");
+        let blocks: Vec<&str> = resolution.blocks.iter().map(|b| b.block.as_str()).collect();
+        assert_eq!(blocks, ["Host db.internal", "Host *.internal !bastion.internal"]);
+        assert_eq!(resolution.options["port"].0, "2222");
+        assert_eq!(resolution.options["proxyjump"].0, "bastion");
+        assert_eq!(resolution.options["user"].0, "everyone");
+        assert_eq!(resolution.options["user"].1.block, "(global)");
+
+        let resolution = resolve(&configs, "bastion.internal");
+        assert_eq!(resolution.negated.len(), 1);
+        assert_eq!(resolution.options["port"].0, "2200");
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
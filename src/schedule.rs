@@ -0,0 +1,260 @@
+use anyhow::Result;
+use std::fmt;
+use std::io::Write;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::journal;
+use crate::session::format_duration;
+
+/// When the selected command runs (`--at`): the next time the local clock
+/// shows `HH:MM`, or after a delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum When {
+    At { hour: u64, minute: u64 },
+    After(Duration),
+}
+
+impl FromStr for When {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected HH:MM or a delay like +15m, 90s or 1h30m, got {value:?}");
+        if let Some((hour, minute)) = value.split_once(':') {
+            let hour = hour.parse().ok().filter(|&h| h < 24).ok_or_else(invalid)?;
+            let minute = minute.parse().ok().filter(|&m| m < 60 && minute.len() == 2).ok_or_else(invalid)?;
+            return Ok(When::At { hour, minute });
+        }
+        parse_delay(value.strip_prefix('+').unwrap_or(value)).map(When::After).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for When {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            When::At { hour, minute } => write!(f, "at {hour:02}:{minute:02}"),
+            When::After(delay) => write!(f, "in {}", format_duration(*delay)),
+        }
+    }
+}
+
+impl When {
+    /// Time left from `now` (Unix seconds) with the local clock `offset`
+    /// seconds ahead of UTC. A time that has passed today is tomorrow's.
+    #[must_use]
+    pub fn delay(self, now: u64, offset: i64) -> Duration {
+        match self {
+            When::After(delay) => delay,
+            When::At { hour, minute } => {
+                let local = (i64::try_from(now).unwrap_or_default() + offset).rem_euclid(86_400);
+                let target = i64::try_from(hour * 3600 + minute * 60).unwrap_or_default();
+                Duration::from_secs((target - local).rem_euclid(86_400).unsigned_abs())
+            }
+        }
+    }
+
+    /// Unix time `when` comes after `now`, with the local clock offset
+    /// `offset_at` gives for a Unix time. A clock time is worked out with
+    /// the offset in effect at the target, so a daylight saving change in
+    /// between does not move it by an hour.
+    #[must_use]
+    pub fn due(self, now: u64, offset_at: impl Fn(u64) -> i64) -> u64 {
+        match self {
+            When::After(delay) => now + delay.as_secs(),
+            When::At { .. } => {
+                let guess = now + self.delay(now, offset_at(now)).as_secs();
+                now + self.delay(now, offset_at(guess)).as_secs()
+            }
+        }
+    }
+}
+
+/// `90s`, `15m`, `2h` or combinations such as `1h30m`.
+fn parse_delay(text: &str) -> Option<Duration> {
+    let mut secs = 0;
+    let mut digits = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            _ => return None,
+        };
+        secs += digits.parse::<u64>().ok()? * unit;
+        digits.clear();
+    }
+    (digits.is_empty() && !text.is_empty()).then(|| Duration::from_secs(secs))
+}
+
+/// Scheduler a `--at` run is handed to with `--emit`, instead of sgh
+/// waiting for it.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Emit {
+    /// A `systemd-run --user --on-calendar` transient timer.
+    SystemdRun,
+    /// A crontab line.
+    Cron,
+}
+
+/// Seconds the local clock is ahead of UTC at the Unix time `time`, from
+/// `date +%z` (GNU `-d @time`, BSD `-r time`); UTC when neither works.
+#[must_use]
+pub fn local_offset_at(time: u64) -> i64 {
+    let run = |args: &[&str]| {
+        Command::new("date")
+            .args(args)
+            .arg("+%z")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+    };
+    let Some(output) = run(&["-d", &format!("@{time}")]).or_else(|| run(&["-r", &time.to_string()])) else {
+        return 0;
+    };
+    let text = String::from_utf8_lossy(&output.stdout);
+    let text = text.trim();
+    let (sign, digits) = match text.split_at_checked(1) {
+        Some(("-", digits)) => (-1, digits),
+        Some(("+", digits)) => (1, digits),
+        _ => return 0,
+    };
+    match (digits.get(..2).and_then(|h| h.parse::<i64>().ok()), digits.get(2..4).and_then(|m| m.parse::<i64>().ok())) {
+        (Some(hours), Some(minutes)) => sign * (hours * 3600 + minutes * 60),
+        _ => 0,
+    }
+}
+
+#[must_use]
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// The scheduler entry that runs `command` once `when` comes, as a line to
+/// paste into a shell or crontab. `offset_at` gives the local clock offset
+/// for a Unix time. A due time between minutes is rounded up to the next
+/// one, since the schedulers only go down to the minute and running early
+/// is worse than a little late.
+#[must_use]
+pub fn entry(emit: Emit, when: When, command: &str, now: u64, offset_at: impl Fn(u64) -> i64) -> String {
+    let due = when.due(now, &offset_at);
+    let local = (i64::try_from(due).unwrap_or_default() + offset_at(due)).unsigned_abs();
+    let (year, month, day, minutes) = journal::civil(local.div_ceil(60) * 60);
+    let (hour, minute) = (minutes / 60, minutes % 60);
+    match emit {
+        Emit::SystemdRun => {
+            let quoted = shlex::try_quote(command).map_or_else(|_| command.to_string(), |quoted| quoted.into_owned());
+            format!(
+                "systemd-run --user --on-calendar='{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:00' /bin/sh -c {quoted}"
+            )
+        }
+        // cron に一回限りの指定はないので、日付まで固定して実行後に消してもらう
+        // (cron はコマンド中の % を改行として扱うので \% にする)
+        Emit::Cron => format!(
+            "# sgh: runs every year on this date; remove it after it ran\n{minute} {hour} {day} {month} * {}",
+            command.replace('%', "\\%")
+        ),
+    }
+}
+
+/// Waits out `delay` with a countdown on one line, before running `what`.
+/// Returns `false` when Ctrl+C cancelled the wait.
+///
+/// # Errors
+///
+/// Will return `Err` if the Ctrl+C handler cannot be installed.
+pub fn wait(delay: Duration, what: &str) -> Result<bool> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    let id = signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&cancelled))?;
+
+    let deadline = Instant::now() + delay;
+    let mut stdout = std::io::stdout();
+    while !cancelled.load(Ordering::Relaxed) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        // 行末に残りが出ないよう、前の表示より短くなっても空白で消す
+        write!(stdout, "\r[sgh] {what} in {}  (Ctrl+C cancels)    ", format_duration(left))?;
+        stdout.flush()?;
+        std::thread::sleep(left.min(Duration::from_millis(250)));
+    }
+    writeln!(stdout)?;
+
+    #[cfg(unix)]
+    signal_hook::low_level::unregister(id);
+    Ok(!cancelled.load(Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_and_delays_are_parsed() {
+        assert_eq!("02:00".parse(), Ok(When::At { hour: 2, minute: 0 }));
+        assert_eq!("+15m".parse(), Ok(When::After(Duration::from_secs(900))));
+        assert_eq!("1h30m".parse(), Ok(When::After(Duration::from_secs(5400))));
+        for invalid in ["24:00", "2:5", "15", "15x", "+"] {
+            assert!(invalid.parse::<When>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn a_time_that_has_passed_is_tomorrow() {
+        // 2024-02-29 12:34:56 UTC
+        let now = 1_709_210_096;
+        let at = |hour, minute| When::At { hour, minute };
+        assert_eq!(at(13, 0).delay(now, 0), Duration::from_secs(25 * 60 + 4));
+        assert_eq!(at(12, 0).delay(now, 0).as_secs(), 86_400 - 34 * 60 - 56);
+        // UTC+9 では 21:34 なので 22:00 まではあと 25 分ほど
+        assert_eq!(at(22, 0).delay(now, 9 * 3600), Duration::from_secs(25 * 60 + 4));
+    }
+
+    #[test]
+    fn emitted_entries_fire_once_at_the_local_time() {
+        let now = 1_709_210_096;
+        let when = When::At { hour: 2, minute: 0 };
+        assert_eq!(
+            entry(Emit::SystemdRun, when, "ssh -t web uptime", now, |_| 9 * 3600),
+            "systemd-run --user --on-calendar='2024-03-01 02:00:00' /bin/sh -c 'ssh -t web uptime'"
+        );
+        assert!(entry(Emit::Cron, when, "ssh -t web uptime", now, |_| 0).ends_with("\n0 2 1 3 * ssh -t web uptime"));
+        assert!(entry(Emit::Cron, when, "ssh web 'date +%F'", now, |_| 0).ends_with("\n0 2 1 3 * ssh web 'date +\\%F'"));
+    }
+
+    #[test]
+    fn entries_round_up_to_the_minute() {
+        // 12:34:56 + 90 秒 = 12:36:26 なので、早すぎない 12:37 に
+        let now = 1_709_210_096;
+        let when = When::After(Duration::from_secs(90));
+        assert!(entry(Emit::Cron, when, "uptime", now, |_| 0).ends_with("\n37 12 29 2 * uptime"));
+        let on_the_minute = When::After(Duration::from_secs(64));
+        assert!(entry(Emit::Cron, on_the_minute, "uptime", now, |_| 0).ends_with("\n36 12 29 2 * uptime"));
+    }
+
+    #[test]
+    fn clock_times_use_the_offset_at_the_target() {
+        // 13:04:56 UTC に UTC+1 から UTC+0 へ戻る (夏時間の終わり)
+        let now = 1_709_210_096;
+        let offset_at = |time: u64| if time < now + 1800 { 3600 } else { 0 };
+        let when = When::At { hour: 22, minute: 0 };
+        // 今の UTC+1 のまま計算すると 21:00 UTC (その時点の時計では 21:00) になる
+        assert_eq!(when.due(now, offset_at), now + 9 * 3600 + 25 * 60 + 4);
+        assert!(entry(Emit::Cron, when, "uptime", now, offset_at).ends_with("\n0 22 29 2 * uptime"));
+        assert_eq!(When::After(Duration::from_secs(60)).due(now, offset_at), now + 60);
+    }
+
+    #[test]
+    fn the_local_offset_is_read_from_date() {
+        // サンドボックスや CI の TZ に依らず、分単位の値になる
+        assert_eq!(local_offset_at(0) % 60, 0);
+    }
+}
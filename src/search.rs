@@ -0,0 +1,786 @@
+use anyhow::Result;
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::{ssh, store};
+
+/// Host attributes the search query can be matched against.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SearchField {
+    Name,
+    Aliases,
+    Destination,
+    User,
+    Port,
+    Proxy,
+    Forwards,
+    Tags,
+    Note,
+    /// Kind of destination (`ip`, `ipv4`, `ipv6`, `fqdn`, `name`), only
+    /// reachable through `is:` terms and rules.
+    #[value(skip)]
+    Kind,
+}
+
+impl SearchField {
+    /// Fields searched when `--search-fields` is not given.
+    pub const DEFAULT: [SearchField; 3] = [
+        SearchField::Name,
+        SearchField::Destination,
+        SearchField::Aliases,
+    ];
+
+    /// Name used by `--search-fields`, e.g. `destination`.
+    #[must_use]
+    pub fn name(self) -> String {
+        use clap::ValueEnum;
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    /// The field after `focus` when cycling the column focus with Ctrl+R;
+    /// `None` stands for every configured field.
+    #[must_use]
+    pub fn cycle(focus: Option<SearchField>) -> Option<SearchField> {
+        use clap::ValueEnum;
+        let all = SearchField::value_variants();
+        match focus {
+            None => all.first().copied(),
+            Some(field) => all
+                .iter()
+                .position(|f| *f == field)
+                .and_then(|i| all.get(i + 1))
+                .copied(),
+        }
+    }
+
+    /// Field selected by a `<qualifier>:value` query term, e.g. `tag:prod`.
+    #[must_use]
+    pub fn from_qualifier(qualifier: &str) -> Option<SearchField> {
+        match qualifier.to_lowercase().as_str() {
+            "name" => Some(SearchField::Name),
+            "alias" | "aliases" => Some(SearchField::Aliases),
+            "dest" | "destination" => Some(SearchField::Destination),
+            "user" => Some(SearchField::User),
+            "port" => Some(SearchField::Port),
+            "proxy" => Some(SearchField::Proxy),
+            "fwd" | "forward" | "forwards" => Some(SearchField::Forwards),
+            "tag" | "tags" => Some(SearchField::Tags),
+            "note" => Some(SearchField::Note),
+            "is" => Some(SearchField::Kind),
+            _ => None,
+        }
+    }
+
+    /// The strings of `host` this field contributes to matching.
+    #[must_use]
+    pub fn values(self, host: &ssh::Host) -> Vec<String> {
+        match self {
+            SearchField::Name => vec![host.name.clone()],
+            SearchField::Aliases => vec![host.aliases.clone()],
+            SearchField::Destination => vec![host.destination.clone()],
+            SearchField::User => host.user.iter().cloned().collect(),
+            SearchField::Port => host.port.iter().cloned().collect(),
+            SearchField::Proxy => host
+                .proxy_command
+                .iter()
+                .chain(host.proxy_jump.iter())
+                .cloned()
+                .collect(),
+            SearchField::Forwards => host.forward_specs(),
+            SearchField::Tags => host.tags.clone(),
+            SearchField::Note => host.note.iter().cloned().collect(),
+            SearchField::Kind => host
+                .destination_kind()
+                .map(|kind| kind.names().iter().map(ToString::to_string).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Host attributes the list can be sorted by (`--sort-by tags,name`).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    Name,
+    Tags,
+    User,
+    Destination,
+    Port,
+}
+
+impl SortKey {
+    /// Name used by `--sort-by`, e.g. `tags`.
+    #[must_use]
+    pub fn name(self) -> String {
+        use clap::ValueEnum;
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Compares two hosts by this key alone. Hosts without a value (no tags,
+    /// no user, no port) come after those with one. Text is compared
+    /// ignoring case, in natural order (`web2` before `web10`) unless
+    /// `natural` is off.
+    #[must_use]
+    pub fn compare(self, a: &ssh::Host, b: &ssh::Host, natural: bool) -> Ordering {
+        fn missing_last<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (a, b) => a.is_none().cmp(&b.is_none()),
+            }
+        }
+        let text = |value: &String| SortText {
+            text: value.to_lowercase(),
+            natural,
+        };
+
+        match self {
+            SortKey::Name => text(&a.name).cmp(&text(&b.name)),
+            SortKey::Tags => missing_last(a.tags.first().map(text), b.tags.first().map(text)),
+            SortKey::User => missing_last(a.user.as_ref().map(text), b.user.as_ref().map(text)),
+            SortKey::Destination => text(&a.destination).cmp(&text(&b.destination)),
+            SortKey::Port => missing_last(a.port_number(), b.port_number()),
+        }
+    }
+}
+
+/// Text compared either naturally or byte by byte.
+#[derive(PartialEq, Eq)]
+struct SortText {
+    text: String,
+    natural: bool,
+}
+
+impl Ord for SortText {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self.natural {
+            natural_cmp(&self.text, &other.text)
+        } else {
+            self.text.cmp(&other.text)
+        }
+    }
+}
+
+impl PartialOrd for SortText {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compares runs of digits by their numeric value and everything else
+/// character by character, so `web2` < `web10` and `10.0.0.9` < `10.0.0.10`.
+/// Equal numbers with more leading zeros sort later (`web1` < `web01`).
+#[must_use]
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(ca), Some(cb)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            let (run_a, rest_a) = a.split_at(digits(a));
+            let (run_b, rest_b) = b.split_at(digits(b));
+            let (value_a, value_b) = (run_a.trim_start_matches('0'), run_b.trim_start_matches('0'));
+            // 桁数が多い方が大きい。同じなら辞書順が数値順になる
+            let ordering = value_a
+                .len()
+                .cmp(&value_b.len())
+                .then_with(|| value_a.cmp(value_b))
+                .then_with(|| run_a.len().cmp(&run_b.len()));
+            if ordering.is_ne() {
+                return ordering;
+            }
+            (a, b) = (rest_a, rest_b);
+        } else {
+            if ca != cb {
+                return ca.cmp(&cb);
+            }
+            (a, b) = (&a[ca.len_utf8()..], &b[cb.len_utf8()..]);
+        }
+    }
+}
+
+/// Host attribute the list can be grouped by (`--group-by`).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupKey {
+    /// The first tag.
+    Tag,
+    /// The config file the host is defined in.
+    File,
+    User,
+    /// The `ProxyJump` host.
+    Proxy,
+}
+
+impl GroupKey {
+    /// The group `host` belongs to; `None` for hosts without a value.
+    #[must_use]
+    pub fn value(self, host: &ssh::Host) -> Option<String> {
+        match self {
+            GroupKey::Tag => host.tags.first().cloned(),
+            GroupKey::File => host.source_file.as_deref().map(ssh::contract_home),
+            GroupKey::User => host.user.clone(),
+            GroupKey::Proxy => host.proxy_jump.clone(),
+        }
+    }
+
+    /// Header of the group of hosts without a value.
+    #[must_use]
+    pub fn missing_label(self) -> &'static str {
+        match self {
+            GroupKey::Tag => "(untagged)",
+            GroupKey::File => "(no file)",
+            GroupKey::User => "(no user)",
+            GroupKey::Proxy => "(direct)",
+        }
+    }
+
+    /// Orders groups naturally by value, the group without a value last.
+    #[must_use]
+    pub fn compare(self, a: &ssh::Host, b: &ssh::Host) -> Ordering {
+        match (self.value(a), self.value(b)) {
+            (Some(a), Some(b)) => natural_cmp(&a.to_lowercase(), &b.to_lowercase()),
+            (a, b) => a.is_none().cmp(&b.is_none()),
+        }
+    }
+}
+
+/// Sorts `hosts` by `keys` in order, the later keys breaking ties of the
+/// earlier ones. The sort is stable: hosts equal on every key keep their
+/// config order, so the list does not reshuffle when it is reloaded.
+pub fn sort_hosts(hosts: &mut [ssh::Host], keys: &[SortKey], natural: bool) {
+    if keys.is_empty() {
+        return;
+    }
+    hosts.sort_by(|a, b| {
+        keys.iter()
+            .map(|key| key.compare(a, b, natural))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+/// A parsed search bar value. `field:value` terms narrow the result to hosts
+/// whose field contains the value (tags must match exactly); the remaining
+/// text is fuzzy-matched against the configured search fields.
+#[derive(Debug, Default, PartialEq)]
+pub struct Query {
+    pub text: String,
+    pub terms: Vec<(SearchField, String)>,
+}
+
+impl Query {
+    #[must_use]
+    pub fn parse(input: &str) -> Query {
+        let mut words = Vec::new();
+        let mut terms = Vec::new();
+
+        for word in input.split_whitespace() {
+            let qualified = word
+                .split_once(':')
+                .and_then(|(key, value)| Some((SearchField::from_qualifier(key)?, value)));
+            match qualified {
+                // 値が空 (入力途中の "tag:") の間は絞り込まない
+                Some((_, "")) => {}
+                Some((field, value)) => terms.push((field, value.to_lowercase())),
+                None => words.push(word),
+            }
+        }
+
+        // 修飾子がなければ従来どおり入力全体をそのままファジー検索する
+        let text = if terms.is_empty() && words.len() == input.split_whitespace().count() {
+            input.to_string()
+        } else {
+            words.join(" ")
+        };
+
+        Query { text, terms }
+    }
+
+    fn term_matches(host: &ssh::Host, field: SearchField, value: &str) -> bool {
+        let values = field.values(host);
+        match field {
+            SearchField::Tags | SearchField::Kind => values.iter().any(|tag| tag.to_lowercase() == value),
+            _ => values.iter().any(|v| v.to_lowercase().contains(value)),
+        }
+    }
+}
+
+/// The query last parsed from the search bar, so filtering and ranking
+/// parse the input once per search rather than once per host.
+#[derive(Default)]
+pub struct ParsedQuery {
+    input: String,
+    query: Query,
+}
+
+impl ParsedQuery {
+    /// The query for `input`, parsed again only when the input changed.
+    pub fn get(&mut self, input: &str) -> &Query {
+        if self.input != input {
+            self.query = Query::parse(input);
+            self.input = input.to_string();
+        }
+        &self.query
+    }
+}
+
+/// One separately removable part of a query: a qualified term such as
+/// `tag:prod`, or all of the free text together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chip {
+    pub label: String,
+    /// Indexes of the whitespace-separated words of the input it covers.
+    words: Vec<usize>,
+}
+
+impl Chip {
+    /// The parts of `input`, qualified terms first in input order, then the
+    /// free text. Empty for a plain query without qualifiers.
+    #[must_use]
+    pub fn parse_all(input: &str) -> Vec<Chip> {
+        let mut chips = Vec::new();
+        let mut text = Vec::new();
+        for (i, word) in input.split_whitespace().enumerate() {
+            let is_term = word
+                .split_once(':')
+                .is_some_and(|(key, value)| !value.is_empty() && SearchField::from_qualifier(key).is_some());
+            if is_term {
+                chips.push(Chip {
+                    label: word.to_string(),
+                    words: vec![i],
+                });
+            } else {
+                text.push((i, word));
+            }
+        }
+
+        // 修飾子のない素の検索はチップにしない
+        if chips.is_empty() {
+            return chips;
+        }
+        if !text.is_empty() {
+            chips.push(Chip {
+                label: format!("\"{}\"", text.iter().map(|(_, w)| *w).collect::<Vec<_>>().join(" ")),
+                words: text.iter().map(|(i, _)| *i).collect(),
+            });
+        }
+        chips
+    }
+
+    /// `input` without the words of this chip.
+    #[must_use]
+    pub fn remove_from(&self, input: &str) -> String {
+        input
+            .split_whitespace()
+            .enumerate()
+            .filter(|(i, _)| !self.words.contains(i))
+            .map(|(_, word)| word)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Whether `host` satisfies every qualified term of `query` and, when there is
+/// free text, any of `fields` fuzzy-matches it.
+#[must_use]
+pub fn matches(matcher: &SkimMatcherV2, host: &ssh::Host, fields: &[SearchField], query: &Query) -> bool {
+    query
+        .terms
+        .iter()
+        .all(|(field, value)| Query::term_matches(host, *field, value))
+        && (query.text.is_empty()
+            || fields
+                .iter()
+                .flat_map(|field| field.values(host))
+                .any(|value| matcher.fuzzy_match(&value, &query.text).is_some()))
+}
+
+/// Best fuzzy score of the free text of `query` over `fields`, or 0 when
+/// there is no free text.
+#[must_use]
+pub fn score(matcher: &SkimMatcherV2, host: &ssh::Host, fields: &[SearchField], query: &Query) -> i64 {
+    if query.text.is_empty() {
+        return 0;
+    }
+
+    fields
+        .iter()
+        .flat_map(|field| field.values(host))
+        .filter_map(|value| matcher.fuzzy_match(&value, &query.text))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Hosts that almost match `text` when nothing does: those with a value of
+/// `fields` containing it with a few typos (about one per three characters;
+/// a swapped pair counts as one), closest first, at most `limit`.
+#[must_use]
+pub fn near_misses<'a>(
+    hosts: impl IntoIterator<Item = &'a ssh::Host>,
+    fields: &[SearchField],
+    text: &str,
+    limit: usize,
+) -> Vec<&'a ssh::Host> {
+    let text: Vec<char> = text.trim().to_lowercase().chars().collect();
+    if text.len() < 2 {
+        return Vec::new();
+    }
+    let allowed = (text.len() / 3).max(1);
+
+    let mut misses: Vec<(usize, &ssh::Host)> = hosts
+        .into_iter()
+        .filter(|host| !host.is_pattern)
+        .filter_map(|host| {
+            let distance = fields
+                .iter()
+                .flat_map(|field| field.values(host))
+                .map(|value| typo_distance(&text, &value.to_lowercase().chars().collect::<Vec<_>>()))
+                .min()?;
+            (distance <= allowed).then_some((distance, host))
+        })
+        .collect();
+    // 同じ距離なら一覧の順のまま
+    misses.sort_by_key(|(distance, _)| *distance);
+    misses.into_iter().take(limit).map(|(_, host)| host).collect()
+}
+
+/// Fewest single-character edits (insert, delete, replace, swap two
+/// neighbours) that make `pattern` appear somewhere in `text`.
+fn typo_distance(pattern: &[char], text: &[char]) -> usize {
+    // rows[i][j]: pattern[..i] を text[..j] の末尾に合わせる最小の編集数 (開始位置は自由)
+    let mut rows = vec![vec![0; text.len() + 1]; pattern.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for i in 1..=pattern.len() {
+        for j in 1..=text.len() {
+            let cost = usize::from(pattern[i - 1] != text[j - 1]);
+            let mut best = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && pattern[i - 1] == text[j - 2] && pattern[i - 2] == text[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[pattern.len()].iter().copied().min().unwrap_or(pattern.len())
+}
+
+/// Score added for a host connected to `count` times. Grows logarithmically so
+/// a daily host outranks a rarely used one with a similar match without
+/// burying a clearly better match; `weight` 0 disables it.
+#[must_use]
+pub fn frequency_bonus(count: u32, weight: f64) -> i64 {
+    #[allow(clippy::cast_possible_truncation)]
+    let bonus = (weight * 10.0 * f64::from(count).ln_1p()).round() as i64;
+    bonus
+}
+
+/// Named queries saved from the search bar, persisted in the data directory.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SavedFilters(BTreeMap<String, String>);
+
+impl SavedFilters {
+    const FILE_NAME: &'static str = "filters.json";
+
+    #[must_use]
+    pub fn load() -> SavedFilters {
+        store::load(Self::FILE_NAME)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the filters cannot be written.
+    pub fn save(&self) -> Result<()> {
+        store::save(Self::FILE_NAME, self)
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, name: String, query: String) {
+        self.0.insert(name, query);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.remove(name);
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Filters sorted by name, as `(name, query)`.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+/// Previously used queries, newest first, persisted across sessions.
+#[derive(Default, Serialize, Deserialize)]
+pub struct QueryHistory {
+    entries: Vec<String>,
+}
+
+impl QueryHistory {
+    const FILE_NAME: &'static str = "search_history.json";
+    const MAX_ENTRIES: usize = 100;
+
+    #[must_use]
+    pub fn load() -> QueryHistory {
+        store::load(Self::FILE_NAME)
+    }
+
+    /// # Errors
+    ///
+    /// Will return `Err` if the history cannot be written.
+    pub fn save(&self) -> Result<()> {
+        store::save(Self::FILE_NAME, self)
+    }
+
+    /// Records `query` as the most recent entry, dropping older duplicates.
+    pub fn push(&mut self, query: &str) {
+        let query = query.trim();
+        if query.is_empty() {
+            return;
+        }
+
+        self.entries.retain(|entry| entry != query);
+        self.entries.insert(0, query.to_string());
+        self.entries.truncate(Self::MAX_ENTRIES);
+    }
+
+    /// Entry `index` steps back in time (0 is the most recent).
+    #[must_use]
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(name: &str, destination: &str, tags: &[&str]) -> ssh::Host {
+        ssh::Host {
+            name: name.to_string(),
+            destination: destination.to_string(),
+            tags: tags.iter().map(|t| (*t).to_string()).collect(),
+            ..ssh::Host::default()
+        }
+    }
+
+    #[test]
+    fn later_sort_keys_break_ties_and_config_order_breaks_the_rest() {
+        let mut hosts = vec![
+            host("web-b", "10.0.0.2", &["prod"]),
+            host("scratch", "10.0.0.9", &[]),
+            host("db", "10.0.0.3", &["prod"]),
+            host("web-a", "10.0.0.1", &["dev"]),
+            host("box", "10.0.0.4", &[]),
+        ];
+        sort_hosts(&mut hosts, &[SortKey::Tags, SortKey::Name], true);
+        let names: Vec<&str> = hosts.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, ["web-a", "db", "web-b", "box", "scratch"]);
+
+        // タグだけなら同じタグの中は元の順のまま
+        let mut hosts = vec![host("b", "", &["x"]), host("a", "", &["x"]), host("c", "", &[])];
+        sort_hosts(&mut hosts, &[SortKey::Tags], true);
+        let names: Vec<&str> = hosts.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn numbers_in_names_sort_by_value() {
+        let mut names = vec!["web10", "web2", "web01", "web1", "db", "10.0.0.10", "10.0.0.9", "web"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["10.0.0.9", "10.0.0.10", "db", "web", "web1", "web01", "web2", "web10"]);
+
+        let mut hosts = vec![host("web10", "", &[]), host("web2", "", &[])];
+        sort_hosts(&mut hosts, &[SortKey::Name], false);
+        assert_eq!(hosts[0].name, "web10");
+        sort_hosts(&mut hosts, &[SortKey::Name], true);
+        assert_eq!(hosts[0].name, "web2");
+    }
+
+    #[test]
+    fn plain_query_is_kept_verbatim() {
+        let query = Query::parse("web 01");
+        assert_eq!(query.text, "web 01");
+        assert!(query.terms.is_empty());
+    }
+
+    #[test]
+    fn chips_can_be_removed_one_at_a_time() {
+        let input = "tag:prod web user:root 01 tag:";
+        let chips = Chip::parse_all(input);
+        let labels: Vec<_> = chips.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, vec!["tag:prod", "user:root", "\"web 01 tag:\""]);
+
+        assert_eq!(chips[0].remove_from(input), "web user:root 01 tag:");
+        assert_eq!(chips[2].remove_from(input), "tag:prod user:root");
+        assert!(Chip::parse_all("web 01").is_empty());
+    }
+
+    #[test]
+    fn qualified_terms_are_split_from_text() {
+        let query = Query::parse("tag:prod web dest:EU- unknown:x");
+        assert_eq!(query.text, "web unknown:x");
+        assert_eq!(
+            query.terms,
+            vec![
+                (SearchField::Tags, "prod".to_string()),
+                (SearchField::Destination, "eu-".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn is_terms_filter_by_the_kind_of_destination() {
+        let matcher = SkimMatcherV2::default();
+        let fields = SearchField::DEFAULT;
+        let hosts = [
+            host("a", "10.0.0.1", &[]),
+            host("b", "[fe80::1%eth0]", &[]),
+            host("c", "db.example.com", &[]),
+            host("d", "db", &[]),
+        ];
+        let names = |query: &str| -> Vec<&str> {
+            let query = Query::parse(query);
+            hosts
+                .iter()
+                .filter(|h| matches(&matcher, h, &fields, &query))
+                .map(|h| h.name.as_str())
+                .collect()
+        };
+        assert_eq!(names("is:ip"), ["a", "b"]);
+        assert_eq!(names("is:IPv6"), ["b"]);
+        assert_eq!(names("is:fqdn"), ["c"]);
+        assert_eq!(names("is:name"), ["d"]);
+        // 前方一致はしない
+        assert!(names("is:i").is_empty());
+    }
+
+    #[test]
+    fn near_misses_allow_a_typo_per_three_characters() {
+        assert_eq!(typo_distance(&['w', 'b', 'e'], &['w', 'e', 'b', '1']), 1);
+        assert_eq!(typo_distance(&['d', 'b'], &"my-db-1".chars().collect::<Vec<_>>()), 0);
+
+        let hosts = [
+            host("web1", "10.0.0.1", &[]),
+            host("db", "db.example.com", &[]),
+            host("stagign", "10.0.0.3", &[]),
+        ];
+        let names = |text: &str| -> Vec<&str> {
+            near_misses(&hosts, &SearchField::DEFAULT, text, 5)
+                .iter()
+                .map(|h| h.name.as_str())
+                .collect()
+        };
+        assert_eq!(names("wbe"), ["web1"]);
+        assert_eq!(names("staging"), ["stagign"]);
+        assert_eq!(names("exmaple"), ["db"]);
+        assert!(names("zzz").is_empty());
+        assert!(names("w").is_empty());
+    }
+
+    #[test]
+    fn terms_and_text_must_all_match() {
+        let matcher = SkimMatcherV2::default();
+        let fields = SearchField::DEFAULT;
+        let eu = host("web1", "eu-web1.example", &["prod"]);
+        let us = host("web2", "us-web2.example", &["prod"]);
+        let preprod = host("web3", "eu-web3.example", &["preprod"]);
+
+        let query = Query::parse("tag:prod dest:eu- web");
+        assert!(matches(&matcher, &eu, &fields, &query));
+        assert!(!matches(&matcher, &us, &fields, &query));
+        // tags match exactly, so "prod" does not select "preprod"
+        assert!(!matches(&matcher, &preprod, &fields, &query));
+
+        // an unfinished "tag:" does not hide everything
+        assert!(matches(&matcher, &us, &fields, &Query::parse("tag:")));
+    }
+
+    #[test]
+    fn focus_cycles_through_every_field_and_back() {
+        let mut focus = None;
+        let mut seen = Vec::new();
+        loop {
+            focus = SearchField::cycle(focus);
+            match focus {
+                Some(field) => seen.push(field.name()),
+                None => break,
+            }
+        }
+        assert_eq!(seen.first().map(String::as_str), Some("name"));
+        assert_eq!(seen.len(), 9);
+        assert!(seen.contains(&"destination".to_string()));
+    }
+
+    #[test]
+    fn frequent_hosts_outrank_similar_matches() {
+        let matcher = SkimMatcherV2::default();
+        let fields = SearchField::DEFAULT;
+        let query = Query::parse("db");
+        let daily = host("db-main", "db-main.example", &[]);
+        let rare = host("db-old", "db-old.example", &[]);
+
+        let base = score(&matcher, &daily, &fields, &query);
+        assert_eq!(base, score(&matcher, &rare, &fields, &query));
+        assert!(base + frequency_bonus(30, 1.0) > score(&matcher, &rare, &fields, &query));
+
+        assert_eq!(frequency_bonus(0, 1.0), 0);
+        assert_eq!(frequency_bonus(30, 0.0), 0);
+        assert_eq!(score(&matcher, &daily, &fields, &Query::parse("tag:x")), 0);
+    }
+
+    #[test]
+    fn parsed_queries_follow_the_input() {
+        let mut parsed = ParsedQuery::default();
+        assert_eq!(parsed.get(""), &Query::parse(""));
+        assert_eq!(parsed.get("tag:prod web"), &Query::parse("tag:prod web"));
+        assert_eq!(parsed.get("tag:prod web").text, "web");
+        assert_eq!(parsed.get("db"), &Query::parse("db"));
+    }
+
+    #[test]
+    fn query_history_keeps_newest_first_without_duplicates() {
+        let mut history = QueryHistory::default();
+        history.push("web");
+        history.push("  db ");
+        history.push("");
+        history.push("web");
+
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.get(0), Some("web"));
+        assert_eq!(history.get(1), Some("db"));
+        assert_eq!(history.get(2), None);
+    }
+}
@@ -1,4 +1,8 @@
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+
 type SearchableFn<T> = dyn FnMut(&&T, &str) -> bool;
+type RankFn<T> = dyn FnMut(&T, &str) -> i64;
+type GroupFn<T> = dyn Fn(&T, &T) -> std::cmp::Ordering;
 
 pub struct Searchable<T>
 where
@@ -6,6 +10,8 @@ where
 {
     vec: Vec<T>,
     filter: Box<SearchableFn<T>>,
+    ranker: Option<Box<RankFn<T>>>,
+    group: Option<Box<GroupFn<T>>>,
     filtered: Vec<T>,
 }
 
@@ -21,6 +27,8 @@ where
         let mut searchable = Searchable {
             vec,
             filter: Box::new(predicate),
+            ranker: None,
+            group: None,
             filtered: Vec::new(),
         };
 
@@ -28,18 +36,55 @@ where
         searchable
     }
 
-    pub fn search(&mut self, value: &str) {
-        if value.is_empty() {
-            self.filtered.clone_from(&self.vec);
-            return;
-        }
+    /// Orders the matches of a non-empty search by `ranker`, highest first.
+    /// Items ranked equally keep their original order.
+    #[must_use]
+    pub fn with_ranking<R>(mut self, ranker: R, search_value: &str) -> Self
+    where
+        R: FnMut(&T, &str) -> i64 + 'static,
+    {
+        self.ranker = Some(Box::new(ranker));
+        self.search(search_value);
+        self
+    }
 
+    /// Keeps the matches of each group together, groups ordered by
+    /// `compare`; within a group items stay in ranked (or original) order.
+    #[must_use]
+    pub fn with_grouping<G>(mut self, compare: G, search_value: &str) -> Self
+    where
+        G: Fn(&T, &T) -> std::cmp::Ordering + 'static,
+    {
+        self.group = Some(Box::new(compare));
+        self.search(search_value);
+        self
+    }
+
+    /// Re-filters with `value`. The predicate is consulted even for an empty
+    /// value so it can hide items regardless of the query.
+    pub fn search(&mut self, value: &str) {
         self.filtered = self
             .vec
             .iter()
             .filter(|host| (self.filter)(host, value))
             .cloned()
             .collect();
+
+        if let Some(ranker) = self.ranker.as_mut() {
+            if !value.trim().is_empty() {
+                self.filtered
+                    .sort_by_cached_key(|item| std::cmp::Reverse(ranker(item, value)));
+            }
+        }
+        if let Some(group) = &self.group {
+            self.filtered.sort_by(|a, b| group(a, b));
+        }
+    }
+
+    /// Replaces every item and re-filters with `value`.
+    pub fn set_items(&mut self, vec: Vec<T>, value: &str) {
+        self.vec = vec;
+        self.search(value);
     }
 
     #[allow(clippy::must_use_candidate)]
@@ -57,15 +102,42 @@ where
         self.filtered.is_empty()
     }
 
-    pub fn non_filtered_iter(&self) -> std::slice::Iter<T> {
+    pub fn non_filtered_iter(&self) -> std::slice::Iter<'_, T> {
         self.vec.iter()
     }
 
-    pub fn iter(&self) -> std::slice::Iter<T> {
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
         self.filtered.iter()
     }
 }
 
+/// A row of a list view other than the host table (snippets, tunnels,
+/// sessions, …), which all filter and navigate the same way.
+pub trait TableItem: Clone {
+    /// Column headings, in English; the UI looks them up with `t`.
+    const HEADERS: &'static [&'static str];
+
+    /// Text of each column, in `HEADERS` order. A query is fuzzy-matched
+    /// against every cell.
+    fn cells(&self) -> Vec<String>;
+}
+
+impl<T> Searchable<T>
+where
+    T: TableItem + 'static,
+{
+    /// `vec` filtered by fuzzy-matching the query against each item's
+    /// cells, kept in its own order.
+    #[must_use]
+    pub fn of_items(vec: Vec<T>) -> Self {
+        let matcher = SkimMatcherV2::default();
+        Searchable::new(vec, "", move |item: &&T, value: &str| {
+            let value = value.trim();
+            value.is_empty() || item.cells().iter().any(|cell| matcher.fuzzy_match(cell, value).is_some())
+        })
+    }
+}
+
 impl<'a, T> IntoIterator for &'a Searchable<T>
 where
     T: Clone,
@@ -0,0 +1,379 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::searchable::TableItem;
+use crate::{ssh, template};
+
+/// Local port a SOCKS proxy (Alt+S) tries first.
+pub const DEFAULT_SOCKS_PORT: u16 = 1080;
+
+/// How the rendered command is started once a host is selected.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Launcher {
+    /// Run the command in the current terminal, suspending the picker.
+    #[default]
+    Inline,
+    /// Open the command in a new tmux window and keep the picker open.
+    Tmux,
+    /// Run `--launcher-template` in the background (e.g. a new terminal
+    /// window) and keep the picker open. The launcher has to block until the
+    /// session ends: its exit is taken as the end of the session.
+    Template,
+}
+
+impl Launcher {
+    /// Background launchers leave the picker running while the session lives.
+    #[must_use]
+    pub fn is_background(self) -> bool {
+        self != Launcher::Inline
+    }
+}
+
+enum SessionHandle {
+    /// tmux window id (`@12`), checked against `tmux list-windows`.
+    TmuxWindow(String),
+    /// Launcher process spawned by sgh, checked with `try_wait`; it lives as
+    /// long as the session does.
+    Process(Child),
+    /// `ssh -N -D <port>` SOCKS proxy, stopped with the picker.
+    Socks(Child, u16),
+}
+
+pub struct Session {
+    pub host_name: String,
+    pub started_at: Instant,
+    handle: SessionHandle,
+}
+
+impl Session {
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    #[must_use]
+    pub fn tmux_window(&self) -> Option<&str> {
+        match &self.handle {
+            SessionHandle::TmuxWindow(id) => Some(id),
+            SessionHandle::Process(_) | SessionHandle::Socks(..) => None,
+        }
+    }
+
+    /// Local port of a SOCKS proxy session.
+    #[must_use]
+    pub fn socks_port(&self) -> Option<u16> {
+        match &self.handle {
+            SessionHandle::Socks(_, port) => Some(*port),
+            _ => None,
+        }
+    }
+
+    /// What the Sessions view shows of this session.
+    #[must_use]
+    pub fn summary(&self) -> SessionSummary {
+        let kind = match &self.handle {
+            SessionHandle::TmuxWindow(id) => format!("tmux {id}"),
+            SessionHandle::Process(_) => "launcher".to_string(),
+            SessionHandle::Socks(_, port) => format!("socks :{port}"),
+        };
+        SessionSummary {
+            host_name: self.host_name.clone(),
+            kind,
+            started_at: self.started_at,
+        }
+    }
+}
+
+/// A row of the Sessions view. `Session` owns the process, so the view
+/// keeps these copies instead.
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    pub host_name: String,
+    /// `tmux @12`, `launcher` or `socks :1080`.
+    pub kind: String,
+    pub started_at: Instant,
+}
+
+impl TableItem for SessionSummary {
+    const HEADERS: &'static [&'static str] = &["NAME", "SESSION", "TIME"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![self.host_name.clone(), self.kind.clone(), format_duration(self.started_at.elapsed())]
+    }
+}
+
+/// `ALL_PROXY` and friends pointing at a SOCKS proxy on `port`, one
+/// `export` per line. `socks5h` resolves names through the proxy.
+#[must_use]
+pub fn proxy_exports(port: u16) -> String {
+    ["ALL_PROXY", "HTTPS_PROXY", "HTTP_PROXY"]
+        .iter()
+        .map(|name| format!("export {name}=socks5h://127.0.0.1:{port}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Context for `--launcher-template`: every host field plus the rendered
+/// connect command as `{{command}}` and the window title as `{{title}}`.
+#[derive(Serialize)]
+struct LaunchContext<'a> {
+    #[serde(flatten)]
+    host: &'a ssh::Host,
+    command: &'a str,
+    title: &'a str,
+}
+
+/// Sessions started through a background launcher during this run.
+#[derive(Default)]
+pub struct SessionTracker {
+    sessions: Vec<Session>,
+}
+
+impl SessionTracker {
+    /// Starts `command` for `host` with the given background launcher. With
+    /// `shell` the rendered launcher template runs through `$SHELL -c`.
+    /// `title` names the tmux window.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the launcher cannot be started.
+    pub fn launch(
+        &mut self,
+        launcher: Launcher,
+        launcher_template: Option<&str>,
+        host: &ssh::Host,
+        command: &str,
+        title: &str,
+        shell: bool,
+    ) -> Result<()> {
+        let handle = match launcher {
+            Launcher::Inline => return Err(anyhow!("Inline sessions are not tracked")),
+            Launcher::Tmux => {
+                SessionHandle::TmuxWindow(launch_tmux(title, command, &host.env)?)
+            }
+            Launcher::Template => {
+                let launcher_template = launcher_template
+                    .ok_or(anyhow!("--launcher template requires --launcher-template"))?;
+                let rendered = template::render(launcher_template, &LaunchContext { host, command, title })?;
+                SessionHandle::Process(spawn_detached(&rendered.text, &host.env, shell)?)
+            }
+        };
+
+        self.sessions.push(Session {
+            host_name: host.name.clone(),
+            started_at: Instant::now(),
+            handle,
+        });
+
+        Ok(())
+    }
+
+    /// Starts a SOCKS proxy on `port` through `host` with `ssh -N -D`, reading
+    /// `config_file` with `-F` when given. ssh runs in batch mode since it
+    /// has no terminal to prompt on, so the host needs key or agent auth.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if ssh cannot be started.
+    pub fn start_socks(&mut self, host: &ssh::Host, port: u16, config_file: Option<&Path>) -> Result<()> {
+        let mut command = Command::new("ssh");
+        if let Some(file) = config_file {
+            command.arg("-F").arg(file);
+        }
+        let child = command
+            .args(["-N", "-D", &format!("127.0.0.1:{port}")])
+            .args(["-o", "ExitOnForwardFailure=yes", "-o", "BatchMode=yes"])
+            .arg(&host.name)
+            .envs(&host.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        self.sessions.push(Session {
+            host_name: host.name.clone(),
+            started_at: Instant::now(),
+            handle: SessionHandle::Socks(child, port),
+        });
+        Ok(())
+    }
+
+    /// Stops the SOCKS proxy of `host_name` and returns its port, or `None`
+    /// when there is none.
+    pub fn stop_socks(&mut self, host_name: &str) -> Option<u16> {
+        let index = self
+            .sessions
+            .iter()
+            .position(|s| s.host_name == host_name && s.socks_port().is_some())?;
+        let mut session = self.sessions.remove(index);
+        let port = session.socks_port();
+        if let SessionHandle::Socks(child, _) = &mut session.handle {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        port
+    }
+
+    /// Drops sessions whose tmux window or launcher process has gone away and
+    /// returns them.
+    pub fn refresh(&mut self) -> Vec<Session> {
+        if self.sessions.is_empty() {
+            return Vec::new();
+        }
+
+        let live_windows = if self.sessions.iter().any(|s| s.tmux_window().is_some()) {
+            tmux_window_ids()
+        } else {
+            None
+        };
+
+        let mut ended = Vec::new();
+        for mut session in std::mem::take(&mut self.sessions) {
+            let running = match &mut session.handle {
+                // tmux に問い合わせできなかった場合は消さずに残す
+                SessionHandle::TmuxWindow(id) => live_windows
+                    .as_ref()
+                    .is_none_or(|windows| windows.iter().any(|w| w == id)),
+                SessionHandle::Process(child) | SessionHandle::Socks(child, _) => {
+                    matches!(child.try_wait(), Ok(None))
+                }
+            };
+            if running {
+                self.sessions.push(session);
+            } else {
+                ended.push(session);
+            }
+        }
+        ended
+    }
+
+    #[must_use]
+    pub fn is_active(&self, host_name: &str) -> bool {
+        self.sessions.iter().any(|s| s.host_name == host_name)
+    }
+
+    /// Switches tmux to the most recent window opened for `host_name`.
+    /// Returns `Ok(false)` when no tmux session is tracked for that host.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `tmux select-window` fails.
+    pub fn jump_to(&self, host_name: &str) -> Result<bool> {
+        let Some(window) = self
+            .sessions
+            .iter()
+            .rev()
+            .filter(|s| s.host_name == host_name)
+            .find_map(Session::tmux_window)
+        else {
+            return Ok(false);
+        };
+
+        let status = Command::new("tmux")
+            .args(["select-window", "-t", window])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+        if !status.success() {
+            return Err(anyhow!("tmux select-window failed ({status})"));
+        }
+
+        Ok(true)
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Session> {
+        self.sessions.iter()
+    }
+
+    #[must_use]
+    pub fn summaries(&self) -> Vec<SessionSummary> {
+        self.sessions.iter().map(Session::summary).collect()
+    }
+}
+
+impl Drop for SessionTracker {
+    /// SOCKS proxies belong to the picker and end with it; tmux windows and
+    /// launched terminals are left running.
+    fn drop(&mut self) {
+        for session in &mut self.sessions {
+            if let SessionHandle::Socks(child, _) = &mut session.handle {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+}
+
+/// 経過時間を "45s" / "12m" / "1h05m" のように短く整形する
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Opens `command` in a new tmux window named after the host and returns the
+/// window id.
+fn launch_tmux(window_name: &str, command: &str, env: &BTreeMap<String, String>) -> Result<String> {
+    if std::env::var_os("TMUX").is_none() {
+        return Err(anyhow!("--launcher tmux requires running sgh inside tmux"));
+    }
+
+    // 新しいウィンドウはサーバーの環境で起動するので -e で渡す
+    let env_args = env.iter().flat_map(|(key, value)| ["-e".to_string(), format!("{key}={value}")]);
+    let output = Command::new("tmux")
+        .args(["new-window", "-P", "-F", "#{window_id}", "-n", window_name])
+        .args(env_args)
+        .arg(command)
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!("tmux new-window failed ({})", output.status));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn tmux_window_ids() -> Option<Vec<String>> {
+    let output = Command::new("tmux")
+        .args(["list-windows", "-a", "-F", "#{window_id}"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+fn spawn_detached(command_line: &str, env: &BTreeMap<String, String>, shell: bool) -> Result<Child> {
+    Ok(ssh::build_command(command_line, shell)?
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?)
+}
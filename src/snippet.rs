@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+use crate::searchable::TableItem;
+
+/// Command template a snippet runs with; `{{{snippet}}}` is the snippet's
+/// command quoted as one shell word, so ssh hands it to the remote shell
+/// as written.
+pub const DEFAULT_SNIPPET_TEMPLATE: &str = "ssh -t \"{{{name}}}\" {{{snippet}}}";
+
+/// A `snippets` entry of the config file: a remote command to run on the
+/// selected host from the Alt+R list, e.g. `{ "name": "disk", "command":
+/// "df -h" }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Snippet {
+    pub name: String,
+    pub command: String,
+}
+
+impl Snippet {
+    /// The command as a single shell word, the value of `{{snippet}}`.
+    #[must_use]
+    pub fn quoted(&self) -> String {
+        // NUL を含むなどで引用できないコマンドはそのまま渡す
+        shlex::try_quote(&self.command).map_or_else(|_| self.command.clone(), |quoted| quoted.into_owned())
+    }
+}
+
+impl TableItem for Snippet {
+    const HEADERS: &'static [&'static str] = &["NAME", "COMMAND"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![self.name.clone(), self.command.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh;
+
+    #[test]
+    fn snippets_reach_the_remote_shell_as_one_argument() {
+        let snippet = Snippet {
+            name: "errors".to_string(),
+            command: "journalctl -u app -f | grep 'level=error'".to_string(),
+        };
+        let host = ssh::Host {
+            name: "web".to_string(),
+            snippet: Some(snippet.quoted()),
+            ..ssh::Host::default()
+        };
+        let command = host.render_command_template(DEFAULT_SNIPPET_TEMPLATE, crate::template::Helpers::Run).unwrap().text;
+        let args = shlex::split(&command).unwrap();
+        assert_eq!(args, ["ssh", "-t", "web", "journalctl -u app -f | grep 'level=error'"]);
+    }
+}
@@ -0,0 +1,1110 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{exchange, ssh, store};
+
+/// A place hosts are read from in addition to the SSH config files, given as
+/// `--source <kind>:<argument>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// An age (`.age`) or GPG (`.gpg`, `.asc`) encrypted file holding SSH
+    /// config text or an `sgh export` host list. It is decrypted in memory and
+    /// never written to disk in plain text.
+    Encrypted(PathBuf),
+    /// Machines advertising a DNS-SD service (`_ssh._tcp` unless another
+    /// type is given) on the local network, found with `avahi-browse`.
+    /// They are listed by their `.local` name, so a changing IP does not
+    /// matter.
+    Mdns(String),
+    /// Every address of an IPv4 network (`scan:192.168.1.0/24`) that
+    /// accepts connections on port 22, for networks with no inventory.
+    Scan(Ipv4Addr, u8),
+    /// Docker contexts or Podman connections with an `ssh://` endpoint
+    /// (`docker`, `podman`), plus the running containers of the current
+    /// one with `docker:containers`.
+    Containers { engine: Engine, containers: bool },
+    /// The machines of a Vagrant project directory (`vagrant:~/vm/app`),
+    /// from `vagrant ssh-config`. They are connected to with `vagrant ssh`,
+    /// which uses the generated key and forwarded port.
+    Vagrant(PathBuf),
+    /// Compute instances of a cloud (`gcp:<project>`, `azure:<resource
+    /// group>`, both optional), keeping only those with every `key=value`
+    /// label or tag given after a comma.
+    Cloud {
+        cloud: Cloud,
+        scope: Option<String>,
+        labels: Vec<(String, String)>,
+    },
+}
+
+/// Cloud a `gcp` / `azure` source lists instances of, with its CLI.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cloud {
+    Gcp,
+    Azure,
+}
+
+/// Container engine a `docker` / `podman` source asks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    Docker,
+    Podman,
+}
+
+impl Engine {
+    fn program(self) -> &'static str {
+        match self {
+            Engine::Docker => "docker",
+            Engine::Podman => "podman",
+        }
+    }
+
+    /// Arguments listing the remote endpoints as `name<TAB>ssh://…[<TAB>key]`.
+    fn endpoint_args(self) -> &'static [&'static str] {
+        match self {
+            Engine::Docker => &["context", "ls", "--format", "{{.Name}}\t{{.DockerEndpoint}}"],
+            Engine::Podman => &["system", "connection", "ls", "--format", "{{.Name}}\t{{.URI}}\t{{.Identity}}"],
+        }
+    }
+}
+
+/// Service type `mdns` browses for when none is given.
+const SSH_SERVICE: &str = "_ssh._tcp";
+
+/// Largest network `scan` accepts (a /20), so a typo like `/8` does not
+/// start millions of connections.
+const MAX_SCAN_ADDRESSES: u32 = 4096;
+/// Connections a scan keeps open at once.
+const SCAN_CONCURRENCY: usize = 64;
+/// How long a scan waits for each address to accept.
+const SCAN_TIMEOUT: Duration = Duration::from_millis(300);
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // mdns・docker・podman は引数を省略できる
+        let (kind, argument) = value.split_once(':').unwrap_or((value, ""));
+        let engine = match kind {
+            "docker" => Some(Engine::Docker),
+            "podman" => Some(Engine::Podman),
+            _ => None,
+        };
+        if let Some(engine) = engine {
+            return match argument {
+                "" => Ok(Source::Containers { engine, containers: false }),
+                "containers" => Ok(Source::Containers { engine, containers: true }),
+                _ => Err(format!("unknown {kind} source {argument:?} (expected {kind} or {kind}:containers)")),
+            };
+        }
+        let cloud = match kind {
+            "gcp" => Some(Cloud::Gcp),
+            "azure" => Some(Cloud::Azure),
+            _ => None,
+        };
+        if let Some(cloud) = cloud {
+            return parse_cloud(cloud, argument);
+        }
+        if kind == "mdns" && argument.is_empty() {
+            return Ok(Source::Mdns(SSH_SERVICE.to_string()));
+        }
+        if !value.contains(':') {
+            return Err(format!("expected <kind>:<argument>, got {value:?}"));
+        }
+        if argument.is_empty() {
+            return Err(format!("missing argument for source {kind:?}"));
+        }
+
+        match kind {
+            "encrypted" => Ok(Source::Encrypted(PathBuf::from(
+                shellexpand::tilde(argument).to_string(),
+            ))),
+            "mdns" => Ok(Source::Mdns(argument.to_string())),
+            "vagrant" => Ok(Source::Vagrant(PathBuf::from(
+                shellexpand::tilde(argument).to_string(),
+            ))),
+            "scan" => parse_cidr(argument).map(|(network, prefix)| Source::Scan(network, prefix)),
+            _ => Err(format!(
+                "unknown source kind {kind:?} (expected: encrypted, mdns, scan, docker, podman, vagrant, gcp, azure)"
+            )),
+        }
+    }
+}
+
+/// Names `source_priority` accepts for a source: `config` for the SSH
+/// config, or a kind of `--source`.
+const SOURCE_NAMES: [&str; 9] = ["config", "encrypted", "mdns", "scan", "docker", "podman", "vagrant", "gcp", "azure"];
+
+/// Keywords whose value a source can take over from the SSH config.
+const PRIORITY_KEYWORDS: [&str; 4] = ["hostname", "user", "port", "identityfile"];
+
+/// `source_priority` of the config file: for each keyword, the source whose
+/// value wins when a host from a source is also in the SSH config, e.g.
+/// `{"hostname": "gcp"}` to connect to the address GCP reports. The SSH
+/// config wins for keywords not listed; either side fills in what the
+/// other lacks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourcePriority(BTreeMap<String, String>);
+
+impl SourcePriority {
+    /// # Errors
+    ///
+    /// Will return `Err` for a keyword or source name sgh does not know.
+    pub fn parse(priority: &BTreeMap<String, String>) -> Result<SourcePriority> {
+        for (keyword, source) in priority {
+            if !PRIORITY_KEYWORDS.contains(&keyword.to_lowercase().as_str()) {
+                return Err(anyhow!(
+                    "source_priority: unknown keyword {keyword:?}; expected one of: {}",
+                    PRIORITY_KEYWORDS.join(", ")
+                ));
+            }
+            if !SOURCE_NAMES.contains(&source.as_str()) {
+                return Err(anyhow!(
+                    "source_priority: unknown source {source:?}; expected one of: {}",
+                    SOURCE_NAMES.join(", ")
+                ));
+            }
+        }
+        Ok(SourcePriority(
+            priority.iter().map(|(keyword, source)| (keyword.to_lowercase(), source.clone())).collect(),
+        ))
+    }
+
+    fn source_wins(&self, keyword: &str, kind: &str) -> bool {
+        self.0.get(keyword).is_some_and(|source| source == kind)
+    }
+}
+
+/// `source_defaults` entry of the config file: settings for every host of
+/// one kind of source that the source itself does not give, e.g.
+/// `{"gcp": {"user": "ops"}}`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceDefaults {
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// Connect command template used instead of the source's own (or the
+    /// default one) for its hosts, e.g. `tsh ssh {{name}}`.
+    pub template: Option<String>,
+}
+
+impl SourceDefaults {
+    /// # Errors
+    ///
+    /// Will return `Err` if a key is not a kind of source.
+    pub fn validate(defaults: &BTreeMap<String, SourceDefaults>) -> Result<()> {
+        match defaults.keys().find(|kind| kind.as_str() == "config" || !SOURCE_NAMES.contains(&kind.as_str())) {
+            Some(kind) => Err(anyhow!(
+                "source_defaults: unknown source {kind:?}; expected one of: {}",
+                SOURCE_NAMES[1..].join(", ")
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the user and port of `hosts` that have none (passed to ssh as
+    /// `-o`, since the hosts are not in the SSH config) and their connect
+    /// template.
+    pub fn apply(&self, hosts: &mut [ssh::Host]) {
+        for host in hosts {
+            if let (None, Some(user)) = (&host.user, &self.user) {
+                host.user = Some(user.clone());
+                host.source_options.insert("user".to_string(), user.clone());
+            }
+            if let (None, Some(port)) = (&host.port, self.port) {
+                host.port = Some(port.to_string());
+                host.source_options.insert("port".to_string(), port.to_string());
+            }
+            if let Some(template) = &self.template {
+                host.connect_template = Some(template.clone());
+            }
+        }
+    }
+}
+
+/// Adds the hosts of a `kind` source to `hosts`. A source host with the
+/// name, an alias or the address of a host already listed is folded into
+/// that host instead of getting a row of its own: `priority` decides each
+/// keyword, tags are combined, and a value taken from the source is marked
+/// as coming from it in the detail panel and passed to ssh.
+pub fn merge_hosts(hosts: &mut Vec<ssh::Host>, source_hosts: Vec<ssh::Host>, kind: &str, priority: &SourcePriority) {
+    for incoming in source_hosts {
+        let names = |host: &ssh::Host| {
+            std::iter::once(host.name.clone())
+                .chain(host.aliases.split(", ").filter(|alias| !alias.is_empty()).map(str::to_string))
+                .collect::<Vec<_>>()
+        };
+        let incoming_names = names(&incoming);
+        let Some(host) = hosts.iter_mut().filter(|host| !host.is_pattern).find(|host| {
+            names(host).iter().any(|name| incoming_names.contains(name))
+                || (!incoming.destination.is_empty() && host.destination == incoming.destination)
+        }) else {
+            hosts.push(incoming);
+            continue;
+        };
+
+        let destination = Some(host.destination.clone()).filter(|d| !d.is_empty());
+        let incoming_destination = Some(incoming.destination.clone()).filter(|d| !d.is_empty());
+        let mut taken = Vec::new();
+        for (keyword, current, value) in [
+            ("hostname", &destination, &incoming_destination),
+            ("user", &host.user, &incoming.user),
+            ("port", &host.port, &incoming.port),
+            ("identityfile", &host.identity_file, &incoming.identity_file),
+        ] {
+            let Some(value) = value.as_ref().filter(|value| Some(*value) != current.as_ref()) else {
+                continue;
+            };
+            if current.is_none() || priority.source_wins(keyword, kind) {
+                taken.push((keyword, value.clone()));
+            }
+        }
+        for (keyword, value) in taken {
+            match keyword {
+                "hostname" => host.destination.clone_from(&value),
+                "user" => host.user = Some(value.clone()),
+                "port" => host.port = Some(value.clone()),
+                _ => host.identity_file = Some(value.clone()),
+            }
+            host.options.insert(keyword.to_string(), value.clone());
+            host.option_sources.insert(
+                keyword.to_string(),
+                ssh::OptionSource {
+                    block: format!("{kind} source"),
+                    location: None,
+                    inherited: true,
+                },
+            );
+            host.source_options.insert(keyword.to_string(), value);
+        }
+        for tag in incoming.tags {
+            if !host.tags.contains(&tag) {
+                host.tags.push(tag);
+            }
+        }
+        if host.note.is_none() {
+            host.note = incoming.note;
+        }
+    }
+}
+
+/// How long the hosts of a slow source are reused before it is asked
+/// again, unless `source_cache_ttl` says otherwise.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(600);
+
+/// When `load_cached` may answer from the cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CachePolicy {
+    /// Age up to which cached hosts are used; zero always asks the source.
+    pub ttl: Duration,
+    /// Only use the cache, whatever its age (`--offline`).
+    pub offline: bool,
+}
+
+/// `source-cache.json`: the hosts of each slow source from its last load,
+/// keyed by `Source::cache_key`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct SourceCache {
+    sources: BTreeMap<String, CachedHosts>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedHosts {
+    /// Unix time of the load, in seconds.
+    fetched_at: u64,
+    hosts: Vec<ssh::Host>,
+}
+
+impl SourceCache {
+    const FILE_NAME: &str = "source-cache.json";
+}
+
+/// Held while the cache file is rewritten.
+static CACHE_LOCK: Mutex<()> = Mutex::new(());
+
+impl Source {
+    /// Short name of the kind of source, e.g. `gcp`, shown while it loads.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Source::Encrypted(_) => "encrypted",
+            Source::Mdns(_) => "mdns",
+            Source::Scan(..) => "scan",
+            Source::Containers { engine, .. } => engine.program(),
+            Source::Vagrant(_) => "vagrant",
+            Source::Cloud { cloud: Cloud::Gcp, .. } => "gcp",
+            Source::Cloud { cloud: Cloud::Azure, .. } => "azure",
+        }
+    }
+
+    /// Whether the source asks the network or another program and so is
+    /// loaded in the background. Encrypted bundles are read before the
+    /// picker starts, since decrypting may prompt for a passphrase.
+    #[must_use]
+    pub fn is_slow(&self) -> bool {
+        !matches!(self, Source::Encrypted(_))
+    }
+
+    /// Name of the source in the cache. `None` for encrypted bundles, whose
+    /// hosts must never reach the disk in plain text.
+    fn cache_key(&self) -> Option<String> {
+        match self {
+            Source::Encrypted(_) => None,
+            Source::Mdns(service) => Some(format!("mdns:{service}")),
+            Source::Scan(network, prefix) => Some(format!("scan:{network}/{prefix}")),
+            Source::Containers { engine, containers } => Some(format!(
+                "{}{}",
+                engine.program(),
+                if *containers { ":containers" } else { "" }
+            )),
+            Source::Vagrant(project) => Some(format!("vagrant:{}", project.display())),
+            Source::Cloud { scope, labels, .. } => {
+                let mut parts: Vec<String> = scope.iter().cloned().collect();
+                parts.extend(labels.iter().map(|(key, value)| format!("{key}={value}")));
+                Some(format!("{}:{}", self.kind(), parts.join(",")))
+            }
+        }
+    }
+
+    /// Like `load`, but answers from the cache while it is younger than
+    /// `policy.ttl` (or at any age when offline) and stores fresh results.
+    /// A source that fails falls back to its cached hosts, however old.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the source fails and nothing is cached for it.
+    pub fn load_cached(&self, pipeline: ssh::Pipeline, policy: CachePolicy) -> Result<Vec<ssh::Host>> {
+        let Some(key) = self.cache_key() else {
+            return self.load(pipeline);
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut cache: SourceCache = store::load(SourceCache::FILE_NAME);
+        let cached = cache.sources.remove(&key);
+
+        if policy.offline {
+            // オフラインでは取得したことのないソースは空にする
+            return Ok(cached.map(|cached| cached.hosts).unwrap_or_default());
+        }
+        if let Some(cached) = cached.as_ref().filter(|cached| now.saturating_sub(cached.fetched_at) < policy.ttl.as_secs()) {
+            return Ok(cached.hosts.clone());
+        }
+
+        match self.load(pipeline) {
+            Ok(hosts) => {
+                // ソースはスレッドごとに読まれるので、他のソースの分を読み直して順に書く
+                let _guard = CACHE_LOCK.lock();
+                let mut cache: SourceCache = store::load(SourceCache::FILE_NAME);
+                cache.sources.insert(key, CachedHosts { fetched_at: now, hosts: hosts.clone() });
+                // キャッシュが書けなくても一覧は出す
+                let _ = store::save(SourceCache::FILE_NAME, &cache);
+                Ok(hosts)
+            }
+            Err(err) => cached.map(|cached| cached.hosts).ok_or(err),
+        }
+    }
+
+    /// Reads the hosts of this source.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the source cannot be read or parsed.
+    pub fn load(&self, pipeline: ssh::Pipeline) -> Result<Vec<ssh::Host>> {
+        match self {
+            Source::Encrypted(path) => {
+                let content = decrypt(path)?;
+                parse_bundle(&content, path, pipeline)
+            }
+            Source::Mdns(service) => Ok(parse_avahi(&browse(service)?)),
+            Source::Scan(network, prefix) => Ok(scan(*network, *prefix, 22)
+                .into_iter()
+                .map(|address| scanned_host(address, reverse_lookup(address)))
+                .collect()),
+            Source::Containers { engine, containers } => {
+                let mut hosts = parse_endpoints(*engine, &run_listing(engine.program(), engine.endpoint_args())?);
+                if *containers {
+                    let args = ["ps", "--format", "{{.Names}}\t{{.Image}}"];
+                    hosts.extend(parse_containers(*engine, &run_listing(engine.program(), &args)?));
+                }
+                Ok(hosts)
+            }
+            Source::Vagrant(project) => {
+                let output = Command::new("vagrant")
+                    .arg("ssh-config")
+                    .current_dir(project)
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::null())
+                    .output()
+                    .with_context(|| format!("Failed to run vagrant ssh-config in {}", project.display()))?;
+                // 止まっているマシンがあると失敗するが、動いているマシンの分は出力される
+                if !output.status.success() && output.stdout.is_empty() {
+                    return Err(anyhow!(
+                        "vagrant ssh-config found no running machine in {} ({})",
+                        project.display(),
+                        output.status
+                    ));
+                }
+                parse_vagrant(&String::from_utf8_lossy(&output.stdout), project, pipeline)
+            }
+            Source::Cloud { cloud, scope, labels } => {
+                parse_instances(*cloud, &list_instances(*cloud, scope.as_deref())?, labels)
+            }
+        }
+    }
+}
+
+/// `<scope>,<key>=<value>,…` of a cloud source, each part optional.
+fn parse_cloud(cloud: Cloud, argument: &str) -> Result<Source, String> {
+    let mut scope = None;
+    let mut labels = Vec::new();
+    for part in argument.split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('=') {
+            Some((key, value)) => labels.push((key.to_string(), value.to_string())),
+            None if scope.is_none() => scope = Some(part.to_string()),
+            None => return Err(format!("{argument:?} names more than one project or resource group")),
+        }
+    }
+    Ok(Source::Cloud { cloud, scope, labels })
+}
+
+/// Lists the instances of `cloud` with its CLI as JSON.
+fn list_instances(cloud: Cloud, scope: Option<&str>) -> Result<String> {
+    match cloud {
+        Cloud::Gcp => {
+            let mut args = vec!["compute", "instances", "list", "--format=json"];
+            if let Some(project) = scope {
+                args.extend(["--project", project]);
+            }
+            run_listing("gcloud", &args)
+        }
+        Cloud::Azure => {
+            let mut args = vec!["vm", "list", "--show-details", "--output", "json"];
+            if let Some(group) = scope {
+                args.extend(["--resource-group", group]);
+            }
+            run_listing("az", &args)
+        }
+    }
+}
+
+/// Hosts of the instances in the CLI's JSON output that carry every label.
+/// GCP instances are connected to with `gcloud compute ssh`, which handles
+/// keys and OS Login; Azure VMs with ssh to their public (or else private)
+/// address as the admin user. Labels become `key=value` tags.
+fn parse_instances(cloud: Cloud, json: &str, labels: &[(String, String)]) -> Result<Vec<ssh::Host>> {
+    let instances: Vec<serde_json::Value> = serde_json::from_str(json)?;
+    let text = |value: &serde_json::Value| value.as_str().filter(|text| !text.is_empty()).map(str::to_string);
+    Ok(instances
+        .iter()
+        .filter_map(|instance| {
+            let name = text(&instance["name"])?;
+            let tags: Vec<String> = instance[match cloud {
+                Cloud::Gcp => "labels",
+                Cloud::Azure => "tags",
+            }]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| format!("{key}={}", value.as_str().unwrap_or_default()))
+            .collect();
+            if !labels.iter().all(|(key, value)| tags.contains(&format!("{key}={value}"))) {
+                return None;
+            }
+
+            let mut host = match cloud {
+                Cloud::Gcp => {
+                    let interface = &instance["networkInterfaces"][0];
+                    let zone = text(&instance["zone"])?.rsplit('/').next()?.to_string();
+                    // selfLink の projects/<id>/zones/... からプロジェクトを取る
+                    let project = text(&instance["selfLink"])
+                        .and_then(|link| link.split("/projects/").nth(1).and_then(|rest| rest.split('/').next()).map(str::to_string));
+                    let mut template = "gcloud compute ssh {{{shquote source_values.instance}}} --zone {{{shquote source_values.zone}}}".to_string();
+                    let mut values = BTreeMap::from([("instance".to_string(), name.clone()), ("zone".to_string(), zone)]);
+                    if let Some(project) = project {
+                        template.push_str(" --project {{{shquote source_values.project}}}");
+                        values.insert("project".to_string(), project);
+                    }
+                    ssh::Host {
+                        destination: text(&interface["accessConfigs"][0]["natIP"])
+                            .or_else(|| text(&interface["networkIP"]))
+                            .unwrap_or_default(),
+                        note: Some(format!(
+                            "gcp {} {}",
+                            values["zone"],
+                            text(&instance["status"]).unwrap_or_default().to_lowercase()
+                        )),
+                        connect_template: Some(template),
+                        source_values: values,
+                        ..ssh::Host::default()
+                    }
+                }
+                Cloud::Azure => {
+                    let address = text(&instance["publicIps"])
+                        .or_else(|| text(&instance["privateIps"]))?
+                        .split(',')
+                        .next()?
+                        .trim()
+                        .to_string();
+                    let user = text(&instance["osProfile"]["adminUsername"]);
+                    let target = user.as_ref().map_or_else(|| address.clone(), |user| format!("{user}@{address}"));
+                    ssh::Host {
+                        user,
+                        note: Some(format!(
+                            "azure {} {}",
+                            text(&instance["resourceGroup"]).unwrap_or_default(),
+                            text(&instance["powerState"]).unwrap_or_default().to_lowercase()
+                        )),
+                        connect_template: Some("ssh {{{shquote source_values.target}}}".to_string()),
+                        source_values: BTreeMap::from([("target".to_string(), target)]),
+                        destination: address,
+                        ..ssh::Host::default()
+                    }
+                }
+            };
+            host.name = name;
+            host.tags = tags;
+            host.source_file = Some(
+                match cloud {
+                    Cloud::Gcp => "gcp",
+                    Cloud::Azure => "azure",
+                }
+                .to_string(),
+            );
+            Some(host)
+        })
+        .collect())
+}
+
+/// Hosts of `vagrant ssh-config` output, named `<project>/<machine>` so
+/// the `default` machines of several projects stay apart.
+fn parse_vagrant(output: &str, project: &Path, pipeline: ssh::Pipeline) -> Result<Vec<ssh::Host>> {
+    let project_name = project.file_name().map_or_else(|| project.to_string_lossy(), |name| name.to_string_lossy());
+    let mut hosts = ssh::parse_config_str(output, &project.join("Vagrantfile"), pipeline)
+        .map_err(|err| anyhow!("Failed to parse vagrant ssh-config of {}: {err:?}", project.display()))?;
+    hosts.retain(|host| !host.is_pattern);
+    for host in &mut hosts {
+        let machine = std::mem::take(&mut host.name);
+        host.connect_template = Some("vagrant ssh {{{shquote source_values.machine}}}".to_string());
+        host.source_values.insert("machine".to_string(), machine.clone());
+        host.env.insert("VAGRANT_CWD".to_string(), project.to_string_lossy().into_owned());
+        host.name = format!("{project_name}/{machine}");
+    }
+    Ok(hosts)
+}
+
+/// Output of a listing command such as `docker context ls`.
+fn run_listing(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run {program} for the {program} source"))?;
+    if !output.status.success() {
+        let subcommand = args.iter().take(2).copied().collect::<Vec<_>>().join(" ");
+        return Err(anyhow!("{program} {subcommand} failed ({})", output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Hosts of the contexts / connections whose endpoint is `ssh://…`; local
+/// sockets and TCP endpoints are skipped. The context name becomes an
+/// alias so it can be searched.
+fn parse_endpoints(engine: Engine, output: &str) -> Vec<ssh::Host> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let (name, endpoint) = (fields.next()?, fields.next()?);
+            let identity = fields.next().filter(|identity| !identity.is_empty());
+            let address = endpoint.strip_prefix("ssh://")?;
+            // podman の URI は末尾にソケットのパスが付く
+            let address = address.split_once('/').map_or(address, |(address, _)| address);
+            let mut host = ssh::Host::adhoc(address)?;
+            host.is_adhoc = false;
+            host.aliases = name.to_string();
+            host.identity_file = identity.map(str::to_string);
+            host.source_file = Some(engine.program().to_string());
+            Some(host)
+        })
+        .collect()
+}
+
+/// Running containers as hosts the default command opens with
+/// `<engine> exec` instead of ssh.
+fn parse_containers(engine: Engine, output: &str) -> Vec<ssh::Host> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, image) = line.split_once('\t')?;
+            Some(ssh::Host {
+                name: name.to_string(),
+                note: Some(format!("{} container of {image}", engine.program())),
+                container: Some(ssh::Container {
+                    engine: engine.program().to_string(),
+                    name: name.to_string(),
+                }),
+                connect_template: Some(ssh::CONTAINER_COMMAND_TEMPLATE.to_string()),
+                source_file: Some(engine.program().to_string()),
+                ..ssh::Host::default()
+            })
+        })
+        .collect()
+}
+
+/// `192.168.1.0/24` as the network address and prefix length.
+fn parse_cidr(cidr: &str) -> Result<(Ipv4Addr, u8), String> {
+    let (address, prefix) = cidr.split_once('/').unwrap_or((cidr, "32"));
+    let address: Ipv4Addr = address
+        .parse()
+        .map_err(|_| format!("{cidr:?} is not an IPv4 network like 192.168.1.0/24"))?;
+    let prefix: u8 = prefix
+        .parse()
+        .ok()
+        .filter(|prefix| *prefix <= 32)
+        .ok_or(format!("{cidr:?} has an invalid prefix length"))?;
+    if 1u64 << (32 - prefix) > u64::from(MAX_SCAN_ADDRESSES) {
+        return Err(format!(
+            "{cidr:?} is too large to scan; use /{} or smaller",
+            32 - MAX_SCAN_ADDRESSES.trailing_zeros()
+        ));
+    }
+    let mask = u32::MAX.checked_shl(u32::from(32 - prefix)).unwrap_or(0);
+    Ok((Ipv4Addr::from(u32::from(address) & mask), prefix))
+}
+
+/// Host addresses of a network: without the network and broadcast
+/// addresses, except for /31 and /32 which have none.
+fn scan_addresses(network: Ipv4Addr, prefix: u8) -> Vec<Ipv4Addr> {
+    let first = u32::from(network);
+    let count = 1u32 << (32 - prefix);
+    let range = if prefix >= 31 { first..first + count } else { first + 1..first + count - 1 };
+    range.map(Ipv4Addr::from).collect()
+}
+
+/// Addresses of the network that accept a TCP connection on `port`, in
+/// address order. Up to `SCAN_CONCURRENCY` connections are tried at a time,
+/// each for at most `SCAN_TIMEOUT`.
+fn scan(network: Ipv4Addr, prefix: u8, port: u16) -> Vec<Ipv4Addr> {
+    let queue = Mutex::new(scan_addresses(network, prefix).into_iter());
+    let open = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..SCAN_CONCURRENCY {
+            scope.spawn(|| {
+                while let Some(address) = queue.lock().ok().and_then(|mut queue| queue.next()) {
+                    if TcpStream::connect_timeout(&SocketAddr::from((address, port)), SCAN_TIMEOUT).is_ok() {
+                        if let Ok(mut open) = open.lock() {
+                            open.push(address);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let mut open = open.into_inner().unwrap_or_default();
+    open.sort();
+    open
+}
+
+/// The name `address` resolves back to, via `getent hosts` (so
+/// `/etc/hosts` and the system resolver are both consulted).
+fn reverse_lookup(address: Ipv4Addr) -> Option<String> {
+    let output = Command::new("getent")
+        .args(["hosts", &address.to_string()])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let text = String::from_utf8(output.stdout).ok()?;
+    text.split_whitespace().nth(1).map(str::to_string)
+}
+
+/// A host found by a scan: connected to by address, with the reverse DNS
+/// name as its alias so it can be searched.
+fn scanned_host(address: Ipv4Addr, name: Option<String>) -> ssh::Host {
+    ssh::Host {
+        name: address.to_string(),
+        aliases: name.unwrap_or_default(),
+        destination: address.to_string(),
+        source_file: Some("scan".to_string()),
+        ..ssh::Host::default()
+    }
+}
+
+/// Lists the resolved `service` announcements on the network with
+/// `avahi-browse`, which returns once its cache has been dumped.
+fn browse(service: &str) -> Result<String> {
+    let output = Command::new("avahi-browse")
+        .args(["--resolve", "--terminate", "--parsable", "--no-db-lookup", service])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .context("Failed to run avahi-browse for the mdns source (is avahi installed?)")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "avahi-browse could not browse {service} ({}); is avahi-daemon running?",
+            output.status
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Hosts of `avahi-browse --parsable` output: one per `.local` name, with a
+/// port only when it is not 22. Resolved lines look like
+/// `=;eth0;IPv4;pi;_ssh._tcp;local;pi.local;192.168.1.20;22;`.
+fn parse_avahi(output: &str) -> Vec<ssh::Host> {
+    let mut hosts: Vec<ssh::Host> = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split(';').collect();
+        let ["=", interface, protocol, _, _, _, hostname, address, port, ..] = fields[..] else {
+            continue;
+        };
+        let spec = match port {
+            "22" => hostname.to_string(),
+            port => format!("{hostname}:{port}"),
+        };
+        let Some(mut host) = ssh::Host::adhoc(&spec) else {
+            continue;
+        };
+        // 同じホストが IPv4/IPv6 やインターフェースごとに届くので 1 つにまとめる
+        if let Some(known) = hosts.iter_mut().find(|known| known.name == host.name) {
+            if protocol == "IPv4" {
+                known.note = Some(format!("mDNS {address} on {interface}"));
+            }
+            continue;
+        }
+        host.is_adhoc = false;
+        host.source_file = Some("mdns".to_string());
+        host.note = Some(format!("mDNS {address} on {interface}"));
+        hosts.push(host);
+    }
+    hosts
+}
+
+/// Decrypts `path` with the `age` or `gpg` command, capturing the plain text
+/// from stdout. stdin and stderr stay attached so passphrase and pinentry
+/// prompts work. For age, `$SGH_AGE_IDENTITY` names the identity file.
+fn decrypt(path: &Path) -> Result<String> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let mut command = match extension {
+        "age" => {
+            let mut command = Command::new("age");
+            command.arg("--decrypt");
+            if let Some(identity) = std::env::var_os("SGH_AGE_IDENTITY") {
+                command.arg("--identity").arg(identity);
+            }
+            command
+        }
+        "gpg" | "asc" | "pgp" => {
+            let mut command = Command::new("gpg");
+            command.args(["--quiet", "--decrypt"]);
+            command
+        }
+        _ => {
+            return Err(anyhow!(
+                "Cannot tell how {} is encrypted (expected .age, .gpg or .asc)",
+                path.display()
+            ))
+        }
+    };
+
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .arg(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run {program} to decrypt {}", path.display()))?;
+    if !output.status.success() {
+        return Err(anyhow!("{program} could not decrypt {} ({})", path.display(), output.status));
+    }
+
+    String::from_utf8(output.stdout).with_context(|| format!("{} is not UTF-8 text", path.display()))
+}
+
+/// Hosts of a decrypted bundle: an `sgh export` JSON host list, or SSH
+/// config text.
+fn parse_bundle(content: &str, path: &Path, pipeline: ssh::Pipeline) -> Result<Vec<ssh::Host>> {
+    let config = if content.trim_start().starts_with('{') {
+        exchange::from_json(content)?
+            .iter()
+            .map(|host| host.to_block().render())
+            .collect::<Result<Vec<_>>>()?
+            .join("\n")
+    } else {
+        content.to_string()
+    };
+
+    ssh::parse_config_str(&config, path, pipeline)
+        .map_err(|err| anyhow!("Failed to parse {}: {err:?}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The command a source host connects with, helpers refused so nothing runs.
+    fn connect_command(host: &ssh::Host) -> String {
+        let template = host.connect_template.as_deref().unwrap();
+        let Ok(rendered) = host.render_command_template(template, crate::template::Helpers::Refuse) else {
+            panic!("{template} did not render");
+        };
+        rendered.text
+    }
+
+    #[test]
+    fn sources_are_parsed_from_kind_and_argument() {
+        assert_eq!(
+            "encrypted:/tmp/hosts.age".parse::<Source>(),
+            Ok(Source::Encrypted(PathBuf::from("/tmp/hosts.age")))
+        );
+        assert!("encrypted:".parse::<Source>().is_err());
+        assert!("hosts.age".parse::<Source>().is_err());
+        assert!("ldap:foo".parse::<Source>().is_err());
+        assert_eq!("mdns".parse::<Source>(), Ok(Source::Mdns("_ssh._tcp".to_string())));
+        assert_eq!("mdns:_sftp-ssh._tcp".parse::<Source>(), Ok(Source::Mdns("_sftp-ssh._tcp".to_string())));
+    }
+
+    #[test]
+    fn scans_cover_the_hosts_of_a_bounded_network() {
+        assert_eq!(
+            "scan:192.168.1.77/24".parse::<Source>(),
+            Ok(Source::Scan(Ipv4Addr::new(192, 168, 1, 0), 24))
+        );
+        assert!("scan:10.0.0.0/8".parse::<Source>().is_err());
+        assert!("scan:10.0.0.0/33".parse::<Source>().is_err());
+        assert!("scan:example.com".parse::<Source>().is_err());
+
+        let addresses = scan_addresses(Ipv4Addr::new(10, 0, 0, 0), 30);
+        assert_eq!(addresses, [Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]);
+        assert_eq!(scan_addresses(Ipv4Addr::new(10, 0, 0, 7), 32), [Ipv4Addr::new(10, 0, 0, 7)]);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert_eq!(scan(Ipv4Addr::LOCALHOST, 32, port), [Ipv4Addr::LOCALHOST]);
+        drop(listener);
+        assert!(scan(Ipv4Addr::LOCALHOST, 32, port).is_empty());
+
+        let host = scanned_host(Ipv4Addr::new(10, 0, 0, 5), Some("db.lan".to_string()));
+        assert_eq!((host.name.as_str(), host.aliases.as_str()), ("10.0.0.5", "db.lan"));
+    }
+
+    #[test]
+    fn container_engines_list_ssh_endpoints_and_containers() {
+        assert_eq!(
+            "docker".parse::<Source>(),
+            Ok(Source::Containers { engine: Engine::Docker, containers: false })
+        );
+        assert_eq!(
+            "podman:containers".parse::<Source>(),
+            Ok(Source::Containers { engine: Engine::Podman, containers: true })
+        );
+        assert!("docker:images".parse::<Source>().is_err());
+
+        let contexts = "default\tunix:///var/run/docker.sock\nbuild\tssh://ops@build.example:2222\n";
+        let hosts = parse_endpoints(Engine::Docker, contexts);
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].name, "ssh://ops@build.example:2222");
+        assert_eq!(hosts[0].aliases, "build");
+
+        let connections = "machine\tssh://core@127.0.0.1:53389/run/user/501/podman/podman.sock\t/home/me/.ssh/machine\n";
+        let hosts = parse_endpoints(Engine::Podman, connections);
+        assert_eq!(hosts[0].destination, "127.0.0.1");
+        assert_eq!(hosts[0].identity_file.as_deref(), Some("/home/me/.ssh/machine"));
+
+        let hosts = parse_containers(Engine::Docker, "web-1\tnginx:1.27\n");
+        let command = hosts[0].render_command_template(ssh::CONTAINER_COMMAND_TEMPLATE, crate::template::Helpers::Run).unwrap().text;
+        assert_eq!(command, "docker exec -it \"web-1\" sh");
+    }
+
+    #[test]
+    fn vagrant_machines_are_named_after_their_project() {
+        assert_eq!(
+            "vagrant:/srv/vm/app".parse::<Source>(),
+            Ok(Source::Vagrant(PathBuf::from("/srv/vm/app")))
+        );
+        let output = "\
+Host default
+  HostName 127.0.0.1
+  User vagrant
+  Port 2222
+  IdentityFile /srv/vm/app/.vagrant/machines/default/virtualbox/private_key
+";
+        let hosts = parse_vagrant(output, Path::new("/srv/vm/app"), ssh::Pipeline::default()).unwrap();
+        assert_eq!(hosts[0].name, "app/default");
+        assert_eq!(hosts[0].port.as_deref(), Some("2222"));
+        assert_eq!(connect_command(&hosts[0]), "vagrant ssh default");
+
+        // マシン名はテンプレートとして評価されず、一語として渡る
+        let output = "Host {{destination}}\n  HostName 127.0.0.1\n";
+        let hosts = parse_vagrant(output, Path::new("/srv/vm/app"), ssh::Pipeline::default()).unwrap();
+        assert_eq!(connect_command(&hosts[0]), "vagrant ssh '{{destination}}'");
+        assert_eq!(hosts[0].env.get("VAGRANT_CWD").map(String::as_str), Some("/srv/vm/app"));
+        assert_eq!(hosts[0].source_file.as_deref(), Some("/srv/vm/app/Vagrantfile"));
+    }
+
+    #[test]
+    fn cloud_instances_are_filtered_by_label() {
+        assert_eq!(
+            "gcp:shop-prod,env=prod".parse::<Source>(),
+            Ok(Source::Cloud {
+                cloud: Cloud::Gcp,
+                scope: Some("shop-prod".to_string()),
+                labels: vec![("env".to_string(), "prod".to_string())],
+            })
+        );
+        assert_eq!(
+            "azure".parse::<Source>(),
+            Ok(Source::Cloud { cloud: Cloud::Azure, scope: None, labels: vec![] })
+        );
+        assert!("gcp:a,b".parse::<Source>().is_err());
+
+        let gcp = r#"[
+            {"name": "web-1", "zone": "https://www.googleapis.com/compute/v1/projects/shop-prod/zones/us-central1-a",
+             "selfLink": "https://www.googleapis.com/compute/v1/projects/shop-prod/zones/us-central1-a/instances/web-1",
+             "status": "RUNNING", "labels": {"env": "prod"},
+             "networkInterfaces": [{"networkIP": "10.0.0.2", "accessConfigs": [{"natIP": "34.1.2.3"}]}]},
+            {"name": "web-2", "zone": "zones/us-central1-a", "labels": {"env": "dev"},
+             "networkInterfaces": [{"networkIP": "10.0.0.3"}]}
+        ]"#;
+        let labels = [("env".to_string(), "prod".to_string())];
+        let hosts = parse_instances(Cloud::Gcp, gcp, &labels).unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].destination, "34.1.2.3");
+        assert_eq!(hosts[0].tags, ["env=prod"]);
+        assert_eq!(connect_command(&hosts[0]), "gcloud compute ssh web-1 --zone us-central1-a --project shop-prod");
+        assert_eq!(parse_instances(Cloud::Gcp, gcp, &[]).unwrap()[1].destination, "10.0.0.3");
+
+        let azure = r#"[{"name": "api", "resourceGroup": "shop", "powerState": "VM running", "publicIps": "",
+            "privateIps": "10.1.0.4", "osProfile": {"adminUsername": "azureuser"}, "tags": null}]"#;
+        let hosts = parse_instances(Cloud::Azure, azure, &[]).unwrap();
+        assert_eq!(hosts[0].destination, "10.1.0.4");
+        assert_eq!(connect_command(&hosts[0]), "ssh azureuser@10.1.0.4");
+        assert_eq!(hosts[0].note.as_deref(), Some("azure shop vm running"));
+    }
+
+    #[test]
+    fn slow_sources_are_answered_from_the_cache() {
+        let source: Source = "podman:containers".parse().unwrap();
+        assert_eq!(source.cache_key().as_deref(), Some("podman:containers"));
+        assert_eq!("encrypted:hosts.age".parse::<Source>().unwrap().cache_key(), None);
+
+        let hosts = parse_containers(Engine::Podman, "db\tpostgres:16\n");
+        let mut cache: SourceCache = store::load(SourceCache::FILE_NAME);
+        cache.sources.insert(
+            "podman:containers".to_string(),
+            CachedHosts {
+                fetched_at: 0,
+                hosts: hosts.clone(),
+            },
+        );
+        store::save(SourceCache::FILE_NAME, &cache).unwrap();
+
+        // 古いキャッシュもオフラインならそのまま使い、接続コマンドも残っている
+        let offline = CachePolicy {
+            ttl: DEFAULT_CACHE_TTL,
+            offline: true,
+        };
+        let loaded = source.load_cached(ssh::Pipeline::default(), offline).unwrap();
+        assert_eq!(loaded[0].name, "db");
+        assert_eq!(loaded[0].connect_template, hosts[0].connect_template);
+
+        let never_fetched: Source = "mdns:_sgh-test._tcp".parse().unwrap();
+        assert!(never_fetched.load_cached(ssh::Pipeline::default(), offline).unwrap().is_empty());
+    }
+
+    #[test]
+    fn source_hosts_are_folded_into_configured_hosts() {
+        let configured = ssh::parse_config_str(
+            "Host web-1\n  HostName 10.0.0.2\n  User deploy\n",
+            Path::new("config"),
+            ssh::Pipeline::default(),
+        )
+        .unwrap();
+        let gcp = r#"[{"name": "web-1", "zone": "zones/eu-west1-b", "labels": {"env": "prod"},
+            "networkInterfaces": [{"networkIP": "10.0.0.2", "accessConfigs": [{"natIP": "34.1.2.3"}]}]},
+            {"name": "web-2", "zone": "zones/eu-west1-b", "networkInterfaces": [{"networkIP": "10.0.0.3"}]}]"#;
+        let instances = parse_instances(Cloud::Gcp, gcp, &[]).unwrap();
+
+        // 既定では SSH 設定が勝ち、足りない項目だけ補う
+        let mut hosts = configured.clone();
+        merge_hosts(&mut hosts, instances.clone(), "gcp", &SourcePriority::default());
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(hosts[0].destination, "10.0.0.2");
+        assert_eq!(hosts[0].tags, ["env=prod"]);
+        assert!(hosts[0].source_options.is_empty());
+
+        let priority = BTreeMap::from([("HostName".to_string(), "gcp".to_string())]);
+        let mut hosts = configured;
+        merge_hosts(&mut hosts, instances, "gcp", &SourcePriority::parse(&priority).unwrap());
+        assert_eq!(hosts[0].destination, "34.1.2.3");
+        assert_eq!(hosts[0].user.as_deref(), Some("deploy"));
+        assert_eq!(hosts[0].option_sources["hostname"].describe(), "gcp source");
+        assert_eq!(hosts[0].source_options["hostname"], "34.1.2.3");
+
+        assert!(SourcePriority::parse(&BTreeMap::from([("proxyjump".to_string(), "gcp".to_string())])).is_err());
+        assert!(SourcePriority::parse(&BTreeMap::from([("user".to_string(), "aws".to_string())])).is_err());
+    }
+
+    #[test]
+    fn source_defaults_fill_in_what_the_source_leaves_out() {
+        let defaults = SourceDefaults {
+            user: Some("ec2-user".to_string()),
+            port: Some(2222),
+            template: Some("tsh ssh {{name}}".to_string()),
+        };
+        let mut hosts = parse_instances(Cloud::Azure, r#"[{"name": "api", "privateIps": "10.1.0.4", "osProfile": {"adminUsername": "azureuser"}}]"#, &[]).unwrap();
+        defaults.apply(&mut hosts);
+        assert_eq!(hosts[0].user.as_deref(), Some("azureuser"));
+        assert_eq!(hosts[0].port.as_deref(), Some("2222"));
+        assert_eq!(hosts[0].source_options.get("port").map(String::as_str), Some("2222"));
+        assert!(!hosts[0].source_options.contains_key("user"));
+        assert_eq!(hosts[0].connect_template.as_deref(), Some("tsh ssh {{name}}"));
+
+        assert!(SourceDefaults::validate(&BTreeMap::from([("gcp".to_string(), defaults.clone())])).is_ok());
+        assert!(SourceDefaults::validate(&BTreeMap::from([("config".to_string(), defaults)])).is_err());
+    }
+
+    #[test]
+    fn mdns_announcements_become_one_host_per_name() {
+        let output = "\
++;eth0;IPv6;pi;_ssh._tcp;local
+=;eth0;IPv6;pi;_ssh._tcp;local;pi.local;fe80::1;22;
+=;eth0;IPv4;pi;_ssh._tcp;local;pi.local;192.168.1.20;22;
+=;wlan0;IPv4;nas\\032box;_ssh._tcp;local;nas.local;192.168.1.30;2222;\"model=ds\"
+";
+        let hosts = parse_avahi(output);
+        let names: Vec<&str> = hosts.iter().map(|host| host.name.as_str()).collect();
+        assert_eq!(names, ["pi.local", "ssh://nas.local:2222"]);
+        assert_eq!(hosts[0].note.as_deref(), Some("mDNS 192.168.1.20 on eth0"));
+        assert_eq!(hosts[1].port.as_deref(), Some("2222"));
+        assert_eq!(hosts[1].source_file.as_deref(), Some("mdns"));
+        assert!(!hosts[0].is_adhoc);
+    }
+
+    #[test]
+    fn bundles_may_be_json_host_lists_or_config_text() {
+        let path = Path::new("team.age");
+        let json = r#"{"version": 1, "hosts": [{"name": "db", "hostname": "10.0.0.5", "tags": ["prod"]}]}"#;
+        let hosts = parse_bundle(json, path, ssh::Pipeline::default()).unwrap();
+        assert_eq!(hosts[0].name, "db");
+        assert_eq!(hosts[0].destination, "10.0.0.5");
+        assert_eq!(hosts[0].tags, vec!["prod"]);
+        assert_eq!(hosts[0].source_file.as_deref(), Some("team.age"));
+
+        let text = "Host web\n  HostName web.internal\n";
+        let hosts = parse_bundle(text, path, ssh::Pipeline::default()).unwrap();
+        assert_eq!(hosts[0].destination, "web.internal");
+    }
+
+    #[test]
+    fn unknown_extensions_are_rejected_before_running_anything() {
+        assert!(decrypt(Path::new("hosts.txt")).is_err());
+    }
+}
@@ -1,16 +1,45 @@
 use anyhow::anyhow;
 use glob::glob;
-use handlebars::Handlebars;
 use itertools::Itertools;
-use serde::Serialize;
-use ssh_config::host::LocalForward;
-use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use ssh_config::host::{DynamicForward, LocalForward, RemoteForward};
+use std::collections::{BTreeMap, VecDeque};
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, ExitStatus};
 
 use crate::ssh_config::{self, parser_error::ParseError, HostVecExt};
+use crate::template;
 
-#[derive(Debug, Serialize, Clone)]
+/// Placeholder redact mode shows instead of destinations, users and ports.
+pub const REDACTED: &str = "•••";
+
+/// Raw options that name a machine, a user or a port, masked by redact mode.
+const REDACTED_OPTIONS: &[&str] = &[
+    "hostname",
+    "user",
+    "port",
+    "proxyjump",
+    "proxycommand",
+    "localforward",
+    "remoteforward",
+    "hostkeyalias",
+    "bindaddress",
+];
+
+/// Connect command used when `--template` is not given.
+pub const DEFAULT_COMMAND_TEMPLATE: &str = "ssh \"{{{name}}}\"";
+
+/// Connect command used instead of the default one for hosts with a
+/// `# sgh:dir` directory: the remote shell starts in that directory. The
+/// directory is quoted for the remote shell, and the whole command once
+/// more as the single argument ssh passes on.
+pub const DIR_COMMAND_TEMPLATE: &str = "ssh -t \"{{{name}}}\" {{{shquote \"cd \" (shquote dir) \" && exec $SHELL\"}}}";
+
+/// Connect command used instead of the default one for containers listed by
+/// a `docker:containers` / `podman:containers` source.
+pub const CONTAINER_COMMAND_TEMPLATE: &str = "{{{container.engine}}} exec -it \"{{{container.name}}}\" sh";
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Host {
     pub name: String,
     pub aliases: String,
@@ -21,37 +50,542 @@ pub struct Host {
     pub proxy_jump: Option<String>,
     pub identity_file: Option<String>,
     pub local_forwards: Vec<LocalForward>,
+    pub remote_forwards: Vec<RemoteForward>,
+    pub dynamic_forwards: Vec<DynamicForward>,
+    pub source_file: Option<String>,
+    pub source_line: Option<usize>,
+    /// Every parsed keyword keyed by its lowercase name, exposed to templates
+    /// as `{{options.serveraliveinterval}}`.
+    pub options: BTreeMap<String, String>,
+    /// Where each keyword in `options` was declared.
+    #[serde(skip)]
+    pub option_sources: BTreeMap<String, OptionSource>,
+
+    /// `# sgh:tags prod web` labels used for searching and grouping.
+    pub tags: Vec<String>,
+    /// Free-form `# sgh:note ...` text shown in the detail panel.
+    pub note: Option<String>,
+    /// `# sgh:dir /srv/app` remote directory the default connect command
+    /// starts the shell in, as `{{dir}}`.
+    pub dir: Option<String>,
+    /// `# sgh:env AWS_PROFILE=prod` variables set on the connect command,
+    /// hooks and launched sessions. Also available as `{{env.AWS_PROFILE}}`.
+    pub env: BTreeMap<String, String>,
+
+    /// Wildcard-only `Host` block kept for inspection (`--show-patterns`).
+    /// These are not real destinations and are hidden by default.
+    #[serde(skip)]
+    pub is_pattern: bool,
+    /// Synthesized from a `user@host:port` search query that matched no
+    /// configured host.
+    #[serde(skip)]
+    pub is_adhoc: bool,
+
+    /// Per-host `# sgh:on-start` / `# sgh:on-end` hooks, used instead of the
+    /// global session templates when present.
+    #[serde(skip)]
+    pub on_session_start_template: Option<String>,
+    #[serde(skip)]
+    pub on_session_end_template: Option<String>,
+    /// Per-host `# sgh:otp <command>` printing a one-time code, used instead
+    /// of the global `otp_command`.
+    #[serde(skip)]
+    pub otp_command: Option<String>,
+    /// One-time code fetched right before connecting, exposed to the command
+    /// template and hooks as `{{otp}}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otp: Option<String>,
+    /// `-L` forwards of the tunnel presets picked for this connection
+    /// (Alt+T), added to the connect command.
+    #[serde(skip)]
+    pub tunnels: Vec<String>,
+    /// Remote command picked from the snippets (Alt+R), quoted as one shell
+    /// word and exposed to the snippet template as `{{snippet}}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+    /// Seconds the session lasted, exposed to the session end hook as
+    /// `{{duration}}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    /// Set for a running container from a `docker:containers` source; the
+    /// default command opens a shell in it with `{{container.engine}} exec`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<Container>,
+    /// Command used instead of the default connect command for hosts ssh
+    /// cannot reach by name, such as containers and Vagrant machines. Kept
+    /// in the JSON so cached and daemon-served hosts still connect.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_template: Option<String>,
+    /// Values a source looked up for its `connect_template`, such as the
+    /// GCP zone (`{{source_values.zone}}`). They are passed as data so a
+    /// name from the source is never evaluated as template code.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub source_values: BTreeMap<String, String>,
+    /// Keywords a source's value won over the SSH config for
+    /// (`source_priority`), passed to ssh as `-o keyword=value`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub source_options: BTreeMap<String, String>,
+}
+
+/// A container a host stands for, as `{{container.engine}}` (`docker` or
+/// `podman`) and `{{container.name}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Container {
+    pub engine: String,
+    pub name: String,
+}
+
+/// What a host's destination is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DestinationKind {
+    Ipv4,
+    Ipv6,
+    /// A dotted name such as `db.example.com`.
+    Fqdn,
+    /// A single-label name such as `db`, resolved through the search domains
+    /// or `/etc/hosts`.
+    Name,
+}
+
+impl DestinationKind {
+    /// Values an `is:` query term or color rule matches, e.g. `is:ip`.
+    #[must_use]
+    pub fn names(self) -> &'static [&'static str] {
+        match self {
+            DestinationKind::Ipv4 => &["ip", "ipv4"],
+            DestinationKind::Ipv6 => &["ip", "ipv6"],
+            DestinationKind::Fqdn => &["fqdn"],
+            DestinationKind::Name => &["name"],
+        }
+    }
+
+    /// Label shown in the detail panel.
+    #[must_use]
+    pub fn label(self) -> &'static str {
+        match self {
+            DestinationKind::Ipv4 => "IPv4",
+            DestinationKind::Ipv6 => "IPv6",
+            DestinationKind::Fqdn => "FQDN",
+            DestinationKind::Name => "bare name",
+        }
+    }
+}
+
+/// Names a host template can refer to: every serialized field of a host
+/// with all its optional fields set, so fields skipped while empty (`otp`,
+/// `snippet`, `duration`, `container`, ...) are listed as well.
+#[must_use]
+pub fn template_variables() -> Vec<String> {
+    let host = Host {
+        otp: Some(String::new()),
+        snippet: Some(String::new()),
+        duration: Some(0),
+        container: Some(Container {
+            engine: String::new(),
+            name: String::new(),
+        }),
+        connect_template: Some(String::new()),
+        source_values: BTreeMap::from([(String::new(), String::new())]),
+        source_options: BTreeMap::from([(String::new(), String::new())]),
+        ..Host::default()
+    };
+    match serde_json::to_value(host) {
+        Ok(serde_json::Value::Object(fields)) => fields.keys().cloned().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// `template` with `flags` added right after the command name, or `None`
+/// when the command is not ssh and the flags would mean nothing to it.
+#[must_use]
+pub fn insert_flags(template: &str, flags: &[String]) -> Option<String> {
+    let template = template.trim_start();
+    let (command, rest) = template.split_once(char::is_whitespace).unwrap_or((template, ""));
+    if std::path::Path::new(command).file_name().is_none_or(|name| name != "ssh") {
+        return None;
+    }
+    if flags.is_empty() {
+        return Some(template.to_string());
+    }
+    Some(format!("{command} {} {rest}", flags.join(" ")).trim_end().to_string())
+}
+
+/// Where an option's effective value was declared.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OptionSource {
+    /// `Host *.internal`, `(global)`, or a description of a derived value.
+    pub block: String,
+    /// `~/.ssh/config:12`, the line of the keyword itself.
+    pub location: Option<String>,
+    /// Copied in from a pattern block or the global section rather than
+    /// declared in the host's own block.
+    pub inherited: bool,
+}
+
+impl OptionSource {
+    /// `Host * (~/.ssh/config:1)`
+    #[must_use]
+    pub fn describe(&self) -> String {
+        match &self.location {
+            Some(location) => format!("{} ({location})", self.block),
+            None => self.block.clone(),
+        }
+    }
+}
+
+impl From<&ssh_config::host::Provenance> for OptionSource {
+    fn from(provenance: &ssh_config::host::Provenance) -> Self {
+        OptionSource {
+            block: provenance.block.clone(),
+            location: provenance
+                .source
+                .as_ref()
+                .map(|(file, line)| format!("{}:{line}", contract_home(&file.to_string_lossy()))),
+            inherited: provenance.inherited,
+        }
+    }
 }
 
 impl Host {
-    /// Uses the provided Handlebars template to run a command.
+    fn from_config_host(h: &ssh_config::Host, is_pattern: bool) -> Host {
+        Host {
+            name: h.get_patterns().first().unwrap_or(&String::new()).clone(),
+            aliases: h.get_patterns().iter().skip(1).join(", "),
+            user: h.get(&ssh_config::EntryType::User),
+            destination: h.get(&ssh_config::EntryType::Hostname).unwrap_or_default(),
+            port: h.get(&ssh_config::EntryType::Port),
+            proxy_command: h.get(&ssh_config::EntryType::ProxyCommand),
+            proxy_jump: h.get(&ssh_config::EntryType::ProxyJump),
+            identity_file: h.get(&ssh_config::EntryType::IdentityFile),
+            local_forwards: h.local_forwards.clone(),
+            remote_forwards: h.remote_forwards.clone(),
+            dynamic_forwards: h.dynamic_forwards.clone(),
+            source_file: h
+                .source
+                .as_ref()
+                .map(|(file, _)| file.to_string_lossy().into_owned()),
+            source_line: h.source.as_ref().map(|(_, line)| *line),
+            options: h
+                .entries()
+                .iter()
+                .map(|(key, value)| (key.keyword(), value.clone()))
+                .collect(),
+            option_sources: h
+                .entries()
+                .keys()
+                .filter_map(|key| Some((key.keyword(), OptionSource::from(h.provenance(key)?))))
+                .collect(),
+            tags: parse_tags(h),
+            note: h.directive("note").map(str::to_string),
+            dir: h.directive("dir").map(str::to_string),
+            env: parse_env(h),
+            is_pattern,
+            is_adhoc: false,
+            on_session_start_template: h.directive("on-start").map(str::to_string),
+            on_session_end_template: h.directive("on-end").map(str::to_string),
+            otp_command: h.directive("otp").map(str::to_string),
+            otp: None,
+            tunnels: Vec::new(),
+            snippet: None,
+            duration: None,
+            container: None,
+            connect_template: None,
+            source_values: BTreeMap::new(),
+            source_options: BTreeMap::new(),
+        }
+    }
+
+    /// Builds a host from a `[user@]host[:port]` query (`[::1]:2200` for IPv6)
+    /// to connect to a machine that is not in the config. The name is a
+    /// destination ssh accepts as is (`ssh://admin@myhost:2200` when a port is
+    /// given), so the default template works unchanged.
+    #[must_use]
+    pub fn adhoc(spec: &str) -> Option<Host> {
+        let spec = spec.trim();
+        let (user, rest) = match spec.rsplit_once('@') {
+            Some((user, rest)) if is_adhoc_word(user) => (Some(user), rest),
+            Some(_) => return None,
+            None => (None, spec),
+        };
+
+        let (destination, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+            let (address, after) = bracketed.split_once(']')?;
+            match after.strip_prefix(':') {
+                Some(port) => (address, Some(port)),
+                None if after.is_empty() => (address, None),
+                None => return None,
+            }
+        } else {
+            match rest.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (rest, None),
+            }
+        };
+
+        let valid_destination = !destination.is_empty()
+            && destination
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':'));
+        let valid_port = port.is_none_or(|port| port.parse::<u16>().is_ok_and(|p| p > 0));
+        if !valid_destination || !valid_port || (destination.contains(':') && !rest.starts_with('[')) {
+            return None;
+        }
+
+        let host_part = if destination.contains(':') {
+            format!("[{destination}]")
+        } else {
+            destination.to_string()
+        };
+        let user_part = user.map(|user| format!("{user}@")).unwrap_or_default();
+        let name = match port {
+            Some(port) => format!("ssh://{user_part}{host_part}:{port}"),
+            None => format!("{user_part}{destination}"),
+        };
+
+        Some(Host {
+            name,
+            user: user.map(str::to_string),
+            destination: destination.to_string(),
+            port: port.map(str::to_string),
+            is_adhoc: true,
+            ..Host::default()
+        })
+    }
+
+    /// `port` as a number; `None` when it is unset or not a port ssh accepts
+    /// (1–65535). `port` keeps the text as written for display and
+    /// templates.
+    #[must_use]
+    pub fn port_number(&self) -> Option<u16> {
+        self.port.as_deref().and_then(|port| port.trim().parse().ok()).filter(|port| *port > 0)
+    }
+
+    /// A copy for screenshots with the destination, user, port and whatever
+    /// else points at the machine (jump hosts, forward targets) replaced by
+    /// `REDACTED`. Names, tags and notes stay.
+    #[must_use]
+    pub fn redacted(&self) -> Host {
+        let mask = |value: &Option<String>| value.as_ref().map(|_| REDACTED.to_string());
+        let mut host = Host {
+            user: mask(&self.user),
+            port: mask(&self.port),
+            proxy_command: mask(&self.proxy_command),
+            proxy_jump: mask(&self.proxy_jump),
+            ..self.clone()
+        };
+        if !host.destination.is_empty() {
+            host.destination = REDACTED.to_string();
+        }
+        for (keyword, value) in &mut host.options {
+            if REDACTED_OPTIONS.contains(&keyword.as_str()) {
+                *value = REDACTED.to_string();
+            }
+        }
+        let bind = |address: &mut Option<String>| {
+            if address.is_some() {
+                *address = Some(REDACTED.to_string());
+            }
+        };
+        for forward in &mut host.local_forwards {
+            bind(&mut forward.bind_address);
+            forward.remote_host = REDACTED.to_string();
+        }
+        for forward in &mut host.remote_forwards {
+            bind(&mut forward.bind_address);
+            forward.local_host = REDACTED.to_string();
+        }
+        for forward in &mut host.dynamic_forwards {
+            bind(&mut forward.bind_address);
+        }
+        // ソースが勝った値 (-o hostname=... など) も宛先そのもの
+        for value in host.source_options.values_mut() {
+            *value = REDACTED.to_string();
+        }
+        host
+    }
+
+    /// Whether `port` is set to something ssh would refuse, like `22x`.
+    #[must_use]
+    pub fn has_invalid_port(&self) -> bool {
+        self.port.is_some() && self.port_number().is_none()
+    }
+
+    /// Whether this wildcard block applies to `name` the way ssh matches
+    /// `Host` patterns: some pattern matches and no `!pattern` does.
+    #[must_use]
+    pub fn applies_to(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        let mut matched = false;
+        for pattern in std::iter::once(self.name.as_str()).chain(self.aliases.split(", ")) {
+            let (negated, pattern) = pattern.strip_prefix('!').map_or((false, pattern), |p| (true, p));
+            // ssh の Host パターンは * と ? だけを特別扱いする
+            let glob = glob::Pattern::escape(&pattern.to_lowercase()).replace("[*]", "*").replace("[?]", "?");
+            if !pattern.is_empty() && glob::Pattern::new(&glob).is_ok_and(|glob| glob.matches(&name)) {
+                if negated {
+                    return false;
+                }
+                matched = true;
+            }
+        }
+        matched
+    }
+
+    /// How the destination is written; `None` when there is none (pattern
+    /// blocks without a Hostname).
+    #[must_use]
+    pub fn destination_kind(&self) -> Option<DestinationKind> {
+        // [::1] のような括弧付きや %eth0 付きの IPv6 も IP として扱う
+        let destination = self.destination.trim().trim_start_matches('[').trim_end_matches(']');
+        let address = destination.split_once('%').map_or(destination, |(address, _)| address);
+        if destination.is_empty() {
+            None
+        } else if address.parse::<std::net::Ipv4Addr>().is_ok() {
+            Some(DestinationKind::Ipv4)
+        } else if address.parse::<std::net::Ipv6Addr>().is_ok() {
+            Some(DestinationKind::Ipv6)
+        } else if destination.trim_end_matches('.').contains('.') {
+            Some(DestinationKind::Fqdn)
+        } else {
+            Some(DestinationKind::Name)
+        }
+    }
+
+    /// Forward rules in `ssh -L/-R/-D` argument form, e.g. `5432:localhost:5432`.
+    #[must_use]
+    pub fn forward_specs(&self) -> Vec<String> {
+        let local = self
+            .local_forwards
+            .iter()
+            .map(|lf| format!("{}:{}", lf.listen(), lf.target()));
+        let remote = self
+            .remote_forwards
+            .iter()
+            .map(|rf| format!("{}:{}", rf.listen(), rf.target()));
+        let dynamic = self.dynamic_forwards.iter().map(DynamicForward::listen);
+
+        local.chain(remote).chain(dynamic).collect()
+    }
+
+    /// Returns `file:line` of the block this host was declared in, with the
+    /// home directory contracted to `~` to keep the value short.
+    #[must_use]
+    pub fn source_display(&self) -> Option<String> {
+        let file = contract_home(self.source_file.as_deref()?);
+
+        Some(match self.source_line {
+            Some(line) => format!("{file}:{line}"),
+            None => file,
+        })
+    }
+
+    /// Renders the Handlebars template with this host as context. `helpers`
+    /// decides whether `cmd` and `secret` run; show the result through
+    /// `Rendered::redacted`, never `text`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the template cannot be rendered.
+    pub fn render_command_template(&self, pattern: &str, helpers: template::Helpers) -> anyhow::Result<template::Rendered> {
+        let mut rendered = template::render_with_helpers(pattern, &self, helpers)?;
+        if let Some(otp) = &self.otp {
+            rendered.mask(otp);
+        }
+        Ok(rendered)
+    }
+
+    fn render(&self, pattern: &str) -> anyhow::Result<template::Rendered> {
+        self.render_command_template(pattern, template::Helpers::Run)
+    }
+
+    /// Runs `command` (the host's `# sgh:otp` or the global one) and keeps
+    /// the code it prints as `{{otp}}` for this connection.
     ///
     /// # Errors
     ///
-    /// Will return `Err` if the command cannot be executed.
+    /// Will return `Err` if the command fails.
+    pub fn fetch_otp(&mut self, command: &str) -> anyhow::Result<()> {
+        self.otp = Some(template::fetch(command)?);
+        Ok(())
+    }
+
+    /// Uses the provided Handlebars template to run a command and returns its
+    /// exit status, leaving it to the caller to decide how to react to failure.
+    /// With `shell` the command runs through `$SHELL -c` (see `build_command`).
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// Will panic if the regex cannot be compiled.
-    pub fn run_command_template(&self, pattern: &str) -> anyhow::Result<()> {
-        let handlebars = Handlebars::new();
-        let rendered_command = handlebars.render_template(pattern, &self)?;
+    /// Will return `Err` if the template cannot be rendered or the command
+    /// cannot be executed.
+    pub fn run_command_template(&self, pattern: &str, shell: bool) -> anyhow::Result<ExitStatus> {
+        let rendered = self.render(pattern)?;
 
-        println!("Running command: {rendered_command}");
+        // cmd / secret ヘルパーや OTP で取得した値は画面に出さない
+        println!("Running command: {}", rendered.redacted());
 
-        let mut args = shlex::split(&rendered_command)
-            .ok_or(anyhow!("Failed to parse command: {rendered_command}"))?
-            .into_iter()
-            .collect::<VecDeque<String>>();
-        let command = args.pop_front().ok_or(anyhow!("Failed to get command"))?;
+        Ok(build_command(&rendered.text, shell)?
+            .envs(&self.env)
+            .spawn()?
+            .wait()?)
+    }
 
-        let status = Command::new(command).args(args).spawn()?.wait()?;
-        if !status.success() {
-            std::process::exit(status.code().unwrap_or(1));
-        }
+    /// Like `run_command_template`, but replaces the current process with the
+    /// command. It only returns if rendering or `exec` failed.
+    #[must_use]
+    pub fn exec_command_template(&self, pattern: &str, shell: bool) -> anyhow::Error {
+        let rendered = match self.render(pattern) {
+            Ok(rendered) => rendered,
+            Err(err) => return err,
+        };
+        println!("Running command: {}", rendered.redacted());
 
-        Ok(())
+        let mut command = match build_command(&rendered.text, shell) {
+            Ok(command) => command,
+            Err(err) => return err,
+        };
+        command.envs(&self.env);
+        exec(&mut command)
+    }
+}
+
+#[cfg(unix)]
+fn exec(command: &mut Command) -> anyhow::Error {
+    use std::os::unix::process::CommandExt;
+    command.exec().into()
+}
+
+#[cfg(not(unix))]
+fn exec(_command: &mut Command) -> anyhow::Error {
+    anyhow!("--exec is only supported on Unix")
+}
+
+/// Builds the process for a rendered command line. By default the line is
+/// split into arguments like a shell would, without pipes, `&&` or
+/// redirection; with `shell` it is passed to `$SHELL -c` (`/bin/sh` when
+/// unset) so those work too.
+///
+/// # Errors
+///
+/// Will return `Err` if the line cannot be split or is empty.
+pub fn build_command(command_line: &str, shell: bool) -> anyhow::Result<Command> {
+    if shell {
+        let program = std::env::var("SHELL")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "/bin/sh".to_string());
+        let mut command = Command::new(program);
+        command.arg("-c").arg(command_line);
+        return Ok(command);
     }
+
+    let mut args = shlex::split(command_line)
+        .ok_or(anyhow!("Failed to parse command: {command_line}"))?
+        .into_iter()
+        .collect::<VecDeque<String>>();
+    let program = args.pop_front().ok_or(anyhow!("Failed to get command"))?;
+
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
 }
 
 #[derive(Debug)]
@@ -105,37 +639,226 @@ pub fn expand_config_paths(raw_paths: &[String]) -> Vec<PathBuf> {
     out
 }
 
+/// Which post-processing stages `parse_config_with` runs on the parsed hosts.
+/// Each can be turned off to see the config closer to how it was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pipeline {
+    /// Fold the entries of `Host *` style pattern blocks into matching hosts.
+    pub apply_patterns: bool,
+    /// Use the Host name as Hostname when none is set.
+    pub default_hostname: bool,
+    /// Collapse hosts with identical settings into one row with aliases.
+    pub merge: bool,
+}
+
+impl Pipeline {
+    /// Every stage disabled (`--raw`).
+    pub const RAW: Pipeline = Pipeline {
+        apply_patterns: false,
+        default_hostname: false,
+        merge: false,
+    };
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            apply_patterns: true,
+            default_hostname: true,
+            merge: true,
+        }
+    }
+}
+
 /// # Errors
 ///
 /// Will return `Err` if the SSH configuration file cannot be parsed.
 pub fn parse_config<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<Host>, ParseConfigError> {
+    parse_config_with(path, Pipeline::default())
+}
+
+/// Like `parse_config`, running only the stages enabled in `pipeline`.
+///
+/// # Errors
+///
+/// Will return `Err` if the SSH configuration file cannot be parsed.
+pub fn parse_config_with<P: AsRef<std::path::Path>>(
+    path: P,
+    pipeline: Pipeline,
+) -> Result<Vec<Host>, ParseConfigError> {
     let path = std::fs::canonicalize(path.as_ref())?;
 
-    let hosts = ssh_config::Parser::new()
-        .parse_file(path)?
-        .apply_patterns()
-        .apply_name_to_empty_hostname()
-        .merge_same_hosts()
+    let parsed = ssh_config::Parser::new().parse_file(path)?;
+    Ok(process(&parsed, pipeline))
+}
+
+/// Like `parse_config_with` for config text that is not read from a file
+/// (e.g. decrypted in memory). `source` is recorded as the hosts' location.
+///
+/// # Errors
+///
+/// Will return `Err` if the SSH configuration cannot be parsed.
+pub fn parse_config_str(
+    content: &str,
+    source: &std::path::Path,
+    pipeline: Pipeline,
+) -> Result<Vec<Host>, ParseConfigError> {
+    let parsed = ssh_config::Parser::new()
+        .parse_with_source(&mut std::io::Cursor::new(content), Some(source))?;
+    Ok(process(&parsed, pipeline))
+}
+
+/// Runs the enabled pipeline stages and converts the result, followed by
+/// the pattern hosts.
+fn process(parsed: &Vec<ssh_config::Host>, pipeline: Pipeline) -> Vec<Host> {
+    let pattern_hosts = parsed.pattern_hosts();
+
+    let mut hosts = if pipeline.apply_patterns {
+        parsed.apply_patterns()
+    } else {
+        parsed.remove_patterns()
+    };
+    if pipeline.default_hostname {
+        hosts = hosts.apply_name_to_empty_hostname();
+    }
+    if pipeline.merge {
+        hosts = hosts.merge_same_hosts();
+    }
+
+    hosts
         .iter()
-        .map(|h| Host {
-            name: h.get_patterns().first().unwrap_or(&String::new()).clone(),
-            aliases: h.get_patterns().iter().skip(1).join(", "),
-            user: h.get(&ssh_config::EntryType::User),
-            destination: h.get(&ssh_config::EntryType::Hostname).unwrap_or_default(),
-            port: h.get(&ssh_config::EntryType::Port),
-            proxy_command: h.get(&ssh_config::EntryType::ProxyCommand),
-            proxy_jump: h.get(&ssh_config::EntryType::ProxyJump),
-            identity_file: h.get(&ssh_config::EntryType::IdentityFile),
-            local_forwards: h.local_forwards.clone(),
+        .map(|h| Host::from_config_host(h, false))
+        .chain(pattern_hosts.iter().map(|h| Host::from_config_host(h, true)))
+        .collect()
+}
+
+/// One row per name of every host, as the hosts were before merging:
+/// `Host db db-primary` becomes `db` and `db-primary` with no aliases.
+#[must_use]
+pub fn split_aliases(hosts: &[Host]) -> Vec<Host> {
+    hosts
+        .iter()
+        .flat_map(|host| {
+            std::iter::once(host.name.as_str())
+                .chain(host.aliases.split(", ").filter(|a| !a.is_empty()))
+                .map(|name| Host {
+                    name: name.to_string(),
+                    aliases: String::new(),
+                    ..host.clone()
+                })
         })
+        .collect()
+}
+
+/// The user ssh logs in as when `host` sets no `User`: that of the first
+/// wildcard block among `patterns` applying to its name, as `(user, Some("Host
+/// *"))`, otherwise `local_user` as `(user, None)`. `None` when `host` has a
+/// `User` of its own.
+#[must_use]
+pub fn effective_user<'a>(
+    host: &Host,
+    patterns: impl IntoIterator<Item = &'a Host>,
+    local_user: Option<&str>,
+) -> Option<(String, Option<String>)> {
+    if host.user.is_some() {
+        return None;
+    }
+    let from_pattern = patterns
+        .into_iter()
+        .filter(|pattern| pattern.is_pattern && pattern.applies_to(&host.name))
+        .find_map(|pattern| {
+            let block = std::iter::once(pattern.name.as_str())
+                .chain(pattern.aliases.split(", ").filter(|a| !a.is_empty()))
+                .join(" ");
+            Some((pattern.user.clone()?, Some(format!("Host {block}"))))
+        });
+    from_pattern.or_else(|| local_user.map(|user| (user.to_string(), None)))
+}
+
+/// Name of the user running sgh, from `$USER` or `$LOGNAME`.
+#[must_use]
+pub fn local_user() -> Option<String> {
+    ["USER", "LOGNAME"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|user| !user.is_empty())
+}
+
+/// A local port for a new `LocalForward`: the first one from `from` that no
+/// forward of `hosts` uses and that can be bound on localhost right now.
+#[must_use]
+pub fn free_local_port(hosts: &[Host], from: u16) -> Option<u16> {
+    // 127.0.0.1:5432 のような bind アドレス付きの指定も末尾のポートで比べる
+    let taken: Vec<u16> = hosts
+        .iter()
+        .flat_map(|host| &host.local_forwards)
+        .filter_map(|lf| lf.local_port.parse().ok())
         .collect();
+    (from..=u16::MAX)
+        .filter(|port| !taken.contains(port))
+        .take(200)
+        .find(|&port| std::net::TcpListener::bind(("127.0.0.1", port)).is_ok())
+}
+
+/// A user name usable in an ad-hoc destination.
+fn is_adhoc_word(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// Replaces the home directory prefix with `~`.
+pub(crate) fn contract_home(path: &str) -> String {
+    match std::env::var("HOME") {
+        Ok(home) if !home.is_empty() => contract_dir(path, &home),
+        _ => path.to_string(),
+    }
+}
+
+/// `path` with `home` written as `~` when it lies inside it. Whole
+/// components are compared, so `/home/al` is not a prefix of `/home/alice`.
+fn contract_dir(path: &str, home: &str) -> String {
+    match std::path::Path::new(path).strip_prefix(home) {
+        Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Ok(rest) => format!("~/{}", rest.display()),
+        Err(_) => path.to_string(),
+    }
+}
+
+/// Collects `# sgh:env KEY=VALUE` variables. The first value of a variable
+/// wins, as with ssh keywords, and `~` in values is expanded.
+fn parse_env(host: &ssh_config::Host) -> BTreeMap<String, String> {
+    let mut env = BTreeMap::new();
+    for (key, value) in host
+        .directive_values("env")
+        .filter_map(|value| value.split_once('='))
+    {
+        let key = key.trim();
+        if !key.is_empty() && !key.contains(char::is_whitespace) {
+            env.entry(key.to_string())
+                .or_insert_with(|| shellexpand::tilde(value.trim()).into_owned());
+        }
+    }
+    env
+}
 
-    Ok(hosts)
+/// Collects `# sgh:tags a b` / `# sgh:tag a,b` values, keeping the first
+/// occurrence of each tag.
+fn parse_tags(host: &ssh_config::Host) -> Vec<String> {
+    host.directive_values("tags")
+        .chain(host.directive_values("tag"))
+        .flat_map(|value| value.split([' ', '\t', ',']))
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .unique()
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use handlebars::Handlebars;
     use std::fs;
     use std::io::Write;
 
@@ -144,6 +867,14 @@ mod tests {
         file.write_all(contents.as_bytes()).expect("write test config");
     }
 
+    #[test]
+    fn home_is_only_contracted_at_a_directory_boundary() {
+        assert_eq!(contract_dir("/home/al/.ssh/config", "/home/al"), "~/.ssh/config");
+        assert_eq!(contract_dir("/home/al/.ssh/config", "/home/al/"), "~/.ssh/config");
+        assert_eq!(contract_dir("/home/al", "/home/al"), "~");
+        assert_eq!(contract_dir("/home/alice/.ssh/config", "/home/al"), "/home/alice/.ssh/config");
+    }
+
     #[test]
     fn expand_config_paths_returns_plain_paths_unchanged() {
         let result = expand_config_paths(&[
@@ -226,6 +957,333 @@ mod tests {
         assert_eq!(beta.proxy_jump.as_deref(), Some("alpha"));
     }
 
+    #[test]
+    fn parse_config_records_source_file_and_line() {
+        let dir = tempdir();
+        let main_cfg = dir.join("config");
+        let included = dir.join("included.conf");
+
+        write_config(&included, "\n# comment\nHost inc\n  Hostname inc.example\n");
+        write_config(
+            &main_cfg,
+            &format!(
+                "Include {}\n\nHost main\n  Hostname main.example\n",
+                included.display()
+            ),
+        );
+
+        let hosts = parse_config(&main_cfg).expect("parse");
+
+        let main = hosts.iter().find(|h| h.name == "main").unwrap();
+        let canonical_main = fs::canonicalize(&main_cfg).unwrap();
+        assert_eq!(
+            main.source_file.as_deref(),
+            Some(canonical_main.to_string_lossy().as_ref())
+        );
+        assert_eq!(main.source_line, Some(3));
+
+        let inc = hosts.iter().find(|h| h.name == "inc").unwrap();
+        assert!(inc.source_file.as_deref().unwrap().ends_with("included.conf"));
+        assert_eq!(inc.source_line, Some(3));
+    }
+
+    #[test]
+    fn template_variables_include_fields_skipped_while_empty() {
+        let variables = template_variables();
+        for name in ["name", "options", "env", "otp", "snippet", "duration", "container", "connect_template"] {
+            assert!(variables.iter().any(|variable| variable == name), "{name}");
+        }
+        assert!(!variables.iter().any(|variable| variable == "otp_command"));
+    }
+
+    #[test]
+    fn template_context_exposes_raw_options() {
+        let dir = tempdir();
+        let cfg = dir.join("config");
+        write_config(
+            &cfg,
+            "Host *\n  ServerAliveInterval 30\nHost web\n  Hostname web.example\n  IdentityFile ~/.ssh/id_web\n  XCustomThing yes\n",
+        );
+
+        let hosts = parse_config(&cfg).expect("parse");
+        let web = hosts.iter().find(|h| h.name == "web").unwrap();
+
+        let rendered = Handlebars::new()
+            .render_template(
+                "{{options.identityfile}} {{options.serveraliveinterval}} {{options.xcustomthing}}",
+                web,
+            )
+            .unwrap();
+        assert_eq!(rendered, "~/.ssh/id_web 30 yes");
+    }
+
+    #[test]
+    fn template_context_exposes_forwards_as_arrays() {
+        let dir = tempdir();
+        let cfg = dir.join("config");
+        write_config(
+            &cfg,
+            "Host db\n  LocalForward 5432 localhost:5432\n  LocalForward 6379 cache:6379\n  RemoteForward 9000 localhost:3000\n  DynamicForward 1080\n",
+        );
+
+        let hosts = parse_config(&cfg).expect("parse");
+        let rendered = Handlebars::new()
+            .render_template(
+                "ssh{{#each local_forwards}} -L {{local_port}}:{{remote_host}}:{{remote_port}}{{/each}}{{#each remote_forwards}} -R {{remote_port}}:{{local_host}}:{{local_port}}{{/each}}{{#each dynamic_forwards}} -D {{port}}{{/each}} {{name}}",
+                &hosts[0],
+            )
+            .unwrap();
+        assert_eq!(
+            rendered,
+            "ssh -L 5432:localhost:5432 -L 6379:cache:6379 -R 9000:localhost:3000 -D 1080 db"
+        );
+    }
+
+    #[test]
+    fn forwards_separate_the_bind_address_from_the_port() {
+        let dir = tempdir();
+        let cfg = dir.join("config");
+        write_config(
+            &cfg,
+            "Host db\n  LocalForward [::1]:8080 [fd00::5]:80\n  LocalForward *:5432 db:5432\n  RemoteForward 127.0.0.1:9000 localhost:3000\n  DynamicForward localhost:1080\n",
+        );
+
+        let host = &parse_config(&cfg).expect("parse")[0];
+        assert_eq!(host.local_forwards[0].bind_address.as_deref(), Some("::1"));
+        assert_eq!(host.local_forwards[0].remote_host, "fd00::5");
+        assert_eq!(host.remote_forwards[0].remote_port, "9000");
+        assert_eq!(host.dynamic_forwards[0].bind_address.as_deref(), Some("localhost"));
+        assert_eq!(
+            host.forward_specs(),
+            ["[::1]:8080:[fd00::5]:80", "*:5432:db:5432", "127.0.0.1:9000:localhost:3000", "localhost:1080"]
+        );
+        let rendered = Handlebars::new()
+            .render_template("{{#each dynamic_forwards}}{{port}}{{/each}}", host)
+            .unwrap();
+        assert_eq!(rendered, "1080");
+    }
+
+    #[test]
+    fn parse_config_reads_per_host_hook_directives() {
+        let dir = tempdir();
+        let cfg = dir.join("config");
+        write_config(
+            &cfg,
+            "Host *.corp\n  # sgh:on-start vpn-check\nHost a.corp\n  User a\nHost b.corp\n  # sgh:on-start echo b\n  #sgh:on-end echo bye\n  # sgh:otp ykman oath code -s corp\nHost plain\n  User p\n",
+        );
+
+        let hosts = parse_config(&cfg).expect("parse");
+        let find = |name: &str| hosts.iter().find(|h| h.name == name).unwrap();
+
+        assert_eq!(find("a.corp").on_session_start_template.as_deref(), Some("vpn-check"));
+        assert_eq!(find("b.corp").on_session_start_template.as_deref(), Some("echo b"));
+        assert_eq!(find("b.corp").on_session_end_template.as_deref(), Some("echo bye"));
+        assert!(find("plain").on_session_start_template.is_none());
+        assert_eq!(find("b.corp").otp_command.as_deref(), Some("ykman oath code -s corp"));
+        assert!(find("plain").otp_command.is_none());
+    }
+
+    #[test]
+    fn parse_config_reads_tags_and_note() {
+        let dir = tempdir();
+        let cfg = dir.join("config");
+        write_config(
+            &cfg,
+            "Host web\n  # sgh:tags prod, web\n  # sgh:tag eu prod\n  # sgh:note behind the LB\n  Hostname web.example\n",
+        );
+
+        let hosts = parse_config(&cfg).expect("parse");
+        assert_eq!(hosts[0].tags, vec!["prod", "web", "eu"]);
+        assert_eq!(hosts[0].note.as_deref(), Some("behind the LB"));
+    }
+
+    #[test]
+    fn build_command_splits_or_passes_the_line_to_a_shell() {
+        let output = |shell| {
+            let output = build_command("echo a 'b c' | tr a-z A-Z", shell).unwrap().output().unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+        assert_eq!(output(false), "a b c | tr a-z A-Z\n");
+        assert_eq!(output(true), "A B C\n");
+        assert!(build_command("", false).is_err());
+    }
+
+    #[test]
+    fn exec_returns_only_on_failure() {
+        let host = Host {
+            name: "web".to_string(),
+            ..Host::default()
+        };
+        let err = host.exec_command_template("sgh-no-such-command {{name}}", false);
+        assert!(err.to_string().contains("No such file"), "{err}");
+    }
+
+    #[test]
+    fn parse_config_reads_env_directives() {
+        let dir = tempdir();
+        let cfg = dir.join("config");
+        write_config(
+            &cfg,
+            "Host *.corp
+  # sgh:env AWS_PROFILE=corp
+Host eks.corp
+  Hostname eks
+Host web
+  # sgh:env AWS_PROFILE=web
+  # sgh:env KUBECONFIG = /k/web=1
+  # sgh:env AWS_PROFILE=ignored
+  # sgh:env broken
+  Hostname web.example
+",
+        );
+
+        let hosts = parse_config(&cfg).expect("parse");
+        let find = |name: &str| hosts.iter().find(|h| h.name == name).unwrap();
+        assert_eq!(
+            find("web").env,
+            BTreeMap::from([
+                ("AWS_PROFILE".to_string(), "web".to_string()),
+                ("KUBECONFIG".to_string(), "/k/web=1".to_string()),
+            ])
+        );
+        assert_eq!(find("eks.corp").env["AWS_PROFILE"], "corp");
+        assert_eq!(
+            find("web").render_command_template("{{env.AWS_PROFILE}}", template::Helpers::Run).unwrap().text,
+            "web"
+        );
+    }
+
+    #[test]
+    fn parse_config_keeps_pattern_hosts_marked() {
+        let dir = tempdir();
+        let cfg = dir.join("config");
+        write_config(
+            &cfg,
+            "Host *\n  User def\nHost *.internal db\n  Port 2222\nHost web\n  Hostname web.example\n",
+        );
+
+        let hosts = parse_config(&cfg).expect("parse");
+        let real: Vec<&str> = hosts.iter().filter(|h| !h.is_pattern).map(|h| h.name.as_str()).collect();
+        let patterns: Vec<&str> = hosts.iter().filter(|h| h.is_pattern).map(|h| h.name.as_str()).collect();
+
+        assert_eq!(real, vec!["db", "web"]);
+        assert_eq!(patterns, vec!["*", "*.internal"]);
+    }
+
+    #[test]
+    fn parse_config_records_where_options_were_inherited_from() {
+        let dir = tempdir();
+        let cfg = dir.join("config");
+        write_config(
+            &cfg,
+            "Compression yes\nHost web\n  User web\nHost *.internal\n  User internal\n  Port 2222\nHost db.internal\n  Port 22\nHost *\n  ServerAliveInterval 30\n",
+        );
+
+        let hosts = parse_config(&cfg).expect("parse");
+        let find = |name: &str| hosts.iter().find(|h| h.name == name).unwrap();
+
+        let db = find("db.internal");
+        assert!(!db.option_sources["port"].inherited);
+        assert!(db.option_sources["port"].location.as_ref().unwrap().ends_with("config:8"));
+        let user = &db.option_sources["user"];
+        assert!(user.inherited);
+        assert_eq!(user.block, "Host *.internal");
+        // the line of the keyword, not of the Host line
+        assert!(user.location.as_ref().unwrap().ends_with("config:5"));
+        assert_eq!(db.option_sources["serveraliveinterval"].block, "Host *");
+        let compression = &db.option_sources["compression"];
+        assert_eq!(compression.block, "(global)");
+        assert!(compression.location.as_ref().unwrap().ends_with("config:1"));
+
+        let web = find("web");
+        assert!(!web.option_sources["user"].inherited);
+        assert_eq!(web.option_sources["user"].block, "Host web");
+        assert_eq!(web.option_sources["hostname"].describe(), "(defaults to the Host name)");
+    }
+
+    #[test]
+    fn parse_config_with_skips_disabled_stages() {
+        let dir = tempdir();
+        let cfg = dir.join("config");
+        write_config(
+            &cfg,
+            "Host *\n  User def\nHost a b\n  Hostname shared\nHost c\n  Hostname shared\nHost d\n  Port 1\n",
+        );
+
+        let merged = parse_config(&cfg).expect("parse");
+        let merged: Vec<&Host> = merged.iter().filter(|h| !h.is_pattern).collect();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].user.as_deref(), Some("def"));
+
+        let no_merge = parse_config_with(
+            &cfg,
+            Pipeline {
+                merge: false,
+                ..Pipeline::default()
+            },
+        )
+        .expect("parse");
+        let names: Vec<&str> = no_merge.iter().filter(|h| !h.is_pattern).map(|h| h.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c", "d"]);
+
+        let raw = parse_config_with(&cfg, Pipeline::RAW).expect("parse");
+        let find = |name: &str| raw.iter().find(|h| h.name == name).unwrap();
+        assert_eq!(find("a").user, None);
+        assert_eq!(find("d").destination, "");
+        // pattern blocks are still listed for --show-patterns
+        assert!(raw.iter().any(|h| h.is_pattern && h.name == "*"));
+    }
+
+    #[test]
+    fn hosts_without_a_user_show_the_one_ssh_would_use() {
+        let config = "Host *.internal\n  User ops\nHost web.internal box\n  Port 2\nHost own\n  User me\n";
+        let raw = parse_config_str(config, std::path::Path::new("config"), Pipeline::RAW).expect("parse");
+        let effective = |name: &str| {
+            let host = raw.iter().find(|h| h.name == name).unwrap();
+            effective_user(host, &raw, Some("alice"))
+        };
+        assert_eq!(effective("web.internal"), Some(("ops".to_string(), Some("Host *.internal".to_string()))));
+        assert_eq!(effective("box"), Some(("alice".to_string(), None)));
+        assert_eq!(effective("own"), None);
+    }
+
+    #[test]
+    fn split_aliases_gives_each_name_a_row() {
+        let host = Host {
+            name: "db".to_string(),
+            aliases: "db-primary, pg".to_string(),
+            destination: "10.0.0.5".to_string(),
+            ..Host::default()
+        };
+
+        let rows = split_aliases(&[host]);
+        let names: Vec<&str> = rows.iter().map(|h| h.name.as_str()).collect();
+        assert_eq!(names, vec!["db", "db-primary", "pg"]);
+        assert!(rows.iter().all(|h| h.aliases.is_empty() && h.destination == "10.0.0.5"));
+    }
+
+    #[test]
+    fn adhoc_hosts_are_parsed_from_queries() {
+        let host = Host::adhoc("admin@myhost:2200").expect("adhoc");
+        assert_eq!(host.name, "ssh://admin@myhost:2200");
+        assert_eq!(host.user.as_deref(), Some("admin"));
+        assert_eq!(host.destination, "myhost");
+        assert_eq!(host.port.as_deref(), Some("2200"));
+        assert!(host.is_adhoc);
+
+        assert_eq!(Host::adhoc("root@10.0.0.5").unwrap().name, "root@10.0.0.5");
+        assert_eq!(Host::adhoc("box.lan").unwrap().name, "box.lan");
+        assert_eq!(Host::adhoc("[fe80::1]:22").unwrap().name, "ssh://[fe80::1]:22");
+        assert_eq!(Host::adhoc("[fe80::1]:22").unwrap().destination, "fe80::1");
+
+        assert!(Host::adhoc("").is_none());
+        assert!(Host::adhoc("web 01").is_none());
+        assert!(Host::adhoc("myhost:ssh").is_none());
+        assert!(Host::adhoc("myhost:0").is_none());
+        assert!(Host::adhoc("@myhost").is_none());
+        assert!(Host::adhoc("fe80::1").is_none());
+    }
+
     /// Minimal stand-in for `tempfile::tempdir()` so we do not add a new
     /// dev-dependency just for these tests. Cleanup is best-effort; the OS
     /// will reclaim the directory eventually.
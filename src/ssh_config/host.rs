@@ -1,23 +1,104 @@
 use regex::Regex;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 use super::EntryType;
 
-#[derive(Debug, serde::Serialize, Clone)]
+/// `LocalForward [bind_address:]<local_port> <remote_host>:<remote_port>`
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
 pub struct LocalForward {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<String>,
     pub local_port: String,
     pub remote_host: String,
     pub remote_port: String,
 }
 
+impl LocalForward {
+    /// `[bind_address:]port` ssh listens on locally.
+    #[must_use]
+    pub fn listen(&self) -> String {
+        listen_address(self.bind_address.as_deref(), &self.local_port)
+    }
+
+    /// `host:port` connections are forwarded to.
+    #[must_use]
+    pub fn target(&self) -> String {
+        host_port(&self.remote_host, &self.remote_port)
+    }
+}
+
+/// `RemoteForward [bind_address:]<remote_port> <local_host>:<local_port>`
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct RemoteForward {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<String>,
+    pub remote_port: String,
+    pub local_host: String,
+    pub local_port: String,
+}
+
+impl RemoteForward {
+    /// `[bind_address:]port` the server listens on.
+    #[must_use]
+    pub fn listen(&self) -> String {
+        listen_address(self.bind_address.as_deref(), &self.remote_port)
+    }
+
+    /// `host:port` connections are forwarded to, from this side.
+    #[must_use]
+    pub fn target(&self) -> String {
+        host_port(&self.local_host, &self.local_port)
+    }
+}
+
+/// `DynamicForward [bind_address:]port`
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct DynamicForward {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_address: Option<String>,
+    pub port: String,
+}
+
+impl DynamicForward {
+    /// `[bind_address:]port` the SOCKS proxy listens on.
+    #[must_use]
+    pub fn listen(&self) -> String {
+        listen_address(self.bind_address.as_deref(), &self.port)
+    }
+}
+
 pub(crate) type Entry = (EntryType, String);
 
+/// Where an entry's effective value was declared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// Block the keyword appeared in: `Host *.internal`, `(global)` for
+    /// settings outside any Host block, or a description of a derived value.
+    pub block: String,
+    /// File and 1-based line of the keyword itself, when known.
+    pub source: Option<(PathBuf, usize)>,
+    /// Whether the value was copied in from another block (pattern, global
+    /// section) rather than declared in the host's own block.
+    pub inherited: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct Host {
     patterns: Vec<String>,
     entries: HashMap<EntryType, String>,
+    provenance: HashMap<EntryType, Provenance>,
 
     pub local_forwards: Vec<LocalForward>,
+    pub remote_forwards: Vec<RemoteForward>,
+    pub dynamic_forwards: Vec<DynamicForward>,
+
+    /// `# sgh:<key> <value>` comment directives, in file order.
+    directives: Vec<(String, String)>,
+
+    /// File and 1-based line of the `Host` keyword that opened this block.
+    /// `None` for blocks parsed from a reader without a known path.
+    pub source: Option<(PathBuf, usize)>,
 }
 
 impl Host {
@@ -26,7 +107,28 @@ impl Host {
         Host {
             patterns,
             entries: HashMap::new(),
+            provenance: HashMap::new(),
             local_forwards: vec![],
+            remote_forwards: vec![],
+            dynamic_forwards: vec![],
+            directives: vec![],
+            source: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_source(mut self, file: PathBuf, line: usize) -> Host {
+        self.source = Some((file, line));
+        self
+    }
+
+    /// Like `update`, additionally recording the file and line the entry was
+    /// read from.
+    pub(crate) fn update_at(&mut self, entry: Entry, file: Option<&Path>, line: usize) {
+        let key = entry.0.clone();
+        self.update(entry);
+        if let Some(provenance) = self.provenance.get_mut(&key) {
+            provenance.source = file.map(|file| (file.to_path_buf(), line));
         }
     }
 
@@ -35,46 +137,132 @@ impl Host {
         match entry.0 {
             EntryType::LocalForward => {
                 // 例: value = "8888 localhost:8888"
-                let trimmed = entry.1.trim();
-                let parts: Vec<&str> = trimmed.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let local_port = parts[0];
-                    let remote_info = parts[1];
-                    let remote_parts: Vec<&str> = remote_info.split(':').collect();
-                    if remote_parts.len() == 2 {
-                        let lf = LocalForward {
-                            local_port: local_port.to_string(),
-                            remote_host: remote_parts[0].to_string(),
-                            remote_port: remote_parts[1].to_string(),
-                        };
-                        self.local_forwards.push(lf);
-                    }
+                if let Some((bind_address, local_port, Some((remote_host, remote_port)))) = parse_forward(&entry.1) {
+                    self.local_forwards.push(LocalForward {
+                        bind_address,
+                        local_port,
+                        remote_host,
+                        remote_port,
+                    });
+                }
+            }
+            EntryType::RemoteForward => {
+                // 例: value = "9000 localhost:3000"
+                if let Some((bind_address, remote_port, Some((local_host, local_port)))) = parse_forward(&entry.1) {
+                    self.remote_forwards.push(RemoteForward {
+                        bind_address,
+                        remote_port,
+                        local_host,
+                        local_port,
+                    });
+                }
+            }
+            EntryType::DynamicForward => {
+                if let Some((bind_address, port, None)) = parse_forward(&entry.1) {
+                    self.dynamic_forwards.push(DynamicForward { bind_address, port });
                 }
             }
             _ => {
+                // 自ブロックでの宣言として出どころを記録し直す
+                self.provenance.insert(
+                    entry.0.clone(),
+                    Provenance {
+                        block: self.block_name(),
+                        source: None,
+                        inherited: false,
+                    },
+                );
                 self.entries.insert(entry.0, entry.1);
             }
         }
     }
 
+    /// sgh 独自のコメントディレクティブ (`# sgh:on-start ...` など) を追加する
+    pub fn add_directive(&mut self, key: String, value: String) {
+        self.directives.push((key, value));
+    }
+
+    /// Last value of the given directive, so later lines override earlier ones.
+    #[allow(clippy::must_use_candidate)]
+    pub fn directive(&self, key: &str) -> Option<&str> {
+        self.directives
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// All values of the given directive, in file order.
+    pub fn directive_values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.directives
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
     pub(crate) fn extend_patterns(&mut self, host: &Host) {
         self.patterns.extend(host.patterns.clone());
     }
 
+    /// `Host a b`, or `(global)` for the settings outside any Host block.
+    fn block_name(&self) -> String {
+        if self.patterns.is_empty() {
+            "(global)".to_string()
+        } else {
+            format!("Host {}", self.patterns.join(" "))
+        }
+    }
+
+    /// Provenance to record when copying `key` out of `host` into another
+    /// host: always marked as inherited.
+    fn inherited_provenance(host: &Host, key: &EntryType) -> Provenance {
+        let mut provenance = host.provenance.get(key).cloned().unwrap_or_else(|| Provenance {
+            block: host.block_name(),
+            source: None,
+            inherited: false,
+        });
+        provenance.inherited = true;
+        provenance
+    }
+
     pub(crate) fn extend_entries(&mut self, host: &Host) {
+        for key in host.entries.keys() {
+            // 同一内容のホストのマージでは自分側の記録を残す。
+            // グローバル設定 (Include 等) は継承として記録する
+            if host.patterns.is_empty() {
+                self.provenance
+                    .insert(key.clone(), Self::inherited_provenance(host, key));
+            } else if let Some(provenance) = host.provenance.get(key) {
+                self.provenance
+                    .entry(key.clone())
+                    .or_insert_with(|| provenance.clone());
+            }
+        }
         self.entries.extend(host.entries.clone());
+        self.directives.extend(host.directives.clone());
+        self.extend_forwards(host);
+    }
+
+    fn extend_forwards(&mut self, host: &Host) {
         self.local_forwards.extend(host.local_forwards.clone());
+        self.remote_forwards.extend(host.remote_forwards.clone());
+        self.dynamic_forwards.extend(host.dynamic_forwards.clone());
     }
 
     pub(crate) fn extend_if_not_contained(&mut self, host: &Host) {
         for (key, value) in &host.entries {
             if !self.entries.contains_key(key) {
                 self.entries.insert(key.clone(), value.clone());
+                self.provenance
+                    .insert(key.clone(), Self::inherited_provenance(host, key));
             }
         }
-        for lf in &host.local_forwards {
-            self.local_forwards.push(lf.clone());
+        for (key, value) in &host.directives {
+            if self.directive(key).is_none() {
+                self.directives.push((key.clone(), value.clone()));
+            }
         }
+        self.extend_forwards(host);
     }
 
     #[allow(clippy::must_use_candidate)]
@@ -121,10 +309,73 @@ impl Host {
         self.entries.get(entry).cloned()
     }
 
+    /// Where the effective value of `entry` was declared.
+    #[allow(clippy::must_use_candidate)]
+    pub fn provenance(&self, entry: &EntryType) -> Option<&Provenance> {
+        self.provenance.get(entry)
+    }
+
+    /// Every keyword/value pair collected for this host, after pattern and
+    /// global settings have been applied.
+    #[allow(clippy::must_use_candidate)]
+    pub fn entries(&self) -> &HashMap<EntryType, String> {
+        &self.entries
+    }
+
     #[allow(clippy::must_use_candidate)]
     pub fn is_empty(&self) -> bool {
-        self.entries.is_empty() && self.local_forwards.is_empty()
+        self.entries.is_empty()
+            && self.directives.is_empty()
+            && self.local_forwards.is_empty()
+            && self.remote_forwards.is_empty()
+            && self.dynamic_forwards.is_empty()
+    }
+}
+
+/// A forward directive's value split into the listening side, as the bind
+/// address and port, and the `host:port` target when one is given. All
+/// three forward keywords share this so `[::1]:8080` and `*:8080` mean the
+/// same everywhere.
+type ParsedForward = (Option<String>, String, Option<(String, String)>);
+
+fn parse_forward(value: &str) -> Option<ParsedForward> {
+    let mut parts = value.split_whitespace();
+    let (bind_address, port) = split_address(parts.next()?);
+    if port.is_empty() {
+        return None;
     }
+    let target = match parts.next() {
+        Some(target) => match split_address(target) {
+            (Some(host), port) if !port.is_empty() => Some((host, port)),
+            _ => return None,
+        },
+        None => None,
+    };
+    Some((bind_address, port, target))
+}
+
+/// Splits `[address:]port`, with an IPv6 address in brackets (`[::1]:80`).
+fn split_address(value: &str) -> (Option<String>, String) {
+    if let Some((address, port)) = value.strip_prefix('[').and_then(|rest| rest.split_once("]:")) {
+        return (Some(address.to_string()), port.to_string());
+    }
+    match value.rsplit_once(':') {
+        Some((address, port)) => (Some(address.to_string()).filter(|a| !a.is_empty()), port.to_string()),
+        None => (None, value.to_string()),
+    }
+}
+
+/// `address:port`, bracketing an IPv6 address.
+fn host_port(address: &str, port: &str) -> String {
+    if address.contains(':') {
+        format!("[{address}]:{port}")
+    } else {
+        format!("{address}:{port}")
+    }
+}
+
+fn listen_address(bind_address: Option<&str>, port: &str) -> String {
+    bind_address.map_or_else(|| port.to_string(), |address| host_port(address, port))
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -144,6 +395,16 @@ pub trait HostVecExt {
     /// Apply patterns entries to non-pattern hosts and remove the pattern hosts.
     #[must_use]
     fn apply_patterns(&self) -> Self;
+
+    /// The pattern hosts (`Host *`, `Host *.internal`, `Host !foo`) that
+    /// `apply_patterns` folds into other hosts, one per pattern.
+    #[must_use]
+    fn pattern_hosts(&self) -> Self;
+
+    /// Spreads the hosts like `apply_patterns` and removes the pattern hosts
+    /// without applying their entries.
+    #[must_use]
+    fn remove_patterns(&self) -> Self;
 }
 
 impl HostVecExt for Vec<Host> {
@@ -154,6 +415,14 @@ impl HostVecExt for Vec<Host> {
             if host.get(&EntryType::Hostname).is_none() {
                 if let Some(name) = host.patterns.first() {
                     host.update((EntryType::Hostname, name.clone()));
+                    host.provenance.insert(
+                        EntryType::Hostname,
+                        Provenance {
+                            block: "(defaults to the Host name)".to_string(),
+                            source: None,
+                            inherited: true,
+                        },
+                    );
                 }
             }
         }
@@ -178,6 +447,11 @@ impl HostVecExt for Vec<Host> {
                     continue;
                 }
 
+                // sgh ディレクティブ (フック等) が異なるホストはまとめない
+                if current_host.directives != target_host.directives {
+                    continue;
+                }
+
                 // if we want to compare local_forwards as well
 
                 // if current_host.local_forwards != target_host.local_forwards {
@@ -262,4 +536,18 @@ impl HostVecExt for Vec<Host> {
 
         hosts
     }
+
+    fn pattern_hosts(&self) -> Self {
+        self.spread()
+            .into_iter()
+            .filter(|host| !host.matching_pattern_regexes().is_empty())
+            .collect()
+    }
+
+    fn remove_patterns(&self) -> Self {
+        self.spread()
+            .into_iter()
+            .filter(|host| host.matching_pattern_regexes().is_empty())
+            .collect()
+    }
 }
@@ -108,3 +108,15 @@ pub enum EntryType {
     VisualHostKey,
     XAuthLocation,
 }
+
+impl EntryType {
+    /// Lowercase keyword as written in `ssh_config`, e.g. `serveraliveinterval`.
+    /// Unknown keywords keep the spelling found in the file, lowercased.
+    #[must_use]
+    pub fn keyword(&self) -> String {
+        match self {
+            EntryType::Unknown(key) => key.to_lowercase(),
+            other => other.to_string().to_lowercase(),
+        }
+    }
+}
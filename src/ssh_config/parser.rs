@@ -38,15 +38,29 @@ impl Parser {
     where
         P: AsRef<Path>,
     {
+        let path = path.as_ref();
         let mut reader = BufReader::new(File::open(path)?);
-        self.parse(&mut reader)
+        self.parse_with_source(&mut reader, Some(path))
     }
 
     /// # Errors
     ///
     /// Will return `Err` if the SSH configuration cannot be parsed.
     pub fn parse(&self, reader: &mut impl BufRead) -> Result<Vec<Host>, ParseError> {
-        let (global_host, mut hosts) = self.parse_raw(reader)?;
+        self.parse_with_source(reader, None)
+    }
+
+    /// Like `parse`, recording `path` as the location of the hosts.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the SSH configuration cannot be parsed.
+    pub fn parse_with_source(
+        &self,
+        reader: &mut impl BufRead,
+        path: Option<&Path>,
+    ) -> Result<Vec<Host>, ParseError> {
+        let (global_host, mut hosts) = self.parse_blocks(reader, path)?;
 
         // merge global settings into each host
         if !global_host.is_empty() {
@@ -58,15 +72,37 @@ impl Parser {
         Ok(hosts)
     }
 
-    fn parse_raw(&self, reader: &mut impl BufRead) -> Result<(Host, Vec<Host>), ParseError> {
+    /// Like `parse_with_source`, but returns the settings outside any Host
+    /// block separately instead of merging them into each block, and the
+    /// blocks as written, in file order.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the SSH configuration cannot be parsed.
+    pub fn parse_blocks(
+        &self,
+        reader: &mut impl BufRead,
+        path: Option<&Path>,
+    ) -> Result<(Host, Vec<Host>), ParseError> {
         let mut global_host = Host::new(Vec::new());
         let mut is_in_host_block = false;
-        let mut hosts = Vec::new();
+        let mut hosts: Vec<Host> = Vec::new();
 
         let mut line = String::new();
+        let mut line_number = 0;
         while reader.read_line(&mut line)? > 0 {
+            line_number += 1;
             let original_line = line.clone();
             line = line.trim().to_string();
+            if let Some((key, value)) = parse_directive(&line) {
+                if is_in_host_block {
+                    hosts.last_mut().unwrap().add_directive(key, value);
+                } else {
+                    global_host.add_directive(key, value);
+                }
+                line.clear();
+                continue;
+            }
             if line.is_empty() || line.starts_with('#') {
                 line.clear();
                 continue;
@@ -76,18 +112,20 @@ impl Parser {
             line.clear();
 
             match entry.0 {
-                EntryType::Unknown(_) => {
-                    if !self.ignore_unknown_entries {
-                        return Err(UnknownEntryError {
-                            line: original_line,
-                            entry: entry.0.to_string(),
-                        }
-                        .into());
+                EntryType::Unknown(_) if !self.ignore_unknown_entries => {
+                    return Err(UnknownEntryError {
+                        line: original_line,
+                        entry: entry.0.to_string(),
                     }
+                    .into());
                 }
                 EntryType::Host => {
                     let patterns = parse_patterns(&entry.1);
-                    hosts.push(Host::new(patterns));
+                    let host = match path {
+                        Some(path) => Host::new(patterns).with_source(path.to_path_buf(), line_number),
+                        None => Host::new(patterns),
+                    };
+                    hosts.push(host);
                     is_in_host_block = true;
                     continue;
                 }
@@ -122,8 +160,9 @@ impl Parser {
                             }
                         };
 
-                        let mut file = BufReader::new(File::open(path)?);
-                        let (included_global_host, included_hosts) = self.parse_raw(&mut file)?;
+                        let mut file = BufReader::new(File::open(&path)?);
+                        let (included_global_host, included_hosts) =
+                            self.parse_blocks(&mut file, Some(&path))?;
 
                         if is_in_host_block {
                             // Can't include hosts inside a host block
@@ -154,9 +193,9 @@ impl Parser {
             }
 
             if is_in_host_block {
-                hosts.last_mut().unwrap().update(entry);
+                hosts.last_mut().unwrap().update_at(entry, path, line_number);
             } else {
-                global_host.update(entry);
+                global_host.update_at(entry, path, line_number);
             }
         }
 
@@ -185,6 +224,21 @@ fn parse_line(line: &str) -> Result<Entry, ParseError> {
     ))
 }
 
+/// `# sgh:<key> <value>` 形式のコメントを sgh 用ディレクティブとして読む。
+/// ssh 自体にとっては通常のコメントなので設定の互換性は保たれる。
+fn parse_directive(line: &str) -> Option<(String, String)> {
+    let rest = line.strip_prefix('#')?.trim_start().strip_prefix("sgh:")?;
+    let (key, value) = match rest.split_once(char::is_whitespace) {
+        Some((key, value)) => (key, value.trim()),
+        None => (rest, ""),
+    };
+    if key.is_empty() {
+        return None;
+    }
+
+    Some((key.to_lowercase(), value.to_string()))
+}
+
 fn parse_patterns(entry_value: &str) -> Vec<String> {
     let mut patterns = Vec::new();
 
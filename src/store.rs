@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+
+/// Directory holding sgh's persisted state: `$SGH_DATA_DIR` when set,
+/// otherwise `sgh` under the platform data directory
+/// (`~/.local/share/sgh` on Linux).
+#[must_use]
+pub fn data_dir() -> Option<PathBuf> {
+    match std::env::var_os("SGH_DATA_DIR") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        // テストでは実際のデータディレクトリを汚さない
+        _ if cfg!(test) => {
+            Some(std::env::temp_dir().join(format!("sgh-test-data-{}", std::process::id())))
+        }
+        _ => dirs::data_dir().map(|dir| dir.join("sgh")),
+    }
+}
+
+/// Reads `<data_dir>/<file_name>` as JSON. A missing or unreadable file yields
+/// the default value so a corrupt state file never prevents sgh from starting.
+#[must_use]
+pub fn load<T>(file_name: &str) -> T
+where
+    T: DeserializeOwned + Default,
+{
+    data_dir()
+        .map(|dir| dir.join(file_name))
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Writes `value` as pretty JSON to `<data_dir>/<file_name>`, creating the
+/// directory if needed.
+///
+/// # Errors
+///
+/// Will return `Err` if the data directory cannot be determined or written.
+pub fn save<T>(file_name: &str, value: &T) -> Result<()>
+where
+    T: Serialize,
+{
+    let dir = data_dir().ok_or(anyhow!("Cannot determine the data directory"))?;
+    std::fs::create_dir_all(&dir)?;
+
+    // 書き込み途中で壊れないよう一時ファイル経由で置き換える
+    let path = dir.join(file_name);
+    let tmp = dir.join(format!("{file_name}.tmp"));
+    std::fs::write(&tmp, serde_json::to_string_pretty(value)?)?;
+    std::fs::rename(tmp, path)?;
+
+    Ok(())
+}
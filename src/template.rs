@@ -0,0 +1,501 @@
+use anyhow::Result;
+use handlebars::template::{HelperTemplate, Parameter, Template, TemplateElement};
+use handlebars::{
+    Context, Handlebars, Helper, HelperResult, Output, Path, PathSeg, RenderContext, RenderError,
+    RenderErrorReason,
+};
+use serde::Serialize;
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Helpers available in every template: Handlebars' built-ins plus `cmd`,
+/// `secret` and `shquote`.
+pub const HELPERS: [&str; 20] = [
+    "if", "unless", "each", "with", "lookup", "raw", "log", "eq", "ne", "gt", "gte", "lt", "lte",
+    "and", "or", "not", "len", "cmd", "secret", "shquote",
+];
+
+/// Command `{{secret "<path>"}}` runs with the path appended.
+pub const DEFAULT_SECRET_COMMAND: &str = "pass show";
+
+static SECRET_COMMAND: OnceLock<String> = OnceLock::new();
+
+/// Sets the command used by the `secret` helper (from the config file). Only
+/// the first call has an effect.
+pub fn set_secret_command(command: String) {
+    let _ = SECRET_COMMAND.set(command);
+}
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Makes rendering fail on references to missing fields (`{{destinaton}}`,
+/// an unset `{{options.port}}`) instead of inserting an empty string.
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// What the `cmd` and `secret` helpers do while rendering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Helpers {
+    /// Run the command and insert the first line of its output.
+    Run,
+    /// Insert `<cmd …>` / `<secret …>` without running anything, to show what
+    /// a command would look like (`--demo`).
+    Placeholder,
+    /// Fail: for text that is written out or shown rather than run (exported
+    /// commands, scheduler entries, titles), where the value would end up in
+    /// plain text.
+    Refuse,
+}
+
+/// A rendered template, remembering the values fetched by the `cmd` and
+/// `secret` helpers so they can be masked when the command is shown.
+pub struct Rendered {
+    pub text: String,
+    secrets: Vec<String>,
+}
+
+impl Rendered {
+    /// The text with every fetched secret replaced by `****`, for printing.
+    #[must_use]
+    pub fn redacted(&self) -> String {
+        self.secrets
+            .iter()
+            .filter(|secret| !secret.is_empty())
+            .fold(self.text.clone(), |text, secret| text.replace(secret.as_str(), "****"))
+    }
+
+    /// Also masks `value` (e.g. a one-time code passed in the context).
+    pub fn mask(&mut self, value: &str) {
+        self.secrets.push(value.to_string());
+    }
+}
+
+/// Runs `command_line` and returns the first line of its output, the way the
+/// `cmd` helper does. Used for values fetched before rendering, such as the
+/// one-time code of `otp_command`.
+///
+/// # Errors
+///
+/// Will return `Err` if the command cannot be run or exits with failure.
+pub fn fetch(command_line: &str) -> Result<String> {
+    Ok(run_for_value(command_line)?)
+}
+
+/// Renders a user template with `context`. Besides the built-in helpers,
+/// `{{cmd "pass show work/bastion"}}` inserts the first line of a command's
+/// output and `{{secret "work/bastion"}}` does the same through the
+/// configured secret command. Fetched values only live in the returned
+/// string; nothing is cached or written to disk.
+///
+/// # Errors
+///
+/// Will return `Err` if the template is invalid or a helper command fails.
+pub fn render<T: Serialize>(template: &str, context: &T) -> Result<Rendered> {
+    render_with(template, context, STRICT.load(Ordering::Relaxed), Helpers::Run)
+}
+
+/// Like `render`, with `helpers` deciding whether `cmd` and `secret` run.
+///
+/// # Errors
+///
+/// Will return `Err` if the template is invalid, a helper command fails, or
+/// a helper is used with `Helpers::Refuse`.
+pub fn render_with_helpers<T: Serialize>(template: &str, context: &T, helpers: Helpers) -> Result<Rendered> {
+    render_with(template, context, STRICT.load(Ordering::Relaxed), helpers)
+}
+
+fn render_with<T: Serialize>(template: &str, context: &T, strict: bool, helpers: Helpers) -> Result<Rendered> {
+    let secrets = Arc::new(Mutex::new(Vec::new()));
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(strict);
+
+    let cmd_secrets = Arc::clone(&secrets);
+    handlebars.register_helper(
+        "cmd",
+        Box::new(
+            move |h: &Helper, _: &Handlebars, _: &Context, _: &mut RenderContext, out: &mut dyn Output| -> HelperResult {
+                let command = string_param(h, "cmd")?;
+                match helpers {
+                    Helpers::Run => write_secret(&cmd_secrets, &run_for_value(command)?, out),
+                    Helpers::Placeholder => Ok(out.write(&format!("<cmd {command}>"))?),
+                    Helpers::Refuse => Err(refused("cmd")),
+                }
+            },
+        ),
+    );
+
+    let secret_secrets = Arc::clone(&secrets);
+    handlebars.register_helper(
+        "secret",
+        Box::new(
+            move |h: &Helper, _: &Handlebars, _: &Context, _: &mut RenderContext, out: &mut dyn Output| -> HelperResult {
+                let path = string_param(h, "secret")?;
+                match helpers {
+                    Helpers::Run => {}
+                    Helpers::Placeholder => return Ok(out.write(&format!("<secret {path}>"))?),
+                    Helpers::Refuse => return Err(refused("secret")),
+                }
+                let command = SECRET_COMMAND
+                    .get()
+                    .map_or(DEFAULT_SECRET_COMMAND, String::as_str);
+                let quoted = shlex::try_quote(path).map_err(|_| {
+                    RenderError::from(RenderErrorReason::Other(format!("Invalid secret path: {path}")))
+                })?;
+                write_secret(&secret_secrets, &run_for_value(&format!("{command} {quoted}"))?, out)
+            },
+        ),
+    );
+
+    handlebars.register_helper(
+        "shquote",
+        Box::new(
+            |h: &Helper, _: &Handlebars, _: &Context, _: &mut RenderContext, out: &mut dyn Output| -> HelperResult {
+                let text: String = h
+                    .params()
+                    .iter()
+                    .map(|param| param.value().as_str().map_or_else(|| param.value().to_string(), str::to_string))
+                    .collect();
+                Ok(out.write(&shell_quote(&text)?)?)
+            },
+        ),
+    );
+
+    let text = handlebars.render_template(template, context)?;
+    let secrets = secrets.lock().map(|s| s.clone()).unwrap_or_default();
+    Ok(Rendered { text, secrets })
+}
+
+/// A mistake found in a template before it is rendered.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Checks `template` without rendering it (so no helper command runs):
+/// syntax errors, unknown helpers, and variables that are not among
+/// `variables` such as a misspelt `{{destinaton}}`. Only names looked up in
+/// the top-level context are checked; the inside of `each` / `with` blocks
+/// is not.
+#[must_use]
+pub fn validate(template: &str, variables: &[String]) -> Vec<Problem> {
+    let compiled = match Template::compile(template) {
+        Ok(compiled) => compiled,
+        Err(err) => {
+            let (line, column) = err.pos().unwrap_or((1, 1));
+            return vec![Problem {
+                line,
+                column,
+                message: err.reason().to_string(),
+            }];
+        }
+    };
+
+    let mut problems = Vec::new();
+    check_template(&compiled, variables, &mut problems);
+    problems
+}
+
+/// Whether `template` calls `cmd` or `secret`, whose commands may prompt on
+/// the terminal (a password manager asking for its passphrase, ...).
+#[must_use]
+pub fn runs_commands(template: &str) -> bool {
+    Template::compile(template).is_ok_and(|compiled| calls_command(&compiled))
+}
+
+fn calls_command(template: &Template) -> bool {
+    template.elements.iter().any(|element| match element {
+        TemplateElement::Expression(helper)
+        | TemplateElement::HtmlExpression(helper)
+        | TemplateElement::HelperBlock(helper) => helper_calls_command(helper),
+        _ => false,
+    })
+}
+
+fn helper_calls_command(helper: &HelperTemplate) -> bool {
+    let in_subexpression = |parameter: &Parameter| match parameter {
+        Parameter::Subexpression(sub) => {
+            matches!(sub.as_element(), TemplateElement::Expression(inner) if helper_calls_command(inner))
+        }
+        _ => false,
+    };
+    matches!(&helper.name, Parameter::Name(name) if name == "cmd" || name == "secret")
+        || helper.params.iter().chain(helper.hash.values()).any(in_subexpression)
+        || helper.template.iter().chain(helper.inverse.iter()).any(calls_command)
+}
+
+fn check_template(template: &Template, variables: &[String], problems: &mut Vec<Problem>) {
+    let mut position = (1, 1);
+    for (i, element) in template.elements.iter().enumerate() {
+        if let Some(mapping) = template.mapping.get(i) {
+            position = (mapping.0, mapping.1);
+        }
+        match element {
+            TemplateElement::Expression(helper) | TemplateElement::HtmlExpression(helper) => {
+                check_helper(helper, false, position, variables, problems);
+            }
+            TemplateElement::HelperBlock(helper) => {
+                check_helper(helper, true, position, variables, problems);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn check_helper(
+    helper: &HelperTemplate,
+    block: bool,
+    position: (usize, usize),
+    variables: &[String],
+    problems: &mut Vec<Problem>,
+) {
+    let mut report = |message: String| {
+        problems.push(Problem {
+            line: position.0,
+            column: position.1,
+            message,
+        });
+    };
+
+    // 引数付き・ブロックならヘルパー呼び出し、そうでなければ変数参照
+    let is_call = block || !helper.params.is_empty() || !helper.hash.is_empty();
+    match &helper.name {
+        Parameter::Name(name) if is_call && !HELPERS.contains(&name.as_str()) => {
+            report(format!("unknown helper `{name}`"));
+        }
+        Parameter::Name(name)
+            if !HELPERS.contains(&name.as_str()) && !variables.contains(name) =>
+        {
+            report(format!("unknown variable `{name}`"));
+        }
+        Parameter::Name(_) => {}
+        parameter => {
+            if let Some(message) = check_parameter(parameter, variables) {
+                report(message);
+            }
+        }
+    }
+    for parameter in helper.params.iter().chain(helper.hash.values()) {
+        if let Some(message) = check_parameter(parameter, variables) {
+            report(message);
+        }
+    }
+
+    // each / with はコンテキストが変わるので中身は検査しない
+    let keeps_context = matches!(&helper.name, Parameter::Name(n) if n == "if" || n == "unless");
+    if block && keeps_context {
+        for inner in helper.template.iter().chain(helper.inverse.iter()) {
+            check_template(inner, variables, problems);
+        }
+    }
+}
+
+fn check_parameter(parameter: &Parameter, variables: &[String]) -> Option<String> {
+    match parameter {
+        Parameter::Name(name) => {
+            (!variables.contains(name)).then(|| format!("unknown variable `{name}`"))
+        }
+        Parameter::Path(Path::Relative((segments, _))) => match segments.first() {
+            Some(PathSeg::Named(name)) if !variables.contains(name) => {
+                Some(format!("unknown variable `{name}`"))
+            }
+            _ => None,
+        },
+        Parameter::Subexpression(sub) => {
+            let TemplateElement::Expression(helper) = sub.as_element() else {
+                return None;
+            };
+            let mut problems = Vec::new();
+            check_helper(helper, false, (0, 0), variables, &mut problems);
+            problems.into_iter().next().map(|p| p.message)
+        }
+        _ => None,
+    }
+}
+
+fn string_param<'a>(h: &'a Helper, name: &'static str) -> Result<&'a str, RenderError> {
+    h.param(0)
+        .ok_or(RenderErrorReason::ParamNotFoundForIndex(name, 0))?
+        .value()
+        .as_str()
+        .ok_or(RenderErrorReason::InvalidParamType("string").into())
+}
+
+/// `text` as one shell word. A leading `~` or `~/` stays outside the quotes
+/// so the (remote) shell still expands it to the home directory.
+fn shell_quote(text: &str) -> Result<String, RenderError> {
+    let (home, rest) = match text.strip_prefix('~') {
+        Some("") => return Ok("~".to_string()),
+        Some(rest) if rest.starts_with('/') => ("~/", &rest[1..]),
+        _ => ("", text),
+    };
+    if rest.is_empty() {
+        return Ok(home.to_string());
+    }
+    let quoted = shlex::try_quote(rest)
+        .map_err(|_| RenderError::from(RenderErrorReason::Other(format!("Cannot quote {text:?} for a shell"))))?;
+    Ok(format!("{home}{quoted}"))
+}
+
+fn refused(helper: &str) -> RenderError {
+    RenderError::from(RenderErrorReason::Other(format!(
+        "`{{{{{helper}}}}}` is only evaluated when connecting; its value would be written out in plain text here"
+    )))
+}
+
+fn write_secret(secrets: &Mutex<Vec<String>>, value: &str, out: &mut dyn Output) -> HelperResult {
+    if let Ok(mut secrets) = secrets.lock() {
+        secrets.push(value.to_string());
+    }
+    out.write(value)?;
+    Ok(())
+}
+
+/// Runs `command_line` and returns the first line of its stdout. stdin and
+/// stderr stay attached so password prompts (gpg, pass) keep working.
+fn run_for_value(command_line: &str) -> Result<String, RenderError> {
+    let fail = |message: String| RenderError::from(RenderErrorReason::Other(message));
+
+    let mut args = shlex::split(command_line)
+        .ok_or_else(|| fail(format!("Failed to parse command: {command_line}")))?
+        .into_iter();
+    let program = args.next().ok_or_else(|| fail("Empty command".to_string()))?;
+
+    let output = Command::new(&program)
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| fail(format!("Failed to run {program}: {err}")))?;
+    if !output.status.success() {
+        return Err(fail(format!("`{command_line}` failed ({})", output.status)));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn cmd_helper_inserts_the_first_line_of_output() {
+        let rendered = render(
+            r#"sshpass -p {{cmd "printf 'hunter2\nextra'"}} ssh {{name}}"#,
+            &json!({"name": "bastion"}),
+        )
+        .unwrap();
+        assert_eq!(rendered.text, "sshpass -p hunter2 ssh bastion");
+        assert_eq!(rendered.redacted(), "sshpass -p **** ssh bastion");
+    }
+
+    #[test]
+    fn failing_helper_commands_abort_rendering() {
+        assert!(render(r#"{{cmd "false"}}"#, &json!({})).is_err());
+        assert!(render(r"{{cmd}}", &json!({})).is_err());
+    }
+
+    #[test]
+    fn helpers_can_be_refused_for_text_that_is_written_out() {
+        let context = json!({"name": "db"});
+        // Rendered は値を含むので Debug を持たない
+        let Err(err) = render_with_helpers(r#"PGPASSWORD={{cmd "echo hunter2"}} psql -h {{name}}"#, &context, Helpers::Refuse) else {
+            panic!("cmd was evaluated");
+        };
+        let err = err.to_string();
+        assert!(err.contains("{{cmd}}") && !err.contains("hunter2"), "{err}");
+        assert!(render_with_helpers(r#"{{secret "db"}}"#, &context, Helpers::Refuse).is_err());
+        assert_eq!(render_with_helpers("ssh {{name}}", &context, Helpers::Refuse).unwrap().text, "ssh db");
+    }
+
+    #[test]
+    fn placeholders_stand_in_for_helpers_without_running_them() {
+        let rendered = render_with_helpers(
+            r#"sshpass -p '{{secret "corp/db"}}' ssh {{name}} {{cmd "touch /nonexistent/ran"}}"#,
+            &json!({"name": "db"}),
+            Helpers::Placeholder,
+        )
+        .unwrap();
+        assert_eq!(rendered.text, "sshpass -p '<secret corp/db>' ssh db <cmd touch /nonexistent/ran>");
+    }
+
+    #[test]
+    fn templates_running_commands_are_recognised() {
+        assert!(runs_commands(r#"sshpass -p {{{secret "ssh/web"}}} ssh {{name}}"#));
+        assert!(runs_commands(r#"ssh {{#if user}}{{{shquote (cmd "whoami")}}}{{/if}} {{name}}"#));
+        assert!(runs_commands(r#"{{#each local_forwards}}{{cmd "true"}}{{/each}}"#));
+        assert!(!runs_commands(r#"ssh -t "{{{name}}}" {{{shquote dir}}}"#));
+        assert!(!runs_commands("ssh {{#if"));
+    }
+
+    #[test]
+    fn shquote_makes_one_shell_word_and_keeps_the_home_prefix() {
+        let quote = |dir: &str| render("{{{shquote dir}}}", &json!({ "dir": dir })).unwrap().text;
+        assert_eq!(quote("/srv/app"), "/srv/app");
+        assert_eq!(shlex::split(&quote("/srv/it's $(id)")).unwrap(), ["/srv/it's $(id)"]);
+        assert_eq!(quote("~/my app"), "~/'my app'");
+        assert_eq!(quote("~"), "~");
+        assert_eq!(quote("~root"), "'~root'");
+        assert_eq!(render(r#"{{{shquote "a " 1}}}"#, &json!({})).unwrap().text, "'a 1'");
+    }
+
+    #[test]
+    fn plain_templates_are_not_redacted() {
+        let rendered = render(r#"ssh "{{{name}}}""#, &json!({"name": "a&b"})).unwrap();
+        assert_eq!(rendered.text, r#"ssh "a&b""#);
+        assert_eq!(rendered.redacted(), rendered.text);
+    }
+
+    #[test]
+    fn masked_context_values_are_redacted() {
+        let mut rendered = render("ssh {{name}} --code {{otp}}", &json!({"name": "vpn", "otp": "123456"})).unwrap();
+        rendered.mask("123456");
+        assert_eq!(rendered.redacted(), "ssh vpn --code ****");
+        assert_eq!(fetch("echo 654321").unwrap(), "654321");
+    }
+
+    #[test]
+    fn strict_mode_rejects_missing_fields() {
+        let context = json!({"name": "web", "user": null});
+        assert_eq!(render_with("ssh {{destinaton}}{{name}}", &context, false, Helpers::Run).unwrap().text, "ssh web");
+        assert!(render_with("ssh {{destinaton}}{{name}}", &context, true, Helpers::Run).is_err());
+        // 値が null のフィールドは存在するので通る
+        assert_eq!(render_with("ssh {{user}}{{name}}", &context, true, Helpers::Run).unwrap().text, "ssh web");
+    }
+
+    #[test]
+    fn validate_reports_typos_with_their_position() {
+        let variables = ["name".to_string(), "user".to_string(), "options".to_string()];
+        let messages = |template: &str| {
+            validate(template, &variables)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(messages(r#"ssh {{#if user}}{{user}}@{{/if}}{{{name}}} {{options.port}} {{secret "x"}}"#).is_empty());
+        assert!(messages("{{#each options}}{{@key}}={{this}} {{whatever}}{{/each}}").is_empty());
+        assert_eq!(messages("ssh {{destinaton}}"), vec!["line 1, column 5: unknown variable `destinaton`"]);
+        assert_eq!(
+            messages("ssh \\\n  {{#if usr}}-l {{user}}{{/if}} {{name}}"),
+            vec!["line 2, column 3: unknown variable `usr`"]
+        );
+        assert_eq!(messages(r#"{{shout name}}"#), vec!["line 1, column 1: unknown helper `shout`"]);
+        assert!(messages("ssh {{name}")[0].starts_with("line 1, column 11: invalid handlebars syntax"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+pub const ELLIPSIS: &str = "…";
+
+/// Cuts `value` so it fits in `max_width` columns, ending with `…` when
+/// shortened. Suits names and hostnames where the beginning matters most.
+#[must_use]
+pub fn truncate_end(value: &str, max_width: usize) -> Cow<'_, str> {
+    if value.width() <= max_width {
+        return Cow::Borrowed(value);
+    }
+    if max_width == 0 {
+        return Cow::Borrowed("");
+    }
+
+    let mut out = take_width(value.graphemes(true), max_width - 1);
+    out.push_str(ELLIPSIS);
+    Cow::Owned(out)
+}
+
+/// Cuts the middle of `value` so it fits in `max_width` columns, keeping both
+/// ends: `~/.ssh/…/id_ed25519`. Suits paths and commands whose tail (file
+/// name, target host) is as informative as their head.
+#[must_use]
+pub fn truncate_middle(value: &str, max_width: usize) -> Cow<'_, str> {
+    if value.width() <= max_width {
+        return Cow::Borrowed(value);
+    }
+    if max_width < 3 {
+        return truncate_end(value, max_width);
+    }
+
+    let budget = max_width - 1;
+    let head_width = budget.div_ceil(2);
+    let tail_width = budget - head_width;
+
+    let head = take_width(value.graphemes(true), head_width);
+    let tail: String = take_width(value.graphemes(true).rev(), tail_width)
+        .graphemes(true)
+        .rev()
+        .collect();
+
+    Cow::Owned(format!("{head}{ELLIPSIS}{tail}"))
+}
+
+/// Pads `value` with spaces up to `width` display columns. Unlike `{:<N}`,
+/// which counts chars, this keeps CJK and emoji aligned.
+#[must_use]
+pub fn pad_end(value: &str, width: usize) -> String {
+    let pad = width.saturating_sub(value.width());
+    format!("{value}{}", " ".repeat(pad))
+}
+
+/// Key glyphs used in hints and their ASCII spelling (`--ascii`).
+const KEY_NAMES: [(&str, &str); 4] = [("↑↓", "up/down"), ("↵", "enter"), ("⌫", "bksp"), ("^", "ctrl+")];
+
+/// Spells out key glyphs such as `↵` so hints read well without Unicode and
+/// in screen readers.
+#[must_use]
+pub fn spell_keys(text: &str) -> String {
+    KEY_NAMES
+        .iter()
+        .fold(text.to_string(), |text, (glyph, name)| text.replace(glyph, name))
+}
+
+/// One-column ASCII stand-in for a box-drawing or symbol glyph, `None` for
+/// everything else (host names keep their characters).
+#[must_use]
+pub fn ascii_symbol(symbol: &str) -> Option<&'static str> {
+    let ascii = match symbol {
+        "─" | "━" | "═" | "┄" => "-",
+        "│" | "┃" | "║" | "┆" => "|",
+        "╭" | "╮" | "╰" | "╯" | "┌" | "┐" | "└" | "┘" | "├" | "┤" | "┬" | "┴" | "┼" => "+",
+        "❯" | "▌" | "→" | "▶" => ">",
+        "←" | "◀" | "↩" => "<",
+        "↑" => "^",
+        "↓" => "v",
+        "●" | "•" | "✓" => "*",
+        "·" => ".",
+        "≠" => "#",
+        "…" => "~",
+        "↵" => "<",
+        "⌫" => "<",
+        "█" | "▒" | "░" => "#",
+        _ => return None,
+    };
+    Some(ascii)
+}
+
+/// Collects grapheme clusters until adding the next one would exceed
+/// `max_width`. Working on clusters keeps combining marks and ZWJ emoji
+/// sequences intact, and a wide character never straddles the limit.
+fn take_width<'a>(graphemes: impl Iterator<Item = &'a str>, max_width: usize) -> String {
+    let mut out = String::new();
+    let mut width = 0;
+    for grapheme in graphemes {
+        let w = grapheme.width();
+        if width + w > max_width {
+            break;
+        }
+        width += w;
+        out.push_str(grapheme);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyphs_have_ascii_stand_ins() {
+        assert_eq!(spell_keys(" ↵ save  ^t template  ↑↓ field "), " enter save  ctrl+t template  up/down field ");
+        assert_eq!(ascii_symbol("╭"), Some("+"));
+        assert_eq!(ascii_symbol("…"), Some("~"));
+        assert_eq!(ascii_symbol("東"), None);
+    }
+
+    #[test]
+    fn short_values_are_borrowed_unchanged() {
+        assert!(matches!(truncate_end("web", 10), Cow::Borrowed("web")));
+        assert!(matches!(truncate_middle("web", 3), Cow::Borrowed("web")));
+    }
+
+    #[test]
+    fn truncate_end_keeps_prefix() {
+        assert_eq!(truncate_end("database-primary", 8), "databas…");
+        assert_eq!(truncate_end("database-primary", 8).width(), 8);
+    }
+
+    #[test]
+    fn truncate_middle_keeps_both_ends() {
+        let cmd = "ssh -W %h:%p bastion.example.com";
+        let out = truncate_middle(cmd, 15);
+        assert_eq!(out, "ssh -W …ple.com");
+        assert_eq!(out.width(), 15);
+    }
+
+    #[test]
+    fn wide_characters_never_straddle_the_limit() {
+        // 各文字が 2 桁幅。半端な 1 桁は余白として残し、はみ出しはしない
+        let name = "東京サーバー";
+        assert_eq!(name.width(), 12);
+        assert_eq!(truncate_end(name, 7), "東京サ…");
+        assert_eq!(truncate_end(name, 6), "東京…");
+        assert_eq!(truncate_end(name, 6).width(), 5);
+        assert_eq!(truncate_middle(name, 9), "東京…バー");
+    }
+
+    #[test]
+    fn grapheme_clusters_are_kept_intact() {
+        // e + U+0301 (combining acute) is one column and must not be split.
+        let combining = "cafe\u{301}-prod";
+        assert_eq!(truncate_end(combining, 5), "cafe\u{301}…");
+
+        // Family emoji is a single ZWJ sequence.
+        let family = "👨\u{200d}👩\u{200d}👧 home";
+        let out = truncate_end(family, 4);
+        assert!(out.starts_with("👨\u{200d}👩\u{200d}👧"));
+        assert!(out.width() <= 4);
+
+        let flag = "🇯🇵-tokyo-01";
+        assert_eq!(truncate_middle(flag, 6), "🇯🇵-…01");
+    }
+
+    #[test]
+    fn pad_end_counts_display_columns() {
+        assert_eq!(pad_end("東京", 6), "東京  ");
+        assert_eq!(pad_end("web", 6), "web   ");
+        assert_eq!(pad_end("toolong", 3), "toolong");
+    }
+
+    #[test]
+    fn tiny_widths_fall_back_to_end_truncation() {
+        assert_eq!(truncate_middle("abcdef", 2), "a…");
+        assert_eq!(truncate_end("abcdef", 0), "");
+    }
+}
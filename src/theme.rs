@@ -1,4 +1,16 @@
 use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+/// Color scheme of the picker (`--theme`).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeName {
+    #[default]
+    Dark,
+    /// The 16 basic terminal colors at full brightness, for low vision and
+    /// terminals without true color.
+    HighContrast,
+}
 
 /// Modern dark palette. RGB tuples are stored explicitly so the theme remains
 /// readable when comparing against the design notes in `.tmp/design-plan.md`.
@@ -6,14 +18,19 @@ pub struct Theme {
     pub primary: Color,
     pub accent: Color,
     pub success: Color,
+    pub error: Color,
     pub muted: Color,
     pub border: Color,
     pub border_focused: Color,
     pub selection_bg: Color,
+    /// Background of the row under the mouse cursor.
+    pub hover_bg: Color,
     pub selection_marker: Color,
     pub match_highlight: Color,
     pub text: Color,
     pub text_dim: Color,
+    /// Draw borders, markers and key names with ASCII only (`--ascii`).
+    pub ascii: bool,
 }
 
 impl Theme {
@@ -22,17 +39,52 @@ impl Theme {
             primary: Color::Rgb(0x7D, 0xD3, 0xFC),       // sky-300
             accent: Color::Rgb(0xC0, 0x84, 0xFC),        // purple-400
             success: Color::Rgb(0x86, 0xEF, 0xAC),       // green-300
+            error: Color::Rgb(0xF8, 0x71, 0x71),         // red-400
             muted: Color::Rgb(0x64, 0x74, 0x8B),         // slate-500
             border: Color::Rgb(0x33, 0x41, 0x55),        // slate-700
             border_focused: Color::Rgb(0x7D, 0xD3, 0xFC), // sky-300
             selection_bg: Color::Rgb(0x1E, 0x29, 0x3B),  // slate-800
+            hover_bg: Color::Rgb(0x17, 0x20, 0x30),      // between slate-800 and 900
             selection_marker: Color::Rgb(0xC0, 0x84, 0xFC),
             match_highlight: Color::Rgb(0xFB, 0xBF, 0x24), // amber-400
             text: Color::Rgb(0xE2, 0xE8, 0xF0),          // slate-200
             text_dim: Color::Rgb(0x94, 0xA3, 0xB8),      // slate-400
+            ascii: false,
         }
     }
 
+    pub const fn high_contrast() -> Self {
+        Self {
+            primary: Color::LightCyan,
+            accent: Color::LightYellow,
+            success: Color::LightGreen,
+            error: Color::LightRed,
+            muted: Color::Gray,
+            border: Color::White,
+            border_focused: Color::LightYellow,
+            selection_bg: Color::Blue,
+            hover_bg: Color::DarkGray,
+            selection_marker: Color::LightYellow,
+            match_highlight: Color::LightYellow,
+            text: Color::White,
+            text_dim: Color::White,
+            ascii: false,
+        }
+    }
+
+    pub const fn named(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Dark => Self::dark(),
+            ThemeName::HighContrast => Self::high_contrast(),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
+    }
+
     pub fn header_style(&self) -> Style {
         Style::default()
             .fg(self.text_dim)
@@ -45,6 +97,10 @@ impl Theme {
             .add_modifier(Modifier::BOLD)
     }
 
+    pub fn hover_style(&self) -> Style {
+        Style::default().bg(self.hover_bg)
+    }
+
     pub fn border_style(&self) -> Style {
         Style::default().fg(self.border)
     }
@@ -0,0 +1,83 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Title used when `--title-template` is not given.
+pub const DEFAULT_TITLE_TEMPLATE: &str = "{{name}}";
+
+/// Sets the terminal title (and the tmux window name when running inside
+/// tmux) for the duration of a session. The previous title is restored when
+/// the guard is dropped.
+pub struct TitleGuard {
+    /// Window name and `automatic-rename` setting to put back.
+    tmux_window: Option<(String, bool)>,
+}
+
+impl TitleGuard {
+    #[must_use]
+    pub fn set(title: &str) -> TitleGuard {
+        // xterm 互換端末のタイトルスタックに退避してから設定する
+        write_osc(&format!("\x1b[22;0t\x1b]0;{}\x07", sanitize(title)));
+
+        let tmux_window = if std::env::var_os("TMUX").is_some() {
+            let previous = tmux(&["display-message", "-p", "#W\t#{automatic-rename}"]);
+            previous.and_then(|previous| {
+                let (name, automatic) = previous.split_once('\t')?;
+                tmux(&["rename-window", title])?;
+                Some((name.to_string(), automatic == "1"))
+            })
+        } else {
+            None
+        };
+
+        TitleGuard { tmux_window }
+    }
+}
+
+impl Drop for TitleGuard {
+    fn drop(&mut self) {
+        write_osc("\x1b[23;0t");
+
+        if let Some((name, automatic)) = self.tmux_window.take() {
+            if automatic {
+                tmux(&["set-window-option", "automatic-rename", "on"]);
+            } else {
+                tmux(&["rename-window", &name]);
+            }
+        }
+    }
+}
+
+/// Control characters would end the escape sequence early.
+fn sanitize(title: &str) -> String {
+    title.chars().filter(|c| !c.is_control()).collect()
+}
+
+fn write_osc(sequence: &str) {
+    let mut stdout = std::io::stdout();
+    let _ = stdout.write_all(sequence.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// Runs a tmux command and returns its trimmed output, or `None` on failure.
+fn tmux(args: &[&str]) -> Option<String> {
+    let output = Command::new("tmux")
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_characters_are_removed_from_titles() {
+        assert_eq!(sanitize("web\x07\x1b]0;evil"), "web]0;evil");
+        assert_eq!(sanitize("db (prod)"), "db (prod)");
+    }
+}
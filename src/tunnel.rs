@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+use crate::searchable::TableItem;
+use crate::ssh;
+
+/// A named set of local forwards from the `tunnels` section of the config
+/// file, e.g. `"db": ["5432:localhost:5432"]`. Picked at connect time and
+/// passed to ssh as `-L` flags, so the SSH config is left alone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tunnel {
+    pub name: String,
+    /// Forwards in `ssh -L` form, `[bind:]port:host:hostport`.
+    pub forwards: Vec<String>,
+}
+
+impl Tunnel {
+    /// # Errors
+    ///
+    /// Will return `Err` if a tunnel has no forwards or one is not in `-L`
+    /// form.
+    pub fn parse_all(tunnels: &BTreeMap<String, Vec<String>>) -> Result<Vec<Tunnel>> {
+        tunnels
+            .iter()
+            .map(|(name, forwards)| {
+                if forwards.is_empty() {
+                    return Err(anyhow!("Tunnel {name:?} has no forwards"));
+                }
+                if let Some(forward) = forwards.iter().find(|forward| !is_forward(forward)) {
+                    return Err(anyhow!(
+                        "Tunnel {name:?}: {forward:?} is not a forward like 5432:localhost:5432"
+                    ));
+                }
+                Ok(Tunnel {
+                    name: name.clone(),
+                    forwards: forwards.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl TableItem for Tunnel {
+    const HEADERS: &'static [&'static str] = &["NAME", "FORWARDS"];
+
+    fn cells(&self) -> Vec<String> {
+        vec![self.name.clone(), self.forwards.join(", ")]
+    }
+}
+
+/// `port:host:hostport`, optionally preceded by a bind address.
+fn is_forward(spec: &str) -> bool {
+    let port = |value: &str| value.parse::<u16>().is_ok_and(|port| port > 0);
+    // [::1] のような IPv6 アドレスは角括弧で囲まれているので、末尾から区切る
+    let Some((rest, host_port)) = spec.rsplit_once(':') else {
+        return false;
+    };
+    let split = if rest.ends_with(']') { rest.rfind('[').and_then(|i| i.checked_sub(1)) } else { rest.rfind(':') };
+    let Some((rest, host)) = split.filter(|&i| rest[i..].starts_with(':')).map(|i| (&rest[..i], &rest[i + 1..])) else {
+        return false;
+    };
+    let local = rest.rsplit_once(':').map_or(rest, |(_, local)| local);
+    port(host_port) && port(local) && !host.is_empty() && !spec.contains(char::is_whitespace)
+}
+
+/// `template` with a `-L` flag per forward added after the command name.
+///
+/// # Errors
+///
+/// Will return `Err` if the template does not start with `ssh`, since the
+/// flags would mean nothing to another command.
+pub fn inject(template: &str, forwards: &[String]) -> Result<String> {
+    if forwards.is_empty() {
+        return Ok(template.to_string());
+    }
+    let flags: Vec<String> = forwards.iter().map(|forward| format!("-L {forward}")).collect();
+    ssh::insert_flags(template, &flags).ok_or_else(|| {
+        let command = template.split_whitespace().next().unwrap_or_default();
+        anyhow!("Tunnels need a command template starting with ssh, not {command}")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwards_are_added_after_the_ssh_command() {
+        let forwards = vec!["5432:localhost:5432".to_string(), "127.0.0.1:8080:web:80".to_string()];
+        assert_eq!(
+            inject("ssh \"{{{name}}}\"", &forwards).unwrap(),
+            "ssh -L 5432:localhost:5432 -L 127.0.0.1:8080:web:80 \"{{{name}}}\""
+        );
+        assert_eq!(inject("/usr/bin/ssh", &forwards[..1]).unwrap(), "/usr/bin/ssh -L 5432:localhost:5432");
+        assert_eq!(inject("mosh {{name}}", &[]).unwrap(), "mosh {{name}}");
+        assert!(inject("mosh {{name}}", &forwards).is_err());
+
+        let config = BTreeMap::from([("db".to_string(), vec!["5432 localhost:5432".to_string()])]);
+        assert!(Tunnel::parse_all(&config).is_err());
+        let config = BTreeMap::from([("web".to_string(), vec!["8443:[::1]:443".to_string()])]);
+        assert_eq!(Tunnel::parse_all(&config).unwrap()[0].forwards, ["8443:[::1]:443"]);
+    }
+}
@@ -1,31 +1,115 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use crossterm::{
     cursor::{Hide, Show},
     event::{
         self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
-        KeyModifiers,
+        KeyModifiers, KeyboardEnhancementFlags, MouseButton, MouseEvent, MouseEventKind,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
     },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{
+        disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
 };
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 #[allow(clippy::wildcard_imports)]
 use ratatui::{prelude::*, widgets::*};
 use std::{
-    cell::RefCell,
+    borrow::Cow,
+    cell::{Cell as StdCell, RefCell},
     cmp::min,
+    collections::{BTreeMap, HashMap, HashSet},
     io,
+    path::PathBuf,
     rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 use tui_input::backend::crossterm::EventHandler;
 use tui_input::Input;
 use unicode_width::UnicodeWidthStr;
 
-use crate::{searchable::Searchable, ssh, theme::Theme};
+#[cfg(unix)]
+use crate::{
+    control::{ControlSocket, RpcError},
+    daemon,
+};
+use crate::{
+    clipboard,
+    demo,
+    color_rule::{self, ColorRule},
+    edit::{self, HostBlock, HostTemplate},
+    help::{Footer, SGH_HELPERS},
+    i18n::{t, t_fmt},
+    icon::{self, IconMode, IconRule},
+    exclude::ExcludePattern,
+    history::{ConnectionHistory, HistoryEntry},
+    import,
+    macros::{self, Macro, Macros},
+    notify,
+    resolve,
+    schedule,
+    search::{self, Chip, GroupKey, ParsedQuery, Query, QueryHistory, SavedFilters, SearchField, SortKey},
+    searchable::{Searchable, TableItem},
+    session::{self, format_duration, Launcher, SessionSummary, SessionTracker},
+    snippet::Snippet,
+    source::{self, CachePolicy, Source, SourceDefaults, SourcePriority},
+    ssh,
+    template::{self, Helpers},
+    text::{self, pad_end, truncate_end, truncate_middle},
+    theme::{Theme, ThemeName},
+    title::TitleGuard,
+    tunnel::{self, Tunnel},
+    verify,
+};
+
+/// Content of the SSH config file created from the onboarding view.
+const NEW_CONFIG_TEMPLATE: &str = "# Hosts look like this:\n#\n# Host web\n#   HostName 192.0.2.10\n#   User admin\n";
 
 const PROMPT: &str = "❯ ";
+const STATUS_MESSAGE_TTL: Duration = Duration::from_secs(4);
 const SELECTION_MARKER: &str = "▌ ";
 const SELECTION_PADDING: &str = "  ";
+const SESSION_MARKER: &str = "● ";
+const LAST_CONNECTED_MARKER: &str = "↩ ";
+/// Where the `LocalForward` editor starts looking for a free local port.
+const FIRST_SUGGESTED_PORT: u16 = 10000;
+
+/// Below this size the layout cannot fit the search bar, a few table rows, and
+/// the detail panel, so a "terminal too small" message is shown instead.
+const MIN_WIDTH: u16 = 40;
+const MIN_HEIGHT: u16 = 15;
+
+/// Widest a data column may grow; longer values are shown with an ellipsis.
+const MAX_COLUMN_WIDTH: usize = 40;
+
+/// Sort key of each data column a header click can sort by (name, aliases,
+/// user, destination, port).
+const COLUMN_SORT_KEYS: [Option<SortKey>; 5] =
+    [Some(SortKey::Name), None, Some(SortKey::User), Some(SortKey::Destination), Some(SortKey::Port)];
+
+/// Two clicks on the same row within this time connect to it.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// A second Esc within this time quits with `--esc double`.
+const DOUBLE_ESC: Duration = Duration::from_millis(1500);
+
+/// What Esc does in the host list (`--esc`). Ctrl+C always quits.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EscAction {
+    /// Quit right away.
+    #[default]
+    Quit,
+    /// Clear the search bar first; Esc with an empty query quits.
+    Clear,
+    /// Quit only when Esc is pressed twice in a row.
+    Double,
+}
 
 #[derive(Clone)]
 pub struct AppConfig {
@@ -35,15 +119,120 @@ pub struct AppConfig {
     /// file (user supplied `--config` explicitly). When false, missing files
     /// are silently ignored (auto-discovered defaults).
     pub strict_missing: bool,
+    pub pipeline: ssh::Pipeline,
+    /// Hosts read from places other than the config files (`--source`).
+    pub sources: Vec<Source>,
+    /// How long slow sources are answered from the cache, and whether only
+    /// the cache is used (`--offline`).
+    pub source_cache: CachePolicy,
+    /// Which side wins when a source host is also in the SSH config.
+    pub source_priority: SourcePriority,
+    /// `source_defaults`: user, port and connect template per kind of source.
+    pub source_defaults: BTreeMap<String, SourceDefaults>,
+
+    /// Globs / `re:` regexes of hosts to hide (`--exclude` + config file).
+    pub exclude: Vec<String>,
 
     pub search_filter: Option<String>,
-    pub sort_by_name: bool,
+    /// Start with the host on the clipboard as the query when no search is
+    /// given (`--from-clipboard`).
+    pub from_clipboard: bool,
+    pub search_fields: Vec<SearchField>,
+    /// Keys the host list is sorted by, in order; empty keeps config order.
+    pub sort_keys: Vec<SortKey>,
+    /// Compare numbers in names by value (`web2` before `web10`).
+    pub natural_sort: bool,
+    /// Group the list by this attribute, with a header row per group.
+    pub group_by: Option<GroupKey>,
+    /// Row styles for hosts matching `color_rules` in the config file.
+    pub color_rules: Vec<ColorRule>,
+    /// Color scheme (`--theme`).
+    pub theme: ThemeName,
+    /// Draw with ASCII only (`--ascii`).
+    pub ascii: bool,
+    /// Footer row settings from the config file.
+    pub footer: Footer,
+    /// What Esc does outside popups (`--esc`).
+    pub esc: EscAction,
+    /// Icon column mode (`--icons`); `Off` hides the column.
+    pub icons: IconMode,
+    /// Icons for hosts matching `icon_rules` in the config file.
+    pub icon_rules: Vec<IconRule>,
+    /// Weight of connection frequency when ranking search results.
+    pub frequency_weight: f64,
     pub show_proxy_command: bool,
+    pub show_source: bool,
+
+    /// Size columns to the currently filtered rows instead of every host.
+    pub fit_columns: bool,
+
+    /// Also list wildcard-only `Host` blocks such as `Host *.internal`.
+    pub show_patterns: bool,
+    pub split_aliases: bool,
+    /// Start with destinations, users and ports masked (`--redact`).
+    pub redact: bool,
+    /// Shown dimmed as the user of hosts no `User` applies to, since ssh
+    /// logs in as the local user then.
+    pub local_user: Option<String>,
 
     pub command_template: String,
+    /// Run rendered templates through `$SHELL -c` instead of splitting them.
+    pub shell: bool,
     pub command_template_on_session_start: Option<String>,
     pub command_template_on_session_end: Option<String>,
+    /// Command printing a one-time code before each connection (`{{otp}}`).
+    pub otp_command: Option<String>,
     pub exit_after_ssh_session_ends: bool,
+    /// Replace the sgh process with the connect command (`--exec`).
+    pub exec: bool,
+    /// Run the selected command later (`--at`), or hand it to a scheduler
+    /// (`--emit`).
+    pub schedule: Option<schedule::When>,
+    pub schedule_emit: Option<schedule::Emit>,
+    /// Show the bundled synthetic hosts and never run commands (`--demo`).
+    pub demo: bool,
+    /// Send a desktop notification when a session that lasted at least this
+    /// long ends.
+    pub notify_after: Option<Duration>,
+    /// Quit when no key is pressed for this long (`--idle-timeout`).
+    pub idle_timeout: Option<Duration>,
+    /// Lock the screen when no key is pressed for this long (`--lock-after`).
+    pub lock_after: Option<Duration>,
+    /// Passphrase that unlocks the screen; any key does when unset.
+    pub lock_passphrase: Option<String>,
+    /// Terminal / tmux window title while a session runs; `None` leaves the
+    /// title alone.
+    pub title_template: Option<String>,
+
+    pub launcher: Launcher,
+    pub launcher_template: Option<String>,
+
+    /// Unix socket to accept JSON-RPC requests on (`--control-socket`).
+    pub control_socket: Option<PathBuf>,
+    /// Socket of the `sgh daemon` to read hosts from (`--use-daemon`).
+    pub daemon_socket: Option<PathBuf>,
+    /// Templates offered when saving an ad-hoc host (`host_templates`).
+    pub host_templates: Vec<HostTemplate>,
+    /// Forward presets picked at connect time with Alt+T (`tunnels`).
+    pub tunnels: Vec<Tunnel>,
+    /// Local port a SOCKS proxy (Alt+S) tries first.
+    pub socks_port: u16,
+    /// Remote commands offered by Alt+R (`snippets`).
+    pub snippets: Vec<Snippet>,
+    /// Command template a picked snippet runs with (`--snippet-template`).
+    pub snippet_template: String,
+}
+
+impl AppConfig {
+    /// The config file to hand to `ssh -F` when it is the only one given
+    /// with `--config`; `None` lets ssh read its defaults like sgh does.
+    #[must_use]
+    pub fn single_config_file(&self) -> Option<PathBuf> {
+        match self.config_paths.as_slice() {
+            [path] if self.strict_missing => Some(PathBuf::from(shellexpand::tilde(path).to_string())),
+            _ => None,
+        }
+    }
 }
 
 pub struct App {
@@ -56,13 +245,479 @@ pub struct App {
     table_state: TableState,
     hosts: Searchable<ssh::Host>,
     table_columns_constraints: Vec<Constraint>,
+    /// Display width of each data column (name onwards), capped at
+    /// `MAX_COLUMN_WIDTH`; values wider than this are truncated.
+    column_widths: Vec<usize>,
+
+    /// Where the table was last drawn, to map mouse positions to rows and
+    /// columns.
+    table_layout: TableLayout,
+    /// Row under the mouse cursor.
+    hovered: Option<usize>,
+    /// Row and time of the last left click, to spot double clicks.
+    last_click: Option<(usize, Instant)>,
+    /// Sort picked by clicking a column header, descending when the flag is
+    /// set; replaces `sort_keys` until clicked a third time.
+    header_sort: Option<(SortKey, bool)>,
+
+    /// When Esc was last pressed, for `EscAction::Double`.
+    last_esc: Option<Instant>,
+    /// Last key press or mouse event, or return from a session, for
+    /// `--idle-timeout`.
+    last_input: Instant,
+    /// Host connected to most recently; its row is marked.
+    last_connected: Option<String>,
+
+    /// Set while the screen is locked (Alt+L, `--lock-after`).
+    lock: Option<Lock>,
+    /// Message shown in a modal popup until the next key press.
+    error_popup: Option<String>,
+    /// Short notice shown in the status bar for `STATUS_MESSAGE_TTL`.
+    status_message: Option<(String, Instant)>,
+    /// Show the key help instead of the status bar (`?`).
+    show_help: bool,
+    /// Config changes made in this run, most recent last (Alt+U undoes).
+    undo_stack: Vec<edit::Backup>,
+    /// Printed once the picker has closed, e.g. the entry of `--emit`.
+    exit_output: Option<String>,
+    /// Exit status of a failed session, passed on as sgh's own once the
+    /// picker has closed.
+    exit_code: Option<i32>,
+
+    /// Sessions started by a background launcher (tmux / template).
+    sessions: SessionTracker,
+
+    saved_filters: SavedFilters,
+    macros: Macros,
+    overlay: Option<Overlay>,
+
+    /// Shared with the search predicate so Ctrl+T can toggle pattern hosts.
+    show_patterns: Rc<StdCell<bool>>,
+    /// Single column the free text is matched against (Ctrl+D / Ctrl+R),
+    /// instead of every `--search-fields` column.
+    focus_field: Rc<StdCell<Option<SearchField>>>,
+
+    /// Row offered when the query is a `user@host:port` destination that
+    /// matches no configured host.
+    adhoc_host: Option<ssh::Host>,
+
+    /// Hosts as merged by the parser; `hosts` holds one row per alias of
+    /// these while `split_aliases` is on (Ctrl+L).
+    merged_hosts: Vec<ssh::Host>,
+    split_aliases: bool,
+    /// Destinations, users and ports are shown as `ssh::REDACTED` (Alt+X).
+    redact: bool,
+    /// `--source`s loading on their own threads, in order; their hosts are
+    /// added to `merged_hosts` as they arrive.
+    background_sources: Vec<BackgroundSource>,
+    source_results: Option<mpsc::Receiver<(usize, Result<Vec<ssh::Host>>)>>,
+    /// When the app was built, for the loading spinner.
+    started_at: Instant,
+
+    query_history: QueryHistory,
+    /// Shared with the search ranking so new connections count immediately.
+    connections: Rc<RefCell<ConnectionHistory>>,
+    /// Position while browsing `query_history` with Alt+↑/↓, and the query
+    /// that was being typed before browsing started.
+    history_cursor: Option<(usize, String)>,
+
+    /// Top-level view on screen; the others keep their query and selection
+    /// while hidden.
+    view: View,
+    tunnel_view: ItemTable<Tunnel>,
+    session_view: ItemTable<SessionSummary>,
+    history_view: ItemTable<HistoryEntry>,
+
+    #[cfg(unix)]
+    control: Option<ControlSocket>,
+}
+
+/// The tabs of the picker, switched with Tab / Shift+Tab or F1–F4. Everything
+/// but the host list is an `ItemTable`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum View {
+    Hosts,
+    Tunnels,
+    Sessions,
+    History,
+}
+
+impl View {
+    const ALL: [View; 4] = [View::Hosts, View::Tunnels, View::Sessions, View::History];
+
+    fn title(self) -> &'static str {
+        match self {
+            View::Hosts => t("Hosts"),
+            View::Tunnels => t("Tunnels"),
+            View::Sessions => t("Sessions"),
+            View::History => t("History"),
+        }
+    }
+
+    /// The next tab to the right (or left), wrapping around.
+    fn cycle(self, forward: bool) -> View {
+        let index = Self::ALL.iter().position(|&view| view == self).unwrap_or(0);
+        let step = if forward { 1 } else { Self::ALL.len() - 1 };
+        Self::ALL[(index + step) % Self::ALL.len()]
+    }
+}
+
+/// A slow `--source` (cloud, scan, containers, …) loaded after the config
+/// hosts are shown.
+struct BackgroundSource {
+    kind: &'static str,
+    /// `None` while it is loading.
+    hosts: Option<Vec<ssh::Host>>,
+}
+
+/// Frames of the spinner shown next to a loading source.
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const ASCII_SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+
+/// The locked screen: hosts stay hidden until a key is pressed, or the
+/// passphrase is typed when one is configured.
+#[derive(Default)]
+struct Lock {
+    passphrase: Input,
+    /// The last passphrase typed was wrong.
+    wrong: bool,
+}
+
+/// The host table as last drawn.
+#[derive(Default)]
+struct TableLayout {
+    /// Inside the padding: the header line, a blank line, then the rows.
+    area: Rect,
+    /// Host index of each displayed row; `None` for group headers.
+    rows: Vec<Option<usize>>,
+    /// First displayed row.
+    offset: usize,
+}
+
+/// The selected host, remembered across a refilter or reload so the
+/// selection can follow it (see `App::after_search`).
+struct SelectionAnchor {
+    name: String,
+    /// Index in the unfiltered rows, for finding a neighbour when the host
+    /// is gone.
+    position: usize,
+}
+
+/// A filter-as-you-type table of `TableItem`s: the list machinery every
+/// view but the host table shares. Typing narrows the rows, arrows move
+/// (wrapping around), and the caller handles Enter and Esc.
+struct ItemTable<T: TableItem + 'static> {
+    items: Searchable<T>,
+    query: Input,
+    state: TableState,
+}
+
+impl<T: TableItem + 'static> ItemTable<T> {
+    fn new(items: Vec<T>) -> ItemTable<T> {
+        ItemTable {
+            items: Searchable::of_items(items),
+            query: Input::default(),
+            state: TableState::default().with_selected(Some(0)),
+        }
+    }
+
+    fn selected(&self) -> Option<&T> {
+        let selected = self.state.selected()?;
+        (selected < self.items.len()).then(|| &self.items[selected])
+    }
+
+    /// Replaces the rows, keeping the query and (as far as it still fits)
+    /// the selection.
+    fn set_items(&mut self, items: Vec<T>) {
+        self.items.set_items(items, self.query.value());
+        let selected = self.state.selected().unwrap_or(0);
+        self.state.select(Some(selected.min(self.items.len().saturating_sub(1))));
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        let count = self.items.len().max(1);
+        let selected = self.state.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Down => self.state.select(Some((selected + 1) % count)),
+            KeyCode::Up => self.state.select(Some(selected.checked_sub(1).unwrap_or(count - 1))),
+            KeyCode::PageDown => self.state.select(Some(min(selected + 10, count - 1))),
+            KeyCode::PageUp => self.state.select(Some(selected.saturating_sub(10))),
+            _ => {
+                if self.query.handle_event(&Event::Key(key)).is_some_and(|change| change.value) {
+                    self.items.search(self.query.value());
+                    self.state.select(Some(0));
+                }
+            }
+        }
+    }
+}
+
+/// Query, selected host and scroll offset before a connection, put back
+/// when the session ends so the list is exactly as it was left.
+struct ReturnPoint {
+    query: String,
+    host: Option<String>,
+    selected: usize,
+    offset: usize,
+}
+
+/// Modal views drawn over the host list. While one is open it receives every
+/// key press instead of the search bar.
+enum Overlay {
+    /// Prompt for the name to save the current query under.
+    SaveFilter(Input),
+    /// List of saved filters to recall or delete.
+    Filters(ListState),
+    /// Effective settings of the selected host and the block each one came
+    /// from, scrolled by the given number of lines, and the comparison with
+    /// `ssh -G` once requested.
+    Explain(u16, Option<Verification>),
+    /// Template variables with the selected host's values, and the helpers,
+    /// scrolled by the given number of lines.
+    Variables(u16),
+    /// Offer to save an ad-hoc host as a new `Host` block.
+    SaveHost(Box<SaveHostForm>),
+    /// Type a hostname and see which blocks would apply to it.
+    Playground(Box<Playground>),
+    /// Set a keyword on every filtered host, after a preview.
+    BulkEdit(Box<BulkEditForm>),
+    /// Add, change or remove the selected host's `LocalForward` lines.
+    Forwards(Box<ForwardEditor>),
+    /// Tunnel presets to connect the selected host with, and which are
+    /// ticked.
+    Tunnels(ListState, Vec<bool>),
+    /// Snippets to run on the selected host.
+    Snippets(ItemTable<Snippet>),
+    /// Prompt for the name to save the query and this snippet under as a
+    /// macro.
+    SaveMacro(Input, Box<Snippet>),
+    /// Recorded macros to replay.
+    Macros(ItemTable<Macro>),
+}
+
+/// State of the `LocalForward` editor (Alt+F). Every change is written to
+/// the host's block right away and can be undone with Alt+U.
+struct ForwardEditor {
+    host: String,
+    /// Names on the `Host` line to edit, and the file it is in.
+    names: Vec<String>,
+    path: PathBuf,
+    /// `LocalForward` values as written in the config, e.g. `5432 localhost:5432`.
+    forwards: Vec<String>,
+    list: ListState,
+    /// Value being typed and the index of the forward it replaces; `None`
+    /// adds a new one.
+    input: Option<(Option<usize>, Input)>,
+    error: Option<String>,
+}
+
+impl ForwardEditor {
+    fn new(host: &ssh::Host, path: PathBuf) -> ForwardEditor {
+        let names = std::iter::once(host.name.as_str())
+            .chain(host.aliases.split(", ").filter(|alias| !alias.is_empty()))
+            .map(str::to_string)
+            .collect();
+        let mut editor = ForwardEditor {
+            host: host.name.clone(),
+            names,
+            path,
+            forwards: Vec::new(),
+            list: ListState::default().with_selected(Some(0)),
+            input: None,
+            error: None,
+        };
+        editor.set_forwards(host);
+        editor
+    }
+
+    /// Takes the forwards of `host`, e.g. as re-read after a change.
+    fn set_forwards(&mut self, host: &ssh::Host) {
+        self.forwards = host
+            .local_forwards
+            .iter()
+            .map(|lf| format!("{} {}", lf.listen(), lf.target()))
+            .collect();
+        let selected = self.list.selected().unwrap_or(0);
+        self.list.select(Some(selected.min(self.forwards.len().saturating_sub(1))));
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.list.selected().filter(|&i| i < self.forwards.len())
+    }
+}
+
+/// A typed forward, `5432 localhost:5432` or `5432:localhost:5432`, in
+/// config file form, or why it is not one.
+fn parse_forward(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    let (local, remote) = match text.split_once(char::is_whitespace) {
+        Some((local, remote)) => (local, remote.trim()),
+        None => text.split_once(':').unwrap_or((text, "")),
+    };
+    let port = |value: &str| value.rsplit(':').next().is_some_and(|port| port.parse::<u16>().is_ok_and(|p| p > 0));
+    let remote_ok = remote.rsplit_once(':').is_some_and(|(host, _)| !host.is_empty()) && port(remote);
+    if !port(local) || !remote_ok || remote.contains(char::is_whitespace) {
+        return Err("Type a local port and a destination, e.g. 5432 localhost:5432.".to_string());
+    }
+    Ok(format!("{local} {remote}"))
+}
+
+/// Values that differ from `ssh -G`, or why ssh could not be asked.
+type Verification = Result<Vec<verify::Mismatch>, String>;
+
+/// State of the "save ad-hoc host" prompt.
+struct SaveHostForm {
+    host: ssh::Host,
+    /// Alias for the new `Host` line, pre-filled with the destination.
+    name: Input,
+    /// Config files the block can be appended to, chosen with Tab.
+    files: Vec<PathBuf>,
+    file_index: usize,
+    /// `host_templates` from the config file, chosen with Ctrl+T; `template`
+    /// is 1-based so 0 means none.
+    templates: Vec<HostTemplate>,
+    template: usize,
+    /// Values for the chosen template's placeholders.
+    placeholders: Vec<(String, Input)>,
+    /// Field the cursor is in: 0 for the alias, then the placeholders.
+    focus: usize,
+    /// Validation message shown below the preview.
+    error: Option<String>,
+}
+
+impl SaveHostForm {
+    fn new(host: ssh::Host, files: Vec<PathBuf>, templates: Vec<HostTemplate>) -> SaveHostForm {
+        SaveHostForm {
+            name: Input::from(host.destination.clone()),
+            host,
+            files,
+            file_index: 0,
+            templates,
+            template: 0,
+            placeholders: Vec::new(),
+            focus: 0,
+            error: None,
+        }
+    }
+
+    fn selected_template(&self) -> Option<&HostTemplate> {
+        self.template.checked_sub(1).and_then(|i| self.templates.get(i))
+    }
+
+    /// Switches to the next template (or back to none), keeping values
+    /// typed for placeholders of the same name. `user` and `port` start
+    /// with what was typed in the search bar.
+    fn cycle_template(&mut self) {
+        self.template = (self.template + 1) % (self.templates.len() + 1);
+        let names = self.selected_template().map(HostTemplate::placeholders).unwrap_or_default();
+        let previous = std::mem::take(&mut self.placeholders);
+        self.placeholders = names
+            .into_iter()
+            .map(|name| {
+                let value = previous
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, input)| input.value().to_string())
+                    .or_else(|| match name.as_str() {
+                        "user" => self.host.user.clone(),
+                        "port" => self.host.port.clone(),
+                        "hostname" => Some(self.host.destination.clone()),
+                        _ => None,
+                    })
+                    .unwrap_or_default();
+                (name, Input::from(value))
+            })
+            .collect();
+        self.focus = 0;
+    }
+
+    fn focused_input(&mut self) -> &mut Input {
+        match self.focus.checked_sub(1) {
+            Some(i) => &mut self.placeholders[i].1,
+            None => &mut self.name,
+        }
+    }
+
+    fn block(&self) -> HostBlock {
+        let mut block = HostBlock::from_adhoc(self.name.value().trim(), &self.host);
+        if let Some(template) = self.selected_template() {
+            let values: Vec<(String, String)> = self
+                .placeholders
+                .iter()
+                .map(|(name, input)| (name.clone(), input.value().to_string()))
+                .collect();
+            block.apply_template(template, &values);
+        }
+        block
+    }
+}
+
+/// State of the pattern playground: the typed hostname and the config as
+/// written, read once when the playground opens.
+struct Playground {
+    name: Input,
+    configs: Result<Vec<resolve::Blocks>, String>,
+    scroll: u16,
+}
+
+/// State of the bulk edit prompt (Alt+E).
+struct BulkEditForm {
+    /// `Keyword value` to set.
+    option: Input,
+    /// Config files and the names of the filtered hosts defined in them.
+    targets: Vec<(PathBuf, Vec<String>)>,
+    hosts: usize,
+    /// Filtered hosts that are not read from a config file (`--source`).
+    skipped: usize,
+    /// Blocks that would change, shown after the first Enter; the second
+    /// applies them.
+    preview: Option<Result<Vec<edit::OptionChange>, String>>,
+    scroll: u16,
+}
+
+impl BulkEditForm {
+    /// The typed keyword and value, or why they cannot be used.
+    fn option(&self) -> Result<(String, String), String> {
+        let text = self.option.value().trim();
+        let (keyword, value) = text
+            .split_once(|c: char| c.is_whitespace() || c == '=')
+            .map(|(k, v)| (k, v.trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim()))
+            .unwrap_or((text, ""));
+        if keyword.is_empty() || value.is_empty() || !keyword.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err("Type a keyword and its value, e.g. ServerAliveInterval 30.".to_string());
+        }
+        if ["host", "match", "include"].iter().any(|k| keyword.eq_ignore_ascii_case(k)) {
+            return Err(format!("{keyword} cannot be set on hosts."));
+        }
+        Ok((keyword.to_string(), value.to_string()))
+    }
 }
 
-#[derive(PartialEq)]
 enum AppKeyAction {
     Ok,
     Stop,
     Continue,
+    Suspend,
+    /// Enter on a host; the event loop connects since that needs the
+    /// terminal.
+    Connect(Box<ssh::Host>),
+    /// Shift+Enter: connect through this launcher instead of `--launcher`.
+    ConnectIn(Box<ssh::Host>, Launcher),
+    /// Alt+I on the onboarding view: walk through the shell history on the
+    /// terminal.
+    ImportHistory,
+    /// Alt+M: run a macro's snippet on these hosts, one after the other.
+    RunMacro(Box<macros::Steps>, Vec<ssh::Host>),
+}
+
+/// Where a scripted sequence of key presses left the app (see `App::feed`).
+#[cfg(test)]
+#[derive(Debug)]
+enum Outcome {
+    Running,
+    Quit,
+    Connect(Box<ssh::Host>),
+    RunMacro(Vec<String>),
 }
 
 impl App {
@@ -70,675 +725,5488 @@ impl App {
     ///
     /// Will return `Err` if the SSH configuration file cannot be parsed.
     pub fn new(config: &AppConfig) -> Result<App> {
-        let mut hosts = Vec::new();
-
-        let expanded = ssh::expand_config_paths(&config.config_paths);
-        for path in &expanded {
-            let parsed_hosts = match ssh::parse_config(path) {
-                Ok(h) => h,
-                Err(err) => {
-                    // Missing files are tolerated for auto-discovered defaults.
-                    // The system-wide config is always optional, even under
-                    // strict mode, to preserve existing behaviour.
-                    let is_missing = matches!(
-                        &err,
-                        ssh::ParseConfigError::Io(io_err)
-                            if io_err.kind() == std::io::ErrorKind::NotFound
-                    );
-                    let is_system_default =
-                        path.as_os_str() == std::ffi::OsStr::new("/etc/ssh/ssh_config");
-
-                    if is_missing && (!config.strict_missing || is_system_default) {
-                        continue;
-                    }
-
-                    anyhow::bail!(
-                        "Failed to parse SSH configuration file {}: {err:?}",
-                        path.display()
-                    );
-                }
-            };
-
-            hosts.extend(parsed_hosts);
+        let (hosts, slow_sources) = load_local_hosts(config)?;
+        validate_templates(config, &hosts)?;
+        let clipboard_config;
+        let config = match clipboard_query(config, &hosts) {
+            Some(query) => {
+                clipboard_config = AppConfig {
+                    search_filter: Some(query),
+                    ..config.clone()
+                };
+                &clipboard_config
+            }
+            None => config,
+        };
+        let warning = invalid_port_warning(&hosts).or_else(|| source_options_warning(config, &hosts));
+        let mut app = App::with_hosts(config, hosts);
+        app.load_in_background(slow_sources);
+        if let Some(warning) = warning {
+            app.flash(warning);
         }
 
-        // ソート (host.name の文字列で)
-        if config.sort_by_name {
-            hosts.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+        #[cfg(unix)]
+        if let Some(path) = &config.control_socket {
+            app.control = Some(ControlSocket::bind(path)?);
+        }
+        #[cfg(not(unix))]
+        if config.control_socket.is_some() {
+            anyhow::bail!("--control-socket is only supported on Unix");
         }
 
+        Ok(app)
+    }
+
+    /// Builds the app around already loaded hosts, without touching the SSH
+    /// config files (used by `new` and the snapshot tests).
+    fn with_hosts(config: &AppConfig, hosts: Vec<ssh::Host>) -> App {
+        let rows = host_rows(&hosts, config.split_aliases, &config.sort_keys, config.natural_sort);
+
         // 検索バーの初期入力
         let search_input = config.search_filter.clone().unwrap_or_default();
         let matcher = SkimMatcherV2::default();
+        let search_fields = config.search_fields.clone();
+        let show_patterns = Rc::new(StdCell::new(config.show_patterns));
+        let predicate_show_patterns = Rc::clone(&show_patterns);
+        let focus_field = Rc::new(StdCell::new(None));
+        let predicate_focus = Rc::clone(&focus_field);
+        let ranker_focus = Rc::clone(&focus_field);
+        let connections = Rc::new(RefCell::new(ConnectionHistory::load()));
+        let ranker_connections = Rc::clone(&connections);
+        let ranker_matcher = SkimMatcherV2::default();
+        let ranker_fields = config.search_fields.clone();
+        let frequency_weight = config.frequency_weight;
+        let mut predicate_query = ParsedQuery::default();
+        let mut ranker_query = ParsedQuery::default();
+
+        let mut searchable = Searchable::new(
+            rows,
+            &search_input,
+            move |host: &&ssh::Host, search_value: &str| -> bool {
+                let fields = focused_fields(predicate_focus.get(), &search_fields);
+                (!host.is_pattern || predicate_show_patterns.get())
+                    && search::matches(&matcher, host, &fields, predicate_query.get(search_value))
+            },
+        )
+        .with_ranking(
+            move |host: &ssh::Host, search_value: &str| -> i64 {
+                // 一致度に接続回数のボーナスを加えて並べ替える
+                let query = ranker_query.get(search_value);
+                if query.text.is_empty() {
+                    return 0;
+                }
+                let count = ranker_connections.borrow().count(&host.name);
+                let fields = focused_fields(ranker_focus.get(), &ranker_fields);
+                search::score(&ranker_matcher, host, &fields, query)
+                    + search::frequency_bonus(count, frequency_weight)
+            },
+            &search_input,
+        );
+        if let Some(group) = config.group_by {
+            searchable = searchable.with_grouping(move |a, b| group.compare(a, b), &search_input);
+        }
 
         // Searchable に格納
         let mut app = App {
             config: config.clone(),
-            theme: Theme::dark(),
+            theme: Theme::named(config.theme).with_ascii(config.ascii),
             matcher: SkimMatcherV2::default(),
 
             search: search_input.clone().into(),
 
             table_state: TableState::default().with_selected(0),
             table_columns_constraints: vec![],
-            hosts: Searchable::new(
-                hosts,
-                &search_input,
-                move |host: &&ssh::Host, search_value: &str| -> bool {
-                    search_value.is_empty()
-                        || matcher.fuzzy_match(&host.name, search_value).is_some()
-                        || matcher
-                            .fuzzy_match(&host.destination, search_value)
-                            .is_some()
-                        || matcher.fuzzy_match(&host.aliases, search_value).is_some()
-                },
-            ),
+            column_widths: vec![],
+            table_layout: TableLayout::default(),
+            hovered: None,
+            last_click: None,
+            header_sort: None,
+            last_esc: None,
+            last_input: Instant::now(),
+            last_connected: None,
+            lock: None,
+            error_popup: None,
+            sessions: SessionTracker::default(),
+            saved_filters: SavedFilters::load(),
+            macros: Macros::load(),
+            overlay: None,
+            show_patterns,
+            focus_field,
+            adhoc_host: None,
+            merged_hosts: hosts,
+            split_aliases: config.split_aliases,
+            redact: config.redact,
+            background_sources: Vec::new(),
+            source_results: None,
+            started_at: Instant::now(),
+            query_history: QueryHistory::load(),
+            connections,
+            history_cursor: None,
+            status_message: None,
+            show_help: false,
+            undo_stack: Vec::new(),
+            exit_output: None,
+            exit_code: None,
+            view: View::Hosts,
+            tunnel_view: ItemTable::new(config.tunnels.clone()),
+            session_view: ItemTable::new(Vec::new()),
+            history_view: ItemTable::new(Vec::new()),
+            #[cfg(unix)]
+            control: None,
+            hosts: searchable,
         };
         app.calculate_table_columns_constraints();
+        app.update_adhoc_host();
 
-        Ok(app)
+        app
     }
 
+    /// Runs the picker until it is closed and returns the exit status for
+    /// sgh: that of a session that failed, 0 otherwise.
+    ///
     /// # Errors
     ///
     /// Will return `Err` if the terminal cannot be configured.
-    pub fn start(&mut self) -> Result<()> {
+    pub fn start(&mut self) -> Result<i32> {
         let stdout = io::stdout().lock();
         let backend = CrosstermBackend::new(stdout);
         let terminal = Rc::new(RefCell::new(Terminal::new(backend)?));
 
+        // 異常終了時にも端末を元に戻せるようにしておく
+        install_panic_hook();
+        let terminate = Arc::new(AtomicBool::new(false));
+        register_termination_signals(&terminate)?;
+
         setup_terminal(&terminal)?;
 
         // create app and run it
-        let res = self.run(&terminal);
+        let res = self.run(&terminal, &terminate);
+
+        // 失敗したセッションから抜けたときは端末がすでに戻っていて、
+        // clear すると ssh のエラー表示が消えてしまう
+        if self.exit_code.is_none() {
+            restore_terminal(&terminal)?;
+        }
 
-        restore_terminal(&terminal)?;
+        self.remember_query();
+        if let Some(output) = &self.exit_output {
+            println!("{output}");
+        }
 
         if let Err(err) = res {
             println!("{err:?}");
         }
 
-        Ok(())
+        Ok(self.exit_code.unwrap_or(0))
     }
 
-    fn run<B>(&mut self, terminal: &Rc<RefCell<Terminal<B>>>) -> Result<()>
+    fn run<B>(&mut self, terminal: &Rc<RefCell<Terminal<B>>>, terminate: &AtomicBool) -> Result<()>
     where
         B: Backend + std::io::Write,
     {
         loop {
+            // SIGINT/SIGTERM/SIGHUP を受けたら通常の終了経路で端末を戻す
+            if terminate.load(Ordering::Relaxed) || INTERRUPTED.get().is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+                break;
+            }
+            if self.is_idle() {
+                let idle = format_duration(self.last_input.elapsed());
+                self.exit_output = Some(format!("[sgh] Quit after {idle} without input (--idle-timeout)"));
+                break;
+            }
+            if self.lock.is_none()
+                && self
+                    .config
+                    .lock_after
+                    .is_some_and(|lock_after| self.last_input.elapsed() >= lock_after)
+            {
+                self.lock = Some(Lock::default());
+            }
+
+            self.receive_source_hosts();
+            for session in self.sessions.refresh() {
+                notify::session_ended(&session.host_name, session.elapsed(), self.config.notify_after);
+                if session.socks_port().is_none() {
+                    self.record_duration(&session.host_name, session.elapsed());
+                }
+                self.flash(t_fmt(
+                    "Session to {host} ended after {duration}",
+                    &[("host", &session.host_name), ("duration", &format_duration(session.elapsed()))],
+                ));
+                self.refresh_view();
+            }
+            if self
+                .status_message
+                .as_ref()
+                .is_some_and(|(_, shown_at)| shown_at.elapsed() >= STATUS_MESSAGE_TTL)
+            {
+                self.status_message = None;
+            }
+
+            #[cfg(unix)]
+            if let Some(host) = self.handle_control_calls() {
+                if let AppKeyAction::Stop = self.connect(terminal, host)? {
+                    break;
+                }
+                self.last_input = Instant::now();
+            }
+
             terminal.borrow_mut().draw(|f| ui(f, self))?;
 
+            // セッション一覧の更新とシグナル確認のため、入力がなくても定期的にループする
+            // (ソースの読み込み中はスピナーを回すため短めに)
+            let tick = if self.source_results.is_some() { 100 } else { 250 };
+            if !event::poll(Duration::from_millis(tick))? {
+                continue;
+            }
             let ev = event::read()?;
 
-            if let Event::Key(key) = ev {
-                if key.kind == KeyEventKind::Press {
-                    let action = self.on_key_press(terminal, key)?;
-                    match action {
-                        AppKeyAction::Ok => continue,
-                        AppKeyAction::Stop => break,
-                        AppKeyAction::Continue => {}
+            if let Event::Resize(_, _) = ev {
+                // 新しいサイズでレイアウトを組み直し、前の描画の残骸を消す
+                self.calculate_table_columns_constraints();
+                terminal.borrow_mut().autoresize()?;
+                terminal.borrow_mut().clear()?;
+                continue;
+            }
+
+            let action = match ev {
+                Event::Key(key) => self.handle_key(key),
+                Event::Mouse(mouse) => self.handle_mouse(mouse),
+                _ => continue,
+            };
+            match action {
+                AppKeyAction::Ok | AppKeyAction::Continue => {}
+                AppKeyAction::Stop => break,
+                AppKeyAction::Suspend => suspend(terminal)?,
+                AppKeyAction::ImportHistory => self.import_history(terminal)?,
+                AppKeyAction::RunMacro(steps, hosts) => self.run_macro(terminal, &steps, &hosts)?,
+                AppKeyAction::Connect(host) => {
+                    if let AppKeyAction::Stop = self.connect(terminal, *host)? {
+                        break;
                     }
                 }
-
-                // 入力が検索バーに反映される
-                self.search.handle_event(&ev);
-                self.hosts.search(self.search.value());
-
-                let selected = self.table_state.selected().unwrap_or(0);
-                if selected >= self.hosts.len() {
-                    self.table_state.select(Some(match self.hosts.len() {
-                        0 => 0,
-                        _ => self.hosts.len() - 1,
-                    }));
+                AppKeyAction::ConnectIn(host, launcher) => {
+                    if let AppKeyAction::Stop = self.connect_with(terminal, *host, launcher)? {
+                        break;
+                    }
                 }
             }
+            // セッションやコマンドから戻った時点から数え直す
+            self.last_input = Instant::now();
         }
 
         Ok(())
     }
 
-    fn on_key_press<B>(
-        &mut self,
-        terminal: &Rc<RefCell<Terminal<B>>>,
-        key: KeyEvent,
-    ) -> Result<AppKeyAction>
-    where
-        B: Backend + std::io::Write,
-    {
-        #[allow(clippy::enum_glob_use)]
-        use KeyCode::*;
-
-        let is_ctrl_pressed = key.modifiers.contains(KeyModifiers::CONTROL);
+    /// Whether `--idle-timeout` has passed since the last input.
+    fn is_idle(&self) -> bool {
+        self.config
+            .idle_timeout
+            .is_some_and(|timeout| self.last_input.elapsed() >= timeout)
+    }
 
-        if is_ctrl_pressed {
-            let action = self.on_key_press_ctrl(key);
-            if action != AppKeyAction::Continue {
-                return Ok(action);
+    /// Applies one key event the way the event loop does. Everything except
+    /// connecting, quitting and suspending happens here, so this is also what
+    /// the scripted tests drive.
+    fn handle_key(&mut self, key: KeyEvent) -> AppKeyAction {
+        self.last_input = Instant::now();
+        if key.kind == KeyEventKind::Press {
+            let action = self.on_key_press(key);
+            if !matches!(action, AppKeyAction::Continue) {
+                return action;
             }
         }
 
-        match key.code {
-            Esc => return Ok(AppKeyAction::Stop),
-            Down => self.next(),
-            Up => self.previous(),
-            Home => self.table_state.select(Some(0)),
-            End => {
-                if !self.hosts.is_empty() {
-                    self.table_state.select(Some(self.hosts.len() - 1));
-                }
-            }
-            PageDown => {
-                let i = self.table_state.selected().unwrap_or(0);
-                let target = min(i.saturating_add(21), self.hosts.len().saturating_sub(1));
+        // 入力が検索バーに反映される
+        self.search.handle_event(&Event::Key(key));
+        self.history_cursor = None;
+        self.apply_search();
+        AppKeyAction::Continue
+    }
 
-                self.table_state.select(Some(target));
-            }
-            PageUp => {
-                let i = self.table_state.selected().unwrap_or(0);
-                let target = i.saturating_sub(21);
+    /// Mouse over the host table: hovering highlights the row, a click selects
+    /// it and a second click connects, the wheel moves the selection and a
+    /// click on a header sorts by that column. Ignored while a popup is open.
+    fn handle_mouse(&mut self, mouse: MouseEvent) -> AppKeyAction {
+        self.last_input = Instant::now();
+        if self.lock.is_some() || self.overlay.is_some() || self.error_popup.is_some() || self.view != View::Hosts {
+            return AppKeyAction::Ok;
+        }
+        let area = self.table_layout.area;
+        let inside = area.contains(Position::new(mouse.column, mouse.row));
+        // 見出しの下に 1 行の余白があり、その次から行が並ぶ
+        let row = (inside && mouse.row >= area.y + 2)
+            .then(|| self.table_layout.offset + usize::from(mouse.row - area.y - 2))
+            .and_then(|row| self.table_layout.rows.get(row).copied().flatten());
 
-                self.table_state.select(Some(target));
+        match mouse.kind {
+            MouseEventKind::Moved => self.hovered = row,
+            MouseEventKind::ScrollDown => self.next(),
+            MouseEventKind::ScrollUp => self.previous(),
+            MouseEventKind::Down(MouseButton::Left) if inside && mouse.row == area.y => {
+                self.sort_by_column(mouse.column);
             }
-            Enter => {
-                let selected = self.table_state.selected().unwrap_or(0);
-                if selected >= self.hosts.len() {
-                    return Ok(AppKeyAction::Ok);
+            MouseEventKind::Down(MouseButton::Left) => {
+                let Some(row) = row else {
+                    return AppKeyAction::Ok;
+                };
+                let double = self
+                    .last_click
+                    .is_some_and(|(last, at)| last == row && at.elapsed() < DOUBLE_CLICK);
+                self.table_state.select(Some(row));
+                if double {
+                    self.last_click = None;
+                    if let Some(host) = self.selected_host().cloned() {
+                        return AppKeyAction::Connect(Box::new(host));
+                    }
+                } else {
+                    self.last_click = Some((row, Instant::now()));
                 }
+            }
+            _ => {}
+        }
+        AppKeyAction::Ok
+    }
 
-                let host: &ssh::Host = &self.hosts[selected];
-
-                restore_terminal(terminal).expect("Failed to restore terminal");
-
-                if let Some(template) = &self.config.command_template_on_session_start {
-                    host.run_command_template(template)?;
-                }
+    /// Header click: sorts by the column at `x`, then reverses it, then goes
+    /// back to the configured order.
+    fn sort_by_column(&mut self, x: u16) {
+        let area = self.table_layout.area;
+        let columns = Layout::horizontal(&self.table_columns_constraints)
+            .flex(layout::Flex::Start)
+            .spacing(2)
+            .split(area);
+        let Some(column) = columns.iter().position(|column| (column.x..column.right()).contains(&x)) else {
+            return;
+        };
+        // 先頭はマーカー (とアイコン) の列
+        let first_data = 1 + usize::from(self.config.icons != IconMode::Off);
+        let Some(key) = column
+            .checked_sub(first_data)
+            .and_then(|column| COLUMN_SORT_KEYS.get(column).copied().flatten())
+        else {
+            return;
+        };
 
-                host.run_command_template(&self.config.command_template)?;
+        self.header_sort = match self.header_sort {
+            Some((current, false)) if current == key => Some((key, true)),
+            Some((current, true)) if current == key => None,
+            _ => Some((key, false)),
+        };
+        let selected = self.selected_host().map(|host| host.name.clone());
+        self.refresh_rows();
+        if let Some(index) = selected.and_then(|name| self.hosts.iter().position(|host| host.name == name)) {
+            self.table_state.select(Some(index));
+        }
+    }
 
-                if let Some(template) = &self.config.command_template_on_session_end {
-                    host.run_command_template(template)?;
-                }
+    /// Answers requests from `--control-socket` clients and returns the host
+    /// a `connect` call asked for; the caller connects since that needs the
+    /// terminal.
+    #[cfg(unix)]
+    fn handle_control_calls(&mut self) -> Option<ssh::Host> {
+        let mut socket = self.control.take()?;
+        let mut connect = None;
+        for call in socket.poll() {
+            let result = self.on_control_call(&call.method, &call.params, &mut connect);
+            socket.reply(&call, result);
+        }
+        self.control = Some(socket);
+        connect
+    }
 
-                setup_terminal(terminal).expect("Failed to setup terminal");
+    #[cfg(unix)]
+    fn on_control_call(
+        &mut self,
+        method: &str,
+        params: &serde_json::Value,
+        connect: &mut Option<ssh::Host>,
+    ) -> Result<serde_json::Value, RpcError> {
+        // ロック中に外から接続や絞り込みをされるとパスフレーズを迂回できてしまう
+        if self.lock.is_some() {
+            return Err(RpcError::failed("screen is locked"));
+        }
+        let name_param = || {
+            params["name"]
+                .as_str()
+                .ok_or_else(|| RpcError::invalid_params("name must be a string"))
+        };
 
-                if self.config.exit_after_ssh_session_ends {
-                    return Ok(AppKeyAction::Stop);
+        match method {
+            "state" => Ok(self.control_state()),
+            "hosts" => {
+                let show_patterns = self.show_patterns.get();
+                let hosts: Vec<&ssh::Host> = if params["all"].as_bool().unwrap_or(false) {
+                    self.hosts
+                        .non_filtered_iter()
+                        .filter(|host| show_patterns || !host.is_pattern)
+                        .collect()
+                } else {
+                    self.hosts.iter().collect()
+                };
+                serde_json::to_value(hosts).map_err(|err| RpcError::failed(err.to_string()))
+            }
+            "set_filter" => {
+                let query = params["query"]
+                    .as_str()
+                    .ok_or_else(|| RpcError::invalid_params("query must be a string"))?;
+                self.history_cursor = None;
+                self.set_search(query.to_string());
+                Ok(self.control_state())
+            }
+            "select" => {
+                let name = name_param()?;
+                let index = self
+                    .hosts
+                    .iter()
+                    .position(|host| host.name == name)
+                    .ok_or_else(|| RpcError::failed(format!("{name} is not in the current results")))?;
+                self.table_state.select(Some(index));
+                Ok(self.control_state())
+            }
+            "connect" => {
+                let host = if params.get("name").is_some() {
+                    let name = name_param()?;
+                    self.hosts
+                        .non_filtered_iter()
+                        .find(|host| host.name == name)
+                        .cloned()
+                        .ok_or_else(|| RpcError::failed(format!("No host named {name}")))?
+                } else {
+                    self.selected_host()
+                        .cloned()
+                        .ok_or_else(|| RpcError::failed("No host selected"))?
+                };
+                if host.is_pattern {
+                    return Err(RpcError::failed(format!(
+                        "{} is a pattern block, not a host you can connect to",
+                        host.name
+                    )));
                 }
+                let result = serde_json::json!({ "host": host.name });
+                *connect = Some(host);
+                Ok(result)
             }
-            _ => return Ok(AppKeyAction::Continue),
+            method => Err(RpcError::method_not_found(method)),
         }
+    }
 
-        Ok(AppKeyAction::Ok)
+    /// Compares the selected host with `ssh -G` (`v` in the explain popup).
+    fn verify_selected(&self) -> Option<Verification> {
+        let host = self.hosts.iter().nth(self.table_state.selected().unwrap_or(0))?;
+        let config = self.config.single_config_file();
+        Some(
+            verify::ssh_effective(&host.name, config.as_deref())
+                .map(|effective| verify::compare(host, &effective))
+                .map_err(|err| err.to_string()),
+        )
     }
 
-    fn on_key_press_ctrl(&mut self, key: KeyEvent) -> AppKeyAction {
-        #[allow(clippy::enum_glob_use)]
-        use KeyCode::*;
+    /// Alt+E: opens the bulk edit prompt for the hosts currently listed.
+    fn open_bulk_edit(&mut self) {
+        if edit::is_read_only() {
+            self.flash(t("Read-only: config files are not changed"));
+            return;
+        }
 
-        match key.code {
-            Char('c') => AppKeyAction::Stop,
-            Char('j' | 'n') => {
-                self.next();
-                AppKeyAction::Ok
-            }
-            Char('k' | 'p') => {
-                self.previous();
-                AppKeyAction::Ok
+        let mut targets: Vec<(PathBuf, Vec<String>)> = Vec::new();
+        let mut hosts = 0;
+        let mut skipped = 0;
+        for host in self.hosts.iter().filter(|host| !host.is_pattern && !host.is_adhoc) {
+            let Some(path) = host.source_file.as_ref().map(PathBuf::from).filter(|path| path.is_file()) else {
+                skipped += 1;
+                continue;
+            };
+            hosts += 1;
+            let names = std::iter::once(host.name.as_str())
+                .chain(host.aliases.split(", ").filter(|alias| !alias.is_empty()))
+                .map(str::to_string);
+            match targets.iter_mut().find(|(p, _)| *p == path) {
+                Some((_, existing)) => existing.extend(names),
+                None => targets.push((path, names.collect())),
             }
-            _ => AppKeyAction::Continue,
         }
+
+        if hosts == 0 {
+            self.flash(t("No listed host is defined in a config file"));
+            return;
+        }
+        self.overlay = Some(Overlay::BulkEdit(Box::new(BulkEditForm {
+            option: Input::default(),
+            targets,
+            hosts,
+            skipped,
+            preview: None,
+            scroll: 0,
+        })));
     }
 
-    fn next(&mut self) {
-        let i = match self.table_state.selected() {
-            Some(i) => {
-                if self.hosts.is_empty() || i >= self.hosts.len() - 1 {
-                    0
-                } else {
-                    i + 1
-                }
+    /// Writes the previewed bulk edit. Returns the form back when the
+    /// write failed so the error can be shown.
+    fn apply_bulk_edit(&mut self, mut form: Box<BulkEditForm>) -> Option<Box<BulkEditForm>> {
+        let (keyword, value) = match form.option() {
+            Ok(option) => option,
+            Err(err) => {
+                form.preview = Some(Err(err));
+                return Some(form);
             }
-            None => 0,
         };
-        self.table_state.select(Some(i));
-    }
-
-    fn previous(&mut self) {
-        let i = match self.table_state.selected() {
-            Some(i) => {
-                if self.hosts.is_empty() {
-                    0
-                } else if i == 0 {
-                    self.hosts.len() - 1
-                } else {
-                    i - 1
+        let before = self.undo_stack.len();
+        let result = edit::set_option(&form.targets, &keyword, &value, &mut self.undo_stack);
+        let files = self.undo_stack.len() - before;
+        if let Err(err) = self.reload_hosts() {
+            self.error_popup = Some(format!("Changed {files} file(s), but failed to reload: {err}"));
+        }
+        if let Err(err) = result {
+            form.preview = Some(Err(format!("{err:#} ({files} file(s) already changed, Alt+U undoes)")));
+            return Some(form);
+        }
+        let blocks = form.preview.and_then(Result::ok).map_or(0, |changes| changes.len());
+        self.flash(t_fmt(
+            "Set {keyword} on {blocks} host block(s) in {files} file(s)",
+            &[("keyword", &keyword), ("blocks", &blocks), ("files", &files)],
+        ));
+        None
+    }
+
+    /// Alt+F: opens the `LocalForward` editor for the selected host.
+    fn open_forward_editor(&mut self) {
+        if edit::is_read_only() {
+            self.flash(t("Read-only: config files are not changed"));
+            return;
+        }
+        let Some(host) = self.selected_host().filter(|host| !host.is_pattern && !host.is_adhoc) else {
+            return;
+        };
+        let Some(path) = host.source_file.as_ref().map(PathBuf::from).filter(|path| path.is_file()) else {
+            self.flash(t_fmt("{host} is not defined in a config file", &[("host", &host.name)]));
+            return;
+        };
+        self.overlay = Some(Overlay::Forwards(Box::new(ForwardEditor::new(host, path))));
+    }
+
+    /// Writes the typed forward (or removes the selected one when `remove`)
+    /// and re-reads the config, keeping the editor open.
+    fn apply_forward_edit(&mut self, mut editor: Box<ForwardEditor>, remove: bool) -> Box<ForwardEditor> {
+        let index = if remove { editor.selected() } else { editor.input.as_ref().and_then(|(index, _)| *index) };
+        let old = index.map(|i| editor.forwards[i].clone());
+        let new = if remove {
+            None
+        } else {
+            let typed = editor.input.as_ref().map(|(_, input)| input.value()).unwrap_or_default();
+            match parse_forward(typed) {
+                Ok(value) => Some(value),
+                Err(err) => {
+                    editor.error = Some(err);
+                    return editor;
                 }
             }
-            None => 0,
         };
-        self.table_state.select(Some(i));
+
+        match edit::edit_local_forward(&editor.path, &editor.names, old.as_deref(), new.as_deref()) {
+            Ok(backup) => {
+                self.undo_stack.push(backup);
+                editor.input = None;
+                editor.error = None;
+            }
+            Err(err) => {
+                editor.error = Some(format!("{err:#}"));
+                return editor;
+            }
+        }
+        if let Err(err) = self.reload_hosts() {
+            editor.error = Some(format!("Saved, but failed to reload: {err}"));
+            return editor;
+        }
+        if let Some(host) = self.merged_hosts.iter().find(|host| host.name == editor.host) {
+            editor.set_forwards(host);
+        }
+        editor
     }
 
-    fn calculate_table_columns_constraints(&mut self) {
-        let mut lengths = Vec::new();
+    /// Alt+T: lists the tunnel presets to connect the selected host with.
+    fn open_tunnels(&mut self) {
+        if self.config.tunnels.is_empty() {
+            self.flash(t("No tunnels in the config file"));
+            return;
+        }
+        if self.selected_host().is_some() {
+            let ticked = vec![false; self.config.tunnels.len()];
+            self.overlay = Some(Overlay::Tunnels(ListState::default().with_selected(Some(0)), ticked));
+        }
+    }
 
-        let name_len = self
-            .hosts
-            .iter()
-            .map(|d| d.name.as_str())
-            .map(UnicodeWidthStr::width)
-            .max()
-            .unwrap_or(0);
-        lengths.push(name_len);
+    /// Connects the selected host with the forwards of the ticked presets,
+    /// or of the highlighted one when none is ticked.
+    fn connect_with_tunnels(&mut self, highlighted: Option<usize>, ticked: &[bool]) -> AppKeyAction {
+        let Some(mut host) = self.selected_host().cloned() else {
+            return AppKeyAction::Ok;
+        };
+        let picked: Vec<&Tunnel> = if ticked.contains(&true) {
+            self.config.tunnels.iter().zip(ticked).filter(|(_, &t)| t).map(|(tunnel, _)| tunnel).collect()
+        } else {
+            highlighted.and_then(|i| self.config.tunnels.get(i)).into_iter().collect()
+        };
+        host.tunnels = picked.iter().flat_map(|tunnel| tunnel.forwards.iter().cloned()).collect();
+        AppKeyAction::Connect(Box::new(host))
+    }
 
-        let aliases_len = self
-            .hosts
-            .non_filtered_iter()
-            .map(|d| d.aliases.as_str())
-            .map(UnicodeWidthStr::width)
-            .max()
-            .unwrap_or(0);
-        lengths.push(aliases_len);
+    /// Alt+R: lists the snippets to run on the selected host.
+    fn open_snippets(&mut self) {
+        if self.config.snippets.is_empty() {
+            self.flash(t("No snippets in the config file"));
+            return;
+        }
+        if self.selected_host().is_some() {
+            self.overlay = Some(Overlay::Snippets(ItemTable::new(self.config.snippets.clone())));
+        }
+    }
 
-        let user_len = self
-            .hosts
-            .non_filtered_iter()
-            .map(|d| match &d.user {
-                Some(u) => u.as_str(),
-                None => "",
-            })
-            .map(UnicodeWidthStr::width)
-            .max()
-            .unwrap_or(0);
-        lengths.push(user_len);
+    /// Runs `snippet` on the selected host through the snippet template.
+    fn run_snippet(&mut self, snippet: Option<&Snippet>) -> AppKeyAction {
+        let Some(snippet) = snippet else {
+            return AppKeyAction::Ok;
+        };
+        let Some(mut host) = self.selected_host().cloned() else {
+            return AppKeyAction::Ok;
+        };
+        host.snippet = Some(snippet.quoted());
+        AppKeyAction::Connect(Box::new(host))
+    }
 
-        let destination_len = self
-            .hosts
-            .non_filtered_iter()
-            .map(|d| d.destination.as_str())
-            .map(UnicodeWidthStr::width)
-            .max()
-            .unwrap_or(0);
-        lengths.push(destination_len);
+    /// Alt+M: lists the recorded macros.
+    fn open_macros(&mut self) {
+        if self.macros.is_empty() {
+            self.flash(t("No macros yet; press Ctrl+S in the snippet list (Alt+R) to record one"));
+            return;
+        }
+        self.overlay = Some(Overlay::Macros(ItemTable::new(self.macros.list())));
+    }
+
+    /// Searches for the macro's query and runs its snippet on every host
+    /// that lists.
+    fn replay_macro(&mut self, recorded: Option<Macro>) -> AppKeyAction {
+        let Some(recorded) = recorded else {
+            return AppKeyAction::Ok;
+        };
+        self.set_search(recorded.steps.query.clone());
+        let hosts: Vec<ssh::Host> = self.hosts.iter().filter(|host| !host.is_pattern).cloned().collect();
+        if hosts.is_empty() {
+            self.flash(t_fmt("No host matches {query}", &[("query", &format!("{:?}", recorded.steps.query))]));
+            return AppKeyAction::Ok;
+        }
+        AppKeyAction::RunMacro(Box::new(recorded.steps), hosts)
+    }
+
+    fn run_macro<B>(&mut self, terminal: &Rc<RefCell<Terminal<B>>>, steps: &macros::Steps, hosts: &[ssh::Host]) -> Result<()>
+    where
+        B: Backend + std::io::Write,
+    {
+        let with_snippet = |host: &ssh::Host| ssh::Host {
+            snippet: Some(steps.snippet.quoted()),
+            ..host.clone()
+        };
+        if self.config.demo {
+            let commands: Vec<String> = hosts
+                .iter()
+                .map(|host| {
+                    let host = with_snippet(host);
+                    self.command_template(&host)
+                        .and_then(|template| host.render_command_template(&template, Helpers::Placeholder))
+                        .map_or_else(|err| format!("{}: {err}", host.name), |command| command.redacted())
+                })
+                .collect();
+            self.error_popup = Some(format!("Demo mode: would run\n{}", commands.join("\n")));
+            return Ok(());
+        }
+        self.remember_query();
+
+        restore_terminal(terminal)?;
+        let result = macros::run(steps, hosts, |host| self.command_template(host), self.config.shell);
+        // 出力を読めるよう、画面を戻す前に Enter を待つ
+        println!("\n[sgh] {}", t("Press Enter to return to sgh."));
+        let _ = std::io::stdin().read_line(&mut String::new());
+        setup_terminal(terminal)?;
+        terminal.borrow_mut().clear()?;
+
+        for host in hosts {
+            self.record_connection(&host.name);
+        }
+        match result {
+            Ok(true) => self.flash(t_fmt(
+                "Ran {snippet} on {count} hosts",
+                &[("snippet", &steps.snippet.name), ("count", &hosts.len())],
+            )),
+            Ok(false) => self.flash(t_fmt("{snippet} failed on some hosts", &[("snippet", &steps.snippet.name)])),
+            Err(err) => self.error_popup = Some(format!("Macro failed: {err}")),
+        }
+        Ok(())
+    }
+
+    /// The command template for `host`: the snippet template when a snippet
+    /// was picked, else the connect template (unless `--template` was
+    /// changed, the host's own command from its source, or a shell started
+    /// in its `# sgh:dir`), with the `-L` flags of the tunnels picked for it
+    /// and `-o` flags for values a source won over the SSH config.
+    fn command_template(&self, host: &ssh::Host) -> Result<String> {
+        command_template(&self.config, host)
+    }
+
+    /// Opens the pattern playground (Ctrl+Y), pre-filled with the selected
+    /// host's name.
+    fn open_playground(&mut self) {
+        let configs = if self.config.demo {
+            demo::blocks().map(|blocks| vec![blocks])
+        } else {
+            resolve::read(&ssh::expand_config_paths(&self.config.config_paths))
+        };
+        let name = self.selected_host().map(|host| host.name.clone()).unwrap_or_default();
+        self.overlay = Some(Overlay::Playground(Box::new(Playground {
+            name: Input::from(name),
+            configs: configs.map_err(|err| err.to_string()),
+            scroll: 0,
+        })));
+    }
+
+    /// Query, selection and match count reported to control clients.
+    #[cfg(unix)]
+    fn control_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "query": self.search.value(),
+            "selected": self.selected_host().map(|host| &host.name),
+            "matches": self.hosts.len(),
+        })
+    }
+
+    /// Feeds `keys` without a terminal and stops at the first one that would
+    /// quit or connect, e.g. "type a query, move down, Enter".
+    #[cfg(test)]
+    fn feed(&mut self, keys: impl IntoIterator<Item = KeyEvent>) -> Outcome {
+        for key in keys {
+            match self.handle_key(key) {
+                AppKeyAction::Stop => return Outcome::Quit,
+                AppKeyAction::Connect(host) | AppKeyAction::ConnectIn(host, _) => return Outcome::Connect(host),
+                AppKeyAction::RunMacro(_, hosts) => {
+                    return Outcome::RunMacro(hosts.into_iter().map(|host| host.name).collect());
+                }
+                AppKeyAction::Ok | AppKeyAction::Continue | AppKeyAction::Suspend | AppKeyAction::ImportHistory => {}
+            }
+        }
+        Outcome::Running
+    }
 
-        let port_len = self
+    /// Re-filters the hosts with the current search bar value and keeps the
+    /// selection within the result.
+    fn apply_search(&mut self) {
+        let anchor = self.selection_anchor();
+        self.hosts.search(self.search.value());
+        self.after_search(anchor);
+    }
+
+    /// Updates what depends on the filtered rows and selects the host of
+    /// `anchor` again, or its nearest visible neighbour in list order when
+    /// it was filtered out. Without an anchor the first row is selected.
+    fn after_search(&mut self, anchor: Option<SelectionAnchor>) {
+        self.update_adhoc_host();
+        if self.config.fit_columns {
+            self.calculate_table_columns_constraints();
+        }
+
+        let selected = anchor.map_or(0, |anchor| self.anchor_row(&anchor));
+        self.table_state.select(Some(selected));
+    }
+
+    /// The selected host, unless the first row is selected: a selection
+    /// left at the top stays at the top so the best match of a new query
+    /// is what Enter picks.
+    fn selection_anchor(&self) -> Option<SelectionAnchor> {
+        let selected = self.table_state.selected().filter(|&i| i > 0 && i < self.hosts.len())?;
+        let name = self.hosts[selected].name.clone();
+        let position = self.hosts.non_filtered_iter().position(|host| host.name == name)?;
+        Some(SelectionAnchor { name, position })
+    }
+
+    /// Row of the anchored host in the filtered list, or of the visible host
+    /// closest to where it sits in the full list.
+    fn anchor_row(&self, anchor: &SelectionAnchor) -> usize {
+        if let Some(row) = self.hosts.iter().position(|host| host.name == anchor.name) {
+            return row;
+        }
+        // 再読み込みで並びが変わっていれば新しい位置を基準にする
+        let positions: HashMap<&str, usize> = self
             .hosts
             .non_filtered_iter()
-            .map(|d| match &d.port {
-                Some(port) => port.as_str(),
-                None => "",
+            .enumerate()
+            .map(|(i, host)| (host.name.as_str(), i))
+            .collect();
+        let position = positions.get(anchor.name.as_str()).copied().unwrap_or(anchor.position);
+        self.hosts
+            .iter()
+            .enumerate()
+            .filter_map(|(row, host)| Some((row, positions.get(host.name.as_str())?.abs_diff(position))))
+            .min_by_key(|(_, distance)| *distance)
+            .map_or(0, |(row, _)| row)
+    }
+
+    /// Offers an ad-hoc row for a `user@host:port` query when nothing matches.
+    fn update_adhoc_host(&mut self) {
+        let value = self.search.value();
+        self.adhoc_host = if self.hosts.is_empty() && Query::parse(value).terms.is_empty() {
+            ssh::Host::adhoc(value)
+        } else {
+            None
+        };
+    }
+
+    /// The highlighted row: a host, or the ad-hoc row offered when nothing
+    /// matches.
+    fn selected_host(&self) -> Option<&ssh::Host> {
+        if self.hosts.is_empty() {
+            return self.adhoc_host.as_ref();
+        }
+        let selected = self.table_state.selected().unwrap_or(0);
+        (selected < self.hosts.len()).then(|| &self.hosts[selected])
+    }
+
+    /// Restricts the free-text search to `field`, or back to every
+    /// configured field with `None`.
+    /// Shows `message` in the status bar for a few seconds.
+    fn flash(&mut self, message: impl Into<String>) {
+        self.status_message = Some((message.into(), Instant::now()));
+    }
+
+    fn set_focus_field(&mut self, field: Option<SearchField>) {
+        self.focus_field.set(field);
+        self.apply_search();
+    }
+
+    /// Rebuilds the table rows from `merged_hosts`.
+    fn refresh_rows(&mut self) {
+        let natural = self.config.natural_sort;
+        let rows = match self.header_sort {
+            Some((key, descending)) => {
+                let mut rows = host_rows(&self.merged_hosts, self.split_aliases, &[key], natural);
+                if descending {
+                    rows.sort_by(|a, b| key.compare(a, b, natural).reverse());
+                }
+                rows
+            }
+            None => host_rows(&self.merged_hosts, self.split_aliases, &self.config.sort_keys, natural),
+        };
+        let anchor = self.selection_anchor();
+        self.hosts.set_items(rows, self.search.value());
+        self.calculate_table_columns_constraints();
+        self.after_search(anchor);
+    }
+
+    /// Re-reads the SSH config files, e.g. after sgh wrote to one of them.
+    /// The hosts of the slow sources are kept as they were loaded.
+    fn reload_hosts(&mut self) -> Result<()> {
+        let (mut hosts, _) = load_local_hosts(&self.config)?;
+        for loaded in &self.background_sources {
+            if let Some(source_hosts) = &loaded.hosts {
+                add_source_hosts(&self.config, &mut hosts, source_hosts.clone(), loaded.kind);
+            }
+        }
+        self.merged_hosts = hosts;
+        self.refresh_rows();
+        Ok(())
+    }
+
+    /// Loads each of `sources` on its own thread; `receive_source_hosts`
+    /// picks up the results.
+    fn load_in_background(&mut self, sources: Vec<Source>) {
+        if sources.is_empty() {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        for (index, source) in sources.into_iter().enumerate() {
+            self.background_sources.push(BackgroundSource {
+                kind: source.kind(),
+                hosts: None,
+            });
+            let sender = sender.clone();
+            let (pipeline, cache) = (self.config.pipeline, self.config.source_cache);
+            std::thread::spawn(move || {
+                // 受け取る側が先に終了していれば結果は捨てる
+                let _ = sender.send((index, source.load_cached(pipeline, cache)));
+            });
+        }
+        self.source_results = Some(receiver);
+    }
+
+    /// Adds the hosts of the sources that finished loading since the last
+    /// call. A failed source is reported in the status bar and left empty.
+    fn receive_source_hosts(&mut self) {
+        let Some(receiver) = &self.source_results else {
+            return;
+        };
+        let results: Vec<_> = receiver.try_iter().collect();
+        if results.is_empty() {
+            return;
+        }
+
+        // 除外パターンは起動時に検証済み
+        let exclude = ExcludePattern::parse_all(&self.config.exclude).unwrap_or_default();
+        for (index, result) in results {
+            let hosts = match result {
+                Ok(mut hosts) => {
+                    hosts.retain(|host| !exclude.iter().any(|pattern| pattern.matches(host)));
+                    hosts
+                }
+                Err(err) => {
+                    let kind = self.background_sources[index].kind;
+                    self.flash(t_fmt("Source {source} failed: {error}", &[("source", &kind), ("error", &err)]));
+                    Vec::new()
+                }
+            };
+            let kind = self.background_sources[index].kind;
+            add_source_hosts(&self.config, &mut self.merged_hosts, hosts.clone(), kind);
+            self.background_sources[index].hosts = Some(hosts);
+        }
+        if self.background_sources.iter().all(|source| source.hosts.is_some()) {
+            self.source_results = None;
+            if let Some(warning) = source_options_warning(&self.config, &self.merged_hosts) {
+                self.flash(warning);
+            }
+        }
+        self.refresh_rows();
+    }
+
+    /// Config files an ad-hoc host can be saved to: the user's config files
+    /// in the order they are read, or `~/.ssh/config` when there are none.
+    fn writable_config_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = ssh::expand_config_paths(&self.config.config_paths)
+            .into_iter()
+            .filter(|path| !path.starts_with("/etc"))
+            .collect();
+        files.dedup();
+        if files.is_empty() {
+            files.push(PathBuf::from(shellexpand::tilde("~/.ssh/config").to_string()));
+        }
+        files
+    }
+
+    /// Whether there is nothing at all to list, so the onboarding view takes
+    /// the place of the empty table: no configured host, no source still
+    /// loading, and no query (which may be an ad-hoc destination).
+    fn is_onboarding(&self) -> bool {
+        self.search.value().is_empty()
+            && self.merged_hosts.iter().all(|host| host.is_pattern)
+            && self.background_sources.iter().all(|source| source.hosts.is_some())
+    }
+
+    /// Whether the query matches none of the hosts there are, so the
+    /// suggestions panel is shown under the (ad-hoc) rows.
+    fn is_no_match(&self) -> bool {
+        self.hosts.is_empty() && !self.search.value().trim().is_empty() && !self.is_onboarding()
+    }
+
+    /// Hosts close to the free text of the query, for the suggestions panel.
+    fn near_misses(&self) -> Vec<&ssh::Host> {
+        let fields = focused_fields(self.focus_field.get(), &self.config.search_fields);
+        let text = Query::parse(self.search.value()).text;
+        search::near_misses(self.hosts.non_filtered_iter(), &fields, &text, 5)
+    }
+
+    /// Alt+C on the onboarding view: creates the SSH config file hosts are
+    /// added to, with a commented example block.
+    fn create_ssh_config(&mut self) {
+        if edit::is_read_only() {
+            self.flash(t("Read-only: config files are not changed"));
+            return;
+        }
+        let Some(path) = self.writable_config_files().into_iter().next() else {
+            return;
+        };
+        if path.exists() {
+            self.flash(t_fmt("{path} already exists", &[("path", &path.display())]));
+            return;
+        }
+        // ~/.ssh は本人だけが読めるようにしておく (ssh は緩い権限を嫌う)
+        #[cfg(unix)]
+        if let Some(dir) = path.parent().filter(|dir| !dir.exists()) {
+            use std::os::unix::fs::DirBuilderExt;
+            let _ = std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir);
+        }
+        match edit::write_config(&path, NEW_CONFIG_TEMPLATE, "Create SSH config") {
+            Ok(backup) => {
+                self.undo_stack.push(backup);
+                self.flash(t_fmt("Created {path}", &[("path", &path.display())]));
+            }
+            Err(err) => self.error_popup = Some(format!("Failed to create {}: {err}", path.display())),
+        }
+    }
+
+    /// Alt+I on the onboarding view: runs the `sgh import --from-history`
+    /// walkthrough on the terminal, then lists the hosts it added.
+    fn import_history<B>(&mut self, terminal: &Rc<RefCell<Terminal<B>>>) -> Result<()>
+    where
+        B: Backend + std::io::Write,
+    {
+        let Some(target) = self.writable_config_files().into_iter().next() else {
+            return Ok(());
+        };
+        restore_terminal(terminal)?;
+        let result = import::run_from_history(&import::default_history_files(), &target, &self.merged_hosts);
+        // 結果のメッセージを読めるよう、画面を戻す前に Enter を待つ
+        println!("\n[sgh] {}", t("Press Enter to return to sgh."));
+        let _ = std::io::stdin().read_line(&mut String::new());
+        setup_terminal(terminal)?;
+        terminal.borrow_mut().clear()?;
+
+        match result.and_then(|()| self.reload_hosts()) {
+            Ok(()) => {}
+            Err(err) => self.error_popup = Some(format!("Import from history failed: {err}")),
+        }
+        Ok(())
+    }
+
+    /// Appends the block from the save-host prompt to the chosen file.
+    /// Returns the form back with an error when the input is not usable.
+    fn save_adhoc_host(&mut self, mut form: Box<SaveHostForm>) -> Option<Box<SaveHostForm>> {
+        let name = form.name.value().trim().to_string();
+        let taken = self
+            .merged_hosts
+            .iter()
+            .any(|h| h.name == name || h.aliases.split(", ").any(|alias| alias == name));
+        form.error = if name.is_empty() || name.contains(char::is_whitespace) {
+            Some("Enter a single-word alias.".to_string())
+        } else if taken {
+            Some(format!("{name} is already defined."))
+        } else {
+            None
+        };
+        if form.error.is_some() {
+            return Some(form);
+        }
+
+        let path = &form.files[form.file_index];
+        match edit::append_host(path, &form.block()) {
+            Ok(backup) => self.undo_stack.push(backup),
+            Err(err) => {
+                form.error = Some(err.to_string());
+                return Some(form);
+            }
+        }
+
+        if let Err(err) = self.reload_hosts() {
+            self.error_popup = Some(format!("Saved, but failed to reload: {err}"));
+        }
+        self.flash(t_fmt("Added {host} to {path}", &[("host", &name), ("path", &path.display())]));
+        self.set_search(name);
+        None
+    }
+
+    /// Ctrl+L: switches between one row per host and one row per alias,
+    /// keeping the selected host selected.
+    fn toggle_split_aliases(&mut self) {
+        let selected = self
+            .table_state
+            .selected()
+            .filter(|&i| i < self.hosts.len())
+            .map(|i| self.hosts[i].clone());
+
+        self.split_aliases = !self.split_aliases;
+        self.refresh_rows();
+
+        // 分割後は元の名前の行、結合後はその名前を別名に含む行を選び直す
+        let index = selected.and_then(|selected| {
+            self.hosts.iter().position(|host| {
+                host.name == selected.name
+                    || host.aliases.split(", ").any(|alias| alias == selected.name)
             })
-            .map(UnicodeWidthStr::width)
-            .max()
-            .unwrap_or(0);
-        lengths.push(port_len);
+        });
+        self.table_state.select(Some(index.unwrap_or(0)));
+        self.apply_search();
+    }
 
-        if self.config.show_proxy_command {
-            let proxy_len = self
-                .hosts
-                .non_filtered_iter()
-                .map(|d| match &d.proxy_command {
-                    Some(p) => p.as_str(),
-                    None => "",
-                })
-                .map(UnicodeWidthStr::width)
-                .max()
-                .unwrap_or(0);
-            lengths.push(proxy_len);
+    /// Adds the current query to the persisted search history.
+    fn remember_query(&mut self) {
+        self.query_history.push(self.search.value());
+        if let Err(err) = self.query_history.save() {
+            eprintln!("Failed to save search history: {err}");
         }
+    }
 
-        let mut new_constraints = vec![
-            // Marker column (▌ / spaces) — width matches SELECTION_MARKER.
-            Constraint::Length(u16::try_from(UnicodeWidthStr::width(SELECTION_MARKER)).unwrap_or(2)),
-            // Name column (+1 for breathing room).
-            Constraint::Length(u16::try_from(lengths[0]).unwrap_or_default() + 1),
-        ];
-        new_constraints.extend(
-            lengths
-                .iter()
-                .skip(1)
-                .map(|len| Constraint::Min(u16::try_from(*len).unwrap_or_default() + 1)),
-        );
+    /// Counts a connection to `host_name` for frequency ranking.
+    fn record_connection(&mut self, host_name: &str) {
+        let mut connections = self.connections.borrow_mut();
+        connections.record(host_name);
+        if let Err(err) = connections.save() {
+            eprintln!("Failed to save connection history: {err}");
+        }
+        drop(connections);
+        self.refresh_view();
+    }
 
-        self.table_columns_constraints = new_constraints;
+    fn record_duration(&mut self, host_name: &str, duration: Duration) {
+        let mut connections = self.connections.borrow_mut();
+        connections.record_duration(host_name, duration);
+        if let Err(err) = connections.save() {
+            eprintln!("Failed to save connection history: {err}");
+        }
+        drop(connections);
+        self.refresh_view();
     }
-}
 
-fn setup_terminal<B>(terminal: &Rc<RefCell<Terminal<B>>>) -> Result<()>
-where
-    B: Backend + std::io::Write,
-{
-    let mut terminal = terminal.borrow_mut();
+    /// Alt+↑ (older) / Alt+↓ (newer) through the search history. Going past the
+    /// newest entry restores the query that was being typed.
+    fn browse_history(&mut self, older: bool) {
+        let next = match (&self.history_cursor, older) {
+            (None, true) => Some(0),
+            (None, false) => return,
+            (Some((i, _)), true) => Some((i + 1).min(self.query_history.len().saturating_sub(1))),
+            (Some((0, _)), false) => None,
+            (Some((i, _)), false) => Some(i - 1),
+        };
 
-    // setup terminal
-    enable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        Hide,
-        EnterAlternateScreen,
-        EnableMouseCapture
-    )?;
+        match next {
+            Some(index) => {
+                let Some(entry) = self.query_history.get(index).map(str::to_string) else {
+                    return;
+                };
+                let draft = match self.history_cursor.take() {
+                    Some((_, draft)) => draft,
+                    None => self.search.value().to_string(),
+                };
+                self.history_cursor = Some((index, draft));
+                self.set_search(entry);
+            }
+            None => {
+                if let Some((_, draft)) = self.history_cursor.take() {
+                    self.set_search(draft);
+                }
+            }
+        }
+    }
 
-    Ok(())
-}
+    fn set_search(&mut self, value: String) {
+        self.search = Input::from(value);
+        self.apply_search();
+    }
 
-fn restore_terminal<B>(terminal: &Rc<RefCell<Terminal<B>>>) -> Result<()>
-where
-    B: Backend + std::io::Write,
-{
-    let mut terminal = terminal.borrow_mut();
-    terminal.clear()?;
+    /// Alt+U: reverts the most recent config change made in this run.
+    fn undo(&mut self) {
+        let Some(backup) = self.undo_stack.pop() else {
+            self.flash(t("Nothing to undo"));
+            return;
+        };
 
-    // restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        Show,
-        LeaveAlternateScreen,
-        DisableMouseCapture,
-    )?;
+        if let Err(err) = backup.restore() {
+            self.error_popup = Some(format!("Failed to undo: {err}"));
+            return;
+        }
+        if let Err(err) = self.reload_hosts() {
+            self.error_popup = Some(format!("Undone, but failed to reload: {err}"));
+            return;
+        }
+        self.flash(t_fmt("Undid the last change to {path}", &[("path", &backup.path.display())]));
+    }
+
+    /// Ctrl+X: drops the rightmost filter chip, ending with the column focus.
+    fn remove_last_chip(&mut self) {
+        if let Some(chip) = Chip::parse_all(self.search.value()).pop() {
+            let value = chip.remove_from(self.search.value());
+            self.set_search(value);
+        } else if self.focus_field.get().is_some() {
+            self.set_focus_field(None);
+        }
+    }
+
+    fn on_key_press(&mut self, key: KeyEvent) -> AppKeyAction {
+        #[allow(clippy::enum_glob_use)]
+        use KeyCode::*;
+
+        if self.lock.is_some() {
+            return self.on_lock_key(key);
+        }
+
+        // ポップアップ表示中は任意のキーで閉じるだけ
+        if self.error_popup.take().is_some() {
+            return AppKeyAction::Ok;
+        }
+
+        if let Some(overlay) = self.overlay.take() {
+            // トンネル選択中の Enter はそのまま接続する
+            if let (Overlay::Tunnels(list, ticked), Enter) = (&overlay, key.code) {
+                return self.connect_with_tunnels(list.selected(), ticked);
+            }
+            if let (Overlay::Snippets(table), Enter) = (&overlay, key.code) {
+                return self.run_snippet(table.selected());
+            }
+            if let (Overlay::Macros(table), Enter) = (&overlay, key.code) {
+                return self.replay_macro(table.selected().cloned());
+            }
+            self.on_overlay_key(overlay, key);
+            return AppKeyAction::Ok;
+        }
+
+        // 表示の切り替えはどの表示からでも効く (一致なしの Tab は候補の検索に使う)
+        match key.code {
+            F(n @ 1..=4) => {
+                self.show_view(View::ALL[usize::from(n) - 1]);
+                return AppKeyAction::Ok;
+            }
+            Tab if self.view != View::Hosts || !self.is_no_match() => {
+                self.show_view(self.view.cycle(true));
+                return AppKeyAction::Ok;
+            }
+            BackTab => {
+                self.show_view(self.view.cycle(false));
+                return AppKeyAction::Ok;
+            }
+            _ => {}
+        }
+        if self.view != View::Hosts {
+            return self.on_view_key(key);
+        }
+
+        let is_ctrl_pressed = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        if key.modifiers.contains(KeyModifiers::ALT) {
+            match key.code {
+                Up | Char('p') => self.browse_history(true),
+                Down | Char('n') => self.browse_history(false),
+                Char('u') => self.undo(),
+                Char('e') => self.open_bulk_edit(),
+                Char('f') => self.open_forward_editor(),
+                Char('t') => self.open_tunnels(),
+                Char('s') => self.toggle_socks(),
+                Char('r') => self.open_snippets(),
+                Char('m') => self.open_macros(),
+                Char('l') => self.lock = Some(Lock::default()),
+                Char('x') => self.redact = !self.redact,
+                Char('c') if self.is_onboarding() => self.create_ssh_config(),
+                Char('i') if self.is_onboarding() => {
+                    if edit::is_read_only() {
+                        self.flash(t("Read-only: config files are not changed"));
+                        return AppKeyAction::Ok;
+                    }
+                    return AppKeyAction::ImportHistory;
+                }
+                Char(digit @ '1'..='9') => self.select_visible_row(digit as usize - '1' as usize),
+                _ => return AppKeyAction::Continue,
+            }
+            return AppKeyAction::Ok;
+        }
+
+        if is_ctrl_pressed {
+            let action = self.on_key_press_ctrl(key);
+            if !matches!(action, AppKeyAction::Continue) {
+                return action;
+            }
+        }
+
+        match key.code {
+            Esc => return self.on_esc(),
+            // 検索語が空のときだけ ? をヘルプ切り替えに使う
+            Char('?') if self.search.value().is_empty() => self.show_help = !self.show_help,
+            Down => self.next(),
+            Up => self.previous(),
+            Tab if self.is_no_match() => {
+                if let Some(name) = self.near_misses().first().map(|host| host.name.clone()) {
+                    self.set_search(name);
+                }
+            }
+            Home => self.table_state.select(Some(0)),
+            End => {
+                if !self.hosts.is_empty() {
+                    self.table_state.select(Some(self.hosts.len() - 1));
+                }
+            }
+            PageDown => {
+                let i = self.table_state.selected().unwrap_or(0);
+                let target = min(i.saturating_add(21), self.hosts.len().saturating_sub(1));
+
+                self.table_state.select(Some(target));
+            }
+            PageUp => {
+                let i = self.table_state.selected().unwrap_or(0);
+                let target = i.saturating_sub(21);
+
+                self.table_state.select(Some(target));
+            }
+            Enter => {
+                let Some(host) = self.selected_host().cloned() else {
+                    return AppKeyAction::Ok;
+                };
+
+                // Shift+Enter は拡張キーボードプロトコルの端末でだけ区別できる
+                if key.modifiers.contains(KeyModifiers::SHIFT) {
+                    return AppKeyAction::ConnectIn(Box::new(host), Launcher::Tmux);
+                }
+                return AppKeyAction::Connect(Box::new(host));
+            }
+            _ => return AppKeyAction::Continue,
+        }
+
+        AppKeyAction::Ok
+    }
+
+    /// Keys on the locked screen: any key unlocks it, or Enter checks the
+    /// typed passphrase when one is configured. Ctrl+C still quits.
+    fn on_lock_key(&mut self, key: KeyEvent) -> AppKeyAction {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return AppKeyAction::Stop;
+        }
+        let Some(passphrase) = self.config.lock_passphrase.as_deref() else {
+            self.lock = None;
+            return AppKeyAction::Ok;
+        };
+        let Some(lock) = &mut self.lock else {
+            return AppKeyAction::Ok;
+        };
+        match key.code {
+            KeyCode::Enter if lock.passphrase.value() == passphrase => self.lock = None,
+            KeyCode::Enter => {
+                lock.passphrase.reset();
+                lock.wrong = true;
+            }
+            _ => {
+                lock.passphrase.handle_event(&Event::Key(key));
+            }
+        }
+        AppKeyAction::Ok
+    }
+
+    /// Esc in the host list, as configured by `--esc`.
+    fn on_esc(&mut self) -> AppKeyAction {
+        match self.config.esc {
+            EscAction::Quit => AppKeyAction::Stop,
+            EscAction::Clear if !self.search.value().is_empty() => {
+                self.set_search(String::new());
+                AppKeyAction::Ok
+            }
+            EscAction::Clear => AppKeyAction::Stop,
+            EscAction::Double => {
+                if self.last_esc.take().is_some_and(|at| at.elapsed() < DOUBLE_ESC) {
+                    return AppKeyAction::Stop;
+                }
+                self.last_esc = Some(Instant::now());
+                self.flash(t("Press Esc again to quit"));
+                AppKeyAction::Ok
+            }
+        }
+    }
+
+    /// Alt+1 … Alt+9: selects the nth host row currently on screen.
+    fn select_visible_row(&mut self, n: usize) {
+        let layout = &self.table_layout;
+        let row = layout.rows.iter().skip(layout.offset).flatten().nth(n).copied();
+        if let Some(row) = row {
+            self.table_state.select(Some(row));
+        }
+    }
+
+    fn connect<B>(&mut self, terminal: &Rc<RefCell<Terminal<B>>>, host: ssh::Host) -> Result<AppKeyAction>
+    where
+        B: Backend + std::io::Write,
+    {
+        self.connect_with(terminal, host, self.config.launcher)
+    }
+
+    fn connect_with<B>(
+        &mut self,
+        terminal: &Rc<RefCell<Terminal<B>>>,
+        host: ssh::Host,
+        launcher: Launcher,
+    ) -> Result<AppKeyAction>
+    where
+        B: Backend + std::io::Write,
+    {
+        let point = self.return_point();
+        let action = self.start_session(terminal, host, launcher)?;
+        if !matches!(action, AppKeyAction::Stop) {
+            self.restore_return_point(point);
+        }
+        Ok(action)
+    }
+
+    fn return_point(&self) -> ReturnPoint {
+        ReturnPoint {
+            query: self.search.value().to_string(),
+            host: self.selected_host().map(|host| host.name.clone()),
+            selected: self.table_state.selected().unwrap_or(0),
+            offset: self.table_state.offset(),
+        }
+    }
+
+    /// Puts the query, selection and scroll offset back. The selection
+    /// follows the host when the rows moved, e.g. after a config reload.
+    fn restore_return_point(&mut self, point: ReturnPoint) {
+        if self.search.value() != point.query {
+            self.set_search(point.query);
+        }
+        let same_row = self.hosts.iter().nth(point.selected).map(|host| &host.name) == point.host.as_ref();
+        let selected = if same_row {
+            point.selected
+        } else {
+            point
+                .host
+                .and_then(|name| self.hosts.iter().position(|host| host.name == name))
+                .unwrap_or(point.selected.min(self.hosts.len().saturating_sub(1)))
+        };
+        // 選択行が画面外になる場合は描画時に ratatui が offset を詰め直す
+        self.table_state = TableState::default().with_selected(selected).with_offset(point.offset);
+    }
+
+    fn start_session<B>(
+        &mut self,
+        terminal: &Rc<RefCell<Terminal<B>>>,
+        mut host: ssh::Host,
+        launcher: Launcher,
+    ) -> Result<AppKeyAction>
+    where
+        B: Backend + std::io::Write,
+    {
+        if host.is_pattern {
+            self.error_popup = Some(format!(
+                "{} is a pattern block, not a host you can connect to.",
+                host.name
+            ));
+            return Ok(AppKeyAction::Ok);
+        }
+        if self.config.demo {
+            // デモでは cmd / secret を実行せず、何が入るかだけを見せる
+            let command = self
+                .command_template(&host)
+                .and_then(|template| host.render_command_template(&template, Helpers::Placeholder))
+                .map(|command| command.redacted());
+            self.error_popup = Some(match command {
+                Ok(command) => format!("Demo mode: would run\n{command}"),
+                Err(err) => format!("Demo mode: the template failed: {err}"),
+            });
+            return Ok(AppKeyAction::Ok);
+        }
+        if let (Some(when), Some(emit)) = (self.config.schedule, self.config.schedule_emit) {
+            // スケジューラに渡す行を出力して終わる (秘密の値は書き出さない)
+            return Ok(match written_command(&self.config, &host) {
+                Ok(command) => {
+                    self.exit_output = Some(schedule::entry(emit, when, &command, schedule::now(), schedule::local_offset_at));
+                    AppKeyAction::Stop
+                }
+                Err(err) => {
+                    self.error_popup = Some(format!("Failed to render the command for {}: {err}", host.name));
+                    AppKeyAction::Ok
+                }
+            });
+        }
+        self.remember_query();
+        if !host.is_adhoc {
+            self.record_connection(&host.name);
+        }
+        self.last_connected = Some(host.name.clone());
+
+        let background = launcher.is_background();
+        if !background || self.config.schedule.is_some() {
+            restore_terminal(terminal).expect("Failed to restore terminal");
+        }
+
+        // バックグラウンドの起動 (Shift+Enter) も --at の時刻まで待つ
+        if let Some(when) = self.config.schedule {
+            let now = schedule::now();
+            let delay = Duration::from_secs(when.due(now, schedule::local_offset_at) - now);
+            let waited = schedule::wait(delay, &format!("Running the command for {}", host.name))?;
+            if !waited || background {
+                setup_terminal(terminal).expect("Failed to setup terminal");
+            }
+            if !waited {
+                self.flash(t_fmt("Cancelled the run for {host} {when}", &[("host", &host.name), ("when", &when)]));
+                return Ok(AppKeyAction::Ok);
+            }
+        }
+
+        if background {
+            self.launch_in_background(terminal, &mut host, launcher);
+            self.offer_to_save(host);
+            return Ok(AppKeyAction::Ok);
+        }
+
+        if !self.prepare_session(&mut host) {
+            setup_terminal(terminal).expect("Failed to setup terminal");
+            return Ok(AppKeyAction::Ok);
+        }
+
+        let title = self.session_title(&host).map(|title| TitleGuard::set(&title));
+
+        if self.config.exec {
+            // 成功すれば戻らない。戻ってきたら exec に失敗している
+            let err = match self.command_template(&host) {
+                Ok(template) => host.exec_command_template(&template, self.config.shell),
+                Err(err) => err,
+            };
+            setup_terminal(terminal).expect("Failed to setup terminal");
+            self.error_popup = Some(format!("Failed to exec the command for {}: {err}", host.name));
+            return Ok(AppKeyAction::Ok);
+        }
+
+        let started_at = Instant::now();
+        let status = host.run_command_template(&self.command_template(&host)?, self.config.shell)?;
+        // スニペットの出力を読んでいる時間は含めない
+        let duration = started_at.elapsed();
+        notify::session_ended(&host.name, duration, self.config.notify_after);
+        host.duration = Some(duration.as_secs());
+        if !host.is_adhoc {
+            self.record_duration(&host.name, duration);
+        }
+        self.flash(t_fmt(
+            "Session to {host} lasted {duration}",
+            &[("host", &host.name), ("duration", &format_duration(duration))],
+        ));
+        if host.snippet.is_some() {
+            // 出力を読めるよう、画面を戻す前に Enter を待つ
+            println!("\n[sgh] {status}. {}", t("Press Enter to return to sgh."));
+            let _ = std::io::stdin().read_line(&mut String::new());
+        } else if !status.success() {
+            // 失敗を呼び出し元に伝えるため、その終了コードで sgh を終える
+            self.exit_code = Some(status.code().unwrap_or(1));
+            return Ok(AppKeyAction::Stop);
+        }
+
+        if let Some(template) = host
+            .on_session_end_template
+            .as_ref()
+            .or(self.config.command_template_on_session_end.as_ref())
+        {
+            let status = host.run_command_template(template, self.config.shell)?;
+            if !status.success() {
+                self.error_popup = Some(format!("Session end hook failed ({status})."));
+            }
+        }
+        drop(title);
+
+        setup_terminal(terminal).expect("Failed to setup terminal");
+
+        if self.config.exit_after_ssh_session_ends {
+            return Ok(AppKeyAction::Stop);
+        }
+
+        self.offer_to_save(host);
+        Ok(AppKeyAction::Ok)
+    }
+
+    /// After connecting to an ad-hoc host, prompts to save it to a config
+    /// file so it shows up as a regular host next time.
+    fn offer_to_save(&mut self, host: ssh::Host) {
+        if host.is_adhoc && self.error_popup.is_none() && !edit::is_read_only() {
+            self.overlay = Some(Overlay::SaveHost(Box::new(SaveHostForm::new(
+                host,
+                self.writable_config_files(),
+                self.config.host_templates.clone(),
+            ))));
+        }
+    }
+
+    /// The rendered `--title-template` for `host`, or `None` when titles are
+    /// disabled or the template fails (the session still starts).
+    fn session_title(&self, host: &ssh::Host) -> Option<String> {
+        let template = self.config.title_template.as_deref()?;
+        host.render_command_template(template, Helpers::Refuse).ok().map(|title| title.redacted())
+    }
+
+    /// The OTP command (per-host or global), if any.
+    fn otp_command<'a>(&'a self, host: &'a ssh::Host) -> Option<&'a String> {
+        host.otp_command.as_ref().or(self.config.otp_command.as_ref())
+    }
+
+    /// Fetches the one-time code and runs the start hook before connecting.
+    /// Returns `false` with the error popup set when either fails. The
+    /// terminal must already be restored so prompts (e.g. a YubiKey touch)
+    /// are visible.
+    fn prepare_session(&mut self, host: &mut ssh::Host) -> bool {
+        if let Some(command) = self.otp_command(host).cloned() {
+            if let Err(err) = host.fetch_otp(&command) {
+                self.error_popup = Some(format!(
+                    "OTP command failed ({err}).\nConnection to {} was aborted.",
+                    host.name
+                ));
+                return false;
+            }
+        }
+
+        self.run_start_hook(host)
+    }
+
+    /// Runs the session start hook (per-host or global). On failure the error
+    /// popup is set and `false` is returned so the caller aborts the connection.
+    /// The terminal must already be restored.
+    fn run_start_hook(&mut self, host: &ssh::Host) -> bool {
+        // ホスト単位のフックがあればグローバル設定より優先する
+        let Some(template) = host
+            .on_session_start_template
+            .as_ref()
+            .or(self.config.command_template_on_session_start.as_ref())
+        else {
+            return true;
+        };
+
+        // 開始フックが失敗したら接続を中止して TUI に戻る (VPN チェック等)
+        let failure = match host.run_command_template(template, self.config.shell) {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(status.to_string()),
+            Err(err) => Some(err.to_string()),
+        };
+        if let Some(reason) = failure {
+            self.error_popup = Some(format!(
+                "Session start hook failed ({reason}).\nConnection to {} was aborted.",
+                host.name
+            ));
+            return false;
+        }
+
+        true
+    }
+
+    /// Starts the session through tmux or `--launcher-template` and keeps the
+    /// picker open. The end hook is not run since the session outlives this call.
+    fn launch_in_background<B>(&mut self, terminal: &Rc<RefCell<Terminal<B>>>, host: &mut ssh::Host, launcher: Launcher)
+    where
+        B: Backend + std::io::Write,
+    {
+        let has_start_hook = host.on_session_start_template.is_some()
+            || self.config.command_template_on_session_start.is_some();
+        let prepares = has_start_hook || self.otp_command(host).is_some();
+        let template = self.command_template(host);
+        let launcher_template = self.config.launcher_template.clone().filter(|_| launcher == Launcher::Template);
+        // cmd / secret のコマンドは端末でパスフレーズなどを尋ねることがあるので、
+        // フックや OTP と同じく raw モードと代替画面を抜けてから描画する
+        let prompts = template.as_deref().is_ok_and(template::runs_commands)
+            || launcher_template.as_deref().is_some_and(template::runs_commands);
+        if prepares || prompts {
+            restore_terminal(terminal).expect("Failed to restore terminal");
+        }
+
+        let ok = !prepares || self.prepare_session(host);
+        let title = self.session_title(host).unwrap_or_else(|| host.name.clone());
+        let result = ok.then(|| {
+            template
+                .and_then(|template| host.render_command_template(&template, Helpers::Run))
+                .and_then(|command| {
+                    self.sessions.launch(
+                        launcher,
+                        launcher_template.as_deref(),
+                        host,
+                        &command.text,
+                        &title,
+                        self.config.shell,
+                    )
+                })
+        });
+
+        if prepares || prompts {
+            setup_terminal(terminal).expect("Failed to setup terminal");
+        }
+        if let Some(Err(err)) = result {
+            self.error_popup = Some(format!("Failed to launch session for {}: {err}", host.name));
+        }
+    }
+
+    /// Alt+S: starts a SOCKS proxy through the selected host, or stops the
+    /// one already running, and copies the proxy variables to the clipboard.
+    fn toggle_socks(&mut self) {
+        let Some(host) = self.selected_host().filter(|host| !host.is_pattern && !host.is_adhoc).cloned() else {
+            return;
+        };
+        if let Some(port) = self.sessions.stop_socks(&host.name) {
+            self.flash(t_fmt("Stopped the SOCKS proxy on :{port} via {host}", &[("port", &port), ("host", &host.name)]));
+            return;
+        }
+        let Some(port) = ssh::free_local_port(&[], self.config.socks_port) else {
+            self.error_popup = Some(format!("No free local port from {}", self.config.socks_port));
+            return;
+        };
+        if self.config.demo {
+            self.error_popup = Some(format!(
+                "Demo mode: would run\nssh -N -D 127.0.0.1:{port} {}\n\n{}",
+                host.name,
+                session::proxy_exports(port)
+            ));
+            return;
+        }
+
+        let config_file = self.config.single_config_file();
+        if let Err(err) = self.sessions.start_socks(&host, port, config_file.as_deref()) {
+            self.error_popup = Some(format!("Failed to start a SOCKS proxy via {}: {err}", host.name));
+            return;
+        }
+        let proxy = format!("ALL_PROXY=socks5h://127.0.0.1:{port}");
+        self.flash(match clipboard::copy(&session::proxy_exports(port)) {
+            Ok(how) => t_fmt(
+                "SOCKS proxy via {host}: {proxy} (exports copied with {how})",
+                &[("host", &host.name), ("proxy", &proxy), ("how", &how)],
+            ),
+            Err(_) => t_fmt("SOCKS proxy via {host}: {proxy}", &[("host", &host.name), ("proxy", &proxy)]),
+        });
+    }
+
+    fn jump_to_session(&mut self) {
+        let selected = self.table_state.selected().unwrap_or(0);
+        if selected >= self.hosts.len() {
+            return;
+        }
+
+        let name = self.hosts[selected].name.clone();
+        self.jump_to(&name);
+    }
+
+    /// Switches tmux to the window of `name`'s session.
+    fn jump_to(&mut self, name: &str) {
+        match self.sessions.jump_to(name) {
+            Ok(true) => {}
+            Ok(false) => self.flash(t_fmt("No tmux session for {host}", &[("host", &name)])),
+            Err(err) => self.error_popup = Some(err.to_string()),
+        }
+    }
+
+    fn show_view(&mut self, view: View) {
+        self.view = view;
+        self.show_help = false;
+        self.refresh_view();
+    }
+
+    /// Re-reads the rows of the views that change while sgh runs.
+    fn refresh_view(&mut self) {
+        match self.view {
+            View::Sessions => self.session_view.set_items(self.sessions.summaries()),
+            View::History => self.history_view.set_items(self.connections.borrow().entries()),
+            View::Hosts | View::Tunnels => {}
+        }
+    }
+
+    /// Keys of the views other than the host list: Enter acts on the row,
+    /// Esc goes back to the hosts and the rest filters and moves.
+    fn on_view_key(&mut self, key: KeyEvent) -> AppKeyAction {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return AppKeyAction::Stop;
+        }
+        match key.code {
+            KeyCode::Esc => self.show_view(View::Hosts),
+            KeyCode::Enter => match self.view {
+                View::Tunnels => {
+                    // トンネルはホスト一覧で選んでいるホストに付けて接続する
+                    let Some(tunnel) = self.tunnel_view.selected() else {
+                        return AppKeyAction::Ok;
+                    };
+                    let Some(mut host) = self.selected_host().cloned() else {
+                        self.flash(t("Select a host in the Hosts view first"));
+                        return AppKeyAction::Ok;
+                    };
+                    host.tunnels.clone_from(&tunnel.forwards);
+                    return AppKeyAction::Connect(Box::new(host));
+                }
+                View::Sessions => {
+                    if let Some(name) = self.session_view.selected().map(|session| session.host_name.clone()) {
+                        self.jump_to(&name);
+                    }
+                }
+                View::History => {
+                    if let Some(host) = self.detail_host() {
+                        return AppKeyAction::Connect(Box::new(host.clone()));
+                    }
+                    if let Some(entry) = self.history_view.selected() {
+                        self.flash(t_fmt("{host} is no longer in the SSH config", &[("host", &entry.name)]));
+                    }
+                }
+                View::Hosts => {}
+            },
+            _ => match self.view {
+                View::Tunnels => self.tunnel_view.handle_key(key),
+                View::Sessions => self.session_view.handle_key(key),
+                View::History => self.history_view.handle_key(key),
+                View::Hosts => {}
+            },
+        }
+        AppKeyAction::Ok
+    }
+
+    /// The search bar's input: the host query, or the current view's.
+    fn view_query(&self) -> &Input {
+        match self.view {
+            View::Hosts => &self.search,
+            View::Tunnels => &self.tunnel_view.query,
+            View::Sessions => &self.session_view.query,
+            View::History => &self.history_view.query,
+        }
+    }
+
+    /// Host the detail panel describes: the selected one of the host list,
+    /// or the host of the highlighted session or history row.
+    fn detail_host(&self) -> Option<&ssh::Host> {
+        let name = match self.view {
+            View::Hosts | View::Tunnels => return self.selected_host(),
+            View::Sessions => &self.session_view.selected()?.host_name,
+            View::History => &self.history_view.selected()?.name,
+        };
+        self.merged_hosts.iter().find(|host| host.name == *name)
+    }
+
+    fn on_overlay_key(&mut self, overlay: Overlay, key: KeyEvent) {
+        #[allow(clippy::enum_glob_use)]
+        use KeyCode::*;
+
+        let is_ctrl_pressed = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match overlay {
+            Overlay::SaveFilter(mut input) => match key.code {
+                Esc => {}
+                Enter => {
+                    let name = input.value().trim().to_string();
+                    if name.is_empty() {
+                        self.overlay = Some(Overlay::SaveFilter(input));
+                        return;
+                    }
+                    self.saved_filters
+                        .insert(name.clone(), self.search.value().trim().to_string());
+                    match self.saved_filters.save() {
+                        Ok(()) => self.flash(t_fmt("Saved filter {name}", &[("name", &name)])),
+                        Err(err) => self.error_popup = Some(format!("Failed to save filter: {err}")),
+                    }
+                }
+                _ => {
+                    input.handle_event(&Event::Key(key));
+                    self.overlay = Some(Overlay::SaveFilter(input));
+                }
+            },
+            Overlay::SaveHost(mut form) => {
+                match key.code {
+                    Esc => return,
+                    Char('c') if is_ctrl_pressed => return,
+                    Tab => form.file_index = (form.file_index + 1) % form.files.len(),
+                    BackTab => {
+                        form.file_index = form.file_index.checked_sub(1).unwrap_or(form.files.len() - 1);
+                    }
+                    Char('t') if is_ctrl_pressed && !form.templates.is_empty() => form.cycle_template(),
+                    Down => form.focus = (form.focus + 1) % (form.placeholders.len() + 1),
+                    Up => form.focus = form.focus.checked_sub(1).unwrap_or(form.placeholders.len()),
+                    Enter => {
+                        self.overlay = self.save_adhoc_host(form).map(Overlay::SaveHost);
+                        return;
+                    }
+                    _ => {
+                        form.focused_input().handle_event(&Event::Key(key));
+                    }
+                }
+                self.overlay = Some(Overlay::SaveHost(form));
+            }
+            Overlay::Snippets(mut table) => {
+                match key.code {
+                    Esc => return,
+                    Char('c') if is_ctrl_pressed => return,
+                    // 今の検索語とこのスニペットをマクロとして記録する
+                    Char('s') if is_ctrl_pressed => {
+                        if let Some(snippet) = table.selected() {
+                            self.overlay = Some(Overlay::SaveMacro(Input::default(), Box::new(snippet.clone())));
+                        }
+                        return;
+                    }
+                    _ => table.handle_key(key),
+                }
+                self.overlay = Some(Overlay::Snippets(table));
+            }
+            Overlay::SaveMacro(mut input, snippet) => match key.code {
+                Esc => {}
+                Char('c') if is_ctrl_pressed => {}
+                Enter => {
+                    let name = input.value().trim().to_string();
+                    if name.is_empty() {
+                        self.overlay = Some(Overlay::SaveMacro(input, snippet));
+                        return;
+                    }
+                    let steps = macros::Steps {
+                        query: self.search.value().trim().to_string(),
+                        snippet: *snippet,
+                    };
+                    self.macros.insert(name.clone(), steps);
+                    match self.macros.save() {
+                        Ok(()) => self.flash(t_fmt("Saved macro {name}", &[("name", &name)])),
+                        Err(err) => self.error_popup = Some(format!("Failed to save macro: {err}")),
+                    }
+                }
+                _ => {
+                    input.handle_event(&Event::Key(key));
+                    self.overlay = Some(Overlay::SaveMacro(input, snippet));
+                }
+            },
+            Overlay::Macros(mut table) => {
+                match key.code {
+                    Esc => return,
+                    Char('c') if is_ctrl_pressed => return,
+                    Delete => {
+                        if let Some(name) = table.selected().map(|recorded| recorded.name.clone()) {
+                            self.macros.remove(&name);
+                            if let Err(err) = self.macros.save() {
+                                self.error_popup = Some(format!("Failed to save macros: {err}"));
+                            }
+                            table.set_items(self.macros.list());
+                        }
+                    }
+                    _ => table.handle_key(key),
+                }
+                self.overlay = Some(Overlay::Macros(table));
+            }
+            Overlay::Tunnels(mut list, mut ticked) => {
+                let count = ticked.len();
+                let selected = list.selected().unwrap_or(0);
+                match key.code {
+                    Esc => return,
+                    Char('c') if is_ctrl_pressed => return,
+                    Down => list.select(Some((selected + 1) % count)),
+                    Up => list.select(Some(selected.checked_sub(1).unwrap_or(count - 1))),
+                    Char(' ') => ticked[selected] = !ticked[selected],
+                    _ => {}
+                }
+                self.overlay = Some(Overlay::Tunnels(list, ticked));
+            }
+            Overlay::Forwards(mut editor) => {
+                if let Some((index, mut input)) = editor.input.take() {
+                    match key.code {
+                        Esc => editor.error = None,
+                        Char('c') if is_ctrl_pressed => editor.error = None,
+                        Enter => {
+                            editor.input = Some((index, input));
+                            editor = self.apply_forward_edit(editor, false);
+                        }
+                        _ => {
+                            input.handle_event(&Event::Key(key));
+                            editor.input = Some((index, input));
+                        }
+                    }
+                    self.overlay = Some(Overlay::Forwards(editor));
+                    return;
+                }
+
+                let count = editor.forwards.len();
+                let selected = editor.list.selected().unwrap_or(0);
+                match key.code {
+                    Esc => return,
+                    Char('c') if is_ctrl_pressed => return,
+                    Down if count > 0 => editor.list.select(Some((selected + 1) % count)),
+                    Up if count > 0 => editor.list.select(Some(selected.checked_sub(1).unwrap_or(count - 1))),
+                    Char('a') => {
+                        // 空いているローカルポートを提案する
+                        let suggestion = ssh::free_local_port(&self.merged_hosts, FIRST_SUGGESTED_PORT)
+                            .map(|port| format!("{port} localhost:"))
+                            .unwrap_or_default();
+                        editor.input = Some((None, Input::from(suggestion)));
+                    }
+                    Enter | Char('e') => {
+                        if let Some(i) = editor.selected() {
+                            editor.input = Some((Some(i), Input::from(editor.forwards[i].clone())));
+                        }
+                    }
+                    Delete | Char('d') if editor.selected().is_some() => {
+                        editor = self.apply_forward_edit(editor, true);
+                    }
+                    _ => {}
+                }
+                self.overlay = Some(Overlay::Forwards(editor));
+            }
+            Overlay::BulkEdit(mut form) => {
+                match key.code {
+                    Esc => return,
+                    Char('c') if is_ctrl_pressed => return,
+                    Enter => {
+                        if form.preview.as_ref().is_some_and(|p| p.as_ref().is_ok_and(|c| !c.is_empty())) {
+                            self.overlay = self.apply_bulk_edit(form).map(Overlay::BulkEdit);
+                            return;
+                        }
+                        form.preview = Some(form.option().and_then(|(keyword, value)| {
+                            edit::plan_option(&form.targets, &keyword, &value).map_err(|err| format!("{err:#}"))
+                        }));
+                        form.scroll = 0;
+                    }
+                    Down => form.scroll = form.scroll.saturating_add(1),
+                    Up => form.scroll = form.scroll.saturating_sub(1),
+                    PageDown => form.scroll = form.scroll.saturating_add(10),
+                    PageUp => form.scroll = form.scroll.saturating_sub(10),
+                    _ => {
+                        if form.option.handle_event(&Event::Key(key)).is_some_and(|c| c.value) {
+                            form.preview = None;
+                        }
+                    }
+                }
+                self.overlay = Some(Overlay::BulkEdit(form));
+            }
+            Overlay::Playground(mut playground) => {
+                match key.code {
+                    Esc => return,
+                    Char('c' | 'y') if is_ctrl_pressed => return,
+                    Down => playground.scroll = playground.scroll.saturating_add(1),
+                    Up => playground.scroll = playground.scroll.saturating_sub(1),
+                    PageDown => playground.scroll = playground.scroll.saturating_add(10),
+                    PageUp => playground.scroll = playground.scroll.saturating_sub(10),
+                    _ => {
+                        if playground.name.handle_event(&Event::Key(key)).is_some_and(|c| c.value) {
+                            playground.scroll = 0;
+                        }
+                    }
+                }
+                self.overlay = Some(Overlay::Playground(playground));
+            }
+            Overlay::Explain(scroll, mut verification) => {
+                if key.code == Char('v') && !is_ctrl_pressed {
+                    verification = self.verify_selected();
+                }
+                let scroll = match key.code {
+                    Esc | Enter => return,
+                    Char('c' | 'o') if is_ctrl_pressed => return,
+                    Down | Char('j') => scroll.saturating_add(1),
+                    Up | Char('k') => scroll.saturating_sub(1),
+                    PageDown => scroll.saturating_add(10),
+                    PageUp => scroll.saturating_sub(10),
+                    _ => scroll,
+                };
+                self.overlay = Some(Overlay::Explain(scroll, verification));
+            }
+            Overlay::Variables(scroll) => {
+                let scroll = match key.code {
+                    Esc | Enter => return,
+                    Char('c' | 'v') if is_ctrl_pressed => return,
+                    Down | Char('j') => scroll.saturating_add(1),
+                    Up | Char('k') => scroll.saturating_sub(1),
+                    PageDown => scroll.saturating_add(10),
+                    PageUp => scroll.saturating_sub(10),
+                    _ => scroll,
+                };
+                self.overlay = Some(Overlay::Variables(scroll));
+            }
+            Overlay::Filters(mut state) => {
+                let len = self.saved_filters.len();
+                let selected = state.selected().unwrap_or(0);
+                let next = if selected + 1 >= len { 0 } else { selected + 1 };
+                let previous = selected.checked_sub(1).unwrap_or(len.saturating_sub(1));
+                match key.code {
+                    Esc => return,
+                    Char('c') if is_ctrl_pressed => return,
+                    Down => state.select(Some(next)),
+                    Char('j' | 'n') if is_ctrl_pressed => state.select(Some(next)),
+                    Up => state.select(Some(previous)),
+                    Char('k' | 'p') if is_ctrl_pressed => state.select(Some(previous)),
+                    Enter => {
+                        let query = self.saved_filters.iter().nth(selected).map(|(_, q)| q.to_string());
+                        if let Some(query) = query {
+                            self.set_search(query);
+                        }
+                        return;
+                    }
+                    Delete => {
+                        let name = self.saved_filters.iter().nth(selected).map(|(n, _)| n.to_string());
+                        if let Some(name) = name {
+                            self.saved_filters.remove(&name);
+                            if let Err(err) = self.saved_filters.save() {
+                                self.error_popup = Some(format!("Failed to save filters: {err}"));
+                            }
+                            state.select(Some(selected.min(self.saved_filters.len().saturating_sub(1))));
+                        }
+                    }
+                    _ => {}
+                }
+                self.overlay = Some(Overlay::Filters(state));
+            }
+        }
+    }
+
+    fn on_key_press_ctrl(&mut self, key: KeyEvent) -> AppKeyAction {
+        #[allow(clippy::enum_glob_use)]
+        use KeyCode::*;
+
+        match key.code {
+            Char('c') => AppKeyAction::Stop,
+            Char('j' | 'n') => {
+                self.next();
+                AppKeyAction::Ok
+            }
+            Char('k' | 'p') => {
+                self.previous();
+                AppKeyAction::Ok
+            }
+            Char('g') => {
+                self.jump_to_session();
+                AppKeyAction::Ok
+            }
+            Char('z') => AppKeyAction::Suspend,
+            Char('o') => {
+                if self.table_state.selected().unwrap_or(0) < self.hosts.len() {
+                    self.overlay = Some(Overlay::Explain(0, None));
+                }
+                AppKeyAction::Ok
+            }
+            Char('v') => {
+                if self.selected_host().is_some() {
+                    self.overlay = Some(Overlay::Variables(0));
+                }
+                AppKeyAction::Ok
+            }
+            Char('y') => {
+                self.open_playground();
+                AppKeyAction::Ok
+            }
+            Char('t') => {
+                let show = !self.show_patterns.get();
+                self.show_patterns.set(show);
+                self.apply_search();
+                self.flash(t(if show { "Showing pattern blocks" } else { "Hiding pattern blocks" }));
+                AppKeyAction::Ok
+            }
+            Char('l') => {
+                self.toggle_split_aliases();
+                AppKeyAction::Ok
+            }
+            Char('d') => {
+                let focus = self.focus_field.get();
+                self.set_focus_field(if focus == Some(SearchField::Destination) {
+                    None
+                } else {
+                    Some(SearchField::Destination)
+                });
+                AppKeyAction::Ok
+            }
+            Char('r') => {
+                self.set_focus_field(SearchField::cycle(self.focus_field.get()));
+                AppKeyAction::Ok
+            }
+            Char('x') => {
+                // 何も一致しないときは検索を丸ごと消す
+                if self.is_no_match() {
+                    self.set_search(String::new());
+                } else {
+                    self.remove_last_chip();
+                }
+                AppKeyAction::Ok
+            }
+            Char('s') => {
+                if !self.search.value().trim().is_empty() {
+                    self.overlay = Some(Overlay::SaveFilter(Input::default()));
+                }
+                AppKeyAction::Ok
+            }
+            Char('f') => {
+                self.overlay = Some(Overlay::Filters(ListState::default().with_selected(Some(0))));
+                AppKeyAction::Ok
+            }
+            _ => AppKeyAction::Continue,
+        }
+    }
+
+    fn next(&mut self) {
+        let i = match self.table_state.selected() {
+            Some(i) => {
+                if self.hosts.is_empty() || i >= self.hosts.len() - 1 {
+                    0
+                } else {
+                    i + 1
+                }
+            }
+            None => 0,
+        };
+        self.table_state.select(Some(i));
+    }
+
+    fn previous(&mut self) {
+        let i = match self.table_state.selected() {
+            Some(i) => {
+                if self.hosts.is_empty() {
+                    0
+                } else if i == 0 {
+                    self.hosts.len() - 1
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+        self.table_state.select(Some(i));
+    }
+
+    /// The user ssh logs in as for a host without a `User`, with the pattern
+    /// block it comes from (`None` for the local user).
+    fn effective_user(&self, host: &ssh::Host) -> Option<(String, Option<String>)> {
+        if host.is_pattern {
+            return None;
+        }
+        let user = ssh::effective_user(host, self.hosts.non_filtered_iter(), self.config.local_user.as_deref());
+        // パターンブロック名も接続先を含みうるので一緒に伏せる
+        if self.redact {
+            return user.map(|_| (ssh::REDACTED.to_string(), Some(ssh::REDACTED.to_string())));
+        }
+        user
+    }
+
+    /// Sizes each column to its widest value. Measures every host by default so
+    /// columns do not jump while typing; with `fit_columns` only the currently
+    /// filtered rows are measured. Call again whenever the host set changes.
+    fn calculate_table_columns_constraints(&mut self) {
+        let rows: Vec<&ssh::Host> = if self.config.fit_columns {
+            self.hosts.iter().collect()
+        } else {
+            self.hosts.non_filtered_iter().collect()
+        };
+        let width_of = |value: fn(&ssh::Host) -> String| {
+            rows.iter()
+                .map(|h| UnicodeWidthStr::width(value(h).as_str()))
+                .max()
+                .unwrap_or(0)
+                .min(MAX_COLUMN_WIDTH)
+        };
+
+        let mut lengths = vec![
+            width_of(|h| h.name.clone()),
+            width_of(|h| h.aliases.clone()),
+            width_of(|h| h.user.clone().unwrap_or_default()),
+            width_of(|h| h.destination.clone()),
+            width_of(|h| h.port.clone().unwrap_or_default()),
+        ];
+        // User のないホストに表示する実効ユーザーも列幅に含める
+        let effective_users = rows
+            .iter()
+            .filter_map(|h| self.effective_user(h))
+            .map(|(user, _)| user.width().min(MAX_COLUMN_WIDTH))
+            .max()
+            .unwrap_or(0);
+        lengths[2] = lengths[2].max(effective_users);
+        if self.config.show_proxy_command {
+            lengths.push(width_of(|h| h.proxy_command.clone().unwrap_or_default()));
+        }
+        if self.config.show_source {
+            lengths.push(width_of(|h| h.source_display().unwrap_or_default()));
+        }
+        if let Some(group) = self.config.group_by {
+            // グループ見出しも名前の列に収める
+            let headers = rows
+                .iter()
+                .map(|h| group.value(h).map_or(group.missing_label().width(), |value| value.width()))
+                .max()
+                .unwrap_or(0);
+            lengths[0] = lengths[0].max(headers.min(MAX_COLUMN_WIDTH));
+        }
+
+        let mut new_constraints = vec![
+            // Marker column (▌ / spaces) — width matches SELECTION_MARKER.
+            Constraint::Length(u16::try_from(UnicodeWidthStr::width(SELECTION_MARKER)).unwrap_or(2)),
+        ];
+        if self.config.icons != IconMode::Off {
+            // Icon column, only as wide as the widest icon shown.
+            let icons = rows
+                .iter()
+                .filter_map(|h| icon::icon_for(self.config.icons, &self.config.icon_rules, h))
+                .map(|icon| icon.width().min(MAX_COLUMN_WIDTH))
+                .max()
+                .unwrap_or(1);
+            new_constraints.push(Constraint::Length(u16::try_from(icons).unwrap_or(1)));
+        }
+        // Name column (+1 for breathing room).
+        new_constraints.push(Constraint::Length(u16::try_from(lengths[0]).unwrap_or_default() + 1));
+        // 翻訳した見出しが値より長くても欠けないようにする
+        new_constraints.extend(
+            lengths
+                .iter()
+                .zip(column_headers(&self.config))
+                .skip(1)
+                .map(|(len, header)| Constraint::Min(u16::try_from((*len).max(header.width())).unwrap_or_default() + 1)),
+        );
+
+        self.table_columns_constraints = new_constraints;
+        self.column_widths = lengths;
+    }
+}
+
+/// Whether the terminal speaks the kitty keyboard protocol, asked once.
+static KEYBOARD_ENHANCEMENT: OnceLock<bool> = OnceLock::new();
+
+/// Set by SIGINT. Cleared whenever the picker takes the terminal back, since
+/// a Ctrl+C typed while ssh or a hook had it was meant for that child.
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+fn setup_terminal<B>(terminal: &Rc<RefCell<Terminal<B>>>) -> Result<()>
+where
+    B: Backend + std::io::Write,
+{
+    let mut terminal = terminal.borrow_mut();
+    if let Some(interrupted) = INTERRUPTED.get() {
+        interrupted.store(false, Ordering::Relaxed);
+    }
+
+    // setup terminal
+    enable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        Hide,
+        EnterAlternateScreen,
+        EnableMouseCapture
+    )?;
+    // 対応端末では Shift+Enter などの組み合わせを区別できるようにする
+    if *KEYBOARD_ENHANCEMENT.get_or_init(|| supports_keyboard_enhancement().unwrap_or(false)) {
+        execute!(
+            terminal.backend_mut(),
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        )?;
+    }
+
+    Ok(())
+}
+
+fn restore_terminal<B>(terminal: &Rc<RefCell<Terminal<B>>>) -> Result<()>
+where
+    B: Backend + std::io::Write,
+{
+    let mut terminal = terminal.borrow_mut();
+    terminal.clear()?;
+
+    // restore terminal
+    if KEYBOARD_ENHANCEMENT.get() == Some(&true) {
+        execute!(terminal.backend_mut(), PopKeyboardEnhancementFlags)?;
+    }
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        Show,
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+    )?;
+
+    Ok(())
+}
+
+/// Restores the terminal without a `Terminal` handle, for use from the panic
+/// hook. Errors are ignored since there is nothing left to report them to.
+fn force_restore_terminal() {
+    if KEYBOARD_ENHANCEMENT.get() == Some(&true) {
+        let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+    }
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), Show, LeaveAlternateScreen, DisableMouseCapture);
+}
+
+/// Restores the terminal before the default hook prints the panic message, so
+/// the message is readable and the shell is not left in raw mode.
+fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        force_restore_terminal();
+        previous(info);
+    }));
+}
+
+/// Turns SIGINT/SIGTERM/SIGHUP into flags polled by the main loop instead of
+/// killing the process with the terminal still in raw mode. SIGINT has its
+/// own flag (`INTERRUPTED`) so the Ctrl+C of a child session can be dropped.
+fn register_termination_signals(flag: &Arc<AtomicBool>) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let interrupted = INTERRUPTED.get_or_init(Arc::default);
+        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(interrupted))?;
+        for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGHUP] {
+            signal_hook::flag::register(signal, Arc::clone(flag))?;
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = flag;
+
+    Ok(())
+}
+
+/// Ctrl+Z: raw mode では SIGTSTP が発生しないため、端末を戻してから自分で
+/// SIGTSTP を送り、fg で再開されたら代替スクリーンに戻って全体を再描画する。
+fn suspend<B>(terminal: &Rc<RefCell<Terminal<B>>>) -> Result<()>
+where
+    B: Backend + std::io::Write,
+{
+    restore_terminal(terminal)?;
+
+    #[cfg(unix)]
+    signal_hook::low_level::raise(signal_hook::consts::SIGTSTP)?;
+
+    setup_terminal(terminal)?;
+    terminal.borrow_mut().clear()?;
+
+    Ok(())
+}
+
+/// メインの描画関数
+fn ui(f: &mut Frame, app: &mut App) {
+    let area = f.area();
+    if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+        render_too_small(f, app, area);
+        return;
+    }
+    if let Some(lock) = &app.lock {
+        render_lock(f, &app.theme, lock, app.config.lock_passphrase.is_some());
+        return;
+    }
+
+    // セッションがある時だけパネルを表示 (最大 4 行 + 上枠)
+    let sessions_height = match app.sessions.len() {
+        0 => 0,
+        n => u16::try_from(n.min(4)).unwrap_or(4) + 1,
+    };
+
+    let chips = filter_chips(app);
+    let chips_height = u16::from(!chips.is_empty());
+    // 隠していてもヘルプや通知の間だけは出す
+    let footer_height = u16::from(!app.config.footer.hidden || app.show_help || app.status_message.is_some());
+
+    let layout_main = Layout::vertical([
+        Constraint::Length(3),               // search bar (single line + borders)
+        Constraint::Length(chips_height),    // active filter chips
+        Constraint::Min(6),                  // host table (fills available space)
+        Constraint::Length(8),               // detail panel
+        Constraint::Length(sessions_height), // background sessions
+        Constraint::Length(footer_height),   // footer (single-line, no border)
+    ])
+    .split(f.area());
+
+    render_searchbar(f, app, layout_main[0]);
+    if chips_height > 0 {
+        render_chips(f, &app.theme, &chips, layout_main[1]);
+    }
+    if app.view != View::Hosts {
+        render_view(f, app, layout_main[2]);
+    } else if app.is_onboarding() {
+        render_onboarding(f, app, layout_main[2]);
+    } else {
+        render_table(f, app, layout_main[2]);
+        if app.is_no_match() {
+            render_no_matches(f, app, layout_main[2]);
+        }
+    }
+    render_detail_panel(f, app, layout_main[3]);
+    if sessions_height > 0 {
+        render_sessions_panel(f, app, layout_main[4]);
+    }
+    if footer_height > 0 {
+        render_footer(f, app, layout_main[5]);
+    }
+
+    match &mut app.overlay {
+        Some(Overlay::SaveFilter(input)) => render_name_popup(f, &app.theme, t("Save filter as"), input),
+        Some(Overlay::SaveMacro(input, _)) => render_name_popup(f, &app.theme, t("Save macro as"), input),
+        Some(Overlay::Macros(table)) => render_macros_popup(f, &app.theme, table),
+        Some(Overlay::Filters(state)) => {
+            render_filters_popup(f, &app.theme, &app.saved_filters, state);
+        }
+        Some(Overlay::SaveHost(form)) => render_save_host_popup(f, &app.theme, form),
+        Some(Overlay::Playground(playground)) => render_playground_popup(f, &app.theme, playground),
+        Some(Overlay::BulkEdit(form)) => render_bulk_edit_popup(f, &app.theme, form),
+        Some(Overlay::Forwards(editor)) => render_forwards_popup(f, &app.theme, editor),
+        Some(Overlay::Snippets(table)) => render_snippets_popup(f, &app.theme, table),
+        Some(Overlay::Tunnels(list, ticked)) => {
+            render_tunnels_popup(f, &app.theme, &app.config.tunnels, list, ticked);
+        }
+        Some(Overlay::Explain(scroll, verification)) => {
+            let scroll = *scroll;
+            if let Some(host) = app.hosts.iter().nth(app.table_state.selected().unwrap_or(0)) {
+                let redacted = app.redact.then(|| host.redacted());
+                let host = redacted.as_ref().unwrap_or(host);
+                // ssh -G の値は伏せようがないので比較結果は出さない
+                let verification = verification.as_ref().filter(|_| redacted.is_none());
+                render_explain_popup(f, &app.theme, host, scroll, verification);
+            }
+        }
+        Some(Overlay::Variables(scroll)) => {
+            let scroll = *scroll;
+            if let Some(host) = app.selected_host() {
+                let redacted = app.redact.then(|| host.redacted());
+                render_variables_popup(f, &app.theme, redacted.as_ref().unwrap_or(host), scroll);
+            }
+        }
+        None => {}
+    }
+
+    if app.error_popup.is_some() {
+        render_error_popup(f, app);
+    }
+    if app.theme.ascii {
+        to_ascii(f.buffer_mut());
+    }
+
+    // 入力欄を持つポップアップはカーソルを自分で置く
+    if matches!(
+        app.overlay,
+        Some(
+            Overlay::SaveFilter(_)
+                | Overlay::SaveMacro(..)
+                | Overlay::SaveHost(_)
+                | Overlay::Playground(_)
+                | Overlay::BulkEdit(_)
+                | Overlay::Forwards(_)
+        )
+    ) {
+        return;
+    }
+
+    // Place cursor inside the search bar (1 line border + PROMPT width).
+    let prompt_width = u16::try_from(UnicodeWidthStr::width(PROMPT)).unwrap_or(2);
+    let mut cursor_position = layout_main[0].as_position();
+    // visual_cursor は全角文字の表示幅を考慮した位置
+    cursor_position.x += u16::try_from(app.view_query().visual_cursor()).unwrap_or_default() + prompt_width + 1;
+    cursor_position.y += 1;
+    f.set_cursor_position(cursor_position);
+}
+
+fn render_too_small(f: &mut Frame, app: &App, area: Rect) {
+    let theme = &app.theme;
+    let lines = vec![
+        Line::from(Span::styled(
+            t("Terminal too small"),
+            Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
+        )),
+        Line::from(Span::styled(
+            format!("{}x{} (need {MIN_WIDTH}x{MIN_HEIGHT})", area.width, area.height),
+            Style::default().fg(theme.muted),
+        )),
+    ];
+
+    let vertical_pad = area.height.saturating_sub(2) / 2;
+    let paragraph = Paragraph::new(lines)
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true })
+        .block(Block::default().padding(Padding::top(vertical_pad)));
+    f.render_widget(paragraph, area);
+}
+
+/// The locked screen: nothing of the host list, only how to unlock it.
+fn render_lock(f: &mut Frame, theme: &Theme, lock: &Lock, with_passphrase: bool) {
+    let area = f.area();
+    let mut lines = vec![Line::from(Span::styled(
+        t("Locked"),
+        Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
+    ))];
+    if with_passphrase {
+        // 入力した文字数だけを伏せ字で見せる
+        let masked = "•".repeat(lock.passphrase.value().chars().count());
+        lines.push(Line::from(vec![
+            Span::styled(PROMPT, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)),
+            Span::styled(masked, Style::default().fg(theme.text)),
+        ]));
+        let (hint, color) = if lock.wrong {
+            (t("Wrong passphrase"), theme.error)
+        } else {
+            (t("Type the passphrase and press Enter"), theme.muted)
+        };
+        lines.push(Line::from(Span::styled(hint, Style::default().fg(color))));
+    } else {
+        lines.push(Line::from(Span::styled(t("Press any key to unlock"), Style::default().fg(theme.muted))));
+    }
+
+    let vertical_pad = area.height.saturating_sub(u16::try_from(lines.len()).unwrap_or_default()) / 2;
+    let paragraph = Paragraph::new(lines)
+        .alignment(Alignment::Center)
+        .block(Block::default().padding(Padding::top(vertical_pad)));
+    f.render_widget(paragraph, area);
+    if theme.ascii {
+        to_ascii(f.buffer_mut());
+    }
+}
+
+/// The command template used to connect to `host`: the snippet template
+/// for a snippet, the host's own template (`{{dir}}`, containers) unless
+/// `--template` is set, with its tunnels and source options added.
+///
+/// # Errors
+///
+/// Will return `Err` if a tunnel forward cannot be added to the template.
+pub fn command_template(config: &AppConfig, host: &ssh::Host) -> Result<String> {
+    let template = tunnel::inject(base_template(config, host), &host.tunnels)?;
+    if host.source_options.is_empty() {
+        return Ok(template);
+    }
+    // 値はテンプレートの文字列に埋め込まず、{{source_options}} から引く
+    let options = "{{#each source_options}}-o {{{shquote @key \"=\" this}}}{{#unless @last}} {{/unless}}{{/each}}";
+    // ssh 以外のコマンドには -o を付けられないので、そのまま使う (source_options_warning で知らせる)
+    Ok(ssh::insert_flags(&template, &[options.to_string()]).unwrap_or(template))
+}
+
+/// The template `command_template` starts from, before tunnels and source
+/// options are added.
+fn base_template<'a>(config: &'a AppConfig, host: &'a ssh::Host) -> &'a str {
+    // 独自の --template はそのまま使い、{{dir}} や {{container}} を使うかは任せる
+    match &host.snippet {
+        Some(_) => &config.snippet_template,
+        None if config.command_template == ssh::DEFAULT_COMMAND_TEMPLATE => host
+            .connect_template
+            .as_deref()
+            .or(host.dir.as_ref().map(|_| ssh::DIR_COMMAND_TEMPLATE))
+            .unwrap_or(&config.command_template),
+        None => &config.command_template,
+    }
+}
+
+/// Hosts whose values from a source (`source_priority`, `source_defaults`)
+/// cannot be passed on as `-o`, because their connect command is not ssh.
+fn source_options_warning(config: &AppConfig, hosts: &[ssh::Host]) -> Option<String> {
+    let dropped: Vec<String> = hosts
+        .iter()
+        .filter(|host| !host.source_options.is_empty())
+        .filter_map(|host| {
+            let template = base_template(config, host);
+            ssh::insert_flags(template, &[]).is_none().then(|| {
+                let command = template.split_whitespace().next().unwrap_or_default();
+                format!("{} ({command})", host.name)
+            })
+        })
+        .collect();
+    match dropped.as_slice() {
+        [] => None,
+        [host] => Some(t_fmt("Source values are not passed to a non-ssh command for {host}", &[("host", host)])),
+        [first, rest @ ..] => Some(t_fmt(
+            "Source values are not passed to a non-ssh command for {host} and {count} more",
+            &[("host", first), ("count", &rest.len())],
+        )),
+    }
+}
+
+/// The command Enter would run for `host` as one line of shell that runs on
+/// its own later: `# sgh:env` becomes an `env` prefix, and helpers and the
+/// one-time code, which only exist while connecting, are refused.
+///
+/// # Errors
+///
+/// Will return `Err` if the template cannot be rendered, or uses `{{otp}}`
+/// while an OTP command applies.
+pub fn written_command(config: &AppConfig, host: &ssh::Host) -> Result<String> {
+    let template = command_template(config, host)?;
+    if host.otp_command.is_some() || config.otp_command.is_some() {
+        // コードは接続のたびに取るので、{{otp}} を使うなら書き出せない
+        const PROBE: &str = "sgh-otp-probe";
+        let probe = ssh::Host {
+            otp: Some(PROBE.to_string()),
+            ..host.clone()
+        };
+        if probe.render_command_template(&template, Helpers::Refuse)?.text.contains(PROBE) {
+            anyhow::bail!(
+                "The command for {} uses `{{{{otp}}}}`, which is only fetched when connecting",
+                host.name
+            );
+        }
+    }
+    let command = host.render_command_template(&template, Helpers::Refuse)?.text;
+    if host.env.is_empty() {
+        return Ok(command);
+    }
+    let env: Vec<String> = host
+        .env
+        .iter()
+        .map(|(key, value)| {
+            let pair = format!("{key}={value}");
+            shlex::try_quote(&pair).map_or(pair.clone(), Cow::into_owned)
+        })
+        .collect();
+    Ok(format!("env {} {command}", env.join(" ")))
+}
+
+/// The command Enter would run for each of `hosts`, for `sgh
+/// export-commands`.
+///
+/// # Errors
+///
+/// Will return `Err` if a command cannot be written out (see `written_command`).
+pub fn connect_commands(config: &AppConfig, hosts: &[ssh::Host]) -> Result<Vec<String>> {
+    hosts.iter().map(|host| written_command(config, host)).collect()
+}
+
+/// Parses every configured SSH config file, reads the sources, and drops
+/// excluded hosts.
+///
+/// # Errors
+///
+/// Will return `Err` if a config file cannot be parsed, a source fails, or
+/// an exclude pattern is invalid.
+pub fn load_hosts(config: &AppConfig) -> Result<Vec<ssh::Host>> {
+    let (mut hosts, slow_sources) = load_local_hosts(config)?;
+    let exclude = ExcludePattern::parse_all(&config.exclude)?;
+    for source in &slow_sources {
+        let mut source_hosts = source.load_cached(config.pipeline, config.source_cache)?;
+        source_hosts.retain(|host| !exclude.iter().any(|pattern| pattern.matches(host)));
+        add_source_hosts(config, &mut hosts, source_hosts, source.kind());
+    }
+    Ok(hosts)
+}
+
+/// The hosts that are read right away: the SSH config files (or the
+/// daemon's hosts) and encrypted sources, whose passphrase prompt needs the
+/// terminal. Returns the slow sources left to load, without excluded
+/// hosts; none when the daemon already served everything.
+///
+/// # Errors
+///
+/// Will return `Err` if a config file cannot be parsed, an encrypted source
+/// fails, or an exclude pattern is invalid.
+fn load_local_hosts(config: &AppConfig) -> Result<(Vec<ssh::Host>, Vec<Source>)> {
+    if config.demo {
+        return Ok((demo::hosts(config.pipeline)?, Vec::new()));
+    }
+
+    let exclude = ExcludePattern::parse_all(&config.exclude)?;
+
+    #[cfg(unix)]
+    if let Some(socket) = &config.daemon_socket {
+        // デーモンが動いていない、または設定が違うときは自分で読む
+        if let Ok(mut hosts) = daemon::fetch_hosts(socket, config) {
+            hosts.retain(|host| !exclude.iter().any(|pattern| pattern.matches(host)));
+            return Ok((hosts, Vec::new()));
+        }
+    }
+
+    let mut hosts = Vec::new();
+
+    let expanded = ssh::expand_config_paths(&config.config_paths);
+    for path in &expanded {
+        let parsed_hosts = match ssh::parse_config_with(path, config.pipeline) {
+            Ok(h) => h,
+            Err(err) => {
+                // Missing files are tolerated for auto-discovered defaults.
+                // The system-wide config is always optional, even under
+                // strict mode, to preserve existing behaviour.
+                let is_missing = matches!(
+                    &err,
+                    ssh::ParseConfigError::Io(io_err)
+                        if io_err.kind() == std::io::ErrorKind::NotFound
+                );
+                let is_system_default =
+                    path.as_os_str() == std::ffi::OsStr::new("/etc/ssh/ssh_config");
+
+                if is_missing && (!config.strict_missing || is_system_default) {
+                    continue;
+                }
+
+                anyhow::bail!(
+                    "Failed to parse SSH configuration file {}: {err:?}",
+                    path.display()
+                );
+            }
+        };
+
+        hosts.extend(parsed_hosts);
+    }
+
+    let (slow_sources, local_sources): (Vec<Source>, Vec<Source>) =
+        config.sources.iter().cloned().partition(Source::is_slow);
+    for source in &local_sources {
+        add_source_hosts(config, &mut hosts, source.load(config.pipeline)?, source.kind());
+    }
+
+    hosts.retain(|host| !exclude.iter().any(|pattern| pattern.matches(host)));
+
+    Ok((hosts, slow_sources))
+}
+
+/// Status bar notice naming the hosts whose `Port` is not a number, since
+/// connecting to them would fail.
+fn invalid_port_warning(hosts: &[ssh::Host]) -> Option<String> {
+    let invalid: Vec<String> = hosts
+        .iter()
+        .filter(|host| host.has_invalid_port())
+        .map(|host| format!("{} ({})", host.name, host.port.as_deref().unwrap_or_default()))
+        .collect();
+    match invalid.as_slice() {
+        [] => None,
+        [host] => Some(t_fmt("Invalid Port for {host}", &[("host", host)])),
+        [first, rest @ ..] => Some(t_fmt("Invalid Port for {host} and {count} more", &[("host", first), ("count", &rest.len())])),
+    }
+}
+
+/// Adds the hosts of a `kind` source to `hosts` with its `source_defaults`
+/// applied, folding those already configured into their hosts.
+fn add_source_hosts(config: &AppConfig, hosts: &mut Vec<ssh::Host>, mut source_hosts: Vec<ssh::Host>, kind: &str) {
+    if let Some(defaults) = config.source_defaults.get(kind) {
+        defaults.apply(&mut source_hosts);
+    }
+    source::merge_hosts(hosts, source_hosts, kind, &config.source_priority);
+}
+
+/// The query `--from-clipboard` starts with: the clipboard's text when it
+/// names a listed host. Skipped when a search was given.
+fn clipboard_query(config: &AppConfig, hosts: &[ssh::Host]) -> Option<String> {
+    if !config.from_clipboard || config.search_filter.is_some() || config.demo {
+        return None;
+    }
+    clipboard_host(&clipboard::paste()?, hosts)
+}
+
+/// The hostname or IP in `text` (one word, optionally `user@host`) if it
+/// is the name, an alias or the destination of one of `hosts`; other
+/// clipboard content is ignored so a copied sentence does not become the
+/// query.
+fn clipboard_host(text: &str, hosts: &[ssh::Host]) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() || text.len() > 253 || text.contains(char::is_whitespace) {
+        return None;
+    }
+    let name = text.rsplit_once('@').map_or(text, |(_, host)| host);
+    let is_listed = hosts.iter().filter(|host| !host.is_pattern).any(|host| {
+        std::iter::once(host.name.as_str())
+            .chain(host.aliases.split(", "))
+            .chain(std::iter::once(host.destination.as_str()))
+            .any(|candidate| !candidate.is_empty() && candidate.eq_ignore_ascii_case(name))
+    });
+    is_listed.then(|| name.to_string())
+}
+
+/// Checks every template sgh may render (command, hooks, launcher and the
+/// per-host `# sgh:on-start` / `# sgh:on-end` directives) so a typo is
+/// reported at startup rather than after a host is selected.
+///
+/// # Errors
+///
+/// Will return `Err` listing each problem with its template and position.
+pub fn validate_templates(config: &AppConfig, hosts: &[ssh::Host]) -> Result<()> {
+    let variables = ssh::template_variables();
+    let mut launcher_variables = variables.clone();
+    launcher_variables.extend(["command".to_string(), "title".to_string()]);
+
+    let mut templates = vec![("--template".to_string(), config.command_template.as_str(), &variables)];
+    let global = [
+        ("--on-session-start-template", &config.command_template_on_session_start),
+        ("--on-session-end-template", &config.command_template_on_session_end),
+    ];
+    for (label, template) in global {
+        if let Some(template) = template {
+            templates.push((label.to_string(), template, &variables));
+        }
+    }
+    if let Some(template) = &config.title_template {
+        templates.push(("--title-template".to_string(), template, &variables));
+    }
+    if let Some(template) = &config.launcher_template {
+        templates.push(("--launcher-template".to_string(), template, &launcher_variables));
+    }
+    templates.push(("--snippet-template".to_string(), &config.snippet_template, &variables));
+    for (kind, defaults) in &config.source_defaults {
+        if let Some(template) = &defaults.template {
+            templates.push((format!("source_defaults.{kind}.template"), template, &variables));
+        }
+    }
+
+    // パターンから継承したディレクティブは同じ文字列なので一度だけ検査する
+    let mut seen = HashSet::new();
+    for host in hosts {
+        let directives = [
+            ("# sgh:on-start", &host.on_session_start_template),
+            ("# sgh:on-end", &host.on_session_end_template),
+            ("connect template", &host.connect_template),
+        ];
+        for (key, template) in directives {
+            if let Some(template) = template.as_deref().filter(|t| seen.insert(*t)) {
+                let location = host.source_display().unwrap_or_else(|| host.name.clone());
+                templates.push((format!("{key} of {} ({location})", host.name), template, &variables));
+            }
+        }
+    }
+
+    let mut problems = templates
+        .iter()
+        .flat_map(|(label, template, variables)| {
+            template::validate(template, variables)
+                .into_iter()
+                .map(move |problem| format!("  {label}: {problem}"))
+        })
+        .collect::<Vec<_>>();
+    // host_templates は handlebars ではなく単純な {{name}} 置換
+    for host_template in &config.host_templates {
+        problems.extend(
+            host_template
+                .problems()
+                .into_iter()
+                .map(|problem| format!("  host_templates {:?}: {problem}", host_template.name)),
+        );
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid templates:\n{}", problems.join("\n")))
+    }
+}
+
+/// The fields free text is matched against: only `focus` when set.
+fn focused_fields(focus: Option<SearchField>, fields: &[SearchField]) -> Vec<SearchField> {
+    match focus {
+        Some(field) => vec![field],
+        None => fields.to_vec(),
+    }
+}
+
+/// Table rows for `hosts`: one per host, or one per alias when `split_aliases`
+/// is on, sorted by `sort_keys`.
+fn host_rows(hosts: &[ssh::Host], split_aliases: bool, sort_keys: &[SortKey], natural: bool) -> Vec<ssh::Host> {
+    let mut rows = if split_aliases {
+        ssh::split_aliases(hosts)
+    } else {
+        hosts.to_vec()
+    };
+
+    search::sort_hosts(&mut rows, sort_keys, natural);
+
+    rows
+}
+
+fn render_searchbar(f: &mut Frame, app: &mut App, area: Rect) {
+    let theme = &app.theme;
+    let prompt = Span::styled(PROMPT, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD));
+    let query = Span::styled(app.view_query().value(), Style::default().fg(theme.text));
+    let content = Line::from(vec![prompt, query]);
+
+    let (matched, total) = match app.view {
+        View::Hosts => {
+            let show_patterns = app.show_patterns.get();
+            let total = app
+                .hosts
+                .non_filtered_iter()
+                .filter(|host| show_patterns || !host.is_pattern)
+                .count();
+            (app.hosts.len(), total)
+        }
+        View::Tunnels => (app.tunnel_view.items.len(), app.tunnel_view.items.total_len()),
+        View::Sessions => (app.session_view.items.len(), app.session_view.items.total_len()),
+        View::History => (app.history_view.items.len(), app.history_view.items.total_len()),
+    };
+    let count = format!(" {matched} / {total} ");
+    let title_right = Line::from(Span::styled(
+        count,
+        Style::default().fg(theme.muted),
+    ))
+    .right_aligned();
+
+    // タイトルは表示のタブ。列を絞っている間は対象の列名も出す
+    let mut title: Vec<Span> = View::ALL
+        .iter()
+        .map(|&view| {
+            let style = if view == app.view {
+                Style::default().fg(theme.primary).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(theme.muted)
+            };
+            Span::styled(format!(" {} ", view.title()), style)
+        })
+        .collect();
+    if let (View::Hosts, Some(field)) = (app.view, app.focus_field.get()) {
+        title.push(Span::styled(
+            format!("in {} ", field.name()),
+            Style::default().fg(theme.accent).add_modifier(Modifier::BOLD),
+        ));
+    }
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(theme.border_focused))
+        .title(Line::from(title))
+        .title(title_right);
+
+    let paragraph = Paragraph::new(content).block(block);
+    f.render_widget(paragraph, area);
+}
+
+fn render_table(f: &mut Frame, app: &mut App, area: Rect) {
+    let theme = &app.theme;
+    // 修飾子 (tag: など) を除いた自由入力部分だけをハイライトに使う
+    let query = Query::parse(app.search.value()).text;
+
+    // First column is the marker (and the icon), then the data columns.
+    let mut header_cells: Vec<Cell> = vec![Cell::from("")];
+    if app.config.icons != IconMode::Off {
+        header_cells.push(Cell::from(""));
+    }
+    // ヘッダーのクリックで並べ替えている列には向きを添える
+    header_cells.extend(column_headers(&app.config).iter().enumerate().map(|(i, h)| {
+        let arrow = match app.header_sort {
+            Some((key, descending)) if COLUMN_SORT_KEYS.get(i) == Some(&Some(key)) => {
+                if descending { " ↓" } else { " ↑" }
+            }
+            _ => "",
+        };
+        Cell::from(Span::styled(format!("{h}{arrow}"), theme.header_style()))
+    }));
+
+    let header = Row::new(header_cells).height(1).bottom_margin(1);
+    let selected_idx = app.table_state.selected().unwrap_or(usize::MAX);
+
+    let mut rows = Vec::new();
+    // マウス位置から行を引けるよう、表示行ごとのホスト番号を控える
+    let mut row_hosts = Vec::new();
+    // 見出し行は選択できないので、表示上の選択位置は見出しの数だけずれる
+    let mut display_selected = app.table_state.selected();
+    let mut previous: Option<&ssh::Host> = None;
+    for (idx, host) in app.hosts.iter().enumerate() {
+        if let Some(group) = app.config.group_by {
+            if previous.is_none_or(|previous| group.compare(previous, host).is_ne()) {
+                let count = app.hosts.iter().skip(idx).take_while(|h| group.compare(host, h).is_eq()).count();
+                rows.push(group_header_row(group, host, count, app));
+                row_hosts.push(None);
+                if idx <= selected_idx {
+                    display_selected = display_selected.map(|selected| selected + 1);
+                }
+            }
+            previous = Some(host);
+        }
+        rows.push(build_row(idx, selected_idx, host, &query, app));
+        row_hosts.push(Some(idx));
+    }
+    if let Some(host) = &app.adhoc_host {
+        rows.push(build_row(app.hosts.len(), selected_idx, host, &query, app));
+        row_hosts.push(Some(app.hosts.len()));
+    }
+
+    let block = Block::default()
+        .borders(Borders::NONE)
+        .padding(Padding::horizontal(1));
+
+    let inner = block.inner(area);
+    let table = Table::new(rows, &app.table_columns_constraints)
+        .header(header)
+        .row_highlight_style(theme.selection_style())
+        .column_spacing(2)
+        .block(block);
+
+    if app.config.group_by.is_some() {
+        let mut state = app.table_state.clone().with_selected(display_selected);
+        f.render_stateful_widget(table, area, &mut state);
+        *app.table_state.offset_mut() = state.offset();
+    } else {
+        f.render_stateful_widget(table, area, &mut app.table_state);
+    }
+    app.table_layout = TableLayout {
+        area: inner,
+        rows: row_hosts,
+        offset: app.table_state.offset(),
+    };
+}
+
+/// An `ItemTable` inside `block`: the query (while one is typed) above a
+/// table with a column per `TableItem` heading.
+fn render_item_table<T: TableItem + 'static>(
+    f: &mut Frame,
+    theme: &Theme,
+    table: &mut ItemTable<T>,
+    block: Block,
+    area: Rect,
+) {
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let [query_area, table_area] = Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(inner);
+
+    let query = if table.query.value().is_empty() {
+        Line::from(Span::styled(PROMPT, Style::default().fg(theme.muted)))
+    } else {
+        Line::from(vec![
+            Span::styled(PROMPT, Style::default().fg(theme.primary)),
+            Span::styled(table.query.value().to_string(), Style::default().fg(theme.text)),
+        ])
+    };
+    f.render_widget(Paragraph::new(query), query_area);
+    render_item_rows(f, theme, table, table_area);
+}
+
+/// The rows of an `ItemTable` under a heading per `TableItem` column.
+fn render_item_rows<T: TableItem + 'static>(f: &mut Frame, theme: &Theme, table: &mut ItemTable<T>, area: Rect) {
+    // 列幅はすべての行で測り、絞り込み中に列が動かないようにする
+    let mut widths = vec![0; T::HEADERS.len()];
+    for item in table.items.non_filtered_iter() {
+        for (width, cell) in widths.iter_mut().zip(item.cells()) {
+            *width = (*width).max(cell.width()).min(MAX_COLUMN_WIDTH);
+        }
+    }
+    let last = widths.len().saturating_sub(1);
+    let constraints: Vec<Constraint> = widths
+        .iter()
+        .enumerate()
+        .map(|(i, &width)| {
+            let width = u16::try_from(width.max(t(T::HEADERS[i]).width())).unwrap_or(u16::MAX);
+            if i == last { Constraint::Min(width) } else { Constraint::Length(width) }
+        })
+        .collect();
+
+    let header = Row::new(T::HEADERS.iter().map(|heading| Cell::from(Span::styled(t(heading), theme.header_style()))));
+    let rows: Vec<Row> = table
+        .items
+        .iter()
+        .map(|item| {
+            Row::new(item.cells().into_iter().enumerate().map(|(i, cell)| {
+                let color = if i == 0 { theme.text } else { theme.text_dim };
+                Cell::from(Span::styled(cell, Style::default().fg(color)))
+            }))
+        })
+        .collect();
+    let widget = Table::new(rows, constraints)
+        .header(header)
+        .column_spacing(2)
+        .row_highlight_style(theme.selection_style())
+        .highlight_symbol(SELECTION_MARKER);
+    f.render_stateful_widget(widget, area, &mut table.state);
+}
+
+/// A view other than the host list, in place of the host table. Its query
+/// is typed in the search bar.
+fn render_view(f: &mut Frame, app: &mut App, area: Rect) {
+    let theme = &app.theme;
+    let area = Block::default().padding(Padding::horizontal(1)).inner(area);
+    let (empty, hint) = match app.view {
+        View::Hosts => return,
+        View::Tunnels => {
+            render_item_rows(f, theme, &mut app.tunnel_view, area);
+            (app.tunnel_view.items.total_len() == 0, t("No tunnels in the config file"))
+        }
+        View::Sessions => {
+            render_item_rows(f, theme, &mut app.session_view, area);
+            (app.session_view.items.total_len() == 0, t("No sessions started from sgh are running"))
+        }
+        View::History => {
+            render_item_rows(f, theme, &mut app.history_view, area);
+            (app.history_view.items.total_len() == 0, t("No connections made from sgh yet"))
+        }
+    };
+    if empty {
+        let [_, message] = Layout::vertical([Constraint::Length(2), Constraint::Min(0)]).areas(area);
+        f.render_widget(Paragraph::new(Span::styled(hint, Style::default().fg(theme.muted))), message);
+    }
+}
+
+/// Shown instead of the table when there is no host at all: where sgh
+/// looked and how to get a first host.
+fn render_onboarding(f: &mut Frame, app: &App, area: Rect) {
+    let theme = &app.theme;
+    let heading = Style::default().fg(theme.primary).add_modifier(Modifier::BOLD);
+    let muted = Style::default().fg(theme.muted);
+    let key = Style::default().fg(theme.accent).add_modifier(Modifier::BOLD);
+
+    let mut lines = vec![
+        Line::from(Span::styled(t("No hosts yet"), heading)),
+        Line::from(""),
+    ];
+    if app.config.config_paths.is_empty() {
+        lines.push(Line::from(Span::styled(t("No SSH config files are read (--no-default-config)."), muted)));
+    } else {
+        lines.push(Line::from(Span::styled(t("sgh looked in:"), muted)));
+        let width = app.config.config_paths.iter().map(|path| path.width()).max().unwrap_or(0);
+        for path in &app.config.config_paths {
+            lines.push(Line::from(vec![
+                Span::styled(format!("  {}  ", pad_end(path, width)), Style::default().fg(theme.text)),
+                Span::styled(t(config_path_status(path)), muted),
+            ]));
+        }
+    }
+
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(t("To add your first host:"), muted)));
+    // 書き込めないときは設定ファイルを作る・取り込む案内を出さない
+    if !app.config.demo && !edit::is_read_only() {
+        let config_file = app.writable_config_files().into_iter().next();
+        if let Some(path) = config_file.filter(|path| !path.exists()) {
+            let path = ssh::contract_home(&path.to_string_lossy());
+            lines.push(Line::from(vec![
+                Span::styled("  alt+c  ", key),
+                Span::raw(format!("{} {path}", t("create"))),
+            ]));
+        }
+        lines.push(Line::from(vec![
+            Span::styled("  alt+i  ", key),
+            Span::raw(t("import the ssh destinations in your shell history")),
+        ]));
+    }
+    lines.push(Line::from(vec![
+        Span::styled("  user@host  ", key),
+        Span::raw(t("type a destination and press Enter; sgh offers to save it afterwards")),
+    ]));
+
+    let paragraph = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().padding(Padding::new(3, 1, 1, 0)));
+    f.render_widget(paragraph, area);
+}
+
+/// Under the table when the query matches nothing: hosts it nearly
+/// matches and the keys to use one or to start over.
+fn render_no_matches(f: &mut Frame, app: &App, area: Rect) {
+    let theme = &app.theme;
+    let muted = Style::default().fg(theme.muted);
+    let key = Style::default().fg(theme.accent).add_modifier(Modifier::BOLD);
+
+    // 見出し (と空行) と臨時ホストの行の下に出す
+    let top = 2 + u16::from(app.adhoc_host.is_some()) + 1;
+    if area.height <= top {
+        return;
+    }
+    let area = Rect {
+        y: area.y + top,
+        height: area.height - top,
+        ..area
+    };
+
+    let mut lines = vec![Line::from(Span::styled(
+        format!("{} \"{}\"", t("No host matches"), app.search.value().trim()),
+        Style::default().fg(theme.text).add_modifier(Modifier::BOLD),
+    ))];
+    let misses = app.near_misses();
+    if !misses.is_empty() {
+        let names: Vec<&str> = misses.iter().map(|host| host.name.as_str()).collect();
+        lines.push(Line::from(vec![
+            Span::styled(format!("{} ", t("Did you mean")), muted),
+            Span::styled(names.join(", "), Style::default().fg(theme.primary)),
+            Span::styled("?", muted),
+        ]));
+        lines.push(Line::from(vec![
+            Span::styled("tab  ", key),
+            Span::styled(format!("{} {}", t("search for"), names[0]), muted),
+        ]));
+    }
+    lines.push(Line::from(vec![
+        Span::styled("^x   ", key),
+        Span::styled(t("clear the search"), muted),
+    ]));
+
+    let paragraph = Paragraph::new(lines).block(Block::default().padding(Padding::horizontal(5)));
+    f.render_widget(paragraph, area);
+}
+
+/// What reading a `config_paths` entry found, for the onboarding view.
+fn config_path_status(raw: &str) -> &'static str {
+    let files = ssh::expand_config_paths(&[raw.to_string()]);
+    if raw.contains(['*', '?', '[']) {
+        if files.is_empty() {
+            "no files"
+        } else {
+            "no Host blocks"
+        }
+    } else if files.iter().all(|path| path.exists()) {
+        "no Host blocks"
+    } else {
+        "missing"
+    }
+}
+
+/// Headers of the data columns, in the UI language.
+fn column_headers(config: &AppConfig) -> Vec<&'static str> {
+    let mut headers = vec![t("NAME"), t("ALIASES"), t("USER"), t("DESTINATION"), t("PORT")];
+    if config.show_proxy_command {
+        headers.push(t("PROXY"));
+    }
+    if config.show_source {
+        headers.push(t("SOURCE"));
+    }
+    headers
+}
+
+/// Non-selectable row starting the group of `host` (`--group-by`).
+fn group_header_row<'a>(group: GroupKey, host: &ssh::Host, count: usize, app: &App) -> Row<'a> {
+    let theme = &app.theme;
+    // user や proxy でまとめたときは見出しにも実際の値が出るので、行と同じく隠す
+    let redacted = app.redact.then(|| host.redacted());
+    let label = group
+        .value(redacted.as_ref().unwrap_or(host))
+        .unwrap_or_else(|| group.missing_label().to_string());
+    let width = app.column_widths.first().copied().unwrap_or(MAX_COLUMN_WIDTH);
+    let hosts = if count == 1 { "1 host".to_string() } else { format!("{count} hosts") };
+
+    let mut cells = vec![Cell::from("")];
+    if app.config.icons != IconMode::Off {
+        cells.push(Cell::from(""));
+    }
+    cells.push(Cell::from(Span::styled(
+        truncate_end(&label, width).into_owned(),
+        Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
+    )));
+    cells.push(Cell::from(Span::styled(hosts, Style::default().fg(theme.muted))));
+    Row::new(cells)
+}
+
+fn build_row<'a>(
+    idx: usize,
+    selected_idx: usize,
+    host: &'a ssh::Host,
+    query: &str,
+    app: &App,
+) -> Row<'a> {
+    let (theme, matcher, config) = (&app.theme, &app.matcher, &app.config);
+
+    let marker = if idx == selected_idx {
+        Cell::from(Span::styled(
+            SELECTION_MARKER,
+            Style::default()
+                .fg(theme.selection_marker)
+                .add_modifier(Modifier::BOLD),
+        ))
+    } else if app.sessions.is_active(&host.name) {
+        Cell::from(Span::styled(SESSION_MARKER, Style::default().fg(theme.success)))
+    } else if app.last_connected.as_ref() == Some(&host.name) {
+        Cell::from(Span::styled(LAST_CONNECTED_MARKER, Style::default().fg(theme.accent)))
+    } else {
+        Cell::from(SELECTION_PADDING)
+    };
+
+    // 列幅を超える値は省略記号で切り詰める (全文は詳細パネルで確認できる)
+    let width = |i: usize| app.column_widths.get(i).copied().unwrap_or(MAX_COLUMN_WIDTH);
+    // color_rules の色は各セルの既定の色より優先する
+    let rule = color_rule::style_for(&config.color_rules, host).unwrap_or_default();
+    let text = Style::default().fg(theme.text).patch(rule);
+    let dim = |value: String| Cell::from(Span::styled(value, Style::default().fg(theme.text_dim).patch(rule)));
+
+    let name_cell = if host.is_pattern {
+        // パターンブロックは接続先ではないので控えめに表示する
+        Cell::from(Span::styled(
+            truncate_end(&host.name, width(0)).into_owned(),
+            Style::default().fg(theme.muted).add_modifier(Modifier::ITALIC).patch(rule),
+        ))
+    } else {
+        highlighted_cell(&host.name, width(0), query, matcher, theme, text)
+    };
+    let aliases_cell = if host.is_adhoc {
+        Cell::from(Span::styled(
+            t("ad-hoc"),
+            Style::default().fg(theme.accent).add_modifier(Modifier::ITALIC),
+        ))
+    } else {
+        dim(truncate_end(&host.aliases, width(1)).into_owned())
+    };
+    let user_cell = match app.effective_user(host) {
+        // 設定にはないが ssh が使うユーザーは、書かれた値と区別できるよう薄く表示する
+        Some((user, _)) => Cell::from(Span::styled(
+            truncate_end(&user, width(2)).into_owned(),
+            Style::default().fg(theme.muted).add_modifier(Modifier::DIM).patch(rule),
+        )),
+        None if app.redact && host.user.is_some() => dim(ssh::REDACTED.to_string()),
+        None => dim(truncate_end(host.user.as_deref().unwrap_or_default(), width(2)).into_owned()),
+    };
+    let destination_cell = if app.redact && !host.destination.is_empty() {
+        Cell::from(Span::styled(ssh::REDACTED, text))
+    } else {
+        highlighted_cell(&host.destination, width(3), query, matcher, theme, text)
+    };
+    let port_cell = match host.port.as_deref() {
+        Some(_) if app.redact => dim(ssh::REDACTED.to_string()),
+        port => dim(truncate_end(port.unwrap_or_default(), width(4)).into_owned()),
+    };
+
+    let mut cells = vec![marker];
+    if config.icons != IconMode::Off {
+        let icon = icon::icon_for(config.icons, &config.icon_rules, host).unwrap_or_default();
+        cells.push(Cell::from(Span::styled(icon, Style::default().fg(theme.accent).patch(rule))));
+    }
+    cells.extend([name_cell, aliases_cell, user_cell, destination_cell, port_cell]);
+    let mut column = 5;
+    if config.show_proxy_command {
+        let proxy = match host.proxy_command.as_deref() {
+            Some(_) if app.redact => ssh::REDACTED,
+            proxy => proxy.unwrap_or_default(),
+        };
+        cells.push(dim(truncate_middle(proxy, width(column)).into_owned()));
+        column += 1;
+    }
+    if config.show_source {
+        let source = host.source_display().unwrap_or_default();
+        cells.push(Cell::from(Span::styled(
+            truncate_middle(&source, width(column)).into_owned(),
+            Style::default().fg(theme.muted).patch(rule),
+        )));
+    }
+
+    let style = if app.hovered == Some(idx) { rule.patch(theme.hover_style()) } else { rule };
+    Row::new(cells).style(style)
+}
+
+fn highlighted_cell<'a>(
+    value: &'a str,
+    max_width: usize,
+    query: &str,
+    matcher: &SkimMatcherV2,
+    theme: &Theme,
+    base: Style,
+) -> Cell<'a> {
+    let shown = truncate_end(value, max_width);
+    if query.is_empty() {
+        return Cell::from(Span::styled(shown.into_owned(), base));
+    }
+
+    // マッチ位置は元の値で計算する。末尾切り詰めなので先頭側の位置はそのまま使える
+    let Some((_, indices)) = matcher.fuzzy_indices(value, query) else {
+        return Cell::from(Span::styled(shown.into_owned(), base));
+    };
+    let value = shown.as_ref();
+
+    let highlight = theme.match_style();
+    let mut spans = Vec::new();
+    let mut buf = String::new();
+    let mut buf_is_match: Option<bool> = None;
+
+    for (i, ch) in value.chars().enumerate() {
+        let is_match = indices.contains(&i);
+        match buf_is_match {
+            Some(prev) if prev == is_match => buf.push(ch),
+            Some(prev) => {
+                spans.push(Span::styled(
+                    std::mem::take(&mut buf),
+                    if prev { highlight } else { base },
+                ));
+                buf.push(ch);
+                buf_is_match = Some(is_match);
+            }
+            None => {
+                buf.push(ch);
+                buf_is_match = Some(is_match);
+            }
+        }
+    }
+
+    if let Some(prev) = buf_is_match {
+        spans.push(Span::styled(buf, if prev { highlight } else { base }));
+    }
+
+    Cell::from(Line::from(spans))
+}
+
+/// 詳細パネル: 選択中のホストの ProxyJump / ProxyCommand / IdentityFile /
+/// LocalForward と定義元 (file:line) を key:value 表示する。値が空の項目は省略する。
+fn render_detail_panel(f: &mut Frame, app: &mut App, area: Rect) {
+    let theme = &app.theme;
+
+    let block = Block::default()
+        .borders(Borders::TOP)
+        .border_style(theme.border_style())
+        .title(Line::from(Span::styled(
+            format!(" {} ", t("Host detail")),
+            Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
+        )))
+        .padding(Padding::horizontal(2));
+
+    let Some(host) = app.detail_host() else {
+        let paragraph = Paragraph::new(Span::styled(
+            t("No host selected"),
+            Style::default().fg(theme.muted),
+        ))
+        .block(block);
+        f.render_widget(paragraph, area);
+        return;
+    };
+    let has_invalid_port = host.has_invalid_port();
+    let redacted = app.redact.then(|| host.redacted());
+    let host = redacted.as_ref().unwrap_or(host);
+    let mut lines: Vec<Line> = Vec::new();
+
+    // keyword を渡した項目は、継承した値ならその出どころを後ろに添える
+    let mut push_field = |label: &str, value: &str, keyword: &str| {
+        if value.is_empty() {
+            return;
+        }
+        let mut spans = vec![
+            Span::styled(
+                pad_end(label, 14),
+                Style::default().fg(theme.muted).add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(value.to_string(), Style::default().fg(theme.text)),
+        ];
+        if let Some(source) = host.option_sources.get(keyword).filter(|s| s.inherited) {
+            spans.push(Span::styled(
+                format!("  ← {}", source.describe()),
+                Style::default().fg(theme.muted),
+            ));
+        }
+        lines.push(Line::from(spans));
+    };
+
+    if host.is_pattern {
+        push_field(t("Pattern"), t("defaults applied to matching hosts"), "");
+    }
+    if host.is_adhoc {
+        push_field(t("Ad-hoc"), t("not in your SSH config; connects directly"), "");
+    }
+    match host.destination_kind().filter(|_| redacted.is_none()) {
+        Some(kind) => push_field("Hostname", &format!("{}  ({})", host.destination, t(kind.label())), "hostname"),
+        None => push_field("Hostname", &host.destination, "hostname"),
+    }
+    push_field(t("Tags"), &host.tags.join(", "), "");
+    if let Some(v) = host.note.as_deref() {
+        push_field(t("Note"), v, "");
+    }
+    if let Some(v) = host.dir.as_deref() {
+        push_field(t("Directory"), v, "");
+    }
+    if let Some(v) = host.user.as_deref() {
+        push_field("User", v, "user");
+    } else if let Some((user, block)) = app.effective_user(host) {
+        let from = block.unwrap_or_else(|| t("local user").to_string());
+        push_field("User", &format!("{user}  ({from})"), "");
+    }
+    if let Some(v) = host.port.as_deref() {
+        if has_invalid_port {
+            push_field("Port", &format!("{v}  ({})", t("not a port number; ssh will refuse it")), "port");
+        } else {
+            push_field("Port", v, "port");
+        }
+    }
+    if let Some(v) = host.proxy_jump.as_deref() {
+        push_field("ProxyJump", v, "proxyjump");
+    }
+    if let Some(v) = host.proxy_command.as_deref() {
+        push_field("ProxyCommand", v, "proxycommand");
+    }
+    if let Some(v) = host.identity_file.as_deref() {
+        push_field("IdentityFile", v, "identityfile");
+    }
+
+    let forward_groups = [
+        (
+            "LocalForward",
+            host.local_forwards
+                .iter()
+                .map(|lf| format!("{} → {}", lf.listen(), lf.target()))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "RemoteForward",
+            host.remote_forwards
+                .iter()
+                .map(|rf| format!("{} ← {}", rf.listen(), rf.target()))
+                .collect(),
+        ),
+        (
+            "DynamicForward",
+            host.dynamic_forwards.iter().map(|df| df.listen()).collect(),
+        ),
+        (
+            "Env",
+            host.env
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect(),
+        ),
+    ];
+
+    for (label, values) in &forward_groups {
+        // 2 件目以降はラベルを空にしてインデントだけ揃える
+        for (i, value) in values.iter().enumerate() {
+            push_field(if i == 0 { label } else { "" }, value, "");
+        }
+    }
+
+    if let Some(source) = host.source_display() {
+        lines.push(Line::from(vec![
+            Span::styled(
+                pad_end(t("Source"), 14),
+                Style::default().fg(theme.muted).add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(source, Style::default().fg(theme.text_dim)),
+        ]));
+    }
+
+    if lines.is_empty() {
+        lines.push(Line::from(Span::styled(
+            t("(no extra settings)"),
+            Style::default().fg(theme.muted),
+        )));
+    }
+
+    // 長い ProxyCommand なども省略せず折り返して全文を表示する
+    let paragraph = Paragraph::new(lines).wrap(Wrap { trim: false }).block(block);
+    f.render_widget(paragraph, area);
+}
+
+fn render_sessions_panel(f: &mut Frame, app: &App, area: Rect) {
+    let theme = &app.theme;
+
+    let block = Block::default()
+        .borders(Borders::TOP)
+        .border_style(theme.border_style())
+        .title(Line::from(Span::styled(
+            format!(" {} ({}) ", t("Sessions"), app.sessions.len()),
+            Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
+        )))
+        .padding(Padding::horizontal(2));
+
+    let lines: Vec<Line> = app
+        .sessions
+        .iter()
+        .map(|session| {
+            Line::from(vec![
+                Span::styled(SESSION_MARKER, Style::default().fg(theme.success)),
+                Span::styled(
+                    pad_end(&truncate_end(&session.host_name, 23), 24),
+                    Style::default().fg(theme.text),
+                ),
+                Span::styled(
+                    match session.socks_port() {
+                        Some(port) => format!("{:<12}", format!("socks :{port}")),
+                        None => format!("{:<12}", session.tmux_window().unwrap_or("")),
+                    },
+                    Style::default().fg(theme.text_dim),
+                ),
+                Span::styled(format_duration(session.elapsed()), Style::default().fg(theme.muted)),
+            ])
+        })
+        .collect();
+
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// Labels of the active filter parts: the column focus, then the chips of the
+/// query. Empty when only plain text is being searched.
+fn filter_chips(app: &App) -> Vec<String> {
+    let focus = app.focus_field.get().map(|field| format!("in {}", field.name()));
+    focus
+        .into_iter()
+        .chain(Chip::parse_all(app.search.value()).into_iter().map(|chip| chip.label))
+        .collect()
+}
+
+fn render_chips(f: &mut Frame, theme: &Theme, chips: &[String], area: Rect) {
+    let mut spans = vec![Span::raw(" ")];
+    for chip in chips {
+        spans.push(Span::styled(
+            format!(" {chip} "),
+            Style::default().fg(theme.text).bg(theme.selection_bg),
+        ));
+        spans.push(Span::raw(" "));
+    }
+    spans.push(Span::styled(t("^x remove last"), Style::default().fg(theme.muted)));
+
+    f.render_widget(Paragraph::new(Line::from(spans)), area);
+}
+
+fn render_footer(f: &mut Frame, app: &mut App, area: Rect) {
+    if app.show_help {
+        render_help(f, &app.theme, &app.config.footer, area);
+    } else {
+        render_status_bar(f, app, area);
+    }
+}
+
+/// Host counts, ordering, search scope and the latest notice, with the key
+/// help one `?` away.
+fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
+    let theme = &app.theme;
+    let sep = Span::styled("  │  ", Style::default().fg(theme.border));
+    let label = |text: String| Span::styled(text, Style::default().fg(theme.muted));
+
+    let show_patterns = app.show_patterns.get();
+    let total = app
+        .hosts
+        .non_filtered_iter()
+        .filter(|host| show_patterns || !host.is_pattern)
+        .count();
+    let sort = if !app.search.value().trim().is_empty() {
+        t("best match").to_string()
+    } else if let Some((key, descending)) = app.header_sort {
+        format!("{}{}", key.name(), if descending { " ↓" } else { " ↑" })
+    } else if app.config.sort_keys.is_empty() {
+        t("config order").to_string()
+    } else {
+        app.config.sort_keys.iter().map(|key| key.name()).collect::<Vec<_>>().join(", ")
+    };
+    let scope = app.focus_field.get().map_or(t("all fields").to_string(), SearchField::name);
+
+    let mut items = vec![
+        Span::styled(
+            format!("{}/{total}", app.hosts.len()),
+            Style::default().fg(theme.accent).add_modifier(Modifier::BOLD),
+        ),
+        label(format!(" {}", t("hosts"))),
+        sep.clone(),
+        label(format!("{}: {sort}", t("sort"))),
+        sep.clone(),
+        label(format!("{}: {scope}", t("search"))),
+    ];
+    if let Some(group) = app.config.group_by {
+        use clap::ValueEnum;
+        let name = group.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+        items.extend([sep.clone(), label(format!("{}: {name}", t("group")))]);
+    }
+    if app.split_aliases {
+        items.extend([sep.clone(), label(t("one row per alias").to_string())]);
+    }
+    if show_patterns {
+        items.extend([sep.clone(), label(t("patterns shown").to_string())]);
+    }
+    if app.redact {
+        items.extend([sep.clone(), label(t("redacted").to_string())]);
+    }
+    if app.config.demo {
+        items.extend([sep.clone(), label(t("demo").to_string())]);
+    } else if edit::is_read_only() {
+        items.extend([sep.clone(), label(t("read-only").to_string())]);
+    }
+    let frames: &[&str] = if theme.ascii { &ASCII_SPINNER } else { &SPINNER };
+    let frame = frames[usize::try_from(app.started_at.elapsed().as_millis() / 100).unwrap_or_default() % frames.len()];
+    for source in app.background_sources.iter().filter(|source| source.hosts.is_none()) {
+        items.extend([sep.clone(), label(format!("{frame} {}", source.kind))]);
+    }
+    if let Some((message, _)) = &app.status_message {
+        items.extend([sep.clone(), Span::styled(message.clone(), Style::default().fg(theme.text))]);
+    }
+
+    let hint = match &app.config.footer.text {
+        Some(text) => Line::from(label(format!("{text} "))),
+        None => Line::from(vec![
+            Span::styled(" ? ", Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)),
+            label(format!("{} ", t("help"))),
+        ]),
+    };
+    let hint_width = u16::try_from(hint.width()).unwrap_or_default();
+    let [left, right] =
+        Layout::horizontal([Constraint::Min(0), Constraint::Length(hint_width)]).areas(area);
+
+    items.insert(0, Span::raw(" "));
+    f.render_widget(Paragraph::new(Line::from(items)), left);
+    f.render_widget(Paragraph::new(hint), right);
+}
+
+fn render_help(f: &mut Frame, theme: &Theme, footer: &Footer, area: Rect) {
+    let sep = Span::styled("  │  ", Style::default().fg(theme.border));
+
+    let chips = footer.chips();
+
+    let mut spans: Vec<Span> = Vec::new();
+    spans.push(Span::raw(" "));
+    for (i, (key, label)) in chips.enumerate() {
+        if i > 0 {
+            spans.push(sep.clone());
+        }
+        let key = if theme.ascii { text::spell_keys(key) } else { key.to_string() };
+        spans.push(Span::styled(
+            format!(" {key} "),
+            Style::default()
+                .fg(theme.accent)
+                .add_modifier(Modifier::BOLD),
+        ));
+        spans.push(Span::styled(
+            t(label),
+            Style::default().fg(theme.muted),
+        ));
+    }
+
+    let paragraph = Paragraph::new(Line::from(spans));
+    f.render_widget(paragraph, area);
+}
+
+/// One-line prompt for a name, e.g. to save the query as a filter.
+fn render_name_popup(f: &mut Frame, theme: &Theme, title: &str, input: &Input) {
+    let area = centered_rect(50, 3, f.area());
+    let block = popup_block(theme, &format!(" {title} "));
+
+    let line = Line::from(vec![
+        Span::styled(PROMPT, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)),
+        Span::styled(input.value(), Style::default().fg(theme.text)),
+    ]);
+
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(line).block(block), area);
+
+    let prompt_width = u16::try_from(UnicodeWidthStr::width(PROMPT)).unwrap_or(2);
+    f.set_cursor_position(Position::new(
+        area.x + 2 + prompt_width + u16::try_from(input.visual_cursor()).unwrap_or_default(),
+        area.y + 1,
+    ));
+}
+
+fn render_playground_popup(f: &mut Frame, theme: &Theme, playground: &Playground) {
+    let full = f.area();
+    let area = centered_rect(full.width * 9 / 10, full.height * 8 / 10, full);
+    let block = popup_block(theme, &format!(" {} ", t("Pattern playground")))
+        .title_bottom(hint_line(theme, " type a hostname  ↑↓ scroll  esc close  (Match blocks are not evaluated) "));
+
+    let prompt = Line::from(vec![
+        Span::styled(PROMPT, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)),
+        Span::styled(playground.name.value(), Style::default().fg(theme.text)),
+    ]);
+    let muted = |text: String| Line::from(Span::styled(text, Style::default().fg(theme.muted)));
+    let heading = |text: &str| {
+        Line::from(Span::styled(
+            text.to_string(),
+            Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
+        ))
+    };
+
+    let mut lines = Vec::new();
+    let name = playground.name.value().trim();
+    match &playground.configs {
+        Err(err) => lines.push(Line::from(Span::styled(err.clone(), Style::default().fg(theme.error)))),
+        Ok(_) if name.is_empty() => lines.push(muted(t("Type a hostname to see which blocks apply to it.").to_string())),
+        Ok(configs) => {
+            let resolution = resolve::resolve(configs, name);
+            lines.push(heading(t("Blocks")));
+            if resolution.blocks.is_empty() {
+                lines.push(muted(t("No Host block matches.").to_string()));
+            }
+            for applied in &resolution.blocks {
+                lines.push(Line::from(vec![
+                    Span::styled(applied.block.clone(), Style::default().fg(theme.text)),
+                    Span::styled(
+                        format!("  {} {}  {}", applied.pattern, t("matches"), applied.location.clone().unwrap_or_default()),
+                        Style::default().fg(theme.muted),
+                    ),
+                ]));
+            }
+            for applied in &resolution.negated {
+                lines.push(Line::from(vec![
+                    Span::styled(applied.block.clone(), Style::default().fg(theme.muted)),
+                    Span::styled(format!("  {} {}", t("excluded by"), applied.pattern), Style::default().fg(theme.error)),
+                ]));
+            }
+            if resolution.has_match {
+                lines.push(muted(t("A Match block applies; its conditions are not evaluated.").to_string()));
+            }
+
+            lines.extend([Line::from(""), heading(t("Effective options"))]);
+            let key_width = resolution.options.keys().map(String::len).max().unwrap_or(0) + 2;
+            for (keyword, (value, source)) in &resolution.options {
+                lines.push(Line::from(vec![
+                    Span::styled(
+                        pad_end(keyword, key_width),
+                        Style::default().fg(theme.text_dim).add_modifier(Modifier::BOLD),
+                    ),
+                    Span::styled(value.clone(), Style::default().fg(theme.text)),
+                    Span::styled(format!("  ← {}", source.describe()), Style::default().fg(theme.muted)),
+                ]));
+            }
+        }
+    }
+
+    let inner = block.inner(area);
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+    let [prompt_area, _, body] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Min(0),
+    ])
+    .areas(inner);
+    f.render_widget(Paragraph::new(prompt), prompt_area);
+    f.render_widget(Paragraph::new(lines).scroll((playground.scroll, 0)), body);
+
+    let prompt_width = u16::try_from(UnicodeWidthStr::width(PROMPT)).unwrap_or(2);
+    f.set_cursor_position(Position::new(
+        prompt_area.x + prompt_width + u16::try_from(playground.name.visual_cursor()).unwrap_or_default(),
+        prompt_area.y,
+    ));
+}
+
+fn render_bulk_edit_popup(f: &mut Frame, theme: &Theme, form: &BulkEditForm) {
+    let full = f.area();
+    let area = centered_rect(full.width * 9 / 10, full.height * 8 / 10, full);
+    let hint = match &form.preview {
+        Some(Ok(changes)) if !changes.is_empty() => " ↵ apply  ↑↓ scroll  esc cancel ",
+        _ => " ↵ preview  esc cancel ",
+    };
+    let block = popup_block(theme, &format!(" {} ", t("Set an option on the listed hosts")))
+        .title_bottom(hint_line(theme, hint));
+
+    let prompt = Line::from(vec![
+        Span::styled(PROMPT, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)),
+        Span::styled(form.option.value(), Style::default().fg(theme.text)),
+    ]);
+    let mut summary = format!("{} host(s) in {} file(s)", form.hosts, form.targets.len());
+    if form.skipped > 0 {
+        summary.push_str(&format!(", {} not from a config file skipped", form.skipped));
+    }
+    let muted = |text: String| Line::from(Span::styled(text, Style::default().fg(theme.muted)));
+
+    let mut lines = vec![muted(summary), Line::default()];
+    match &form.preview {
+        None => lines.push(muted(t("Type `Keyword value`, e.g. ServerAliveInterval 30, and press Enter to preview.").to_string())),
+        Some(Err(err)) => lines.push(Line::from(Span::styled(err.clone(), Style::default().fg(theme.error)))),
+        Some(Ok(changes)) if changes.is_empty() => {
+            lines.push(muted(t("Every listed host already has this value.").to_string()));
+        }
+        Some(Ok(changes)) => {
+            let value = form.option().map(|(_, value)| value).unwrap_or_default();
+            for change in changes {
+                let location = format!("{}:{}", change.path.display(), change.line);
+                let action = match &change.previous {
+                    Some(previous) => format!("  {previous} → {value}"),
+                    None => format!("  + {value}"),
+                };
+                lines.push(Line::from(vec![
+                    Span::styled(change.block.clone(), Style::default().fg(theme.text)),
+                    Span::styled(action, Style::default().fg(theme.accent)),
+                    Span::styled(format!("  {location}"), Style::default().fg(theme.muted)),
+                ]));
+            }
+        }
+    }
+
+    let inner = block.inner(area);
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+    let [prompt_area, _, body] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Min(0),
+    ])
+    .areas(inner);
+    f.render_widget(Paragraph::new(prompt), prompt_area);
+    f.render_widget(Paragraph::new(lines).scroll((form.scroll, 0)), body);
+
+    let prompt_width = u16::try_from(UnicodeWidthStr::width(PROMPT)).unwrap_or(2);
+    f.set_cursor_position(Position::new(
+        prompt_area.x + prompt_width + u16::try_from(form.option.visual_cursor()).unwrap_or_default(),
+        prompt_area.y,
+    ));
+}
+
+fn render_forwards_popup(f: &mut Frame, theme: &Theme, editor: &mut ForwardEditor) {
+    let height = u16::try_from(editor.forwards.len().clamp(1, 10)).unwrap_or(10) + 5;
+    let area = centered_rect(64, height, f.area());
+    let hint = if editor.input.is_some() {
+        " ↵ save  esc cancel "
+    } else {
+        " a add  ↵ edit  del remove  esc close "
+    };
+    let block = popup_block(theme, &format!(" {}: {} ", t("Local forwards"), editor.host))
+        .title_bottom(hint_line(theme, hint));
+
+    let inner = block.inner(area);
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+    let [list_area, input_area, error_area] = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(inner);
+
+    if editor.forwards.is_empty() {
+        f.render_widget(
+            Paragraph::new(Span::styled(
+                t("No LocalForward yet. Press a to add one."),
+                Style::default().fg(theme.muted),
+            )),
+            list_area,
+        );
+    } else {
+        let items: Vec<ListItem> = editor
+            .forwards
+            .iter()
+            .map(|forward| ListItem::new(Span::styled(forward.clone(), Style::default().fg(theme.text))))
+            .collect();
+        let list = List::new(items)
+            .highlight_style(theme.selection_style())
+            .highlight_symbol(SELECTION_MARKER);
+        f.render_stateful_widget(list, list_area, &mut editor.list);
+    }
+
+    if let Some((_, input)) = &editor.input {
+        f.render_widget(
+            Paragraph::new(Line::from(vec![
+                Span::styled(PROMPT, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)),
+                Span::styled(input.value().to_string(), Style::default().fg(theme.text)),
+            ])),
+            input_area,
+        );
+        let prompt_width = u16::try_from(UnicodeWidthStr::width(PROMPT)).unwrap_or(2);
+        f.set_cursor_position(Position::new(
+            input_area.x + prompt_width + u16::try_from(input.visual_cursor()).unwrap_or_default(),
+            input_area.y,
+        ));
+    }
+    if let Some(error) = &editor.error {
+        f.render_widget(
+            Paragraph::new(Span::styled(error.clone(), Style::default().fg(theme.error))),
+            error_area,
+        );
+    }
+}
+
+fn render_snippets_popup(f: &mut Frame, theme: &Theme, table: &mut ItemTable<Snippet>) {
+    // 見出し、検索行、枠の分だけ高くする
+    let height = u16::try_from(table.items.total_len().clamp(1, 12)).unwrap_or(12) + 5;
+    let area = centered_rect(72, height, f.area());
+    let block = popup_block(theme, &format!(" {} ", t("Run a snippet")))
+        .title_bottom(hint_line(theme, " type to filter  ↵ run  ^s save as macro  esc close "));
+
+    f.render_widget(Clear, area);
+    render_item_table(f, theme, table, block, area);
+}
+
+fn render_macros_popup(f: &mut Frame, theme: &Theme, table: &mut ItemTable<Macro>) {
+    let height = u16::try_from(table.items.total_len().clamp(1, 12)).unwrap_or(12) + 5;
+    let area = centered_rect(80, height, f.area());
+    let block = popup_block(theme, &format!(" {} ", t("Replay a macro")))
+        .title_bottom(hint_line(theme, " type to filter  ↵ run  del remove  esc close "));
+
+    f.render_widget(Clear, area);
+    render_item_table(f, theme, table, block, area);
+}
+
+fn render_tunnels_popup(f: &mut Frame, theme: &Theme, tunnels: &[Tunnel], list: &mut ListState, ticked: &[bool]) {
+    let height = u16::try_from(tunnels.len().clamp(1, 12)).unwrap_or(12) + 2;
+    let area = centered_rect(64, height, f.area());
+    let block = popup_block(theme, &format!(" {} ", t("Connect with tunnels")))
+        .title_bottom(hint_line(theme, " space pick  ↵ connect  esc close "));
+
+    let name_width = tunnels.iter().map(|tunnel| tunnel.name.width()).max().unwrap_or(0);
+    let items: Vec<ListItem> = tunnels
+        .iter()
+        .zip(ticked)
+        .map(|(tunnel, &ticked)| {
+            ListItem::new(Line::from(vec![
+                Span::styled(if ticked { "[x] " } else { "[ ] " }, Style::default().fg(theme.accent)),
+                Span::styled(pad_end(&tunnel.name, name_width + 2), Style::default().fg(theme.text)),
+                Span::styled(tunnel.forwards.join("  "), Style::default().fg(theme.text_dim)),
+            ]))
+        })
+        .collect();
+
+    f.render_widget(Clear, area);
+    let list_widget = List::new(items)
+        .block(block)
+        .highlight_style(theme.selection_style())
+        .highlight_symbol(SELECTION_MARKER);
+    f.render_stateful_widget(list_widget, area, list);
+}
+
+fn render_save_host_popup(f: &mut Frame, theme: &Theme, form: &SaveHostForm) {
+    let block_text = form.block().render().unwrap_or_else(|err| err.to_string());
+    let has_templates = !form.templates.is_empty();
+    let fields = form.placeholders.len() + usize::from(has_templates);
+    let height = u16::try_from(block_text.lines().count() + fields).unwrap_or(4) + 7;
+    let area = centered_rect(64, height, f.area());
+    let hint = if has_templates {
+        " ↵ save  tab file  ^t template  ↑↓ field  esc skip "
+    } else {
+        " ↵ save  tab file  esc skip "
+    };
+    let block = popup_block(theme, &format!(" {} ", t("Save ad-hoc host")))
+        .title_bottom(hint_line(theme, hint));
+
+    let label_width = form
+        .placeholders
+        .iter()
+        .map(|(name, _)| name.width() + 1)
+        .max()
+        .unwrap_or(0)
+        .max(9);
+    let label = |text: &str| {
+        Span::styled(
+            pad_end(text, label_width),
+            Style::default().fg(theme.muted).add_modifier(Modifier::BOLD),
+        )
+    };
+    let input = |input: &Input| {
+        vec![
+            Span::styled(PROMPT, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD)),
+            Span::styled(input.value().to_string(), Style::default().fg(theme.text)),
+        ]
+    };
+    let file = form.files[form.file_index].to_string_lossy().into_owned();
+
+    let mut lines = vec![Line::from([vec![label("Host")], input(&form.name)].concat())];
+    for (name, value) in &form.placeholders {
+        lines.push(Line::from([vec![label(name)], input(value)].concat()));
+    }
+    lines.push(Line::from(vec![
+        label(t("File")),
+        Span::styled(file, Style::default().fg(theme.text_dim)),
+    ]));
+    if has_templates {
+        let template = form.selected_template().map_or(t("(none)"), |template| template.name.as_str());
+        lines.push(Line::from(vec![
+            label(t("Template")),
+            Span::styled(template.to_string(), Style::default().fg(theme.text_dim)),
+        ]));
+    }
+    lines.push(Line::default());
+    lines.extend(
+        block_text
+            .lines()
+            .map(|line| Line::from(Span::styled(line.to_string(), Style::default().fg(theme.text)))),
+    );
+    if let Some(error) = &form.error {
+        lines.push(Line::default());
+        lines.push(Line::from(Span::styled(error.as_str(), Style::default().fg(theme.error))));
+    }
+
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(lines).block(block), area);
+
+    let prompt_width = u16::try_from(UnicodeWidthStr::width(PROMPT)).unwrap_or(2);
+    let focused = match form.focus.checked_sub(1) {
+        Some(i) => &form.placeholders[i].1,
+        None => &form.name,
+    };
+    f.set_cursor_position(Position::new(
+        area.x
+            + 2
+            + u16::try_from(label_width).unwrap_or(9)
+            + prompt_width
+            + u16::try_from(focused.visual_cursor()).unwrap_or_default(),
+        area.y + 1 + u16::try_from(form.focus).unwrap_or_default(),
+    ));
+}
+
+fn render_filters_popup(f: &mut Frame, theme: &Theme, filters: &SavedFilters, state: &mut ListState) {
+    let height = u16::try_from(filters.len().clamp(1, 12)).unwrap_or(12) + 3;
+    let area = centered_rect(60, height, f.area());
+    let block = popup_block(theme, &format!(" {} ", t("Saved filters")))
+        .title_bottom(hint_line(theme, " ↵ apply  del remove  esc close "));
+
+    f.render_widget(Clear, area);
+    if filters.is_empty() {
+        let hint = Paragraph::new(Span::styled(
+            t("No saved filters. Press Ctrl+S to save the current query."),
+            Style::default().fg(theme.muted),
+        ))
+        .wrap(Wrap { trim: true })
+        .block(block);
+        f.render_widget(hint, area);
+        return;
+    }
+
+    let name_width = filters.iter().map(|(name, _)| name.width()).max().unwrap_or(0);
+    let items: Vec<ListItem> = filters
+        .iter()
+        .map(|(name, query)| {
+            ListItem::new(Line::from(vec![
+                Span::styled(pad_end(name, name_width + 2), Style::default().fg(theme.text)),
+                Span::styled(query.to_string(), Style::default().fg(theme.text_dim)),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(theme.selection_style())
+        .highlight_symbol(SELECTION_MARKER);
+    f.render_stateful_widget(list, area, state);
+}
+
+/// 「explain」ビュー: 各設定値と、それがどのブロック (Host * など) から
+/// 継承されたかを一覧表示する
+fn render_explain_popup(
+    f: &mut Frame,
+    theme: &Theme,
+    host: &ssh::Host,
+    scroll: u16,
+    verification: Option<&Verification>,
+) {
+    let full = f.area();
+    let area = centered_rect(full.width * 9 / 10, full.height * 8 / 10, full);
+    let title = format!(" {}: {} ", t("Effective settings"), host.name);
+    let block = popup_block(theme, &title)
+        .title_bottom(hint_line(theme, " ↑↓ scroll  v check with ssh -G  esc close  (Match blocks are not evaluated) "));
+
+    let key_width = host.options.keys().map(|k| k.width()).max().unwrap_or(0) + 2;
+    let value_width = host
+        .options
+        .values()
+        .map(|v| v.width())
+        .max()
+        .unwrap_or(0)
+        .min(40)
+        + 2;
+
+    let mismatches = match verification {
+        Some(Ok(mismatches)) => mismatches.as_slice(),
+        _ => &[],
+    };
+
+    let mut lines: Vec<Line> = host
+        .options
+        .iter()
+        .map(|(keyword, value)| {
+            let origin = match host.option_sources.get(keyword) {
+                Some(source) if source.inherited => Span::styled(
+                    format!("← {}", source.describe()),
+                    Style::default().fg(theme.accent),
+                ),
+                Some(source) => {
+                    let location = source.location.as_ref().map(|l| format!(" ({l})"));
+                    Span::styled(
+                        format!("{}{}", t("own block"), location.unwrap_or_default()),
+                        Style::default().fg(theme.muted),
+                    )
+                }
+                None => Span::styled(t("own block"), Style::default().fg(theme.muted)),
+            };
+            let mut spans = vec![
+                Span::styled(
+                    pad_end(keyword, key_width),
+                    Style::default().fg(theme.text_dim).add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(
+                    pad_end(&truncate_middle(value, value_width - 2), value_width),
+                    Style::default().fg(theme.text),
+                ),
+                origin,
+            ];
+            if let Some(mismatch) = mismatches.iter().find(|m| &m.keyword == keyword) {
+                spans.push(Span::styled(
+                    format!("  ≠ ssh -G: {}", mismatch.ssh_display()),
+                    Style::default().fg(theme.error),
+                ));
+            }
+            Line::from(spans)
+        })
+        .collect();
+
+    let summary = match verification {
+        None => None,
+        Some(Ok(mismatches)) if mismatches.is_empty() => Some(Span::styled(
+            t("ssh -G agrees on every value"),
+            Style::default().fg(theme.accent),
+        )),
+        Some(Ok(mismatches)) => Some(Span::styled(
+            format!("{} value(s) differ from ssh -G", mismatches.len()),
+            Style::default().fg(theme.error),
+        )),
+        Some(Err(err)) => Some(Span::styled(err.clone(), Style::default().fg(theme.error))),
+    };
+    if let Some(summary) = summary {
+        lines.extend([Line::from(""), Line::from(summary)]);
+    }
+
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(lines).scroll((scroll, 0)).block(block), area);
+}
+
+fn render_variables_popup(f: &mut Frame, theme: &Theme, host: &ssh::Host, scroll: u16) {
+    let full = f.area();
+    let area = centered_rect(full.width * 9 / 10, full.height * 8 / 10, full);
+    let title = format!(" {}: {} ", t("Template variables"), host.name);
+    let block = popup_block(theme, &title)
+        .title_bottom(hint_line(theme, " ↑↓ scroll  esc close "));
+
+    let variables = template_variable_rows(host);
+    let name_width = variables
+        .iter()
+        .map(|(name, _)| name.width())
+        .chain(SGH_HELPERS.iter().map(|(usage, _)| usage.width()))
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let value_width = usize::from(area.width).saturating_sub(name_width + 4).max(10);
+    let heading = |text: &str| {
+        Line::from(Span::styled(
+            text.to_string(),
+            Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
+        ))
+    };
+    let row = |name: &str, value: &str, style: Style| -> Line<'static> {
+        Line::from(vec![
+            Span::styled(
+                pad_end(name, name_width),
+                Style::default().fg(theme.text_dim).add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(truncate_end(value, value_width).into_owned(), style),
+        ])
+    };
+
+    let mut lines = vec![heading(t("Variables"))];
+    for (name, value) in &variables {
+        let style = if value.starts_with('(') {
+            Style::default().fg(theme.muted)
+        } else {
+            Style::default().fg(theme.text)
+        };
+        lines.push(row(name, value, style));
+    }
+    lines.extend([Line::from(""), heading(t("Helpers"))]);
+    for (usage, description) in SGH_HELPERS {
+        lines.push(row(usage, description, Style::default().fg(theme.text)));
+    }
+    let builtins: Vec<&str> = template::HELPERS
+        .iter()
+        .copied()
+        .filter(|name| !SGH_HELPERS.iter().any(|(usage, _)| usage.starts_with(&format!("{{{{{name} "))))
+        .collect();
+    lines.push(row(t("built-in"), &builtins.join(", "), Style::default().fg(theme.text)));
+    lines.push(Line::from(Span::styled(
+        t("Use {{{var}}} (three braces) to insert a value without HTML escaping."),
+        Style::default().fg(theme.muted),
+    )));
+
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(lines).scroll((scroll, 0)).block(block), area);
+}
+
+/// `{{name}}`-style references with the value they would render to for
+/// `host`. Maps (`options`, `env`) are listed one entry per row.
+fn template_variable_rows(host: &ssh::Host) -> Vec<(String, String)> {
+    let mut rows = Vec::new();
+    let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(host) else {
+        return rows;
+    };
+
+    for (name, value) in fields {
+        match value {
+            serde_json::Value::Object(entries) if !entries.is_empty() => {
+                for (key, value) in entries {
+                    rows.push((format!("{{{{{name}.{key}}}}}"), json_display(&value)));
+                }
+            }
+            value => rows.push((format!("{{{{{name}}}}}"), json_display(&value))),
+        }
+    }
+    if host.otp.is_none() {
+        rows.push(("{{otp}}".to_string(), t("(fetched when connecting)").to_string()));
+    }
+    rows
+}
+
+fn json_display(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => t("(not set)").to_string(),
+        serde_json::Value::String(text) if text.is_empty() => t("(empty)").to_string(),
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Array(items) if items.is_empty() => t("(none)").to_string(),
+        serde_json::Value::Object(entries) if entries.is_empty() => "(none)".to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Key hint shown at the bottom of a popup.
+fn hint_line(theme: &Theme, hint: &'static str) -> Line<'static> {
+    let hint = t(hint);
+    let hint = if theme.ascii { Cow::Owned(text::spell_keys(hint)) } else { Cow::Borrowed(hint) };
+    Line::from(Span::styled(hint, Style::default().fg(theme.muted)))
+}
+
+/// `--ascii`: swaps the box-drawing and symbol glyphs left on screen for
+/// ASCII, after everything is drawn.
+fn to_ascii(buffer: &mut Buffer) {
+    for cell in &mut buffer.content {
+        if let Some(ascii) = text::ascii_symbol(cell.symbol()) {
+            cell.set_symbol(ascii);
+        }
+    }
+}
+
+fn popup_block(theme: &Theme, title: &str) -> Block<'static> {
+    Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(theme.border_focused_style())
+        .title(Line::from(Span::styled(
+            title.to_string(),
+            Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
+        )))
+        .padding(Padding::horizontal(1))
+}
+
+fn render_error_popup(f: &mut Frame, app: &App) {
+    let theme = &app.theme;
+    let Some(message) = app.error_popup.as_deref() else {
+        return;
+    };
+
+    let mut lines: Vec<Line> = message
+        .lines()
+        .map(|l| Line::from(Span::styled(l.to_string(), Style::default().fg(theme.text))))
+        .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "press any key to dismiss",
+        Style::default().fg(theme.muted),
+    )));
+
+    let height = u16::try_from(lines.len()).unwrap_or(u16::MAX).saturating_add(2);
+    let area = centered_rect(60, height, f.area());
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(theme.error))
+        .title(Line::from(Span::styled(
+            " Error ",
+            Style::default().fg(theme.error).add_modifier(Modifier::BOLD),
+        )))
+        .padding(Padding::horizontal(1));
+
+    f.render_widget(Clear, area);
+    f.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(block),
+        area,
+    );
+}
+
+/// 画面中央に width x height の領域を確保する (画面より大きければ切り詰める)
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use std::path::Path;
+
+    pub(crate) fn test_config() -> AppConfig {
+        AppConfig {
+            config_paths: vec![],
+            strict_missing: false,
+            pipeline: ssh::Pipeline::default(),
+            sources: vec![],
+            source_cache: CachePolicy {
+                ttl: crate::source::DEFAULT_CACHE_TTL,
+                offline: false,
+            },
+            source_priority: SourcePriority::default(),
+            source_defaults: BTreeMap::new(),
+            exclude: vec![],
+            search_filter: None,
+            from_clipboard: false,
+            search_fields: vec![SearchField::Name, SearchField::Aliases, SearchField::Destination],
+            sort_keys: vec![],
+            natural_sort: true,
+            group_by: None,
+            color_rules: vec![],
+            theme: ThemeName::Dark,
+            ascii: false,
+            footer: Footer::default(),
+            esc: EscAction::Quit,
+            icons: IconMode::Off,
+            icon_rules: vec![],
+            frequency_weight: 0.0,
+            show_proxy_command: false,
+            show_source: false,
+            fit_columns: false,
+            show_patterns: false,
+            split_aliases: false,
+            redact: false,
+            local_user: Some("me".to_string()),
+            command_template: ssh::DEFAULT_COMMAND_TEMPLATE.to_string(),
+            shell: false,
+            command_template_on_session_start: None,
+            command_template_on_session_end: None,
+            otp_command: None,
+            exit_after_ssh_session_ends: false,
+            exec: false,
+            schedule: None,
+            schedule_emit: None,
+            // ステータスバーの read-only 表示が他のテストの状態に左右されないように
+            demo: true,
+            notify_after: None,
+            idle_timeout: None,
+            lock_after: None,
+            lock_passphrase: None,
+            title_template: None,
+            launcher: Launcher::Inline,
+            launcher_template: None,
+            control_socket: None,
+            daemon_socket: None,
+            host_templates: vec![],
+            tunnels: vec![],
+            socks_port: session::DEFAULT_SOCKS_PORT,
+            snippets: vec![],
+            snippet_template: crate::snippet::DEFAULT_SNIPPET_TEMPLATE.to_string(),
+        }
+    }
+
+    fn demo_app() -> App {
+        let config = test_config();
+        App::with_hosts(&config, demo::hosts(config.pipeline).unwrap())
+    }
+
+    /// The screen as plain text, one line per row with trailing spaces
+    /// removed.
+    fn render(app: &mut App, width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| ui(f, app)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| {
+                let line: String = (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect();
+                format!("{}\n", line.trim_end())
+            })
+            .collect()
+    }
+
+    /// Compares `actual` with `src/snapshots/<name>.txt`. Run the tests with
+    /// `UPDATE_SNAPSHOTS=1` to (re)write the file after an intended change.
+    fn assert_snapshot(name: &str, actual: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/snapshots")
+            .join(format!("{name}.txt"));
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+            return;
+        }
+
+        // 無い場合に書いて通すと、消えたスナップショットに気付けない
+        let Ok(expected) = std::fs::read_to_string(&path) else {
+            panic!("snapshot {name} is missing (UPDATE_SNAPSHOTS=1 to write it)\n--- actual\n{actual}");
+        };
+        assert!(
+            expected == actual,
+            "snapshot {name} changed (UPDATE_SNAPSHOTS=1 to accept)\n--- expected\n{expected}--- actual\n{actual}"
+        );
+    }
+
+    fn typed(text: &str) -> Vec<KeyEvent> {
+        text.chars().map(|c| KeyEvent::from(KeyCode::Char(c))).collect()
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    #[test]
+    fn esc_can_clear_the_query_or_ask_for_a_second_press() {
+        let config = AppConfig {
+            esc: EscAction::Clear,
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        app.feed(typed("web"));
+        assert!(matches!(app.feed([KeyCode::Esc.into()]), Outcome::Running));
+        assert_eq!(app.search.value(), "");
+        assert!(matches!(app.feed([KeyCode::Esc.into()]), Outcome::Quit));
+
+        let config = AppConfig {
+            esc: EscAction::Double,
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        app.feed(typed("web"));
+        assert!(matches!(app.feed([KeyCode::Esc.into()]), Outcome::Running));
+        assert_eq!(app.search.value(), "web");
+        assert!(matches!(app.feed([KeyCode::Esc.into()]), Outcome::Quit));
+    }
+
+    #[test]
+    fn returning_from_a_session_keeps_the_list_as_it_was() {
+        let mut app = demo_app();
+        app.feed(typed("web"));
+        app.table_state = TableState::default().with_selected(1).with_offset(1);
+        let point = app.return_point();
+        let host = app.selected_host().unwrap().name.clone();
+
+        // セッション中に検索や並びが変わっても元に戻る
+        app.set_search(String::new());
+        app.restore_return_point(point);
+        assert_eq!(app.search.value(), "web");
+        assert_eq!(app.selected_host().unwrap().name, host);
+        assert_eq!(app.table_state.offset(), 1);
+
+        // 選択を移すと、直前に接続したホストの行に印が残る
+        app.last_connected = Some(host.clone());
+        app.table_state.select(Some(0));
+        let screen = render(&mut app, 100, 30);
+        assert!(screen.lines().any(|line| line.trim_start().starts_with('↩') && line.contains(&host)));
+    }
+
+    #[test]
+    fn shift_enter_and_alt_digits_are_told_apart() {
+        let mut app = demo_app();
+        render(&mut app, 100, 30);
+
+        assert!(matches!(app.handle_key(KeyEvent::new(KeyCode::Char('3'), KeyModifiers::ALT)), AppKeyAction::Ok));
+        assert_eq!(app.table_state.selected(), Some(2));
+        assert_eq!(app.search.value(), "");
+
+        let action = app.handle_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::SHIFT));
+        assert!(matches!(action, AppKeyAction::ConnectIn(host, Launcher::Tmux) if host.name == "web1"));
+    }
+
+    #[test]
+    fn typing_a_query_then_enter_connects_to_the_selected_host() {
+        let mut app = demo_app();
+        let keys = [typed("db"), vec![KeyCode::Down.into(), KeyCode::Enter.into()]].concat();
+
+        let Outcome::Connect(host) = app.feed(keys) else {
+            panic!("Enter should connect");
+        };
+        assert_eq!(host.name, "db");
+        assert_eq!(app.search.value(), "db");
+    }
+
+    #[test]
+    fn backspace_edits_the_query() {
+        let mut app = demo_app();
+        let keys = [typed("nasx"), vec![KeyCode::Backspace.into()]].concat();
+
+        assert!(matches!(app.feed(keys), Outcome::Running));
+        assert_eq!(app.hosts.len(), 1);
+        assert_eq!(app.selected_host().unwrap().name, "nas");
+    }
+
+    #[test]
+    fn overlays_take_keys_until_closed() {
+        let mut app = demo_app();
+        let keys = [ctrl('o'), KeyCode::Esc.into(), KeyCode::Down.into()];
+
+        assert!(matches!(app.feed(keys), Outcome::Running));
+        assert!(app.overlay.is_none());
+        assert_eq!(app.table_state.selected(), Some(1));
+        assert!(matches!(app.feed([KeyCode::Esc.into()]), Outcome::Quit));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn control_calls_filter_select_and_connect() {
+        use serde_json::json;
+
+        let mut app = demo_app();
+        let mut connect = None;
+        let mut call = |app: &mut App, method: &str, params: serde_json::Value| {
+            app.on_control_call(method, &params, &mut connect)
+        };
+
+        let state = call(&mut app, "set_filter", json!({ "query": "tag:home" })).unwrap();
+        assert_eq!(state, json!({ "query": "tag:home", "selected": "raspberry", "matches": 2 }));
+        let hosts = call(&mut app, "hosts", json!({})).unwrap();
+        assert_eq!(hosts.as_array().unwrap().len(), 2);
+        assert_eq!(hosts[1]["port"], "2200");
+
+        call(&mut app, "select", json!({ "name": "nas" })).unwrap();
+        assert_eq!(call(&mut app, "connect", json!({})).unwrap(), json!({ "host": "nas" }));
+        assert!(call(&mut app, "select", json!({ "name": "bastion" })).is_err());
+        assert_eq!(call(&mut app, "reboot", json!({})).unwrap_err().code, -32601);
+        assert_eq!(connect.map(|host| host.name).as_deref(), Some("nas"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn control_calls_are_refused_while_locked() {
+        use serde_json::json;
+
+        let mut app = demo_app();
+        app.feed([KeyEvent::new(KeyCode::Char('l'), KeyModifiers::ALT)]);
+        let mut connect = None;
+        for method in ["state", "set_filter", "select", "connect"] {
+            let params = json!({ "query": "nas", "name": "nas" });
+            let err = app.on_control_call(method, &params, &mut connect).unwrap_err();
+            assert_eq!(err.message, "screen is locked");
+        }
+        assert!(connect.is_none());
+        assert_eq!(app.search.value(), "");
+    }
+
+    #[test]
+    fn snapshot_host_list() {
+        let mut app = demo_app();
+        assert_snapshot("host_list", &render(&mut app, 100, 30));
+    }
+
+    #[test]
+    fn snapshot_search_with_query() {
+        let mut app = demo_app();
+        app.set_search("tag:prod db".to_string());
+        assert_snapshot("search_with_query", &render(&mut app, 100, 30));
+    }
+
+    #[test]
+    fn snapshot_grouped_by_tag() {
+        let config = AppConfig {
+            group_by: Some(GroupKey::Tag),
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        app.next();
+        app.next();
+        assert_snapshot("grouped_by_tag", &render(&mut app, 100, 30));
+    }
+
+    #[test]
+    fn snapshot_ascii_icons() {
+        let config = AppConfig {
+            icons: IconMode::Ascii,
+            icon_rules: IconRule::parse_all(&[icon::IconRuleConfig {
+                matches: "name:raspberry".to_string(),
+                icon: "raspberry".to_string(),
+            }])
+            .unwrap(),
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        assert_snapshot("ascii_icons", &render(&mut app, 100, 30));
+    }
+
+    #[test]
+    fn snapshot_ascii_mode() {
+        let config = AppConfig {
+            ascii: true,
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        app.feed(typed("?"));
+        app.feed([ctrl('v')]);
+        let screen = render(&mut app, 100, 30);
+        assert!(screen.is_ascii());
+        assert_snapshot("ascii_mode", &screen);
+    }
+
+    #[test]
+    fn hidden_footer_returns_for_help_only() {
+        let config = AppConfig {
+            footer: Footer {
+                hidden: true,
+                keys: vec!["connect".to_string(), "quit".to_string()],
+                text: None,
+            },
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        let screen = render(&mut app, 100, 30);
+        assert!(!screen.contains("config order"));
+
+        app.feed(typed("?"));
+        let help = render(&mut app, 100, 30);
+        let footer = help.lines().last().unwrap();
+        assert!(footer.contains("connect") && footer.contains("quit"));
+        assert!(!footer.contains("explain"));
+    }
+
+    fn click(app: &mut App, kind: MouseEventKind, column: u16, row: u16) -> AppKeyAction {
+        app.handle_mouse(MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        })
+    }
+
+    #[test]
+    fn mouse_hovers_selects_and_connects_on_double_click() {
+        let mut app = demo_app();
+        render(&mut app, 100, 30);
+        // 見出しは 3 行目、ホストの行は 5 行目から
+        click(&mut app, MouseEventKind::Moved, 10, 7);
+        assert_eq!(app.hovered, Some(2));
+
+        let left = MouseEventKind::Down(MouseButton::Left);
+        assert!(matches!(click(&mut app, left, 10, 6), AppKeyAction::Ok));
+        assert_eq!(app.selected_host().unwrap().name, "web-01.corp.example.com");
+        assert!(matches!(click(&mut app, left, 10, 6), AppKeyAction::Connect(host) if host.name == "web-01.corp.example.com"));
+
+        click(&mut app, MouseEventKind::ScrollDown, 10, 6);
+        assert_eq!(app.table_state.selected(), Some(2));
+    }
+
+    #[test]
+    fn header_clicks_cycle_the_column_sort() {
+        let mut app = demo_app();
+        render(&mut app, 100, 30);
+        let left = MouseEventKind::Down(MouseButton::Left);
+        let first = |app: &App| app.hosts.iter().next().unwrap().name.clone();
+
+        click(&mut app, left, 6, 3);
+        assert_eq!(app.header_sort, Some((SortKey::Name, false)));
+        assert_eq!(first(&app), "bastion");
+        assert!(render(&mut app, 100, 30).contains("NAME ↑"));
+
+        click(&mut app, left, 6, 3);
+        assert_eq!(first(&app), "web2");
+        click(&mut app, left, 6, 3);
+        assert_eq!(app.header_sort, None);
+        assert_eq!(first(&app), "bastion");
+    }
+
+    #[test]
+    fn snapshot_explain_popup() {
+        let mut app = demo_app();
+        app.next();
+        app.overlay = Some(Overlay::Explain(0, None));
+        assert_snapshot("explain_popup", &render(&mut app, 100, 30));
+    }
+
+    #[test]
+    fn snapshot_variables_popup() {
+        let mut app = demo_app();
+        app.feed([ctrl('v')]);
+        assert_snapshot("variables_popup", &render(&mut app, 100, 30));
+    }
+
+    #[test]
+    fn snapshot_pattern_playground() {
+        let mut app = demo_app();
+        app.feed([ctrl('y')]);
+        app.feed([ctrl('u')]);
+        app.feed(typed("DB-primary.corp.example.com"));
+        assert_snapshot("pattern_playground", &render(&mut app, 100, 30));
+    }
+
+    #[test]
+    fn snapshot_save_host_with_template() {
+        let mut app = demo_app();
+        let template = HostTemplate {
+            name: "behind bastion".to_string(),
+            entries: vec!["ProxyJump {{bastion}}".to_string(), "User {{user}}".to_string()],
+            tags: vec!["prod".to_string()],
+        };
+        app.overlay = Some(Overlay::SaveHost(Box::new(SaveHostForm::new(
+            ssh::Host::adhoc("alice@192.0.2.50").unwrap(),
+            vec![PathBuf::from("/home/demo/.ssh/config")],
+            vec![template],
+        ))));
+        app.feed([ctrl('t'), KeyEvent::from(KeyCode::Down)]);
+        app.feed(typed("bastion"));
+
+        let Some(Overlay::SaveHost(form)) = &app.overlay else {
+            panic!("the save host prompt was closed");
+        };
+        assert_eq!(
+            form.block().render().unwrap(),
+            "Host 192.0.2.50\n  HostName 192.0.2.50\n  User alice\n  ProxyJump bastion\n  # sgh:tags prod\n"
+        );
+        assert_snapshot("save_host_with_template", &render(&mut app, 100, 30));
+    }
+
+    #[test]
+    fn bulk_edit_sets_the_option_on_the_listed_hosts() {
+        let path = std::env::temp_dir().join(format!("sgh-bulk-edit-{}", std::process::id()));
+        std::fs::write(&path, "Host web-1\n  HostName 10.0.0.1\n\nHost web-2\n  ServerAliveInterval 60\n\nHost db\n  Port 2222\n").unwrap();
+        let config = AppConfig {
+            config_paths: vec![path.to_string_lossy().into_owned()],
+            demo: false,
+            ..test_config()
+        };
+        let mut app = App::new(&config).unwrap();
+
+        let alt_e = KeyEvent::new(KeyCode::Char('e'), KeyModifiers::ALT);
+        app.feed(typed("web"));
+        app.feed([alt_e]);
+        app.feed(typed("ServerAliveInterval 30"));
+        app.feed([KeyEvent::from(KeyCode::Enter)]);
+        let Some(Overlay::BulkEdit(form)) = &app.overlay else {
+            panic!("the bulk edit prompt was closed");
+        };
+        assert_eq!(form.preview.as_ref().unwrap().as_ref().unwrap().len(), 2);
+
+        app.feed([KeyEvent::from(KeyCode::Enter)]);
+        assert!(app.overlay.is_none());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "Host web-1\n  HostName 10.0.0.1\n  ServerAliveInterval 30\n\nHost web-2\n  ServerAliveInterval 30\n\nHost db\n  Port 2222\n"
+        );
+
+        app.feed([KeyEvent::new(KeyCode::Char('u'), KeyModifiers::ALT)]);
+        assert!(std::fs::read_to_string(&path).unwrap().contains("ServerAliveInterval 60"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn without_hosts_the_onboarding_view_explains_where_sgh_looked() {
+        let dir = std::env::temp_dir().join(format!("sgh-onboarding-{}", std::process::id()));
+        let path = dir.join("config");
+        let config = AppConfig {
+            config_paths: vec![path.to_string_lossy().into_owned()],
+            demo: false,
+            ..test_config()
+        };
+        let mut app = App::new(&config).unwrap();
+        let screen = render(&mut app, 100, 30);
+        assert!(screen.contains("No hosts yet"), "{screen}");
+        assert!(screen.contains("missing"), "{screen}");
+        assert!(screen.contains("alt+c"), "{screen}");
+
+        app.feed([KeyEvent::new(KeyCode::Char('c'), KeyModifiers::ALT)]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), NEW_CONFIG_TEMPLATE);
+        let screen = render(&mut app, 100, 30);
+        assert!(screen.contains("no Host blocks"), "{screen}");
+        assert!(!screen.contains("alt+c"), "{screen}");
+
+        // 入力を始めたら臨時ホストの行を見せるため案内は引っ込める
+        app.feed(typed("admin@192.0.2.10"));
+        assert!(!render(&mut app, 100, 30).contains("No hosts yet"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn forwards_are_edited_in_the_config_file() {
+        let path = std::env::temp_dir().join(format!("sgh-forwards-{}", std::process::id()));
+        std::fs::write(&path, "Host db\n  HostName db.lan\n  LocalForward 8080 localhost:80\n").unwrap();
+        let config = AppConfig {
+            config_paths: vec![path.to_string_lossy().into_owned()],
+            demo: false,
+            ..test_config()
+        };
+        let mut app = App::new(&config).unwrap();
+        let key = |code: KeyCode| KeyEvent::from(code);
+
+        app.feed([KeyEvent::new(KeyCode::Char('f'), KeyModifiers::ALT), key(KeyCode::Enter)]);
+        app.feed([key(KeyCode::Backspace), key(KeyCode::Backspace)]);
+        app.feed(typed("8080"));
+        app.feed([key(KeyCode::Enter)]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "Host db\n  HostName db.lan\n  LocalForward 8080 localhost:8080\n"
+        );
+
+        // 不正な値は書き込まず、エラーを出して入力を続けさせる
+        app.feed([key(KeyCode::Char('a'))]);
+        let Some(Overlay::Forwards(editor)) = &mut app.overlay else {
+            panic!("the forward editor was closed");
+        };
+        assert!(editor.input.as_ref().is_some_and(|(_, input)| input.value().ends_with(" localhost:")));
+        editor.input = Some((None, Input::from("5432:db")));
+        app.feed([key(KeyCode::Enter)]);
+        let Some(Overlay::Forwards(editor)) = &mut app.overlay else {
+            panic!("the forward editor was closed");
+        };
+        assert!(editor.error.is_some());
+        editor.input = Some((None, Input::from("5432:localhost:5432")));
+        app.feed([key(KeyCode::Enter), key(KeyCode::Char('d'))]);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "Host db\n  HostName db.lan\n  LocalForward 5432 localhost:5432\n"
+        );
+        assert_eq!(app.merged_hosts[0].local_forwards.len(), 1);
 
-    Ok(())
-}
+        app.feed([key(KeyCode::Esc), KeyEvent::new(KeyCode::Char('u'), KeyModifiers::ALT)]);
+        assert!(std::fs::read_to_string(&path).unwrap().contains("LocalForward 8080 localhost:8080"));
+        std::fs::remove_file(path).unwrap();
+    }
 
-/// メインの描画関数
-fn ui(f: &mut Frame, app: &mut App) {
-    let layout_main = Layout::vertical([
-        Constraint::Length(3),  // search bar (single line + borders)
-        Constraint::Min(6),     // host table (fills available space)
-        Constraint::Length(8),  // detail panel
-        Constraint::Length(1),  // footer (single-line, no border)
-    ])
-    .split(f.area());
+    #[test]
+    fn tunnel_presets_add_forwards_to_the_connection() {
+        let tunnel = |name: &str, forward: &str| Tunnel {
+            name: name.to_string(),
+            forwards: vec![forward.to_string()],
+        };
+        let config = AppConfig {
+            tunnels: vec![tunnel("db", "5432:localhost:5432"), tunnel("web", "8080:localhost:80")],
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        let alt_t = KeyEvent::new(KeyCode::Char('t'), KeyModifiers::ALT);
 
-    render_searchbar(f, app, layout_main[0]);
-    render_table(f, app, layout_main[1]);
-    render_detail_panel(f, app, layout_main[2]);
-    render_footer(f, app, layout_main[3]);
+        // 何も選ばなければカーソル位置のプリセットで接続する
+        let Outcome::Connect(host) = app.feed([alt_t, KeyCode::Down.into(), KeyCode::Enter.into()]) else {
+            panic!("Enter did not connect");
+        };
+        assert_eq!(host.tunnels, ["8080:localhost:80"]);
+        assert_eq!(app.command_template(&host).unwrap(), "ssh -L 8080:localhost:80 \"{{{name}}}\"");
 
-    // Place cursor inside the search bar (1 line border + PROMPT width).
-    let prompt_width = u16::try_from(UnicodeWidthStr::width(PROMPT)).unwrap_or(2);
-    let mut cursor_position = layout_main[0].as_position();
-    cursor_position.x += u16::try_from(app.search.cursor()).unwrap_or_default() + prompt_width + 1;
-    cursor_position.y += 1;
-    f.set_cursor_position(cursor_position);
-}
+        let Outcome::Connect(host) = app.feed([
+            alt_t,
+            KeyCode::Char(' ').into(),
+            KeyCode::Down.into(),
+            KeyCode::Char(' ').into(),
+            KeyCode::Enter.into(),
+        ]) else {
+            panic!("Enter did not connect");
+        };
+        assert_eq!(host.tunnels, ["5432:localhost:5432", "8080:localhost:80"]);
+    }
 
-fn render_searchbar(f: &mut Frame, app: &mut App, area: Rect) {
-    let theme = &app.theme;
-    let prompt = Span::styled(PROMPT, Style::default().fg(theme.accent).add_modifier(Modifier::BOLD));
-    let query = Span::styled(app.search.value(), Style::default().fg(theme.text));
-    let content = Line::from(vec![prompt, query]);
+    #[test]
+    fn idle_timeout_counts_from_the_last_key_press() {
+        let mut app = demo_app();
+        app.last_input = Instant::now().checked_sub(Duration::from_secs(120)).unwrap();
+        assert!(!app.is_idle());
 
-    let matched = app.hosts.len();
-    let total = app.hosts.total_len();
-    let count = format!(" {matched} / {total} ");
-    let title_right = Line::from(Span::styled(
-        count,
-        Style::default().fg(theme.muted),
-    ))
-    .right_aligned();
+        let config = AppConfig {
+            idle_timeout: Some(Duration::from_secs(60)),
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        app.last_input = Instant::now().checked_sub(Duration::from_secs(120)).unwrap();
+        assert!(app.is_idle());
+        app.feed(typed("web"));
+        assert!(!app.is_idle());
+    }
 
-    let block = Block::default()
-        .borders(Borders::ALL)
-        .border_type(BorderType::Rounded)
-        .border_style(Style::default().fg(theme.border_focused))
-        .title(Line::from(Span::styled(
-            " Search ",
-            Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
-        )))
-        .title(title_right);
+    #[test]
+    fn the_lock_screen_hides_the_hosts_until_unlocked() {
+        let alt_l = KeyEvent::new(KeyCode::Char('l'), KeyModifiers::ALT);
+        let mut app = demo_app();
+        assert!(render(&mut app, 100, 30).contains("web1"));
+        app.feed([alt_l]);
+        let screen = render(&mut app, 100, 30);
+        assert!(!screen.contains("web1") && screen.contains("Press any key to unlock"), "{screen}");
+        // 解除のキーは検索語に入らない
+        app.feed(typed("x"));
+        assert!(app.lock.is_none());
+        assert_eq!(app.search.value(), "");
 
-    let paragraph = Paragraph::new(content).block(block);
-    f.render_widget(paragraph, area);
-}
+        let config = AppConfig {
+            lock_passphrase: Some("open".to_string()),
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        app.feed([alt_l]);
+        app.feed(typed("nope"));
+        app.feed([KeyCode::Enter.into()]);
+        assert!(render(&mut app, 100, 30).contains("Wrong passphrase"));
+        let mut keys = typed("open");
+        keys.push(KeyCode::Enter.into());
+        app.feed(keys);
+        assert!(app.lock.is_none());
+        assert_eq!(app.search.value(), "");
+    }
 
-fn render_table(f: &mut Frame, app: &mut App, area: Rect) {
-    let theme = &app.theme;
-    let query = app.search.value().to_string();
+    #[test]
+    fn redact_mode_masks_destinations_users_and_ports() {
+        let alt_x = KeyEvent::new(KeyCode::Char('x'), KeyModifiers::ALT);
+        let mut app = demo_app();
+        let secrets = ["192.0.2.10", "198.51.100.20", "ubuntu", "2222"];
+        let screen = render(&mut app, 140, 40);
+        assert!(secrets.iter().all(|secret| screen.contains(secret)), "{screen}");
 
-    // First column is the marker, then the existing data columns.
-    let mut header_cells: Vec<Cell> = vec![Cell::from("")];
-    let data_headers = if app.config.show_proxy_command {
-        vec!["NAME", "ALIASES", "USER", "DESTINATION", "PORT", "PROXY"]
-    } else {
-        vec!["NAME", "ALIASES", "USER", "DESTINATION", "PORT"]
-    };
-    header_cells.extend(
-        data_headers
-            .iter()
-            .map(|h| Cell::from(Span::styled(*h, theme.header_style()))),
-    );
+        app.feed([alt_x]);
+        let screen = render(&mut app, 140, 40);
+        assert!(!secrets.iter().any(|secret| screen.contains(secret)), "{screen}");
+        assert!(screen.contains("web1") && screen.contains(ssh::REDACTED) && screen.contains("redacted"));
+        app.feed([ctrl('v')]);
+        let screen = render(&mut app, 140, 40);
+        assert!(!secrets.iter().any(|secret| screen.contains(secret)), "{screen}");
 
-    let header = Row::new(header_cells).height(1).bottom_margin(1);
-    let selected_idx = app.table_state.selected().unwrap_or(usize::MAX);
+        app.feed([KeyCode::Esc.into(), alt_x]);
+        assert!(render(&mut app, 140, 40).contains("192.0.2.10"));
+    }
 
-    let rows = app
-        .hosts
-        .iter()
-        .enumerate()
-        .map(|(idx, host)| build_row(idx, selected_idx, host, &query, &app.matcher, theme, app.config.show_proxy_command))
-        .collect::<Vec<_>>();
+    #[test]
+    fn redact_mode_masks_group_headers_and_forward_targets() {
+        let config = AppConfig {
+            group_by: Some(GroupKey::User),
+            redact: true,
+            ..test_config()
+        };
+        let mut hosts = ssh::parse_config_str(
+            "Host app\n  HostName 203.0.113.7\n  User alice\n  HostKeyAlias app.internal\n  LocalForward 5432 db.internal:5432\n  DynamicForward 10.8.0.2:1080\n",
+            Path::new("fixture"),
+            config.pipeline,
+        )
+        .unwrap();
+        hosts[0].source_options = BTreeMap::from([("hostname".to_string(), "34.1.2.3".to_string())]);
+        let secrets = ["203.0.113.7", "alice", "app.internal", "db.internal", "10.8.0.2", "34.1.2.3"];
+        let mut app = App::with_hosts(&config, hosts);
+        let screen = render(&mut app, 140, 40);
+        assert!(screen.contains("app") && !secrets.iter().any(|secret| screen.contains(secret)), "{screen}");
+        app.feed([ctrl('v')]);
+        let screen = render(&mut app, 140, 40);
+        assert!(!secrets.iter().any(|secret| screen.contains(secret)), "{screen}");
+    }
 
-    let block = Block::default()
-        .borders(Borders::NONE)
-        .padding(Padding::horizontal(1));
+    #[test]
+    fn exported_commands_use_the_template_enter_would() {
+        let config = AppConfig {
+            command_template: "mosh {{{name}}}".to_string(),
+            ..test_config()
+        };
+        let hosts = crate::macros::targets(&demo::hosts(config.pipeline).unwrap(), &config.search_fields, "tag:sql");
+        assert_eq!(
+            connect_commands(&config, &hosts).unwrap(),
+            ["mosh db-primary.corp.example.com", "mosh db", "mosh db-replica.corp.example.com"]
+        );
+    }
 
-    let table = Table::new(rows, &app.table_columns_constraints)
-        .header(header)
-        .row_highlight_style(theme.selection_style())
-        .column_spacing(2)
-        .block(block);
+    #[test]
+    fn exported_commands_keep_the_environment_and_refuse_one_time_codes() {
+        let host = ssh::Host {
+            name: "vpn".to_string(),
+            env: BTreeMap::from([("AWS_PROFILE".to_string(), "prod admin".to_string())]),
+            otp_command: Some("oathtool --totp KEY".to_string()),
+            ..ssh::Host::default()
+        };
+        let config = test_config();
+        assert_eq!(written_command(&config, &host).unwrap(), "env 'AWS_PROFILE=prod admin' ssh \"vpn\"");
 
-    f.render_stateful_widget(table, area, &mut app.table_state);
-}
+        let config = AppConfig {
+            command_template: "ssh {{{name}}} -- login {{otp}}".to_string(),
+            ..test_config()
+        };
+        let err = written_command(&config, &host).unwrap_err().to_string();
+        assert!(err.contains("{{otp}}"), "{err}");
+        // OTP コマンドがなければ対話接続と同じく空のまま
+        let host = ssh::Host { otp_command: None, ..host };
+        assert!(written_command(&config, &host).unwrap().ends_with("ssh vpn -- login "));
+    }
 
-fn build_row<'a>(
-    idx: usize,
-    selected_idx: usize,
-    host: &'a ssh::Host,
-    query: &str,
-    matcher: &SkimMatcherV2,
-    theme: &Theme,
-    show_proxy: bool,
-) -> Row<'a> {
-    let marker = if idx == selected_idx {
-        Cell::from(Span::styled(
-            SELECTION_MARKER,
-            Style::default()
-                .fg(theme.selection_marker)
-                .add_modifier(Modifier::BOLD),
-        ))
-    } else {
-        Cell::from(SELECTION_PADDING)
-    };
+    #[test]
+    fn views_keep_their_own_query_and_selection() {
+        let tunnel = |name: &str, forward: &str| Tunnel {
+            name: name.to_string(),
+            forwards: vec![forward.to_string()],
+        };
+        let config = AppConfig {
+            tunnels: vec![tunnel("db", "5432:localhost:5432"), tunnel("web", "8080:localhost:80")],
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        app.feed(typed("bastion"));
+        let selected = app.selected_host().unwrap().name.clone();
 
-    let name_cell = highlighted_cell(&host.name, query, matcher, theme);
-    let aliases_cell = Cell::from(Span::styled(
-        host.aliases.clone(),
-        Style::default().fg(theme.text_dim),
-    ));
-    let user_cell = Cell::from(Span::styled(
-        host.user.clone().unwrap_or_default(),
-        Style::default().fg(theme.text_dim),
-    ));
-    let destination_cell = highlighted_cell(&host.destination, query, matcher, theme);
-    let port_cell = Cell::from(Span::styled(
-        host.port.clone().unwrap_or_default(),
-        Style::default().fg(theme.text_dim),
-    ));
+        // Tab で次の表示へ。入力はその表示の絞り込みになる
+        app.feed([KeyEvent::from(KeyCode::Tab)]);
+        assert_eq!(app.view, View::Tunnels);
+        app.feed(typed("web"));
+        assert_eq!(app.tunnel_view.items.len(), 1);
+        assert_eq!(app.search.value(), "bastion");
+        assert_snapshot("tunnels_view", &render(&mut app, 100, 24));
 
-    let mut cells = vec![marker, name_cell, aliases_cell, user_cell, destination_cell, port_cell];
-    if show_proxy {
-        cells.push(Cell::from(Span::styled(
-            host.proxy_command.clone().unwrap_or_default(),
-            Style::default().fg(theme.text_dim),
-        )));
+        app.feed([KeyEvent::from(KeyCode::F(4)), KeyEvent::from(KeyCode::BackTab)]);
+        assert_eq!(app.view, View::Sessions);
+        app.feed([KeyEvent::from(KeyCode::Esc)]);
+        assert_eq!(app.view, View::Hosts);
+
+        // 戻ってきても各表示の状態はそのまま
+        app.feed([KeyEvent::from(KeyCode::F(2))]);
+        assert_eq!(app.tunnel_view.query.value(), "web");
+        let Outcome::Connect(host) = app.feed([KeyEvent::from(KeyCode::Enter)]) else {
+            panic!("Enter did not connect");
+        };
+        assert_eq!(host.name, selected);
+        assert_eq!(host.tunnels, ["8080:localhost:80"]);
     }
 
-    Row::new(cells)
-}
+    #[test]
+    fn socks_proxy_is_only_described_in_demo_mode() {
+        let mut app = demo_app();
+        app.feed([KeyEvent::new(KeyCode::Char('s'), KeyModifiers::ALT)]);
+        let popup = app.error_popup.clone().unwrap();
+        assert!(popup.contains("ssh -N -D 127.0.0.1:"), "{popup}");
+        assert!(popup.contains("export ALL_PROXY=socks5h://127.0.0.1:"), "{popup}");
+        assert!(app.sessions.is_empty());
+    }
 
-fn highlighted_cell<'a>(
-    value: &'a str,
-    query: &str,
-    matcher: &SkimMatcherV2,
-    theme: &Theme,
-) -> Cell<'a> {
-    let base = Style::default().fg(theme.text);
-    if query.is_empty() {
-        return Cell::from(Span::styled(value.to_string(), base));
+    fn template_error(config: &AppConfig, hosts: &[ssh::Host]) -> String {
+        validate_templates(config, hosts).unwrap_err().to_string()
     }
 
-    let Some((_, indices)) = matcher.fuzzy_indices(value, query) else {
-        return Cell::from(Span::styled(value.to_string(), base));
-    };
+    #[test]
+    fn startup_validation_checks_the_snippet_template() {
+        let config = AppConfig {
+            snippet_template: "ssh -t {{nmae}} {{{snippet}}}".to_string(),
+            ..test_config()
+        };
+        let err = template_error(&config, &[]);
+        assert!(err.contains("--snippet-template: line 1, column 8"), "{err}");
+        assert!(err.contains("nmae"), "{err}");
+    }
 
-    let highlight = theme.match_style();
-    let mut spans = Vec::new();
-    let mut buf = String::new();
-    let mut buf_is_match: Option<bool> = None;
+    #[test]
+    fn startup_validation_checks_source_default_templates() {
+        let config = AppConfig {
+            source_defaults: BTreeMap::from([(
+                "gcp".to_string(),
+                SourceDefaults {
+                    template: Some("tsh ssh {{nmae}}".to_string()),
+                    ..SourceDefaults::default()
+                },
+            )]),
+            ..test_config()
+        };
+        let err = template_error(&config, &[]);
+        assert!(err.contains("source_defaults.gcp.template: "), "{err}");
+        assert!(err.contains("nmae"), "{err}");
+    }
 
-    for (i, ch) in value.chars().enumerate() {
-        let is_match = indices.contains(&i);
-        match buf_is_match {
-            Some(prev) if prev == is_match => buf.push(ch),
-            Some(prev) => {
-                spans.push(Span::styled(
-                    std::mem::take(&mut buf),
-                    if prev { highlight } else { base },
-                ));
-                buf.push(ch);
-                buf_is_match = Some(is_match);
-            }
-            None => {
-                buf.push(ch);
-                buf_is_match = Some(is_match);
-            }
-        }
+    #[test]
+    fn startup_validation_checks_connect_templates_of_hosts() {
+        let config = test_config();
+        let host = ssh::Host {
+            name: "box".to_string(),
+            connect_template: Some("vagrant ssh {{{shquote source_values.machine}}} {{nmae}}".to_string()),
+            ..ssh::Host::default()
+        };
+        let err = template_error(&config, &[host]);
+        assert!(err.contains("connect template of box (box): "), "{err}");
+        assert!(err.contains("nmae"), "{err}");
+        assert!(!err.contains("source_values"), "{err}");
     }
 
-    if let Some(prev) = buf_is_match {
-        spans.push(Span::styled(buf, if prev { highlight } else { base }));
+    #[test]
+    fn startup_validation_checks_host_templates() {
+        let host_template = |entries: &[&str]| HostTemplate {
+            name: "behind bastion".to_string(),
+            entries: entries.iter().map(ToString::to_string).collect(),
+            tags: vec![],
+        };
+        let config = AppConfig {
+            host_templates: vec![host_template(&["ProxyJump {{bastion}}", "User {{user}"])],
+            ..test_config()
+        };
+        let err = template_error(&config, &[]);
+        assert!(err.contains("host_templates \"behind bastion\": unclosed placeholder in \"User {{user}\""), "{err}");
+
+        for (entry, problem) in [("User {{}}", "empty placeholder"), ("{{key}} yes", "placeholder in the keyword")] {
+            let config = AppConfig {
+                host_templates: vec![host_template(&[entry])],
+                ..test_config()
+            };
+            assert!(template_error(&config, &[]).contains(problem), "{entry}");
+        }
+
+        let config = AppConfig {
+            host_templates: vec![host_template(&["ProxyJump {{bastion}}", "LocalForward {{port}} localhost:{{port}}"])],
+            ..test_config()
+        };
+        assert!(validate_templates(&config, &[]).is_ok());
     }
 
-    Cell::from(Line::from(spans))
-}
+    #[test]
+    fn snippets_run_through_the_snippet_template() {
+        let snippet = |name: &str, command: &str| Snippet {
+            name: name.to_string(),
+            command: command.to_string(),
+        };
+        let config = AppConfig {
+            snippets: vec![snippet("disk", "df -h"), snippet("logs", "journalctl -u app -f")],
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
 
-/// 詳細パネル: 選択中のホストの ProxyJump / ProxyCommand / IdentityFile /
-/// LocalForward を key:value 表示する。値が空の項目は省略する。
-fn render_detail_panel(f: &mut Frame, app: &mut App, area: Rect) {
-    let theme = &app.theme;
-    let selected_index = app.table_state.selected().unwrap_or(0);
+        let alt_r = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::ALT);
+        let Outcome::Connect(host) = app.feed([alt_r, KeyCode::Up.into(), KeyCode::Enter.into()]) else {
+            panic!("Enter did not run the snippet");
+        };
+        assert_eq!(host.snippet.as_deref(), Some("'journalctl -u app -f'"));
+        let command = host.render_command_template(&app.command_template(&host).unwrap(), Helpers::Run).unwrap().text;
+        assert_eq!(command, format!("ssh -t \"{}\" 'journalctl -u app -f'", host.name));
 
-    let block = Block::default()
-        .borders(Borders::TOP)
-        .border_style(theme.border_style())
-        .title(Line::from(Span::styled(
-            " Host detail ",
-            Style::default().fg(theme.primary).add_modifier(Modifier::BOLD),
-        )))
-        .padding(Padding::horizontal(2));
+        // Esc で閉じれば何も実行しない
+        assert!(matches!(app.feed([alt_r, KeyCode::Esc.into()]), Outcome::Running));
+        assert!(app.overlay.is_none());
 
-    if app.hosts.is_empty() || selected_index >= app.hosts.len() {
-        let paragraph = Paragraph::new(Span::styled(
-            "No host selected",
-            Style::default().fg(theme.muted),
-        ))
-        .block(block);
-        f.render_widget(paragraph, area);
-        return;
+        // 入力で一覧を絞り込める
+        app.feed([alt_r]);
+        app.feed(typed("jour"));
+        let screen = render(&mut app, 100, 30);
+        assert!(screen.contains("journalctl -u app -f") && !screen.contains("df -h"), "{screen}");
+        let Outcome::Connect(host) = app.feed([KeyCode::Enter.into()]) else {
+            panic!("Enter did not run the filtered snippet");
+        };
+        assert_eq!(host.snippet.as_deref(), Some("'journalctl -u app -f'"));
     }
 
-    let host = &app.hosts[selected_index];
-    let mut lines: Vec<Line> = Vec::new();
+    #[test]
+    fn macros_record_the_query_and_snippet_and_replay_them() {
+        let config = AppConfig {
+            snippets: vec![Snippet {
+                name: "uptime".to_string(),
+                command: "uptime".to_string(),
+            }],
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        let alt_r = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::ALT);
+        let alt_m = KeyEvent::new(KeyCode::Char('m'), KeyModifiers::ALT);
 
-    let mut push_field = |label: &str, value: &str| {
-        if value.is_empty() {
-            return;
-        }
-        lines.push(Line::from(vec![
-            Span::styled(
-                format!("{label:<14}"),
-                Style::default().fg(theme.muted).add_modifier(Modifier::BOLD),
-            ),
-            Span::styled(value.to_string(), Style::default().fg(theme.text)),
-        ]));
-    };
+        // 検索語とスニペットを記録する
+        app.feed(typed("tag:web"));
+        app.feed([alt_r, ctrl('s')]);
+        app.feed(typed("uptime-web"));
+        app.feed([KeyCode::Enter.into()]);
+        assert!(app.overlay.is_none());
+        assert_eq!(app.macros.get("uptime-web").unwrap().steps.query, "tag:web");
 
-    push_field("Hostname", &host.destination);
-    if let Some(v) = host.user.as_deref() {
-        push_field("User", v);
+        // 別の検索をしていても、マクロの検索語で絞ったホストすべてで実行する
+        app.set_search("bastion".to_string());
+        app.feed([alt_m]);
+        app.feed(typed("uptime-web"));
+        let Outcome::RunMacro(hosts) = app.feed([KeyCode::Enter.into()]) else {
+            panic!("Enter did not replay the macro");
+        };
+        assert_eq!(app.search.value(), "tag:web");
+        let listed: Vec<String> = app.hosts.iter().map(|host| host.name.clone()).collect();
+        assert_eq!(hosts, listed);
+        assert!(hosts.contains(&"web1".to_string()) && !hosts.contains(&"bastion".to_string()));
+
+        app.feed([alt_m, KeyCode::Delete.into(), KeyCode::Esc.into()]);
+        assert!(app.macros.get("uptime-web").is_err());
     }
-    if let Some(v) = host.port.as_deref() {
-        push_field("Port", v);
+
+    #[test]
+    fn hosts_with_a_directory_start_the_shell_there() {
+        let host = ssh::Host {
+            name: "app".to_string(),
+            dir: Some("/srv/app".to_string()),
+            ..ssh::Host::default()
+        };
+        let app = App::with_hosts(&test_config(), vec![host.clone()]);
+        let command = host.render_command_template(&app.command_template(&host).unwrap(), Helpers::Run).unwrap().text;
+        let args = shlex::split(&command).unwrap();
+        assert_eq!(args, ["ssh", "-t", "app", "cd /srv/app && exec $SHELL"]);
+
+        // 引用符や $(...) を含むディレクトリでもリモートの cd の引数に収まる
+        let host = ssh::Host {
+            dir: Some("/srv/it's $(id)".to_string()),
+            ..host
+        };
+        let command = host.render_command_template(&app.command_template(&host).unwrap(), Helpers::Run).unwrap().text;
+        let remote = shlex::split(&command).unwrap().pop().unwrap();
+        assert_eq!(shlex::split(&remote).unwrap(), ["cd", "/srv/it's $(id)", "&&", "exec", "$SHELL"]);
+
+        // --template を変えていればそちらを優先する
+        let config = AppConfig {
+            command_template: "mosh {{name}}".to_string(),
+            ..test_config()
+        };
+        let app = App::with_hosts(&config, vec![host.clone()]);
+        assert_eq!(app.command_template(&host).unwrap(), "mosh {{name}}");
     }
-    if let Some(v) = host.proxy_jump.as_deref() {
-        push_field("ProxyJump", v);
+
+    #[test]
+    fn values_a_source_won_are_passed_to_ssh() {
+        let host = ssh::Host {
+            name: "web-1".to_string(),
+            source_options: BTreeMap::from([("hostname".to_string(), "34.1.2.3".to_string())]),
+            ..ssh::Host::default()
+        };
+        let app = App::with_hosts(&test_config(), vec![host.clone()]);
+        let command = |host: &ssh::Host| {
+            let template = app.command_template(host).unwrap();
+            let Ok(rendered) = host.render_command_template(&template, Helpers::Refuse) else {
+                panic!("{template} did not render");
+            };
+            rendered.text
+        };
+        assert_eq!(command(&host), "ssh -o 'hostname=34.1.2.3' \"web-1\"");
+
+        // ソースの値はテンプレートとして評価されない
+        let host = ssh::Host {
+            source_options: BTreeMap::from([
+                ("hostname".to_string(), "{{cmd \"id\"}}".to_string()),
+                ("user".to_string(), "deploy".to_string()),
+            ]),
+            ..host
+        };
+        assert_eq!(command(&host), "ssh -o 'hostname={{cmd \"id\"}}' -o 'user=deploy' \"web-1\"");
+
+        // ssh 以外のコマンドでは付けられないので、黙って捨てずに知らせる
+        let config = AppConfig {
+            command_template: "mosh {{name}}".to_string(),
+            ..test_config()
+        };
+        assert_eq!(
+            source_options_warning(&config, &[host]).as_deref(),
+            Some("Source values are not passed to a non-ssh command for web-1 (mosh)")
+        );
+        assert_eq!(source_options_warning(&config, &[]), None);
     }
-    if let Some(v) = host.proxy_command.as_deref() {
-        push_field("ProxyCommand", v);
+
+    #[test]
+    fn junk_ports_are_kept_as_written_but_reported() {
+        let host = |name: &str, port: &str| ssh::Host {
+            name: name.to_string(),
+            port: Some(port.to_string()),
+            ..ssh::Host::default()
+        };
+        let hosts = [host("web", "2222"), host("db", "22x"), host("cache", "70000")];
+        assert_eq!(hosts[0].port_number(), Some(2222));
+        assert_eq!(hosts[1].port_number(), None);
+        assert_eq!(invalid_port_warning(&hosts).as_deref(), Some("Invalid Port for db (22x) and 1 more"));
+        assert_eq!(invalid_port_warning(&hosts[..1]), None);
+
+        let mut app = App::with_hosts(&test_config(), vec![hosts[1].clone()]);
+        let screen = render(&mut app, 120, 30);
+        assert!(screen.contains("22x  (not a port number; ssh will refuse it)"), "{screen}");
     }
-    if let Some(v) = host.identity_file.as_deref() {
-        push_field("IdentityFile", v);
+
+    #[test]
+    fn clipboard_text_is_only_used_when_it_names_a_host() {
+        let hosts = demo::hosts(test_config().pipeline).unwrap();
+        let bastion = hosts.iter().find(|host| host.name == "bastion").unwrap();
+
+        assert_eq!(clipboard_host(" bastion\n", &hosts).as_deref(), Some("bastion"));
+        assert_eq!(clipboard_host(&bastion.destination, &hosts), Some(bastion.destination.clone()));
+        assert_eq!(clipboard_host(&format!("ops@{}", bastion.destination), &hosts), Some(bastion.destination.clone()));
+        assert_eq!(clipboard_host("BASTION", &hosts).as_deref(), Some("BASTION"));
+        assert_eq!(clipboard_host("please reboot bastion", &hosts), None);
+        assert_eq!(clipboard_host("unknown.example", &hosts), None);
+        assert_eq!(clipboard_host("", &hosts), None);
     }
 
-    if !host.local_forwards.is_empty() {
-        let first = host.local_forwards.first().unwrap();
-        let formatted = format!("{} → {}:{}", first.local_port, first.remote_host, first.remote_port);
-        push_field("LocalForward", &formatted);
-        let indent = " ".repeat(14);
-        for lf in host.local_forwards.iter().skip(1) {
-            lines.push(Line::from(vec![
-                Span::raw(indent.clone()),
-                Span::styled(
-                    format!("{} → {}:{}", lf.local_port, lf.remote_host, lf.remote_port),
-                    Style::default().fg(theme.text),
-                ),
-            ]));
+    #[test]
+    fn slow_sources_load_after_the_picker_is_shown() {
+        let config = AppConfig {
+            // 存在しないディレクトリなので vagrant は起動する前に失敗する
+            source_cache: CachePolicy {
+                ttl: Duration::ZERO,
+                offline: false,
+            },
+            ..test_config()
+        };
+        let mut app = App::with_hosts(&config, demo::hosts(config.pipeline).unwrap());
+        let count = app.merged_hosts.len();
+        app.load_in_background(vec!["vagrant:/nonexistent/sgh-test".parse().unwrap()]);
+
+        let loading = |screen: &str| SPINNER.iter().any(|frame| screen.contains(&format!("{frame} vagrant")));
+        let screen = render(&mut app, 120, 30);
+        assert!(loading(&screen), "{screen}");
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while app.source_results.is_some() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+            app.receive_source_hosts();
         }
+        assert!(app.source_results.is_none());
+        assert_eq!(app.merged_hosts.len(), count);
+        assert!(!loading(&render(&mut app, 120, 30)));
+        assert!(app.status_message.as_ref().is_some_and(|(message, _)| message.starts_with("Source vagrant failed")));
     }
 
-    if lines.is_empty() {
-        lines.push(Line::from(Span::styled(
-            "(no extra settings)",
-            Style::default().fg(theme.muted),
-        )));
+    #[test]
+    fn template_variables_show_the_host_values() {
+        let host = ssh::Host {
+            name: "db".to_string(),
+            port: Some("2222".to_string()),
+            options: [("serveraliveinterval".to_string(), "30".to_string())].into(),
+            ..ssh::Host::default()
+        };
+        let rows = template_variable_rows(&host);
+        let value = |name: &str| rows.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+
+        assert_eq!(value("{{name}}"), Some("db"));
+        assert_eq!(value("{{port}}"), Some("2222"));
+        assert_eq!(value("{{user}}"), Some("(not set)"));
+        assert_eq!(value("{{options.serveraliveinterval}}"), Some("30"));
+        assert_eq!(value("{{env}}"), Some("(none)"));
+        assert_eq!(value("{{otp}}"), Some("(fetched when connecting)"));
     }
 
-    let paragraph = Paragraph::new(lines).block(block);
-    f.render_widget(paragraph, area);
-}
+    #[test]
+    fn snapshot_no_matches() {
+        let mut app = demo_app();
+        app.set_search("no-such-host".to_string());
+        assert_snapshot("no_matches", &render(&mut app, 100, 30));
+    }
 
-fn render_footer(f: &mut Frame, app: &mut App, area: Rect) {
-    let theme = &app.theme;
-    let sep = Span::styled("  │  ", Style::default().fg(theme.border));
+    #[test]
+    fn the_selection_follows_the_host_while_filtering() {
+        let mut app = demo_app();
+        let down = KeyEvent::from(KeyCode::Down);
+        app.feed([down, down, down, down]);
+        assert_eq!(app.selected_host().unwrap().name, "web2");
 
-    let chips = [
-        ("↑↓", "navigate"),
-        ("↵", "connect"),
-        ("⌫", "edit"),
-        ("esc", "quit"),
-    ];
+        app.feed(typed("web"));
+        assert_eq!(app.selected_host().unwrap().name, "web2");
+        // 絞り込みで消えたら、一覧で最も近いホストに移る
+        app.feed(typed("1"));
+        assert_eq!(app.selected_host().unwrap().name, "web1");
 
-    let mut spans: Vec<Span> = Vec::new();
-    spans.push(Span::raw(" "));
-    for (i, (key, label)) in chips.iter().enumerate() {
-        if i > 0 {
-            spans.push(sep.clone());
-        }
-        spans.push(Span::styled(
-            format!(" {key} "),
-            Style::default()
-                .fg(theme.accent)
-                .add_modifier(Modifier::BOLD),
-        ));
-        spans.push(Span::styled(
-            (*label).to_string(),
-            Style::default().fg(theme.muted),
-        ));
+        // 先頭を選んでいるなら先頭のまま (Enter で一番よい一致に接続する)
+        let mut app = demo_app();
+        app.feed(typed("db"));
+        assert_eq!(app.table_state.selected(), Some(0));
     }
 
-    let paragraph = Paragraph::new(Line::from(spans));
-    f.render_widget(paragraph, area);
+    #[test]
+    fn a_query_matching_nothing_suggests_close_hosts() {
+        let mut app = demo_app();
+        app.feed(typed("bastoin"));
+        assert!(app.hosts.is_empty());
+        assert!(render(&mut app, 100, 30).contains("Did you mean bastion?"));
+
+        app.feed([KeyEvent::from(KeyCode::Tab)]);
+        assert_eq!(app.search.value(), "bastion");
+        assert_eq!(app.selected_host().unwrap().name, "bastion");
+
+        app.feed(typed("zz"));
+        app.feed([KeyEvent::new(KeyCode::Char('x'), KeyModifiers::CONTROL)]);
+        assert_eq!(app.search.value(), "");
+        assert_eq!(app.hosts.len(), 15);
+    }
+
+    #[test]
+    fn snapshot_no_hosts() {
+        let mut app = App::with_hosts(&test_config(), vec![]);
+        assert_snapshot("no_hosts", &render(&mut app, 100, 30));
+    }
+
+    #[test]
+    fn snapshot_terminal_too_small() {
+        let mut app = demo_app();
+        assert_snapshot("terminal_too_small", &render(&mut app, 30, 10));
+    }
 }
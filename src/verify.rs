@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+use crate::ssh;
+
+/// Keywords whose values OpenSSH compares without regard to case.
+const CASE_INSENSITIVE: [&str; 2] = ["hostname", "user"];
+
+/// A keyword where sgh's effective value differs from `ssh -G`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub keyword: String,
+    pub sgh: String,
+    /// Every value `ssh -G` printed for the keyword; empty when it printed
+    /// none.
+    pub ssh: Vec<String>,
+}
+
+impl Mismatch {
+    /// `ssh -G` column text, e.g. `(not reported)` or `a, b`.
+    #[must_use]
+    pub fn ssh_display(&self) -> String {
+        if self.ssh.is_empty() {
+            "(not reported)".to_string()
+        } else {
+            self.ssh.join(", ")
+        }
+    }
+}
+
+/// Runs `ssh -G <name>` and returns every keyword with its values. With
+/// `config` the file is passed as `-F`, otherwise ssh reads its defaults.
+///
+/// # Errors
+///
+/// Will return `Err` if ssh cannot be run or rejects the configuration.
+pub fn ssh_effective(name: &str, config: Option<&Path>) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut command = Command::new("ssh");
+    if let Some(config) = config {
+        command.arg("-F").arg(config);
+    }
+    let output = command
+        .arg("-G")
+        .arg(name)
+        .output()
+        .map_err(|err| anyhow!("Failed to run ssh -G: {err}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "ssh -G {name} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let mut options: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some((keyword, value)) = line.split_once(' ') {
+            options
+                .entry(keyword.to_lowercase())
+                .or_default()
+                .push(value.to_string());
+        }
+    }
+    Ok(options)
+}
+
+/// Keywords sgh resolved for `host` that `ssh -G` reports differently.
+/// Values using `%` tokens are skipped since ssh expands them.
+#[must_use]
+pub fn compare(host: &ssh::Host, effective: &BTreeMap<String, Vec<String>>) -> Vec<Mismatch> {
+    host.options
+        .iter()
+        .filter(|(_, value)| !value.contains('%'))
+        .filter_map(|(keyword, value)| {
+            let reported = effective.get(keyword).cloned().unwrap_or_default();
+            let matches = reported
+                .iter()
+                .any(|candidate| normalize(keyword, candidate) == normalize(keyword, value));
+            (!matches).then(|| Mismatch {
+                keyword: keyword.clone(),
+                sgh: value.clone(),
+                ssh: reported,
+            })
+        })
+        .collect()
+}
+
+/// Puts both sides in the form `ssh -G` prints: lowercase yes/no, no
+/// `[host]` brackets in forwards, `~` expanded, single spaces.
+fn normalize(keyword: &str, value: &str) -> String {
+    let value = value
+        .split_whitespace()
+        .map(|word| shellexpand::tilde(&word.replace(['[', ']'], "")).to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let lowercase = value.eq_ignore_ascii_case("yes")
+        || value.eq_ignore_ascii_case("no")
+        || CASE_INSENSITIVE.contains(&keyword);
+    if lowercase {
+        value.to_lowercase()
+    } else {
+        value
+    }
+}
+
+/// `sgh verify`: compares every host (or the named ones) with `ssh -G` and
+/// prints the differences. Returns whether everything matched.
+///
+/// # Errors
+///
+/// Will return `Err` if a named host does not exist.
+pub fn run(hosts: &[ssh::Host], names: &[String], config: Option<&Path>) -> Result<bool> {
+    let selected: Vec<&ssh::Host> = if names.is_empty() {
+        hosts.iter().filter(|host| !host.is_pattern).collect()
+    } else {
+        names
+            .iter()
+            .map(|name| {
+                hosts
+                    .iter()
+                    .find(|host| &host.name == name)
+                    .ok_or(anyhow!("No host named {name}"))
+            })
+            .collect::<Result<_>>()?
+    };
+
+    let mut all_match = true;
+    for host in selected {
+        match ssh_effective(&host.name, config) {
+            Ok(effective) => {
+                let mismatches = compare(host, &effective);
+                if mismatches.is_empty() {
+                    println!("{}: ok", host.name);
+                    continue;
+                }
+                all_match = false;
+                println!("{}: {} mismatch(es)", host.name, mismatches.len());
+                for mismatch in mismatches {
+                    println!(
+                        "  {:<24} sgh: {}  ssh -G: {}",
+                        mismatch.keyword,
+                        mismatch.sgh,
+                        mismatch.ssh_display()
+                    );
+                }
+            }
+            Err(err) => {
+                all_match = false;
+                println!("{}: {err}", host.name);
+            }
+        }
+    }
+
+    Ok(all_match)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equivalent_values_are_not_reported() {
+        let host = ssh::Host {
+            options: [
+                ("hostname", "DB.example.com"),
+                ("forwardagent", "Yes"),
+                ("localforward", "5432 localhost:5432"),
+                ("identityfile", "~/.ssh/id_db"),
+                ("port", "2222"),
+                ("controlpath", "~/.ssh/%r@%h"),
+                ("sendenv", "LANG"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .into(),
+            ..ssh::Host::default()
+        };
+        let effective: BTreeMap<String, Vec<String>> = [
+            ("hostname", vec!["db.example.com"]),
+            ("forwardagent", vec!["yes"]),
+            ("localforward", vec!["5432 [localhost]:5432"]),
+            ("identityfile", vec!["~/.ssh/id_rsa", "~/.ssh/id_db"]),
+            ("port", vec!["22"]),
+        ]
+        .map(|(k, v)| (k.to_string(), v.into_iter().map(str::to_string).collect()))
+        .into();
+
+        let mismatches = compare(&host, &effective);
+        assert_eq!(
+            mismatches,
+            vec![
+                Mismatch {
+                    keyword: "port".to_string(),
+                    sgh: "2222".to_string(),
+                    ssh: vec!["22".to_string()],
+                },
+                Mismatch {
+                    keyword: "sendenv".to_string(),
+                    sgh: "LANG".to_string(),
+                    ssh: vec![],
+                },
+            ]
+        );
+        assert_eq!(mismatches[1].ssh_display(), "(not reported)");
+    }
+}